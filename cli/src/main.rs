@@ -1,14 +1,14 @@
 mod opts;
 
 use std::{
-    io::{Read, Write},
+    io::{self, BufRead, BufReader, Read, Write},
     os::unix::net::UnixStream,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use clap::Parser;
 use serde::Serialize;
-use wpaperd_ipc::{socket_path, IpcError, IpcMessage, IpcResponse};
+use wpaperd_ipc::{format_outputs, socket_path, IpcError, IpcMessage, IpcResponse};
 
 use crate::opts::{Opts, SubCmd};
 
@@ -16,6 +16,7 @@ fn main() {
     let args = Opts::parse();
 
     let mut json_resp = false;
+    let mut image_data = Vec::new();
 
     let mut conn = UnixStream::connect(socket_path().unwrap()).unwrap();
     let msg = match args.subcmd {
@@ -29,8 +30,63 @@ fn main() {
         SubCmd::ReloadWallpaper { monitors } => IpcMessage::ReloadWallpaper { monitors },
         SubCmd::PauseWallpaper { monitors } => IpcMessage::PauseWallpaper { monitors },
         SubCmd::ResumeWallpaper { monitors } => IpcMessage::ResumeWallpaper { monitors },
+        SubCmd::ResetHistory { monitors } => IpcMessage::ResetHistory { monitors },
+        SubCmd::InitialTransition {
+            enable,
+            disable,
+            monitors,
+        } => IpcMessage::InitialTransition {
+            monitors,
+            enabled: (enable || disable).then_some(enable),
+        },
+        SubCmd::Rescan { target } => IpcMessage::Rescan {
+            // wpaperd runs in a different directory, leave the names of the displays untouched
+            target: target.map(|target| match std::env::current_dir() {
+                Ok(dir) if Path::new(&target).exists() => {
+                    dir.join(&target).to_string_lossy().into_owned()
+                }
+                _ => target,
+            }),
+        },
+        SubCmd::NextGroup { group } => IpcMessage::NextGroup { group },
+        SubCmd::Debug { monitor } => IpcMessage::Debug { monitor },
+        SubCmd::Config { monitor } => IpcMessage::Config { monitor },
+        SubCmd::Swap { first, second } => IpcMessage::SwapWallpapers { first, second },
+        SubCmd::Capture { monitor, path } => IpcMessage::Capture {
+            monitor,
+            // wpaperd runs in a different directory, and the file doesn't exist yet
+            path: std::env::current_dir().map_or(path.clone(), |dir| dir.join(&path)),
+        },
+        SubCmd::Outputs => IpcMessage::Outputs,
+        SubCmd::Subscribe => IpcMessage::Subscribe,
+        SubCmd::SetWallpaper { monitor, path, pin } if path == Path::new("-") => {
+            if let Err(err) = io::stdin().read_to_end(&mut image_data) {
+                eprintln!("the image could not be read from the standard input: {err}");
+                std::process::exit(1);
+            }
+            IpcMessage::SetWallpaperData {
+                monitor,
+                size: image_data.len(),
+                pin,
+            }
+        }
+        SubCmd::SetWallpaper { monitor, path, pin } => IpcMessage::SetWallpaper {
+            monitor,
+            // wpaperd runs in a different directory, always send an absolute path
+            path: path.canonicalize().unwrap_or(path),
+            pin,
+        },
     };
     conn.write_all(&serde_json::to_vec(&msg).unwrap()).unwrap();
+    // The image data, if any, directly follows the message
+    conn.write_all(&image_data).unwrap();
+    if let IpcMessage::Subscribe = msg {
+        // The daemon keeps sending events until it exits
+        for line in BufReader::new(conn).lines() {
+            println!("{}", line.expect("reading the events of wpaperd to work"));
+        }
+        return;
+    }
     let mut buf = String::new();
     conn.read_to_string(&mut buf).unwrap();
     let res: Result<IpcResponse, IpcError> =
@@ -62,6 +118,46 @@ fn main() {
                     }
                 }
             }
+            IpcResponse::Debug(info) => {
+                println!(
+                    "transition: {}",
+                    if info.transition_going {
+                        "running"
+                    } else {
+                        "finished"
+                    }
+                );
+                println!("progress: {:.3}", info.progress);
+                println!("time started: {}ms", info.time_started);
+                println!("transition time: {}ms", info.transition_time);
+                match info.frame_interval {
+                    Some(interval) => println!("frame interval: {interval:.1}ms"),
+                    None => println!(
+                        "frame interval: not measured yet, run the command again after some frames have been drawn"
+                    ),
+                }
+            }
+            IpcResponse::HistoryReset { entries } => {
+                for (monitor, cleared) in entries {
+                    println!("{monitor}: {cleared} images forgotten");
+                }
+            }
+            IpcResponse::InitialTransition { entries } => {
+                for (monitor, enabled) in entries {
+                    let state = if enabled { "enabled" } else { "disabled" };
+                    println!("{monitor}: initial transition {state}");
+                }
+            }
+            IpcResponse::Rescanned { entries } => {
+                for (path, images) in entries {
+                    println!("{}: {images} images", path.to_string_lossy());
+                }
+            }
+            IpcResponse::Outputs { outputs } => print!("{}", format_outputs(&outputs)),
+            IpcResponse::Config(info) => println!(
+                "{}",
+                serde_json::to_string_pretty(&info).expect("json encoding to work")
+            ),
             IpcResponse::Ok => (),
         },
         Err(err) => match err {
@@ -73,6 +169,21 @@ fn main() {
                     eprintln!("Wallpaper could not be drawn for monitor {monitor}: {err}")
                 }
             }
+            IpcError::InvalidImage { path, error } => {
+                eprintln!("{} is not a valid image: {error}", path.to_string_lossy())
+            }
+            IpcError::GroupNotFound { group } => {
+                eprintln!("no display belongs to group {group}")
+            }
+            IpcError::CaptureFailed { monitor, error } => {
+                eprintln!("display {monitor} could not be captured: {error}")
+            }
+            IpcError::CannotSwap { monitor, reason } => {
+                eprintln!("the image of display {monitor} cannot be swapped: {reason}")
+            }
+            IpcError::NothingToRescan { target } => {
+                eprintln!("{target} is neither a display showing a directory nor a directory of the configuration")
+            }
         },
     }
 }
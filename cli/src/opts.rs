@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 
 #[derive(Parser)]
@@ -26,4 +28,47 @@ pub enum SubCmd {
     PauseWallpaper { monitors: Vec<String> },
     #[clap(visible_alias = "resume")]
     ResumeWallpaper { monitors: Vec<String> },
+    /// Forget the images drawn recently, so that the random sorting can pick any of them again.
+    /// All the displays are reset when none is given
+    ResetHistory { monitors: Vec<String> },
+    /// Show whether the first wallpaper drawn on a display fades in, or change it with
+    /// `--enable` or `--disable`. It is kept for the next time the display is connected, and all
+    /// the displays are affected when none is given
+    InitialTransition {
+        #[clap(long, conflicts_with = "disable")]
+        enable: bool,
+        #[clap(long)]
+        disable: bool,
+        monitors: Vec<String>,
+    },
+    /// Read again the images in the directory shown on a display, or in a directory of the
+    /// configuration, when the changes are not reported, e.g. on network filesystems. All the
+    /// directories are read when none is given
+    Rescan { target: Option<String> },
+    /// Show the next wallpaper on all the displays of a group at the same time
+    NextGroup { group: String },
+    /// List the outputs handled by wpaperd, with their size, scale and current wallpaper
+    Outputs,
+    /// Print the events of the daemon as they happen, one JSON value per line
+    Subscribe,
+    /// Print the configuration applied to a display as JSON, after merging its section with
+    /// the defaults
+    Config { monitor: String },
+    /// Exchange the images shown on two displays, each one using its own transition
+    Swap { first: String, second: String },
+    /// Save the frame currently shown on a display as a PNG, midway through a running
+    /// transition too
+    Capture { monitor: String, path: PathBuf },
+    /// Show diagnostics about the transitions and the frames drawn on a display
+    Debug { monitor: String },
+    /// Show the image at the given path, regardless of the wallpaper configuration
+    #[clap(visible_alias = "set")]
+    SetWallpaper {
+        monitor: String,
+        /// Path of the image, use `-` to read the image from the standard input
+        path: PathBuf,
+        /// Pause the automatic wallpaper sequence, until the resume command is called
+        #[clap(long)]
+        pin: bool,
+    },
 }
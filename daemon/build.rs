@@ -50,9 +50,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let mut file = File::create(Path::new(&outdir).join("gl_bindings.rs")).unwrap();
 
-    Registry::new(Api::Gles2, (3, 2), Profile::Core, Fallbacks::All, [])
-        .write_bindings(StructGenerator, &mut file)
-        .unwrap();
+    // Only adds the constants of the extensions, their availability is checked at runtime
+    Registry::new(
+        Api::Gles2,
+        (3, 2),
+        Profile::Core,
+        Fallbacks::All,
+        ["GL_EXT_texture_format_BGRA8888"],
+    )
+    .write_bindings(StructGenerator, &mut file)
+    .unwrap();
 
     Ok(())
 }
@@ -0,0 +1,162 @@
+//! Follow the color scheme of the desktop, as exposed by the settings portal in the
+//! `org.freedesktop.appearance` namespace. The portal is reached with zbus, when wpaperd is
+//! built with the `color-scheme` feature.
+
+use color_eyre::Result;
+use smithay_client_toolkit::reexports::calloop::channel::Sender;
+
+/// The color scheme preferred by the user, the wallpapers can follow it with `light_path`
+/// and `dark_path`
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum ColorScheme {
+    /// Also used when the user has no preference
+    #[default]
+    Light,
+    Dark,
+}
+
+impl ColorScheme {
+    /// Map the value of `color-scheme`: `1` is dark, `2` is light and `0` is no preference
+    #[cfg_attr(not(feature = "color-scheme"), allow(dead_code))]
+    fn from_setting(value: u32) -> Self {
+        match value {
+            1 => ColorScheme::Dark,
+            _ => ColorScheme::Light,
+        }
+    }
+}
+
+/// Read the current color scheme, then listen to its changes in another thread and send them
+/// to the main loop. Fails when there is no session bus or no portal
+#[cfg(feature = "color-scheme")]
+pub fn listen(sender: Sender<ColorScheme>) -> Result<ColorScheme> {
+    portal::listen(sender)
+}
+
+#[cfg(not(feature = "color-scheme"))]
+pub fn listen(_sender: Sender<ColorScheme>) -> Result<ColorScheme> {
+    color_eyre::eyre::bail!("wpaperd has been built without the color-scheme feature")
+}
+
+#[cfg(feature = "color-scheme")]
+mod portal {
+    use std::{sync::mpsc, thread, time::Duration};
+
+    use color_eyre::{
+        eyre::{eyre, Context},
+        Result,
+    };
+    use log::warn;
+    use smithay_client_toolkit::reexports::calloop::channel::Sender;
+    use zbus::{
+        blocking::{Connection, Proxy, SignalIterator},
+        zvariant::{OwnedValue, Value},
+    };
+
+    use super::ColorScheme;
+
+    const NAMESPACE: &str = "org.freedesktop.appearance";
+    const KEY: &str = "color-scheme";
+
+    /// How long to wait for the portal to answer at startup
+    const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+    pub fn listen(sender: Sender<ColorScheme>) -> Result<ColorScheme> {
+        let (initial_sender, initial) = mpsc::channel();
+        thread::Builder::new()
+            .name("wpaperd-color-scheme".to_string())
+            .spawn(move || {
+                let signals = match subscribe() {
+                    Ok((color_scheme, signals)) => {
+                        // The portal answered too late, the main loop gets it with the changes
+                        if initial_sender.send(Ok(color_scheme)).is_err()
+                            && sender.send(color_scheme).is_err()
+                        {
+                            return;
+                        }
+                        signals
+                    }
+                    Err(err) => {
+                        let _ = initial_sender.send(Err(err));
+                        return;
+                    }
+                };
+                for message in signals {
+                    let changed = message
+                        .body()
+                        .deserialize::<(String, String, OwnedValue)>()
+                        .ok()
+                        .and_then(|(_, _, value)| setting_value(&value));
+                    if let Some(value) = changed {
+                        if sender.send(ColorScheme::from_setting(value)).is_err() {
+                            // The main loop is not running anymore
+                            return;
+                        }
+                    }
+                }
+                warn!("stopped following the color scheme: the session bus has been closed");
+            })
+            .context("spawning the color scheme thread")?;
+
+        initial
+            .recv_timeout(READ_TIMEOUT)
+            .context("waiting for the settings portal")?
+    }
+
+    /// Listen to the changes of the color scheme, then read its current value
+    fn subscribe() -> Result<(ColorScheme, SignalIterator<'static>)> {
+        let connection = Connection::session().context("connecting to the session bus")?;
+        let proxy = Proxy::new(
+            &connection,
+            "org.freedesktop.portal.Desktop",
+            "/org/freedesktop/portal/desktop",
+            "org.freedesktop.portal.Settings",
+        )?;
+        // Before reading the value, so that no change is missed in between
+        let signals = proxy
+            .receive_signal_with_args("SettingChanged", &[(0, NAMESPACE), (1, KEY)])
+            .context("listening to the changes of the settings portal")?;
+        let value: OwnedValue = proxy
+            .call("Read", &(NAMESPACE, KEY))
+            .with_context(|| format!("the settings portal cannot read {NAMESPACE} {KEY}"))?;
+        let color_scheme = setting_value(&value)
+            .map(ColorScheme::from_setting)
+            .ok_or_else(|| eyre!("unexpected value for {NAMESPACE} {KEY}"))?;
+        Ok((color_scheme, signals))
+    }
+
+    /// The older portals wrap the value returned by `Read` in another variant
+    fn setting_value(value: &Value) -> Option<u32> {
+        match value {
+            Value::U32(value) => Some(*value),
+            Value::Value(value) => setting_value(value),
+            _ => None,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_setting_value() {
+            assert_eq!(setting_value(&Value::U32(1)), Some(1));
+            // A variant containing a variant containing 1
+            let nested = Value::Value(Box::new(Value::Value(Box::new(Value::U32(1)))));
+            assert_eq!(setting_value(&nested), Some(1));
+            assert_eq!(setting_value(&Value::Str("dark".into())), None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_setting() {
+        assert_eq!(ColorScheme::from_setting(1), ColorScheme::Dark);
+        assert_eq!(ColorScheme::from_setting(2), ColorScheme::Light);
+        assert_eq!(ColorScheme::from_setting(0), ColorScheme::Light);
+    }
+}
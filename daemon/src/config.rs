@@ -1,7 +1,9 @@
 use std::{
+    borrow::Cow,
     collections::HashMap,
     fs,
     path::{Path, PathBuf},
+    slice,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -16,28 +18,104 @@ use color_eyre::{
 };
 use dirs::home_dir;
 use hotwatch::{Event, Hotwatch};
-use log::{error, warn};
+use log::{error, info, warn};
 use serde::Deserialize;
-use smithay_client_toolkit::reexports::calloop::ping::Ping;
+use smithay_client_toolkit::reexports::calloop::{
+    ping::Ping,
+    timer::{TimeoutAction, Timer},
+    LoopHandle,
+};
 
 use crate::{
+    color_scheme::ColorScheme,
+    display_info::DisplayInfo,
+    download::is_url,
     image_picker::ImagePicker,
     render::Transition,
-    wallpaper_info::{BackgroundMode, Sorting, WallpaperInfo},
+    wallpaper_info::{
+        BackgroundMode, Color, DurationRange, FileSize, Gradient, Gravity, GroupMode, LayerAnchor,
+        MaxTextureSize, Recursive, Sorting, TileSnap, WallpaperInfo, WallpaperLayer, Weights,
+    },
+    workspace::ActiveWorkspaces,
+    wpaperd::Wpaperd,
 };
 
+/// First interval between the checks of the missing paths, it doubles at each check
+const MIN_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Directory next to the configuration file, its `.toml` files are read after it
+const DROP_IN_DIR: &str = "config.d";
+
 #[derive(Default, Deserialize, PartialEq, Debug, Clone)]
 pub struct SerializedWallpaperInfo {
+    /// Do not draw anything on the displays matching this section
+    pub ignore: Option<bool>,
     #[serde(default, deserialize_with = "tilde_expansion_deserialize")]
     pub path: Option<PathBuf>,
+    /// Used instead of `path` while the desktop prefers a light color scheme
+    #[serde(default, deserialize_with = "tilde_expansion_deserialize")]
+    pub light_path: Option<PathBuf>,
+    /// Used instead of `path` while the desktop prefers a dark color scheme
+    #[serde(default, deserialize_with = "tilde_expansion_deserialize")]
+    pub dark_path: Option<PathBuf>,
+    /// Used instead of `path` while one of these workspaces is shown on the display, by the
+    /// name of the workspace
+    #[serde(default, deserialize_with = "tilde_expansion_map_deserialize")]
+    pub workspace_paths: Option<HashMap<String, PathBuf>>,
+    /// Solid color to show instead of the images in `path`
+    pub color: Option<Color>,
+    /// Linear gradient to show instead of the images in `path`
+    pub gradient: Option<Gradient>,
+    /// Either a single duration (e.g. `30m`) or a range (e.g. `5m..15m`)
+    #[serde(default)]
+    pub duration: Option<DurationRange>,
+    /// Change the image on the multiples of `duration` counted from midnight, e.g. `15m` on every
+    /// quarter of an hour, instead of counting it from when the image was shown
+    pub align: Option<bool>,
+    /// Download the image again this often, when `path` is an URL
     #[serde(default, with = "humantime_serde")]
-    pub duration: Option<Duration>,
+    pub refresh: Option<Duration>,
+    /// Program choosing the next image when `path` is a directory
+    #[serde(default, deserialize_with = "tilde_expansion_deserialize")]
+    pub selector: Option<PathBuf>,
     #[serde(rename = "apply-shadow")]
     pub apply_shadow: Option<bool>,
     pub sorting: Option<Sorting>,
+    /// Pick the same sequence of images on each run with the `random` and `shuffle` sortings
+    pub seed: Option<u64>,
+    /// Start the `ascending` and `descending` sortings at a random image
+    pub random_start: Option<bool>,
+    /// Show the images matching some glob patterns more or less often with the `random` sorting
+    pub weights: Option<Weights>,
+    /// Prefer the images with the same orientation as the display, landscape or portrait
+    pub orientation_preference: Option<bool>,
+    /// Search the subdirectories of `path`, either `true`, `false` or the maximum depth
+    pub recursive: Option<Recursive>,
+    /// Keep the current image when `path` changes, if the new path contains it
+    pub preserve_image: Option<bool>,
     pub mode: Option<BackgroundMode>,
+    /// Draw each image with one of these modes, picked at random, in place of `mode`
+    pub modes: Option<Vec<BackgroundMode>>,
+    pub gravity: Option<Gravity>,
+    /// Logical pixels the image is moved to the right in `cover` and `contain` modes
+    pub offset_x: Option<i32>,
+    /// Logical pixels the image is moved to the bottom in `cover` and `contain` modes
+    pub offset_y: Option<i32>,
+    /// Scale the image of the `tile` mode so that only whole tiles are shown
+    pub tile_snap: Option<TileSnap>,
+    /// Mirror the image from left to right
+    pub flip_horizontal: Option<bool>,
+    /// Mirror the image from top to bottom
+    pub flip_vertical: Option<bool>,
+    /// Columns and rows of the `mosaic` mode, e.g. `[3, 2]`
+    pub mosaic_grid: Option<(u32, u32)>,
+    /// Pixels between the images of the `mosaic` mode
+    pub mosaic_gap: Option<u32>,
     pub queue_size: Option<usize>,
     pub transition_time: Option<u32>,
+    /// Draw the transitions at most this many times per second
+    pub transition_fps: Option<u32>,
 
     /// Determines if we should show the transition between black and first
     /// wallpaper. `Some(false)` means we instantly cut to the first wallpaper,
@@ -45,12 +123,707 @@ pub struct SerializedWallpaperInfo {
     ///
     /// See [crate::wallpaper_info::WallpaperInfo]
     pub initial_transition: Option<bool>,
+    /// Darken the edges of the wallpaper, from `0.0` (disabled) to `1.0`
+    pub vignette: Option<f32>,
+    /// Opacity of the whole wallpaper, from `0.0` (transparent) to `1.0`
+    pub opacity: Option<f32>,
+    /// Tint the wallpaper with this color temperature, in Kelvin
+    pub temperature: Option<u32>,
+    /// Draw animated film grain over the wallpaper, from `0.0` (disabled) to `1.0`
+    pub grain: Option<f32>,
+    /// Color around the images that don't cover the display, transparent by default
+    pub border_color: Option<Color>,
+    /// Fill the borders of the `contain` mode with a blurred copy of the edges of the image
+    pub blur_edges: Option<bool>,
+    /// Move the part of the image shown by the modes that crop it toward its most detailed
+    /// region, instead of following `gravity`
+    pub focal_crop: Option<bool>,
+    /// Draw a shadow around the image when it doesn't cover the display
+    pub drop_shadow: Option<bool>,
+    /// How far the shadow spreads from the image, in logical pixels
+    pub shadow_radius: Option<u32>,
+    /// Opacity of the shadow next to the image, from `0.0` to `1.0`
+    pub shadow_opacity: Option<f32>,
+    /// Dim the wallpaper after the session has been idle for this long
+    #[serde(default, with = "humantime_serde")]
+    pub idle_timeout: Option<Duration>,
+    /// Brightness of the wallpaper while the session is idle, from `0.0` to `1.0`
+    pub idle_brightness: Option<f32>,
+    /// Decode the next wallpaper before `duration` expires
+    pub prefetch: Option<bool>,
+    /// Wait up to this long for `path` to contain images before showing the first one
+    #[serde(default, with = "humantime_serde")]
+    pub startup_delay: Option<Duration>,
+    /// Name of the group of displays this one belongs to, see `wpaperctl next-group`
+    pub group: Option<String>,
+    /// Whether the displays of the group share the image or only the timer
+    pub group_mode: Option<GroupMode>,
+    /// Downscale the images bigger than this, `auto` uses the size of the display
+    pub max_texture_size: Option<MaxTextureSize>,
+    /// Skip the images bigger than this, e.g. `"20M"`
+    pub max_file_size: Option<FileSize>,
+    /// Draw at this many times the resolution of the display and downsample, from `1.0`
+    /// (disabled) to `2.0`
+    pub supersampling: Option<f32>,
+    /// Layer of the compositor where the wallpaper is drawn
+    pub layer: Option<WallpaperLayer>,
+    /// `-1` covers the whole display, `0` avoids the space reserved by other surfaces
+    pub exclusive_zone: Option<i32>,
+    /// Edges of the display the wallpaper is attached to, e.g. `["top", "left", "right"]`
+    pub anchor: Option<Vec<LayerAnchor>>,
+    /// Logical pixels between the wallpaper and the edges, as `[top, right, bottom, left]`
+    pub margin: Option<(i32, i32, i32, i32)>,
+    /// Logical width and height of the wallpaper, `0` stretches it between the opposite edges
+    pub size: Option<(u32, u32)>,
     #[serde(flatten)]
     pub transition: Option<Transition>,
 }
 
 impl SerializedWallpaperInfo {
+    /// Replace `path` with the path set for `color_scheme`, if any
+    fn with_color_scheme(&self, color_scheme: ColorScheme) -> Cow<'_, Self> {
+        let path = match color_scheme {
+            ColorScheme::Light => &self.light_path,
+            ColorScheme::Dark => &self.dark_path,
+        };
+        match path {
+            Some(path) => Cow::Owned(Self {
+                path: Some(path.clone()),
+                ..self.clone()
+            }),
+            None => Cow::Borrowed(self),
+        }
+    }
+
+    fn follows_color_scheme(&self) -> bool {
+        self.light_path.is_some() || self.dark_path.is_some()
+    }
+
+    /// Replace `path` with the path set for `workspace` in `workspace_paths`, if any
+    fn with_workspace(&self, workspace: Option<&str>) -> Cow<'_, Self> {
+        let path = workspace.and_then(|workspace| self.workspace_paths.as_ref()?.get(workspace));
+        match path {
+            Some(path) => Cow::Owned(Self {
+                path: Some(path.clone()),
+                ..self.clone()
+            }),
+            None => Cow::Borrowed(self),
+        }
+    }
+
+    fn workspace_names(&self) -> impl Iterator<Item = &str> {
+        self.workspace_paths
+            .iter()
+            .flat_map(|paths| paths.keys().map(String::as_str))
+    }
+
+    /// Layer `self` over `default` for all the keys that do not depend on `path`.
+    /// Keys set in `self` always win, the others are inherited from `default` and, when
+    /// neither sets them, the builtin defaults are used.
+    pub fn apply(&self, default: &Self) -> WallpaperInfo {
+        let refresh = match (&self.refresh, &default.refresh) {
+            (Some(refresh), _) | (None, Some(refresh)) => Some(*refresh),
+            (None, None) => None,
+        };
+        let selector = match (&self.selector, &default.selector) {
+            (Some(selector), _) | (None, Some(selector)) => Some(selector.clone()),
+            (None, None) => None,
+        };
+        let apply_shadow = match (&self.apply_shadow, &default.apply_shadow) {
+            (Some(apply_shadow), _) | (None, Some(apply_shadow)) => *apply_shadow,
+            (None, None) => false,
+        };
+        let sorting = match (&self.sorting, &default.sorting) {
+            (Some(sorting), _) | (None, Some(sorting)) => *sorting,
+            (None, None) => Sorting::default(),
+        };
+        let seed = match (&self.seed, &default.seed) {
+            (Some(seed), _) | (None, Some(seed)) => Some(*seed),
+            (None, None) => None,
+        };
+        let align = match (&self.align, &default.align) {
+            (Some(align), _) | (None, Some(align)) => *align,
+            (None, None) => false,
+        };
+        let random_start = match (&self.random_start, &default.random_start) {
+            (Some(random_start), _) | (None, Some(random_start)) => *random_start,
+            (None, None) => false,
+        };
+        let weights = match (&self.weights, &default.weights) {
+            (Some(weights), _) | (None, Some(weights)) => weights.clone(),
+            (None, None) => Weights::default(),
+        };
+        let orientation_preference = match (
+            &self.orientation_preference,
+            &default.orientation_preference,
+        ) {
+            (Some(orientation_preference), _) | (None, Some(orientation_preference)) => {
+                *orientation_preference
+            }
+            (None, None) => false,
+        };
+        let preserve_image = match (&self.preserve_image, &default.preserve_image) {
+            (Some(preserve_image), _) | (None, Some(preserve_image)) => *preserve_image,
+            (None, None) => false,
+        };
+        let recursive = match (&self.recursive, &default.recursive) {
+            (Some(recursive), _) | (None, Some(recursive)) => *recursive,
+            (None, None) => Recursive::default(),
+        };
+        let mode = match (&self.mode, &default.mode) {
+            (Some(mode), _) | (None, Some(mode)) => *mode,
+            (None, None) => BackgroundMode::default(),
+        };
+        // A mode set in this section wins over the modes inherited from default
+        let modes = match (&self.modes, &self.mode, &default.modes) {
+            (Some(modes), _, _) | (None, None, Some(modes)) => modes.clone(),
+            (None, Some(_), _) | (None, None, None) => Vec::new(),
+        };
+        let mode = modes.first().copied().unwrap_or(mode);
+        let gravity = match (&self.gravity, &default.gravity) {
+            (Some(gravity), _) | (None, Some(gravity)) => *gravity,
+            (None, None) => Gravity::default(),
+        };
+        let offset_x = match (&self.offset_x, &default.offset_x) {
+            (Some(offset_x), _) | (None, Some(offset_x)) => *offset_x,
+            (None, None) => 0,
+        };
+        let offset_y = match (&self.offset_y, &default.offset_y) {
+            (Some(offset_y), _) | (None, Some(offset_y)) => *offset_y,
+            (None, None) => 0,
+        };
+        let flip_horizontal = match (&self.flip_horizontal, &default.flip_horizontal) {
+            (Some(flip_horizontal), _) | (None, Some(flip_horizontal)) => *flip_horizontal,
+            (None, None) => false,
+        };
+        let flip_vertical = match (&self.flip_vertical, &default.flip_vertical) {
+            (Some(flip_vertical), _) | (None, Some(flip_vertical)) => *flip_vertical,
+            (None, None) => false,
+        };
+        let tile_snap = match (&self.tile_snap, &default.tile_snap) {
+            (Some(tile_snap), _) | (None, Some(tile_snap)) => *tile_snap,
+            (None, None) => TileSnap::default(),
+        };
+        let mosaic_grid = match (&self.mosaic_grid, &default.mosaic_grid) {
+            (Some(mosaic_grid), _) | (None, Some(mosaic_grid)) => *mosaic_grid,
+            (None, None) => (2, 2),
+        };
+        let mosaic_gap = match (&self.mosaic_gap, &default.mosaic_gap) {
+            (Some(mosaic_gap), _) | (None, Some(mosaic_gap)) => *mosaic_gap,
+            (None, None) => 0,
+        };
+        let drawn_images_queue_size = match (&self.queue_size, &default.queue_size) {
+            (Some(size), _) | (None, Some(size)) => *size,
+            (None, None) => ImagePicker::DEFAULT_DRAWN_IMAGES_QUEUE_SIZE,
+        };
+        let initial_transition = match (&self.initial_transition, &default.initial_transition) {
+            (Some(initial_transition), _) | (None, Some(initial_transition)) => *initial_transition,
+            (None, None) => true,
+        };
+
+        let vignette = match (&self.vignette, &default.vignette) {
+            (Some(vignette), _) | (None, Some(vignette)) => *vignette,
+            (None, None) => 0.0,
+        };
+        let opacity = match (&self.opacity, &default.opacity) {
+            (Some(opacity), _) | (None, Some(opacity)) => *opacity,
+            (None, None) => 1.0,
+        };
+        let temperature = match (&self.temperature, &default.temperature) {
+            (Some(temperature), _) | (None, Some(temperature)) => *temperature,
+            (None, None) => 6500,
+        };
+        let grain = match (&self.grain, &default.grain) {
+            (Some(grain), _) | (None, Some(grain)) => *grain,
+            (None, None) => 0.0,
+        };
+        let supersampling = match (&self.supersampling, &default.supersampling) {
+            (Some(supersampling), _) | (None, Some(supersampling)) => *supersampling,
+            (None, None) => 1.0,
+        };
+        let border_color = match (&self.border_color, &default.border_color) {
+            (Some(border_color), _) | (None, Some(border_color)) => Some(*border_color),
+            (None, None) => None,
+        };
+        let blur_edges = match (&self.blur_edges, &default.blur_edges) {
+            (Some(blur_edges), _) | (None, Some(blur_edges)) => *blur_edges,
+            (None, None) => false,
+        };
+        let focal_crop = match (&self.focal_crop, &default.focal_crop) {
+            (Some(focal_crop), _) | (None, Some(focal_crop)) => *focal_crop,
+            (None, None) => false,
+        };
+        let drop_shadow = match (&self.drop_shadow, &default.drop_shadow) {
+            (Some(drop_shadow), _) | (None, Some(drop_shadow)) => *drop_shadow,
+            (None, None) => false,
+        };
+        let shadow_radius = match (&self.shadow_radius, &default.shadow_radius) {
+            (Some(shadow_radius), _) | (None, Some(shadow_radius)) => *shadow_radius,
+            (None, None) => 32,
+        };
+        let shadow_opacity = match (&self.shadow_opacity, &default.shadow_opacity) {
+            (Some(shadow_opacity), _) | (None, Some(shadow_opacity)) => *shadow_opacity,
+            (None, None) => 0.5,
+        };
+
+        let idle_timeout = match (&self.idle_timeout, &default.idle_timeout) {
+            (Some(idle_timeout), _) | (None, Some(idle_timeout)) => Some(*idle_timeout),
+            (None, None) => None,
+        };
+        let startup_delay = match (&self.startup_delay, &default.startup_delay) {
+            (Some(startup_delay), _) | (None, Some(startup_delay)) => Some(*startup_delay),
+            (None, None) => None,
+        };
+        let idle_brightness = match (&self.idle_brightness, &default.idle_brightness) {
+            (Some(idle_brightness), _) | (None, Some(idle_brightness)) => *idle_brightness,
+            (None, None) => 0.5,
+        };
+
+        let prefetch = match (&self.prefetch, &default.prefetch) {
+            (Some(prefetch), _) | (None, Some(prefetch)) => *prefetch,
+            (None, None) => true,
+        };
+
+        let max_texture_size = match (&self.max_texture_size, &default.max_texture_size) {
+            (Some(max_texture_size), _) | (None, Some(max_texture_size)) => *max_texture_size,
+            (None, None) => MaxTextureSize::default(),
+        };
+        let max_file_size = match (&self.max_file_size, &default.max_file_size) {
+            (Some(max_file_size), _) | (None, Some(max_file_size)) => Some(max_file_size.0),
+            (None, None) => None,
+        };
+
+        let group_mode = match (&self.group_mode, &default.group_mode) {
+            (Some(group_mode), _) | (None, Some(group_mode)) => *group_mode,
+            (None, None) => GroupMode::default(),
+        };
+        let group = match (&self.group, &default.group) {
+            (Some(group), _) | (None, Some(group)) => Some(group.clone()),
+            (None, None) => None,
+        };
+
+        let layer = match (&self.layer, &default.layer) {
+            (Some(layer), _) | (None, Some(layer)) => *layer,
+            (None, None) => WallpaperLayer::default(),
+        };
+
+        let exclusive_zone = match (&self.exclusive_zone, &default.exclusive_zone) {
+            (Some(exclusive_zone), _) | (None, Some(exclusive_zone)) => *exclusive_zone,
+            (None, None) => -1,
+        };
+        let anchor = match (&self.anchor, &default.anchor) {
+            (Some(anchor), _) | (None, Some(anchor)) => anchor.clone(),
+            (None, None) => LayerAnchor::ALL.to_vec(),
+        };
+        let margin = match (&self.margin, &default.margin) {
+            (Some(margin), _) | (None, Some(margin)) => *margin,
+            (None, None) => (0, 0, 0, 0),
+        };
+        let size = match (&self.size, &default.size) {
+            (Some(size), _) | (None, Some(size)) => *size,
+            (None, None) => (0, 0),
+        };
+
+        let transition = match (&self.transition, &default.transition) {
+            (Some(transition), _) | (None, Some(transition)) => transition.clone(),
+            (None, None) => Transition::Fade {},
+        };
+
+        let transition_time = match (&self.transition_time, &default.transition_time) {
+            (Some(transition_time), _) | (None, Some(transition_time)) => *transition_time,
+            (None, None) => transition.default_transition_time(),
+        };
+        let transition_fps = match (&self.transition_fps, &default.transition_fps) {
+            (Some(transition_fps), _) | (None, Some(transition_fps)) => Some(*transition_fps),
+            (None, None) => None,
+        };
+
+        WallpaperInfo {
+            path: PathBuf::new(),
+            color: None,
+            gradient: None,
+            duration: None,
+            refresh,
+            selector,
+            apply_shadow,
+            sorting,
+            seed,
+            align,
+            random_start,
+            weights,
+            orientation_preference,
+            recursive,
+            preserve_image,
+            mode,
+            modes,
+            gravity,
+            offset: (offset_x, offset_y),
+            tile_snap,
+            flip_horizontal,
+            flip_vertical,
+            mosaic_grid,
+            mosaic_gap,
+            drawn_images_queue_size,
+            transition_time,
+            transition_fps,
+            initial_transition,
+            vignette,
+            opacity,
+            temperature,
+            grain,
+            border_color,
+            blur_edges,
+            focal_crop,
+            drop_shadow,
+            shadow_radius,
+            shadow_opacity,
+            idle_timeout,
+            idle_brightness,
+            prefetch,
+            startup_delay,
+            max_texture_size,
+            max_file_size,
+            supersampling,
+            group,
+            group_mode,
+            layer,
+            exclusive_zone,
+            anchor,
+            margin,
+            size,
+            transition,
+            // Not part of the sections, see `Config::oneshot`
+            oneshot: false,
+        }
+    }
+
     pub fn apply_and_validate(&self, default: &Self) -> Result<WallpaperInfo> {
+        // The keys set in this section win over the inherited ones
+        let color = match (&self.color, &self.path, &self.gradient, &default.color) {
+            (Some(color), _, _, _) | (None, None, None, Some(color)) => Some(*color),
+            (None, Some(_), _, _) | (None, None, Some(_), _) | (None, None, None, None) => None,
+        };
+        let gradient = match (&self.gradient, &self.path, &self.color, &default.gradient) {
+            (Some(gradient), _, _, _) | (None, None, None, Some(gradient)) => Some(*gradient),
+            (None, Some(_), _, _) | (None, None, Some(_), _) | (None, None, None, None) => None,
+        };
+        if color.is_some() && gradient.is_some() {
+            return Err(anyhow!(
+                "attribute {} is set together with attribute {}",
+                "gradient".bold().italic().blue(),
+                "color".bold().italic().blue(),
+            ));
+        }
+        let (path, duration) = match color.map(|_| "color").or(gradient.map(|_| "gradient")) {
+            Some(generated) => {
+                if self.path.is_some() || self.duration.is_some() {
+                    return Err(anyhow!(
+                        "attribute {} is set together with attribute {} or {}",
+                        generated.bold().italic().blue(),
+                        "path".bold().italic().blue(),
+                        "duration".bold().italic().blue(),
+                    )
+                    .with_suggestion(|| {
+                        format!(
+                            "remove attribute {} to show the images in {}",
+                            generated.bold().italic().blue(),
+                            "path".bold().italic().blue(),
+                        )
+                    }));
+                }
+                (PathBuf::new(), None)
+            }
+            None => self.path_and_duration(default)?,
+        };
+
+        let info = WallpaperInfo {
+            path,
+            color,
+            gradient,
+            duration,
+            ..self.apply(default)
+        };
+        if self
+            .modes
+            .as_ref()
+            .or(default.modes.as_ref())
+            .is_some_and(Vec::is_empty)
+        {
+            return Err(anyhow!(
+                "attribute {} must contain at least one mode",
+                "modes".bold().italic().blue(),
+            ));
+        }
+        if let Some(mode) = info
+            .modes
+            .iter()
+            .find(|mode| matches!(mode, BackgroundMode::Mosaic | BackgroundMode::ContainBlur))
+        {
+            return Err(anyhow!(
+                "attribute {} contains {}, which cannot change from one image to the next",
+                "modes".bold().italic().blue(),
+                mode.name().italic().yellow(),
+            )
+            .with_suggestion(|| {
+                format!(
+                    "set attribute {} to {} instead",
+                    "mode".bold().italic().blue(),
+                    mode.name().italic().yellow(),
+                )
+            }));
+        }
+        self.check_mode_conflicts(default, &info)?;
+        if let Some(selector) = &info.selector {
+            if !cfg!(feature = "script") {
+                return Err(anyhow!(
+                    "attribute {} is set, but wpaperd has been built without the {} feature",
+                    "selector".bold().italic().blue(),
+                    "script".bold(),
+                ))
+                .with_suggestion(|| {
+                    "build wpaperd with `cargo build --features script`".to_string()
+                });
+            }
+            if !selector.is_file() {
+                return Err(anyhow!(
+                    "selector {} for attribute {} does not exist",
+                    selector.to_string_lossy().italic().yellow(),
+                    "selector".bold().italic().blue(),
+                ));
+            }
+        }
+        if matches!(info.transition, Transition::Mask { .. }) {
+            match info.transition.mask() {
+                Some(mask) if mask.is_file() => {}
+                Some(mask) => {
+                    return Err(anyhow!(
+                        "mask {} for attribute {} does not exist",
+                        mask.to_string_lossy().italic().yellow(),
+                        "mask".bold().italic().blue(),
+                    ));
+                }
+                None => {
+                    return Err(anyhow!(
+                        "attribute {} is required by the mask transition",
+                        "mask".bold().italic().blue(),
+                    ))
+                    .with_suggestion(|| {
+                        "set it to a grayscale image, its darker parts show the new wallpaper first"
+                            .to_string()
+                    });
+                }
+            }
+        }
+        if info.align
+            && info
+                .duration
+                .as_ref()
+                .is_some_and(|duration| duration.min != duration.max)
+        {
+            return Err(anyhow!(
+                "attribute {} requires attribute {} to be a single duration, not a range",
+                "align".bold().italic().blue(),
+                "duration".bold().italic().blue(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&info.vignette) {
+            return Err(anyhow!(
+                "attribute {} must be between 0.0 and 1.0",
+                "vignette".bold().italic().blue(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&info.opacity) {
+            return Err(anyhow!(
+                "attribute {} must be between 0.0 and 1.0",
+                "opacity".bold().italic().blue(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&info.shadow_opacity) {
+            return Err(anyhow!(
+                "attribute {} must be between 0.0 and 1.0",
+                "shadow_opacity".bold().italic().blue(),
+            ));
+        }
+        if !(1000..=25000).contains(&info.temperature) {
+            return Err(anyhow!(
+                "attribute {} must be between 1000 and 25000",
+                "temperature".bold().italic().blue(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&info.grain) {
+            return Err(anyhow!(
+                "attribute {} must be between 0.0 and 1.0",
+                "grain".bold().italic().blue(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&info.idle_brightness) {
+            return Err(anyhow!(
+                "attribute {} must be between 0.0 and 1.0",
+                "idle_brightness".bold().italic().blue(),
+            ));
+        }
+        if info.mosaic_grid.0 == 0 || info.mosaic_grid.1 == 0 {
+            return Err(anyhow!(
+                "attribute {} must have at least one column and one row",
+                "mosaic_grid".bold().italic().blue(),
+            ));
+        }
+        if !(-1..=0).contains(&info.exclusive_zone) {
+            return Err(anyhow!(
+                "attribute {} must be either -1 or 0",
+                "exclusive_zone".bold().italic().blue(),
+            ));
+        }
+        // The compositor can only stretch the wallpaper between two opposite edges
+        let anchored =
+            |first, second| info.anchor.contains(&first) && info.anchor.contains(&second);
+        for (side, dimension, (first, second)) in [
+            (
+                "width",
+                info.size.0,
+                (LayerAnchor::Left, LayerAnchor::Right),
+            ),
+            (
+                "height",
+                info.size.1,
+                (LayerAnchor::Top, LayerAnchor::Bottom),
+            ),
+        ] {
+            if dimension == 0 && !anchored(first, second) {
+                return Err(anyhow!(
+                    "attribute {} doesn't set the {side}, but attribute {} doesn't contain both {} and {}",
+                    "size".bold().italic().blue(),
+                    "anchor".bold().italic().blue(),
+                    first.name().italic().yellow(),
+                    second.name().italic().yellow(),
+                )
+                .with_suggestion(|| {
+                    format!(
+                        "set the {side} in attribute {} or add the missing edge to attribute {}",
+                        "size".bold().italic().blue(),
+                        "anchor".bold().italic().blue(),
+                    )
+                }));
+            }
+        }
+        if info.max_texture_size == MaxTextureSize::Pixels(0) {
+            return Err(anyhow!(
+                "attribute {} must be greater than 0",
+                "max_texture_size".bold().italic().blue(),
+            )
+            .with_suggestion(|| {
+                format!(
+                    "set attribute {} to \"none\" to load the images at their full size",
+                    "max_texture_size".bold().italic().blue(),
+                )
+            }));
+        }
+
+        if info.transition_fps == Some(0) {
+            return Err(anyhow!(
+                "attribute {} must be greater than 0",
+                "transition_fps".bold().italic().blue(),
+            ));
+        }
+
+        if !(1.0..=2.0).contains(&info.supersampling) {
+            return Err(anyhow!(
+                "attribute {} must be between 1.0 and 2.0",
+                "supersampling".bold().italic().blue(),
+            ));
+        }
+
+        if info.max_file_size == Some(0) {
+            return Err(anyhow!(
+                "attribute {} must be greater than 0",
+                "max_file_size".bold().italic().blue(),
+            ));
+        }
+
+        Ok(info)
+    }
+
+    /// Check that the keys used only by some modes are not set together with a different `mode`,
+    /// or with `modes` when none of them uses the key. The keys inherited from `default` are
+    /// ignored when this section sets the mode
+    fn check_mode_conflicts(&self, default: &Self, info: &WallpaperInfo) -> Result<()> {
+        let (mode_key, modes) = if info.modes.is_empty() {
+            ("mode", slice::from_ref(&info.mode))
+        } else {
+            ("modes", info.modes.as_slice())
+        };
+        let uses = |used: fn(BackgroundMode) -> bool| modes.iter().copied().any(used);
+        let is_set = |in_section: bool, in_default: bool| {
+            in_section || (in_default && self.mode.is_none() && self.modes.is_none())
+        };
+        let conflicts = [
+            (
+                "gravity",
+                is_set(self.gravity.is_some(), default.gravity.is_some())
+                    && !uses(BackgroundMode::places_image),
+            ),
+            (
+                "offset_x",
+                is_set(self.offset_x.is_some(), default.offset_x.is_some())
+                    && !uses(BackgroundMode::places_image),
+            ),
+            (
+                "offset_y",
+                is_set(self.offset_y.is_some(), default.offset_y.is_some())
+                    && !uses(BackgroundMode::places_image),
+            ),
+            (
+                "tile_snap",
+                is_set(self.tile_snap.is_some(), default.tile_snap.is_some())
+                    && !uses(|mode| mode == BackgroundMode::Tile),
+            ),
+            (
+                "blur_edges",
+                is_set(self.blur_edges.is_some(), default.blur_edges.is_some())
+                    && !uses(|mode| mode == BackgroundMode::Contain),
+            ),
+            (
+                "focal_crop",
+                is_set(self.focal_crop.is_some(), default.focal_crop.is_some())
+                    && !uses(BackgroundMode::crops_image),
+            ),
+            (
+                "mosaic_grid",
+                is_set(self.mosaic_grid.is_some(), default.mosaic_grid.is_some())
+                    && !uses(|mode| mode == BackgroundMode::Mosaic),
+            ),
+            (
+                "mosaic_gap",
+                is_set(self.mosaic_gap.is_some(), default.mosaic_gap.is_some())
+                    && !uses(|mode| mode == BackgroundMode::Mosaic),
+            ),
+        ];
+        match conflicts.into_iter().find(|(_, conflict)| *conflict) {
+            Some((key, _)) => Err(anyhow!(
+                "attribute {} is set, but attribute {} is set to {}, which does not use it",
+                key.bold().italic().blue(),
+                mode_key.bold().italic().blue(),
+                modes
+                    .iter()
+                    .map(|mode| mode.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+                    .italic()
+                    .yellow(),
+            )
+            .with_suggestion(|| {
+                format!(
+                    "remove attribute {} or change attribute {}",
+                    key.bold().italic().blue(),
+                    mode_key.bold().italic().blue(),
+                )
+            })),
+            None => Ok(()),
+        }
+    }
+
+    /// Resolve `path` and `duration`, checking that they can be used together
+    fn path_and_duration(&self, default: &Self) -> Result<(PathBuf, Option<DurationRange>)> {
         let mut path_inherited = false;
         let path = match (&self.path, &default.path) {
             (Some(path), None) | (Some(path), Some(_))=> path,
@@ -72,8 +845,17 @@ impl SerializedWallpaperInfo {
             }
         }
         .to_path_buf();
+        if is_url(&path) {
+            if !cfg!(feature = "http") {
+                return Err(anyhow!(
+                    "attribute {} is set to an URL, but wpaperd has been built without the {} feature",
+                    "path".bold().italic().blue(),
+                    "http".bold(),
+                ))
+                .with_suggestion(|| "build wpaperd with `cargo build --features http`".to_string());
+            }
         // Ensure that a path exists
-        if !path.exists() {
+        } else if !path.exists() {
             return Err(anyhow!(
                 "path {} for attribute {}{} does not exist",
                 path.to_string_lossy().italic().yellow(),
@@ -98,7 +880,7 @@ impl SerializedWallpaperInfo {
         let duration = match (&self.duration, &default.duration) {
             // duration is inherited from default, but this section set path to a file, ignore
             // duration
-            (None, Some(_)) if path.is_file() && !path_inherited => None,
+            (None, Some(_)) if (path.is_file() || is_url(&path)) && !path_inherited => None,
             (Some(duration), _) | (None, Some(duration)) => Some(*duration),
             (None, None) => None,
         };
@@ -119,44 +901,22 @@ impl SerializedWallpaperInfo {
             }));
         }
 
-        let sorting = match (&self.sorting, &default.sorting) {
-            (Some(sorting), _) | (None, Some(sorting)) => *sorting,
-            (None, None) => Sorting::default(),
-        };
-        let mode = match (&self.mode, &default.mode) {
-            (Some(mode), _) | (None, Some(mode)) => *mode,
-            (None, None) => BackgroundMode::default(),
-        };
-        let drawn_images_queue_size = match (&self.queue_size, &default.queue_size) {
-            (Some(size), _) | (None, Some(size)) => *size,
-            (None, None) => ImagePicker::DEFAULT_DRAWN_IMAGES_QUEUE_SIZE,
-        };
-        let initial_transition = match (&self.initial_transition, &default.initial_transition) {
-            (Some(initial_transition), _) | (None, Some(initial_transition)) => *initial_transition,
-            (None, None) => true,
-        };
-
-        let transition = match (&self.transition, &default.transition) {
-            (Some(transition), _) | (None, Some(transition)) => transition.clone(),
-            (None, None) => Transition::Fade {},
-        };
-
-        let transition_time = match (&self.transition_time, &default.transition_time) {
-            (Some(transition_time), _) | (None, Some(transition_time)) => *transition_time,
-            (None, None) => transition.default_transition_time(),
-        };
+        if let Some(duration) = duration {
+            if duration.min > duration.max {
+                return Err(anyhow!(
+                    "the minimum of attribute {} is greater than its maximum",
+                    "duration".bold().italic().blue(),
+                )
+                .with_suggestion(|| {
+                    format!(
+                        "set attribute {} to a range in the form of \"<min>..<max>\", e.g. \"5m..15m\"",
+                        "duration".bold().italic().blue(),
+                    )
+                }));
+            }
+        }
 
-        Ok(WallpaperInfo {
-            path,
-            duration,
-            apply_shadow: false,
-            sorting,
-            mode,
-            drawn_images_queue_size,
-            transition_time,
-            initial_transition,
-            transition,
-        })
+        Ok((path, duration))
     }
 }
 
@@ -172,12 +932,45 @@ pub struct Config {
     pub path: PathBuf,
     #[serde(skip)]
     pub reloaded: Option<Arc<AtomicBool>>,
+    /// Selects between `light_path` and `dark_path`
+    #[serde(skip)]
+    pub color_scheme: ColorScheme,
+    /// Selects the path in `workspace_paths` of each display
+    #[serde(skip)]
+    pub workspaces: ActiveWorkspaces,
+    /// The paths that did not exist when the configuration has been read, e.g. network
+    /// mounts that appear late. See [`Config::retry_missing_paths`]
+    #[serde(skip)]
+    missing_paths: Vec<PathBuf>,
+    /// Set by `wpaperd --oneshot`, see [`WallpaperInfo::oneshot`]
+    #[serde(skip)]
+    pub oneshot: bool,
+    /// Set at runtime by `wpaperctl initial-transition`, by the name of the display. It wins
+    /// over the `initial_transition` of the sections, also after reloading them
+    #[serde(skip)]
+    pub initial_transitions: HashMap<String, bool>,
 }
 
 impl Config {
+    /// Read `path`, then the drop-in files in [`DROP_IN_DIR`] next to it, see [`merge_tables`]
     pub fn new_from_path(path: &Path) -> Result<Self> {
         ensure!(path.exists(), "File {path:?} does not exists");
-        let mut config: Self = toml::from_str(&fs::read_to_string(path)?)?;
+        let mut table = read_table(path)?;
+        for drop_in in drop_ins(path)? {
+            merge_tables(&mut table, read_table(&drop_in)?);
+        }
+        let mut config = Self::new_from_table(table)?;
+        config.path = path.to_path_buf();
+        Ok(config)
+    }
+
+    #[cfg(test)]
+    fn new_from_str(content: &str) -> Result<Self> {
+        Self::new_from_table(toml::from_str(content)?)
+    }
+
+    fn new_from_table(table: toml::Table) -> Result<Self> {
+        let mut config: Self = table.try_into()?;
         config.default = config
             .data
             .get("default")
@@ -188,14 +981,55 @@ impl Config {
             .get("any")
             .unwrap_or(&SerializedWallpaperInfo::default())
             .to_owned();
+        config.missing_paths = config
+            .data
+            .values()
+            .flat_map(|info| {
+                [&info.path, &info.light_path, &info.dark_path]
+                    .into_iter()
+                    .flatten()
+                    .chain(info.workspace_paths.iter().flat_map(HashMap::values))
+            })
+            .filter(|path| !is_url(path) && !path.exists())
+            .cloned()
+            .collect();
+        config.missing_paths.sort_unstable();
+        config.missing_paths.dedup();
         config.data.retain(|name, info| {
             // The default configuration does not follow these rules
             // We still need the default configuration here because the path needs to be cached
-            if info == &config.default {
+            if name == "default" {
+                true
+            } else if info.ignore.or(config.default.ignore) == Some(true) {
+                // Nothing is drawn, there is nothing to validate
                 true
             } else {
-                match info
-                    .apply_and_validate(&config.default)
+                // Both the paths of the light and the dark color schemes need to be valid, and
+                // so do the paths of the workspaces
+                let workspaces: Vec<_> = [None]
+                    .into_iter()
+                    .chain(
+                        info.workspace_names()
+                            .chain(config.default.workspace_names())
+                            .map(Some),
+                    )
+                    .collect();
+                let validated = [ColorScheme::Light, ColorScheme::Dark]
+                    .into_iter()
+                    .try_for_each(|color_scheme| {
+                        workspaces.iter().try_for_each(|workspace| {
+                            info.with_color_scheme(color_scheme)
+                                .with_workspace(*workspace)
+                                .apply_and_validate(
+                                    &config
+                                        .default
+                                        .with_color_scheme(color_scheme)
+                                        .with_workspace(*workspace),
+                                )
+                                .map(|_| ())
+                        })
+                    });
+                match validated
                     .with_context(|| format!("while validating display {}", name.bold().magenta()))
                 {
                     Ok(_) => true,
@@ -208,41 +1042,218 @@ impl Config {
             }
         });
 
-        config.path = path.to_path_buf();
         Ok(config)
     }
 
-    pub fn get_output_by_name(&self, name: &str) -> Result<WallpaperInfo> {
-        self.data
-            .get(name)
+    /// Return the section that applies to `display`: the first section matching one of its
+    /// names (see [`DisplayInfo::section_names`]), otherwise the `any` section. In both cases it
+    /// will be layered over `default`.
+    fn section_for_output(&self, display: &DisplayInfo) -> &SerializedWallpaperInfo {
+        display
+            .section_names()
+            .iter()
+            .find_map(|name| self.data.get(name))
             .unwrap_or(&self.any)
-            .apply_and_validate(&self.default)
     }
 
+    /// True if the section of `display` sets `ignore`, no surface is created for it
+    pub fn is_ignored(&self, display: &DisplayInfo) -> bool {
+        self.section_for_output(display)
+            .ignore
+            .or(self.default.ignore)
+            .unwrap_or(false)
+    }
+
+    /// Name of the section returned by [`Config::section_for_output`], shown by `wpaperctl config`
+    pub fn section_name_for_output(&self, display: &DisplayInfo) -> String {
+        display
+            .section_names()
+            .into_iter()
+            .find(|name| self.data.contains_key(name))
+            .unwrap_or_else(|| "any".to_string())
+    }
+
+    /// The path of the workspace shown on `display` wins over the path of the current color
+    /// scheme, which wins over `path`, both in the section of `display` and in `default`
+    pub fn get_info_for_output(&self, display: &DisplayInfo) -> Result<WallpaperInfo> {
+        let workspace = self.workspaces.get(&display.name).map(String::as_str);
+        self.section_for_output(display)
+            .with_color_scheme(self.color_scheme)
+            .with_workspace(workspace)
+            .apply_and_validate(
+                &self
+                    .default
+                    .with_color_scheme(self.color_scheme)
+                    .with_workspace(workspace),
+            )
+            .map(|info| self.with_overrides(display, info))
+    }
+
+    /// Used when the configuration for `display` is not valid: it keeps all the settings
+    /// inherited from `default` and `any` but without any wallpaper to show.
+    pub fn get_fallback_for_output(&self, display: &DisplayInfo) -> WallpaperInfo {
+        self.with_overrides(
+            display,
+            self.section_for_output(display).apply(&self.default),
+        )
+    }
+
+    /// Apply the settings that are not part of the sections, see [`Config::oneshot`] and
+    /// [`Config::initial_transitions`]
+    fn with_overrides(&self, display: &DisplayInfo, info: WallpaperInfo) -> WallpaperInfo {
+        WallpaperInfo {
+            oneshot: self.oneshot,
+            initial_transition: self
+                .initial_transitions
+                .get(&display.name)
+                .copied()
+                .unwrap_or(info.initial_transition),
+            ..info
+        }
+    }
+
+    /// Reload when the file is modified, or when a drop-in file is added, removed or modified.
+    /// The directory containing the file is watched, so that the drop-in directory is noticed
+    /// even when it is created after wpaperd has started
     pub fn listen_to_changes(&self, hotwatch: &mut Hotwatch, ping: Ping) -> Result<()> {
         let reloaded = self.reloaded.as_ref().unwrap().clone();
+        let file_ping = ping.clone();
         hotwatch
             .watch(&self.path, move |event: Event| {
                 if let hotwatch::EventKind::Modify(_) = event.kind {
                     reloaded.store(true, Ordering::Relaxed);
-                    ping.ping();
+                    file_ping.ping();
                 }
             })
             .with_context(|| format!("watching file {:?}", &self.path))?;
+
+        let drop_in_dir = drop_in_dir(&self.path);
+        let config_dir = drop_in_dir
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .to_path_buf();
+        let reloaded = self.reloaded.as_ref().unwrap().clone();
+        hotwatch
+            .watch(&config_dir, move |event: Event| match event.kind {
+                hotwatch::EventKind::Create(_)
+                | hotwatch::EventKind::Remove(_)
+                | hotwatch::EventKind::Modify(_)
+                    if event
+                        .paths
+                        .iter()
+                        .any(|path| affects_drop_ins(&drop_in_dir, path)) =>
+                {
+                    reloaded.store(true, Ordering::Relaxed);
+                    ping.ping();
+                }
+                _ => {}
+            })
+            .with_context(|| format!("watching directory {config_dir:?}"))?;
+        Ok(())
+    }
+
+    /// Check the missing paths with an increasing interval, up to [`MAX_RETRY_INTERVAL`], and
+    /// reload the configuration once any of them is available
+    pub fn retry_missing_paths(&self, event_loop_handle: &LoopHandle<Wpaperd>) -> Result<()> {
+        let mut interval = MIN_RETRY_INTERVAL;
+        event_loop_handle
+            .insert_source(
+                Timer::from_duration(MIN_RETRY_INTERVAL),
+                move |_, _, wpaperd| {
+                    let config = &wpaperd.config;
+                    if config.missing_paths.is_empty() {
+                        // Check again after the configuration has been reloaded
+                        interval = MIN_RETRY_INTERVAL;
+                        return TimeoutAction::ToDuration(MAX_RETRY_INTERVAL);
+                    }
+                    let recovered: Vec<_> = config
+                        .missing_paths
+                        .iter()
+                        .filter(|path| path.exists())
+                        .collect();
+                    if recovered.is_empty() {
+                        interval = (interval * 2).min(MAX_RETRY_INTERVAL);
+                    } else {
+                        for path in recovered {
+                            info!("path {path:?} is available again");
+                        }
+                        // The main loop updates the configuration and the surfaces
+                        config
+                            .reloaded
+                            .as_ref()
+                            .unwrap()
+                            .store(true, Ordering::Release);
+                        interval = MIN_RETRY_INTERVAL;
+                    }
+                    TimeoutAction::ToDuration(interval)
+                },
+            )
+            .map_err(|e| anyhow!("inserting the missing paths timer in the event loop: {e}"))?;
         Ok(())
     }
 
-    pub fn paths(&self) -> Vec<PathBuf> {
+    /// The directories that can be shown, each with the depth it is searched to. A section
+    /// inherits both the paths and `recursive` from `default`, so every combination is listed
+    pub fn paths(&self) -> Vec<(PathBuf, usize)> {
         let mut paths: Vec<_> = self
             .data
             .values()
-            .filter_map(|info| info.path.as_ref().map(|p| p.to_path_buf()))
+            .flat_map(|info| {
+                let max_depth = info
+                    .recursive
+                    .or(self.default.recursive)
+                    .unwrap_or_default()
+                    .max_depth();
+                [
+                    (&info.path, &self.default.path),
+                    (&info.light_path, &self.default.light_path),
+                    (&info.dark_path, &self.default.dark_path),
+                ]
+                .into_iter()
+                .filter_map(|(path, default_path)| path.as_ref().or(default_path.as_ref()))
+                .chain(
+                    [&info.workspace_paths, &self.default.workspace_paths]
+                        .into_iter()
+                        .flatten()
+                        .flat_map(HashMap::values),
+                )
+                .map(move |path| (path.to_path_buf(), max_depth))
+            })
             .collect();
         paths.sort_unstable();
         paths.dedup();
         paths
     }
 
+    /// True if any section sets `light_path` or `dark_path`
+    pub fn follows_color_scheme(&self) -> bool {
+        self.data
+            .values()
+            .any(SerializedWallpaperInfo::follows_color_scheme)
+    }
+
+    /// Return true if the color scheme changed
+    pub fn set_color_scheme(&mut self, color_scheme: ColorScheme) -> bool {
+        let changed = self.color_scheme != color_scheme;
+        self.color_scheme = color_scheme;
+        changed
+    }
+
+    /// True if any section sets `workspace_paths`
+    pub fn follows_workspaces(&self) -> bool {
+        self.data
+            .values()
+            .any(|info| info.workspace_paths.is_some())
+    }
+
+    /// Return true if the workspace shown on any display changed
+    pub fn set_workspaces(&mut self, workspaces: ActiveWorkspaces) -> bool {
+        let changed = self.workspaces != workspaces;
+        self.workspaces = workspaces;
+        changed
+    }
+
     /// Return true if the struct changed
     pub fn update(&mut self) -> bool {
         // When the config file has been written into
@@ -255,8 +1266,16 @@ impl Config {
         match new_config {
             Ok(new_config) if new_config != *self => {
                 let reloaded = self.reloaded.as_ref().unwrap().clone();
+                let color_scheme = self.color_scheme;
+                let workspaces = std::mem::take(&mut self.workspaces);
+                let oneshot = self.oneshot;
+                let initial_transitions = std::mem::take(&mut self.initial_transitions);
                 *self = new_config;
                 self.reloaded = Some(reloaded);
+                self.color_scheme = color_scheme;
+                self.workspaces = workspaces;
+                self.oneshot = oneshot;
+                self.initial_transitions = initial_transitions;
                 true
             }
             Ok(_) => {
@@ -273,7 +1292,62 @@ impl Config {
 
 impl PartialEq for Config {
     fn eq(&self, other: &Self) -> bool {
-        self.data == other.data
+        self.data == other.data && self.missing_paths == other.missing_paths
+    }
+}
+
+fn read_table(path: &Path) -> Result<toml::Table> {
+    let content = fs::read_to_string(path).with_context(|| format!("reading file {path:?}"))?;
+    toml::from_str(&content).with_context(|| format!("parsing file {path:?}"))
+}
+
+fn drop_in_dir(path: &Path) -> PathBuf {
+    path.with_file_name(DROP_IN_DIR)
+}
+
+/// Only the `.toml` files are read, the others can be e.g. backups left by an editor
+fn is_drop_in(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "toml")
+}
+
+/// True if a change to `path` can change the drop-in files read from `drop_in_dir`: the
+/// directory itself has been created or removed, or one of its drop-in files has changed
+fn affects_drop_ins(drop_in_dir: &Path, path: &Path) -> bool {
+    path == drop_in_dir || (path.parent() == Some(drop_in_dir) && is_drop_in(path))
+}
+
+/// The drop-in files next to the configuration file `path`, sorted by name. There are none when
+/// the directory doesn't exist
+fn drop_ins(path: &Path) -> Result<Vec<PathBuf>> {
+    let dir = drop_in_dir(path);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut drop_ins = Vec::new();
+    for entry in fs::read_dir(&dir).with_context(|| format!("reading directory {dir:?}"))? {
+        let path = entry?.path();
+        if is_drop_in(&path) && path.is_file() {
+            drop_ins.push(path);
+        }
+    }
+    drop_ins.sort_unstable();
+    Ok(drop_ins)
+}
+
+/// Add the sections of `other` to `table`. When both have the same section, the keys of
+/// `other` replace the ones of `table` and the others are kept. Each value is replaced as a
+/// whole, e.g. `workspace_paths` isn't merged with the one of `table`
+fn merge_tables(table: &mut toml::Table, other: toml::Table) {
+    for (name, section) in other {
+        match (table.get_mut(&name), section) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(section)) => {
+                existing.extend(section);
+            }
+            (_, section) => {
+                table.insert(name, section);
+            }
+        }
     }
 }
 
@@ -282,10 +1356,1406 @@ where
     D: serde::Deserializer<'de>,
 {
     let path = String::deserialize(deserializer)?;
-    let path = Path::new(&path);
+
+    Ok(Some(expand_tilde(Path::new(&path))))
+}
+
+pub fn tilde_expansion_map_deserialize<'de, D>(
+    deserializer: D,
+) -> Result<Option<HashMap<String, PathBuf>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let paths = HashMap::<String, String>::deserialize(deserializer)?;
 
     Ok(Some(
-        path.strip_prefix("~")
-            .map_or(path.to_path_buf(), |p| home_dir().unwrap().join(p)),
+        paths
+            .into_iter()
+            .map(|(name, path)| (name, expand_tilde(Path::new(&path))))
+            .collect(),
     ))
 }
+
+fn expand_tilde(path: &Path) -> PathBuf {
+    path.strip_prefix("~")
+        .map_or(path.to_path_buf(), |p| home_dir().unwrap().join(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay_client_toolkit::reexports::client::protocol::wl_output::Transform;
+
+    use super::*;
+    use crate::render::{BarnDoorAxis, CellShape};
+
+    const DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+
+    fn config(content: &str) -> Config {
+        Config::new_from_str(&content.replace("$DIR", DIR)).unwrap()
+    }
+
+    fn display(name: &str) -> DisplayInfo {
+        DisplayInfo {
+            name: name.to_string(),
+            make: "Unknown".to_string(),
+            model: "Unknown".to_string(),
+            serial: None,
+            description: None,
+            width: 0,
+            height: 0,
+            scale: 1,
+            fractional_scale: None,
+            transform: Transform::Normal,
+            mode_dimensions: None,
+        }
+    }
+
+    #[test]
+    fn test_section_overrides_default() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            duration = "30m"
+            mode = "contain"
+            apply-shadow = true
+
+            [DP-1]
+            mode = "tile"
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.path, Path::new(DIR));
+        assert_eq!(info.duration, Some(Duration::from_secs(30 * 60).into()));
+        assert_eq!(info.mode, BackgroundMode::Tile);
+        assert!(info.apply_shadow);
+    }
+
+    #[test]
+    fn test_unknown_output_uses_any_and_default() {
+        let config = config(
+            r#"
+            [default]
+            duration = "10m"
+            mode = "contain"
+
+            [any]
+            path = "$DIR"
+            sorting = "ascending"
+            "#,
+        );
+        let info = config.get_info_for_output(&display("HDMI-A-1")).unwrap();
+        assert_eq!(info.path, Path::new(DIR));
+        assert_eq!(info.duration, Some(Duration::from_secs(10 * 60).into()));
+        assert_eq!(info.mode, BackgroundMode::Contain);
+        assert_eq!(info.sorting, Sorting::Ascending);
+    }
+
+    #[test]
+    fn test_unknown_output_fallback_keeps_default() {
+        let config = config(
+            r#"
+            [default]
+            mode = "stretch"
+            transition_time = 1000
+
+            [DP-1]
+            path = "$DIR"
+            "#,
+        );
+        assert!(config.get_info_for_output(&display("HDMI-A-1")).is_err());
+        let info = config.get_fallback_for_output(&display("HDMI-A-1"));
+        assert_eq!(info.path, PathBuf::new());
+        assert_eq!(info.mode, BackgroundMode::Stretch);
+        assert_eq!(info.transition_time, 1000);
+    }
+
+    #[test]
+    fn test_transition_fps() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            transition_fps = 60
+
+            [DP-1]
+
+            [DP-2]
+            transition_fps = 0
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.transition_fps, Some(60));
+        // transition_fps must be greater than 0
+        assert!(!config.data.contains_key("DP-2"));
+    }
+
+    #[test]
+    fn test_group_mode() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            group = "work"
+
+            [DP-1]
+            group_mode = "timer"
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.group_mode, GroupMode::Timer);
+        let info = config.get_info_for_output(&display("DP-2")).unwrap();
+        assert_eq!(info.group_mode, GroupMode::Image);
+        assert!(Config::new_from_str(
+            r#"
+            [default]
+            group_mode = "path"
+            "#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_flip() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            flip_vertical = true
+
+            [DP-1]
+            flip_horizontal = true
+            flip_vertical = false
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!((info.flip_horizontal, info.flip_vertical), (true, false));
+        let info = config.get_info_for_output(&display("DP-2")).unwrap();
+        assert_eq!((info.flip_horizontal, info.flip_vertical), (false, true));
+    }
+
+    #[test]
+    fn test_supersampling() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+
+            [DP-1]
+            supersampling = 1.5
+
+            [DP-2]
+            supersampling = 3.0
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.supersampling, 1.5);
+        // Disabled unless set
+        let info = config.get_info_for_output(&display("HDMI-A-1")).unwrap();
+        assert_eq!(info.supersampling, 1.0);
+        assert!(!config.data.contains_key("DP-2"));
+    }
+
+    #[test]
+    fn test_transition_time_per_output() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            transition_time = 1000
+
+            [DP-1]
+            transition_time = 200
+
+            [DP-2]
+
+            [DP-3]
+            transition = "zoom"
+            "#,
+        );
+        let time = |name| {
+            config
+                .get_info_for_output(&display(name))
+                .unwrap()
+                .transition_time
+        };
+        assert_eq!(time("DP-1"), 200);
+        assert_eq!(time("DP-2"), 1000);
+        // The value set in default wins over the one of the transition
+        assert_eq!(time("DP-3"), 1000);
+    }
+
+    #[test]
+    fn test_duration_range() {
+        let config = config(
+            r#"
+            [DP-1]
+            path = "$DIR"
+            duration = "5m..15m"
+
+            [DP-2]
+            path = "$DIR"
+            duration = "15m..5m"
+            "#,
+        );
+        let duration = config
+            .get_info_for_output(&display("DP-1"))
+            .unwrap()
+            .duration
+            .unwrap();
+        assert_eq!(duration.min, Duration::from_secs(5 * 60));
+        assert_eq!(duration.max, Duration::from_secs(15 * 60));
+        let picked = duration.pick();
+        assert!(duration.min <= picked && picked <= duration.max);
+
+        // min is greater than max, the section is not valid
+        assert!(!config.data.contains_key("DP-2"));
+    }
+
+    #[test]
+    fn test_idle() {
+        let config = config(
+            r#"
+            [default]
+            idle_timeout = "5m"
+
+            [DP-1]
+            path = "$DIR"
+            idle_brightness = 0.2
+
+            [DP-2]
+            path = "$DIR"
+            idle_brightness = 2.0
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.idle_timeout, Some(Duration::from_secs(5 * 60)));
+        assert_eq!(info.idle_brightness, 0.2);
+
+        // idle_brightness must be between 0.0 and 1.0
+        assert!(!config.data.contains_key("DP-2"));
+    }
+
+    #[test]
+    fn test_align() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            duration = "15m"
+            align = true
+
+            [DP-1]
+
+            [DP-2]
+            duration = "5m..15m"
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert!(info.align);
+
+        // The boundaries of a range are not known in advance
+        assert!(!config.data.contains_key("DP-2"));
+    }
+
+    #[test]
+    fn test_startup_delay() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            startup_delay = "30s"
+
+            [DP-1]
+
+            [DP-2]
+            startup_delay = "2m"
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.startup_delay, Some(Duration::from_secs(30)));
+        let info = config.get_info_for_output(&display("DP-2")).unwrap();
+        assert_eq!(info.startup_delay, Some(Duration::from_secs(2 * 60)));
+        let info = config.get_info_for_output(&display("DP-3")).unwrap();
+        assert_eq!(info.startup_delay, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_opacity() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            opacity = 0.8
+
+            [DP-1]
+
+            [DP-2]
+            opacity = 1.5
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.opacity, 0.8);
+
+        // opacity must be between 0.0 and 1.0
+        assert!(!config.data.contains_key("DP-2"));
+    }
+
+    #[test]
+    fn test_drop_ins() {
+        let dir = std::env::temp_dir().join("wpaperd-drop-ins");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join(DROP_IN_DIR)).unwrap();
+        let path = dir.join("config.toml");
+        let write = |name: &str, content: &str| {
+            fs::write(dir.join(name), content.replace("$DIR", DIR)).unwrap();
+        };
+        write(
+            "config.toml",
+            r#"
+            [default]
+            path = "$DIR"
+            duration = "10m"
+
+            [DP-1]
+            mode = "tile"
+            vignette = 0.2
+            "#,
+        );
+        write(
+            "config.d/20-dp1.toml",
+            r#"
+            [DP-1]
+            vignette = 0.8
+            "#,
+        );
+        write(
+            "config.d/10-dp1.toml",
+            r#"
+            [DP-1]
+            mode = "contain"
+            vignette = 0.5
+
+            [DP-2]
+            opacity = 0.5
+            "#,
+        );
+        // Not a drop-in, e.g. left by an editor
+        write(
+            "config.d/30-dp1.toml~",
+            "[DP-1]
+mode = \"stretch\"
+",
+        );
+
+        let config = Config::new_from_path(&path).unwrap();
+        // The later drop-ins win, the other keys of the section are kept
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.mode, BackgroundMode::Contain);
+        assert_eq!(info.vignette, 0.8);
+        assert_eq!(info.duration, Some(Duration::from_secs(10 * 60).into()));
+        let info = config.get_info_for_output(&display("DP-2")).unwrap();
+        assert_eq!(info.opacity, 0.5);
+
+        // A drop-in that can't be parsed fails the whole configuration
+        write("config.d/40-broken.toml", "[DP-1");
+        assert!(Config::new_from_path(&path).is_err());
+    }
+
+    #[test]
+    fn test_affects_drop_ins() {
+        let drop_in_dir = Path::new("/home/user/.config/wpaperd/config.d");
+        assert!(affects_drop_ins(drop_in_dir, drop_in_dir));
+        assert!(affects_drop_ins(
+            drop_in_dir,
+            &drop_in_dir.join("10-laptop.toml")
+        ));
+        assert!(!affects_drop_ins(
+            drop_in_dir,
+            &drop_in_dir.join("10-laptop.toml~")
+        ));
+        // Watched separately
+        assert!(!affects_drop_ins(
+            drop_in_dir,
+            Path::new("/home/user/.config/wpaperd/config.toml")
+        ));
+        assert!(!affects_drop_ins(
+            drop_in_dir,
+            Path::new("/home/user/.config/wpaperd/themes/dark.toml")
+        ));
+    }
+
+    #[test]
+    fn test_temperature() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+
+            [DP-1]
+            temperature = 3400
+
+            [DP-2]
+            temperature = 100
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.temperature, 3400);
+
+        // temperature must be between 1000 and 25000
+        assert!(!config.data.contains_key("DP-2"));
+    }
+
+    #[test]
+    fn test_grain() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            grain = 0.1
+
+            [DP-1]
+
+            [DP-2]
+            grain = 1.5
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.grain, 0.1);
+
+        // grain must be between 0.0 and 1.0
+        assert!(!config.data.contains_key("DP-2"));
+    }
+
+    #[test]
+    fn test_focal_crop() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            focal_crop = true
+
+            [DP-1]
+
+            [DP-2]
+            mode = "contain"
+
+            [DP-3]
+            mode = "contain"
+            focal_crop = true
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert!(info.focal_crop);
+        // The value inherited from default has no effect in the modes that don't crop
+        let info = config.get_info_for_output(&display("DP-2")).unwrap();
+        assert!(info.focal_crop);
+
+        // contain mode never crops the image
+        assert!(!config.data.contains_key("DP-3"));
+    }
+
+    #[test]
+    fn test_recursive() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+
+            [DP-1]
+            recursive = 2
+
+            [DP-2]
+            recursive = false
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.recursive, Recursive::Depth(2));
+        let info = config.get_info_for_output(&display("DP-2")).unwrap();
+        assert_eq!(info.recursive.max_depth(), 1);
+        // The default path is searched to every depth used by the sections inheriting it
+        let depths: Vec<usize> = config.paths().into_iter().map(|(_, depth)| depth).collect();
+        assert_eq!(depths, [1, 2, usize::MAX]);
+
+        assert!(Config::new_from_str("[default]\nrecursive = 0").is_err());
+    }
+
+    #[test]
+    fn test_drop_shadow() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            drop_shadow = true
+
+            [DP-1]
+            shadow_radius = 16
+
+            [DP-2]
+            shadow_opacity = 2.0
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.drop_shadow(), Some((16, 0.5)));
+
+        // shadow_opacity must be between 0.0 and 1.0
+        assert!(!config.data.contains_key("DP-2"));
+    }
+
+    #[test]
+    fn test_max_file_size() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            max_file_size = "20M"
+
+            [DP-1]
+
+            [DP-2]
+            max_file_size = 4096
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.max_file_size, Some(20 * 1024 * 1024));
+        let info = config.get_info_for_output(&display("DP-2")).unwrap();
+        assert_eq!(info.max_file_size, Some(4096));
+        assert_eq!("1GiB".parse(), Ok(FileSize(1 << 30)));
+        assert!("20 parsecs".parse::<FileSize>().is_err());
+    }
+
+    #[test]
+    fn test_max_texture_size() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+
+            [DP-1]
+            max_texture_size = 4096
+
+            [DP-2]
+            max_texture_size = "none"
+
+            [DP-3]
+            max_texture_size = 0
+            "#,
+        );
+        let info = |name| config.get_info_for_output(&display(name)).unwrap();
+        assert_eq!(info("DP-1").max_texture_size, MaxTextureSize::Pixels(4096));
+        assert_eq!(info("DP-2").max_texture_size, MaxTextureSize::Unlimited);
+        assert_eq!(info("HDMI-1").max_texture_size, MaxTextureSize::Auto);
+
+        // max_texture_size must be greater than 0
+        assert!(!config.data.contains_key("DP-3"));
+    }
+
+    #[test]
+    fn test_layer() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            layer = "bottom"
+
+            [DP-1]
+            layer = "overlay"
+            exclusive_zone = 0
+
+            [DP-2]
+            exclusive_zone = 10
+            "#,
+        );
+        let info = |name| config.get_info_for_output(&display(name)).unwrap();
+        assert_eq!(info("DP-1").layer, WallpaperLayer::Overlay);
+        assert_eq!(info("DP-1").exclusive_zone, 0);
+        assert_eq!(info("HDMI-1").layer, WallpaperLayer::Bottom);
+        assert_eq!(info("HDMI-1").exclusive_zone, -1);
+
+        // exclusive_zone must be either -1 or 0
+        assert!(!config.data.contains_key("DP-2"));
+    }
+
+    #[test]
+    fn test_anchor_margin_size() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+
+            [DP-1]
+            anchor = ["top", "left", "right"]
+            margin = [10, 20, 0, 20]
+            size = [0, 800]
+
+            [DP-2]
+            anchor = ["top", "left", "right"]
+
+            [DP-3]
+            anchor = []
+            size = [1280, 720]
+
+            [DP-4]
+            anchor = ["left"]
+            size = [0, 720]
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(
+            info.anchor,
+            [LayerAnchor::Top, LayerAnchor::Left, LayerAnchor::Right]
+        );
+        assert_eq!(info.margin, (10, 20, 0, 20));
+        assert_eq!(info.size, (0, 800));
+        assert!(!info.covers_display());
+        let info = config.get_info_for_output(&display("DP-3")).unwrap();
+        assert!(info.anchor.is_empty());
+        let info = config.get_info_for_output(&display("HDMI-1")).unwrap();
+        assert_eq!(info.anchor, LayerAnchor::ALL);
+        assert!(info.covers_display());
+        // A side without a size must be stretched between two anchored edges
+        for name in ["DP-2", "DP-4"] {
+            assert!(!config.data.contains_key(name), "{name} is valid");
+        }
+    }
+
+    #[test]
+    fn test_color_scheme_paths() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            dark_path = "$DIR/color_scheme.rs"
+
+            [DP-1]
+            path = "$DIR/config.rs"
+
+            [DP-2]
+            light_path = "$DIR/main.rs"
+
+            [DP-3]
+            dark_path = "$DIR/missing"
+            "#,
+        );
+        assert!(config.follows_color_scheme());
+        let path = |config: &Config, name| config.get_info_for_output(&display(name)).unwrap().path;
+        assert_eq!(path(&config, "DP-1"), Path::new(DIR).join("config.rs"));
+        assert_eq!(path(&config, "DP-2"), Path::new(DIR).join("main.rs"));
+        assert_eq!(path(&config, "HDMI-1"), Path::new(DIR));
+
+        let mut config = config;
+        assert!(config.set_color_scheme(ColorScheme::Dark));
+        assert!(!config.set_color_scheme(ColorScheme::Dark));
+        // The path of the section wins over the one of the color scheme in default
+        assert_eq!(path(&config, "DP-1"), Path::new(DIR).join("config.rs"));
+        assert_eq!(
+            path(&config, "DP-2"),
+            Path::new(DIR).join("color_scheme.rs")
+        );
+        assert_eq!(
+            path(&config, "HDMI-1"),
+            Path::new(DIR).join("color_scheme.rs")
+        );
+
+        // Both color schemes are validated
+        assert!(!config.data.contains_key("DP-3"));
+    }
+
+    #[test]
+    fn test_ignore() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+
+            [DP-1]
+            ignore = true
+
+            [DP-2]
+            ignore = false
+
+            [any]
+            ignore = true
+            path = "$DIR/missing"
+            "#,
+        );
+        assert!(config.is_ignored(&display("DP-1")));
+        assert!(!config.is_ignored(&display("DP-2")));
+        // The ignored sections are not validated
+        assert!(config.data.contains_key("any"));
+        assert!(config.is_ignored(&display("HDMI-1")));
+    }
+
+    #[test]
+    fn test_workspace_paths() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            workspace_paths = { web = "$DIR/main.rs" }
+
+            [DP-1]
+            path = "$DIR/config.rs"
+            workspace_paths = { "2" = "$DIR/workspace.rs" }
+
+            [DP-2]
+            workspace_paths = { "1" = "$DIR/missing" }
+            "#,
+        );
+        assert!(config.follows_workspaces());
+        let path = |config: &Config, name| config.get_info_for_output(&display(name)).unwrap().path;
+        assert_eq!(path(&config, "DP-1"), Path::new(DIR).join("config.rs"));
+        assert_eq!(path(&config, "HDMI-1"), Path::new(DIR));
+
+        let mut config = config;
+        let workspaces = ActiveWorkspaces::from([
+            ("DP-1".to_string(), "2".to_string()),
+            ("HDMI-1".to_string(), "web".to_string()),
+        ]);
+        assert!(config.set_workspaces(workspaces.clone()));
+        assert!(!config.set_workspaces(workspaces));
+        assert_eq!(path(&config, "DP-1"), Path::new(DIR).join("workspace.rs"));
+        assert_eq!(path(&config, "HDMI-1"), Path::new(DIR).join("main.rs"));
+
+        // The paths of all the workspaces are validated
+        assert!(!config.data.contains_key("DP-2"));
+    }
+
+    #[test]
+    fn test_missing_paths() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR/missing"
+
+            [DP-1]
+            path = "$DIR"
+            dark_path = "$DIR/missing-dark"
+            "#,
+        );
+        assert_eq!(
+            config.missing_paths,
+            vec![
+                Path::new(DIR).join("missing"),
+                Path::new(DIR).join("missing-dark")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_url() {
+        let config = config(
+            r#"
+            [DP-1]
+            path = "https://example.org/image.jpg"
+            refresh = "1h"
+            "#,
+        );
+        // The URLs are only supported with the http feature
+        if cfg!(feature = "http") {
+            let info = config.get_info_for_output(&display("DP-1")).unwrap();
+            assert_eq!(info.refresh, Some(Duration::from_secs(60 * 60)));
+            assert!(config.missing_paths.is_empty());
+        } else {
+            assert!(!config.data.contains_key("DP-1"));
+        }
+    }
+
+    #[test]
+    fn test_selector() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            selector = "$DIR/main.rs"
+
+            [DP-1]
+
+            [DP-2]
+            selector = "$DIR/missing"
+            "#,
+        );
+        // The selector is only supported with the script feature
+        if cfg!(feature = "script") {
+            let info = config.get_info_for_output(&display("DP-1")).unwrap();
+            assert_eq!(info.selector, Some(Path::new(DIR).join("main.rs")));
+        } else {
+            assert!(!config.data.contains_key("DP-1"));
+        }
+        assert!(!config.data.contains_key("DP-2"));
+    }
+
+    #[test]
+    fn test_ripple_reveal() {
+        let config = config(
+            r#"
+            [DP-1]
+            path = "$DIR"
+            transition = "ripple-reveal"
+            center = [0.2, 0.8]
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(
+            info.transition,
+            Transition::RippleReveal {
+                center: Some([0.2, 0.8]),
+                amplitude: None
+            }
+        );
+        // transition_time defaults to the one of the transition
+        assert_eq!(info.transition_time, 2500);
+    }
+
+    #[test]
+    fn test_color() {
+        let config = config(
+            r##"
+            [default]
+            path = "$DIR"
+
+            [DP-1]
+            color = "#1e90ff"
+
+            [DP-3]
+            color = "#000000"
+            path = "$DIR"
+            "##,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.color, Some(Color([0x1e, 0x90, 0xff])));
+        assert_eq!(info.path, PathBuf::new());
+        assert_eq!(
+            config
+                .get_info_for_output(&display("HDMI-1"))
+                .unwrap()
+                .color,
+            None
+        );
+
+        // path and color cannot be set together
+        assert!(!config.data.contains_key("DP-3"));
+        // The color is validated when parsing
+        assert!(Config::new_from_str("[DP-2]\ncolor = \"#1e90f\"").is_err());
+    }
+
+    #[test]
+    fn test_gradient() {
+        let config = config(
+            r##"
+            [default]
+            gradient = { from = "#000000", to = "#ffffff" }
+
+            [DP-1]
+            gradient = { from = "#1e90ff", to = "#000000", angle = 45 }
+
+            [DP-2]
+            color = "#1e90ff"
+
+            [DP-3]
+            gradient = { from = "#1e90ff", to = "#000000" }
+            color = "#000000"
+
+            [DP-4]
+            gradient = { from = "#1e90ff", to = "#000000" }
+            duration = "10m"
+
+            [DP-5]
+            path = "$DIR"
+            "##,
+        );
+        let gradient = |name| config.get_info_for_output(&display(name)).unwrap().gradient;
+        assert_eq!(
+            gradient("DP-1"),
+            Some(Gradient {
+                from: Color([0x1e, 0x90, 0xff]),
+                to: Color([0, 0, 0]),
+                angle: 45.0
+            })
+        );
+        assert_eq!(
+            gradient("HDMI-1").map(|gradient| gradient.angle),
+            Some(180.0)
+        );
+        // The color and the path replace the inherited gradient
+        assert_eq!(gradient("DP-2"), None);
+        assert_eq!(gradient("DP-5"), None);
+        assert!(!config.data.contains_key("DP-3"));
+        assert!(!config.data.contains_key("DP-4"));
+
+        // From the left to the right, with the aspect ratio of the display
+        let image = Gradient {
+            from: Color([0, 0, 0]),
+            to: Color([255, 255, 255]),
+            angle: 90.0,
+        }
+        .image(3840, 1920);
+        assert_eq!(image.dimensions(), (256, 128));
+        assert!(image.get_pixel(0, 64).0[0] < 2);
+        assert_eq!(image.get_pixel(128, 0), image.get_pixel(128, 127));
+        assert!(image.get_pixel(255, 64).0[0] > 253);
+    }
+
+    #[test]
+    fn test_mode_conflicts() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            gravity = "top"
+
+            [DP-1]
+            mode = "tile"
+            gravity = "top"
+
+            [DP-2]
+            mode = "stretch"
+            offset_x = 10
+
+            [DP-3]
+            mode = "mosaic"
+            offset_y = 10
+
+            [DP-4]
+            tile_snap = "both"
+
+            [DP-5]
+            mode = "contain"
+            mosaic_grid = [3, 2]
+
+            [DP-6]
+            mode = "tile"
+            mosaic_gap = 4
+
+            [DP-7]
+            mode = "tile"
+            tile_snap = "horizontal"
+
+            [DP-8]
+            mode = "cover"
+            blur_edges = true
+
+            [DP-9]
+            mode = "contain"
+            blur_edges = true
+            "#,
+        );
+        for name in ["DP-1", "DP-2", "DP-3", "DP-4", "DP-5", "DP-6", "DP-8"] {
+            assert!(!config.data.contains_key(name), "{name} is valid");
+        }
+        // The gravity inherited from default is ignored by the mode of this section
+        let info = config.get_info_for_output(&display("DP-7")).unwrap();
+        assert_eq!(info.mode, BackgroundMode::Tile);
+        let info = config.get_info_for_output(&display("DP-9")).unwrap();
+        assert!(info.blur_edges);
+    }
+
+    #[test]
+    fn test_mode_conflicts_in_default() {
+        let err = config(
+            r#"
+            [default]
+            path = "$DIR"
+            mode = "tile"
+            gravity = "top"
+            "#,
+        )
+        .get_info_for_output(&display("DP-1"))
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("gravity") && err.contains("tile"), "{err}");
+    }
+
+    #[test]
+    fn test_modes() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            modes = ["cover", "tile"]
+            gravity = "top"
+
+            [DP-1]
+
+            [DP-2]
+            mode = "stretch"
+
+            [DP-3]
+            modes = ["contain", "mosaic"]
+
+            [DP-4]
+            modes = []
+
+            [DP-5]
+            modes = ["stretch", "tile"]
+            blur_edges = true
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.modes, [BackgroundMode::Cover, BackgroundMode::Tile]);
+        assert_eq!(info.mode, BackgroundMode::Cover);
+        // The mode of the section wins over the modes of default
+        let info = config.get_info_for_output(&display("DP-2")).unwrap();
+        assert!(info.modes.is_empty());
+        assert_eq!(info.mode, BackgroundMode::Stretch);
+        for name in ["DP-3", "DP-4", "DP-5"] {
+            assert!(!config.data.contains_key(name), "{name} is valid");
+        }
+    }
+
+    #[test]
+    fn test_clock_wipe() {
+        let config = config(
+            r#"
+            [DP-1]
+            path = "$DIR"
+            transition = "clock-wipe"
+            clockwise = false
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(
+            info.transition,
+            Transition::ClockWipe {
+                clockwise: Some(false)
+            }
+        );
+        assert_eq!(info.transition_time, 1500);
+    }
+
+    #[test]
+    fn test_morph_dissolve() {
+        let config = config(
+            r#"
+            [DP-1]
+            path = "$DIR"
+            transition = "morph-dissolve"
+            scale = 50.0
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(
+            info.transition,
+            Transition::MorphDissolve { scale: Some(50.0) }
+        );
+    }
+
+    #[test]
+    fn test_border_color() {
+        let config = config(
+            r##"
+            [default]
+            border_color = "#ff8000"
+
+            [DP-1]
+            path = "$DIR"
+
+            [DP-2]
+            path = "$DIR"
+            border_color = "#000000"
+            "##,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.border_color, Some(Color([255, 128, 0])));
+        assert_eq!(
+            info.border_color.unwrap().rgba(),
+            [1.0, 128.0 / 255.0, 0.0, 1.0]
+        );
+        let info = config.get_info_for_output(&display("DP-2")).unwrap();
+        assert_eq!(info.border_color, Some(Color([0, 0, 0])));
+    }
+
+    #[test]
+    fn test_weights() {
+        let config = config(
+            r#"
+            [default]
+            weights = { "favorites/**" = 3.0, "favorites/old/*" = 0.5 }
+
+            [DP-1]
+            path = "$DIR"
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        // The longest pattern wins
+        assert_eq!(info.weights.weight(Path::new("favorites/old/a.png")), 0.5);
+        assert_eq!(info.weights.weight(Path::new("favorites/new/a.png")), 3.0);
+        assert_eq!(info.weights.weight(Path::new("other/a.png")), 1.0);
+
+        assert!(Config::new_from_str(
+            r#"
+            [default]
+            weights = { "*" = -1.0 }
+            "#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_orientation_preference() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            orientation_preference = true
+
+            [DP-1]
+
+            [DP-2]
+            orientation_preference = false
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert!(info.orientation_preference);
+        let info = config.get_info_for_output(&display("DP-2")).unwrap();
+        assert!(!info.orientation_preference);
+    }
+
+    #[test]
+    fn test_oneshot() {
+        let mut config = config(
+            r#"
+            [DP-1]
+            path = "$DIR"
+            duration = "10m"
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert!(!info.oneshot);
+
+        config.oneshot = true;
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert!(info.oneshot);
+        assert!(config.get_fallback_for_output(&display("DP-1")).oneshot);
+    }
+
+    #[test]
+    fn test_initial_transitions() {
+        let mut config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            initial_transition = false
+            "#,
+        );
+        config.initial_transitions.insert("DP-1".to_string(), true);
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert!(info.initial_transition);
+        assert!(
+            config
+                .get_fallback_for_output(&display("DP-1"))
+                .initial_transition
+        );
+        // Only the display set at runtime is affected
+        let info = config.get_info_for_output(&display("DP-2")).unwrap();
+        assert!(!info.initial_transition);
+    }
+
+    #[test]
+    fn test_mask() {
+        let config = config(
+            r#"
+            [DP-1]
+            path = "$DIR"
+            transition = "mask"
+            mask = "$DIR/main.rs"
+
+            [DP-2]
+            path = "$DIR"
+            transition = "mask"
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(
+            info.transition.mask(),
+            Some(Path::new(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/main.rs"
+            )))
+        );
+        assert_eq!(info.transition_time, 2000);
+        // The mask transition needs a mask
+        assert!(!config.data.contains_key("DP-2"));
+    }
+
+    #[test]
+    fn test_glitch() {
+        let config = config(
+            r#"
+            [DP-1]
+            path = "$DIR"
+            transition = "glitch"
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.transition, Transition::Glitch { intensity: None });
+        assert_eq!(info.transition_time, 1000);
+    }
+
+    #[test]
+    fn test_barn_door() {
+        let config = config(
+            r#"
+            [DP-1]
+            path = "$DIR"
+            transition = "barn-door"
+            axis = "vertical"
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(
+            info.transition,
+            Transition::BarnDoor {
+                axis: Some(BarnDoorAxis::Vertical),
+                smoothness: None
+            }
+        );
+        assert_eq!(info.transition_time, 1500);
+    }
+
+    #[test]
+    fn test_displace() {
+        let config = config(
+            r#"
+            [DP-1]
+            path = "$DIR"
+            transition = "displace"
+            strength = 0.5
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(
+            info.transition,
+            Transition::Displace {
+                strength: Some(0.5)
+            }
+        );
+        assert_eq!(info.transition_time, 1500);
+    }
+
+    #[test]
+    fn test_cells() {
+        let config = config(
+            r#"
+            [DP-1]
+            path = "$DIR"
+            transition = "cells"
+            shape = "hex"
+            rows = 20.0
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(
+            info.transition,
+            Transition::Cells {
+                shape: Some(CellShape::Hex),
+                rows: Some(20.0)
+            }
+        );
+        assert_eq!(info.transition_time, 2000);
+    }
+
+    #[test]
+    fn test_no_transition() {
+        let config = config(
+            r#"
+            [default]
+            path = "$DIR"
+            transition = "none"
+            "#,
+        );
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        assert_eq!(info.transition, Transition::None {});
+        assert_eq!(info.transition_time, 0);
+    }
+
+    #[test]
+    fn test_section_matches_make_model_serial() {
+        let config = config(
+            r#"
+            [DP-1]
+            path = "$DIR"
+            mode = "contain"
+
+            ["Dell Inc. DELL U2720Q 7XYZ123"]
+            path = "$DIR"
+            mode = "tile"
+            "#,
+        );
+        let mut display = display("DP-1");
+        assert_eq!(
+            config.get_info_for_output(&display).unwrap().mode,
+            BackgroundMode::Contain
+        );
+
+        // The monitor is identified and the section follows it on any connector
+        display.name = "DP-2".to_string();
+        display.make = "Dell Inc.".to_string();
+        display.model = "DELL U2720Q".to_string();
+        display.serial = Some("7XYZ123".to_string());
+        assert_eq!(
+            config.get_info_for_output(&display).unwrap().mode,
+            BackgroundMode::Tile
+        );
+        display.name = "DP-1".to_string();
+        assert_eq!(
+            config.get_info_for_output(&display).unwrap().mode,
+            BackgroundMode::Tile
+        );
+    }
+
+    #[test]
+    fn test_deprecated_modes() {
+        let config = config(
+            r#"
+            [DP-1]
+            path = "$DIR"
+            mode = "fit"
+
+            [DP-2]
+            path = "$DIR"
+            mode = "center"
+            "#,
+        );
+        assert_eq!(
+            config.get_info_for_output(&display("DP-1")).unwrap().mode,
+            BackgroundMode::Contain
+        );
+        assert_eq!(
+            config.get_info_for_output(&display("DP-2")).unwrap().mode,
+            BackgroundMode::Cover
+        );
+    }
+
+    #[test]
+    fn test_effective_config() {
+        let config = config(
+            r##"
+            [default]
+            duration = "5m..15m"
+            border_color = "#1a2b3c"
+
+            [DP-1]
+            path = "$DIR"
+            mode = "contain-blur"
+            recursive = 2
+            transition = "glitch"
+            intensity = 0.5
+            "##,
+        );
+        assert_eq!(config.section_name_for_output(&display("DP-1")), "DP-1");
+        assert_eq!(config.section_name_for_output(&display("DP-2")), "any");
+
+        let info = config.get_info_for_output(&display("DP-1")).unwrap();
+        let value = serde_json::to_value(&info).unwrap();
+        // The keys can be copied back into the configuration
+        assert_eq!(value["duration"], "5m..15m");
+        assert_eq!(value["mode"], "contain-blur");
+        assert_eq!(value["recursive"], 2);
+        assert_eq!(value["queue_size"], 10);
+        assert_eq!(value["transition"], "glitch");
+        assert_eq!(value["intensity"], 0.5);
+        assert_eq!(value["max_texture_size"], "auto");
+        assert_eq!(value["border_color"], "#1a2b3c");
+    }
+}
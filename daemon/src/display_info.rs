@@ -3,44 +3,170 @@ use smithay_client_toolkit::{
     shell::wlr_layer::LayerSurfaceConfigure,
 };
 
+/// The identifiers of an output advertised by wlr-output-management. Some compositors send a
+/// richer make, model and serial number there than in wl_output
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OutputHeadInfo {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct DisplayInfo {
     pub name: String,
+    pub make: String,
+    pub model: String,
+    /// Not advertised by wl_output, it is parsed from the description when possible
+    pub serial: Option<String>,
+    pub description: Option<String>,
     pub width: i32,
     pub height: i32,
     pub scale: i32,
+    /// Preferred scale sent by the compositor via wp_fractional_scale_v1, in 120ths.
+    /// When it is set, it takes precedence over `scale`
+    pub fractional_scale: Option<u32>,
     pub transform: Transform,
+    /// Resolution of the current mode of the output, in hardware pixels
+    pub mode_dimensions: Option<(i32, i32)>,
 }
 
 impl DisplayInfo {
     pub fn new(info: OutputInfo) -> Self {
+        let name = info.name.unwrap_or_default();
+        let serial = info
+            .description
+            .as_deref()
+            .and_then(|description| parse_serial(description, &info.make, &info.model, &name));
+        let mode_dimensions = info
+            .modes
+            .iter()
+            .find(|mode| mode.current)
+            .map(|mode| mode.dimensions);
         Self {
-            name: info.name.unwrap_or_default(),
+            name,
+            make: info.make,
+            model: info.model,
+            serial,
+            description: info.description,
             width: 0,
             height: 0,
             scale: info.scale_factor,
+            fractional_scale: None,
             transform: info.transform,
+            mode_dimensions,
+        }
+    }
+
+    /// A display of `width`x`height` pixels that is not an output, to draw offscreen
+    pub fn offscreen(width: i32, height: i32) -> Self {
+        Self {
+            name: "offscreen".to_string(),
+            make: String::new(),
+            model: String::new(),
+            serial: None,
+            description: None,
+            width,
+            height,
+            scale: 1,
+            fractional_scale: None,
+            transform: Transform::Normal,
+            mode_dimensions: None,
+        }
+    }
+
+    /// The names of the configuration sections that can refer to this display, from the most
+    /// specific one: `<make> <model> <serial>`, then the name of the output (e.g. `DP-1`) and its
+    /// description. The first identifier is skipped when make and model are unknown
+    pub fn section_names(&self) -> Vec<String> {
+        let mut names = Vec::with_capacity(3);
+        if is_known(&self.make) && is_known(&self.model) {
+            names.push(match &self.serial {
+                Some(serial) => format!("{} {} {serial}", self.make, self.model),
+                None => format!("{} {}", self.make, self.model),
+            });
+        }
+        names.push(self.name.clone());
+        if let Some(description) = &self.description {
+            names.push(description.clone());
+        }
+        names
+    }
+
+    /// Use the identifiers of the output head with the same name, when they are known.
+    /// Return true if any of them changed
+    pub fn merge_head(&mut self, head: &OutputHeadInfo) -> bool {
+        if head.name.as_ref() != Some(&self.name) {
+            return false;
+        }
+        let mut changed = false;
+        let mut merge = |field: &mut String, value: &Option<String>| {
+            if let Some(value) = value.as_ref().filter(|value| is_known(value)) {
+                if field != value {
+                    *field = value.clone();
+                    changed = true;
+                }
+            }
+        };
+        merge(&mut self.make, &head.make);
+        merge(&mut self.model, &head.model);
+        if let Some(serial) = head.serial.as_ref().filter(|serial| is_known(serial)) {
+            if self.serial.as_ref() != Some(serial) {
+                self.serial = Some(serial.clone());
+                changed = true;
+            }
+        }
+        if self.description.is_none() && head.description.is_some() {
+            self.description = head.description.clone();
+            changed = true;
+        }
+        changed
+    }
+
+    /// Convert a logical dimension to the size of the buffer, using the fractional
+    /// scale when available
+    #[inline]
+    fn scale_dimension(&self, dimension: i32) -> i32 {
+        match self.fractional_scale {
+            // Round halfway away from zero, as requested by the protocol
+            Some(scale) => ((dimension as i64 * scale as i64 + 60) / 120) as i32,
+            None => dimension * self.scale,
         }
     }
 
     #[inline]
     pub fn scaled_width(&self) -> i32 {
-        self.width * self.scale
+        self.scale_dimension(self.width)
     }
 
     #[inline]
     pub fn scaled_height(&self) -> i32 {
-        self.height * self.scale
+        self.scale_dimension(self.height)
+    }
+
+    /// Convert an offset in logical pixels, towards the right and the bottom, to openGL
+    /// coordinates, where the display goes from -1.0 to 1.0 and the top is at 1.0
+    pub fn gl_offset(&self, (x, y): (i32, i32)) -> (f32, f32) {
+        let (width, height) = (self.adjusted_width(), self.adjusted_height());
+        if width == 0 || height == 0 {
+            return (0.0, 0.0);
+        }
+        (
+            2.0 * self.scale_dimension(x) as f32 / width as f32,
+            -2.0 * self.scale_dimension(y) as f32 / height as f32,
+        )
     }
 
     #[inline]
     pub fn adjusted_width(&self) -> i32 {
         match self.transform {
             Transform::Normal | Transform::_180 | Transform::Flipped | Transform::Flipped180 => {
-                self.width * self.scale
+                self.scaled_width()
             }
             Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
-                self.height * self.scale
+                self.scaled_height()
             }
             _ => unreachable!(),
         }
@@ -50,21 +176,31 @@ impl DisplayInfo {
     pub fn adjusted_height(&self) -> i32 {
         match self.transform {
             Transform::Normal | Transform::_180 | Transform::Flipped | Transform::Flipped180 => {
-                self.height * self.scale
+                self.scaled_height()
             }
             Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
-                self.width * self.scale
+                self.scaled_width()
             }
             _ => unreachable!(),
         }
     }
 
-    #[inline]
-    pub fn ratio(&self) -> f32 {
-        // adjusted_width and adjusted_height returns the rotated sizes in case
-        // the display is rotated. However, openGL is drawing in the same orientation
-        // as our display (i.e. we don't apply any transform here)
-        self.adjusted_height() as f32 / self.adjusted_width() as f32
+    /// Size of the buffer drawn on the display. Before the surface has been configured, the
+    /// resolution of the output is used instead
+    pub fn pixel_size(&self) -> Option<(u32, u32)> {
+        let (width, height) = if self.width != 0 && self.height != 0 {
+            (self.adjusted_width(), self.adjusted_height())
+        } else {
+            // Modes are not transformed, like the adjusted size
+            self.mode_dimensions?
+        };
+        Some((width.try_into().ok()?, height.try_into().ok()?))
+    }
+
+    /// Width divided by height of the buffer drawn on the display, see [`DisplayInfo::pixel_size`]
+    pub fn ratio(&self) -> Option<f32> {
+        let (width, height) = self.pixel_size()?;
+        (height != 0).then(|| width as f32 / height as f32)
     }
 
     pub fn change_size(&mut self, configure: LayerSurfaceConfigure) -> bool {
@@ -96,4 +232,115 @@ impl DisplayInfo {
             false
         }
     }
+
+    pub fn change_fractional_scale(&mut self, scale: u32) -> bool {
+        if self.fractional_scale != Some(scale) {
+            self.fractional_scale = Some(scale);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// wlroots based compositors use `Unknown` for the fields that the display doesn't provide
+fn is_known(field: &str) -> bool {
+    !field.is_empty() && field != "Unknown"
+}
+
+/// Extract the serial from a description in the form `<make> <model> <serial> (<name>)`,
+/// as sent by most compositors
+fn parse_serial(description: &str, make: &str, model: &str, name: &str) -> Option<String> {
+    if !is_known(make) || !is_known(model) {
+        return None;
+    }
+    let description = description
+        .strip_suffix(&format!(" ({name})"))
+        .unwrap_or(description);
+    let serial = description
+        .strip_prefix(make)?
+        .trim_start()
+        .strip_prefix(model)?
+        .trim();
+    is_known(serial).then(|| serial.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_serial() {
+        assert_eq!(
+            parse_serial(
+                "Dell Inc. DELL U2720Q 7XYZ123 (DP-1)",
+                "Dell Inc.",
+                "DELL U2720Q",
+                "DP-1"
+            ),
+            Some("7XYZ123".to_string())
+        );
+        assert_eq!(
+            parse_serial(
+                "Dell Inc. DELL U2720Q Unknown (DP-1)",
+                "Dell Inc.",
+                "DELL U2720Q",
+                "DP-1"
+            ),
+            None
+        );
+        // The description does not follow the format
+        assert_eq!(
+            parse_serial("Foocorp 11\" Display", "Foocorp", "F11", "DP-1"),
+            None
+        );
+    }
+
+    fn unknown_display() -> DisplayInfo {
+        DisplayInfo {
+            name: "DP-1".to_string(),
+            make: "Unknown".to_string(),
+            model: "Unknown".to_string(),
+            serial: None,
+            description: None,
+            width: 1000,
+            height: 500,
+            scale: 2,
+            fractional_scale: None,
+            transform: Transform::Normal,
+            mode_dimensions: None,
+        }
+    }
+
+    #[test]
+    fn test_gl_offset() {
+        let info = unknown_display();
+        assert_eq!(info.gl_offset((0, 0)), (0.0, 0.0));
+        // Moving to the bottom goes towards -1.0
+        assert_eq!(info.gl_offset((250, 125)), (0.5, -0.5));
+    }
+
+    #[test]
+    fn test_merge_head() {
+        let mut info = unknown_display();
+        let mut head = OutputHeadInfo {
+            name: Some("DP-2".to_string()),
+            make: Some("Dell Inc.".to_string()),
+            model: Some("DELL U2720Q".to_string()),
+            serial: Some("7XYZ123".to_string()),
+            description: None,
+        };
+        // The head belongs to another output
+        assert!(!info.merge_head(&head));
+
+        head.name = Some("DP-1".to_string());
+        assert!(info.merge_head(&head));
+        assert_eq!(info.section_names()[0], "Dell Inc. DELL U2720Q 7XYZ123");
+        assert!(!info.merge_head(&head));
+
+        // Unknown identifiers don't replace the known ones
+        head.serial = Some("Unknown".to_string());
+        assert!(!info.merge_head(&head));
+        assert_eq!(info.serial.as_deref(), Some("7XYZ123"));
+    }
 }
@@ -0,0 +1,150 @@
+//! Wallpapers loaded from HTTP(S) URLs. They are downloaded with ureq by the decoder
+//! threads, and kept in `XDG_CACHE_HOME/wpaperd/downloads` so that the last copy can still
+//! be shown when the download fails.
+
+use std::path::Path;
+#[cfg(feature = "http")]
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::PathBuf,
+    time::Duration,
+};
+
+#[cfg(feature = "http")]
+use color_eyre::{
+    eyre::{ensure, eyre, Context},
+    Result,
+};
+#[cfg(feature = "http")]
+use log::warn;
+
+/// True if `path` is an URL, e.g. `https://example.org/image.jpg`
+pub fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// How long a single download can take
+#[cfg(feature = "http")]
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Bigger downloads are stopped, the images are never that big
+#[cfg(feature = "http")]
+const MAX_DOWNLOAD_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Download `url` and return the path of the local copy, or the previous copy if the download
+/// fails. It blocks, so it must only be called by the decoder threads
+#[cfg(feature = "http")]
+pub fn fetch(url: &Path) -> Result<PathBuf> {
+    let cache_dir = dirs::cache_dir()
+        .ok_or_else(|| eyre!("unable to find the cache directory"))?
+        .join("wpaperd")
+        .join("downloads");
+    fs::create_dir_all(&cache_dir)
+        .with_context(|| format!("creating the directory {cache_dir:?}"))?;
+    let cached = cache_dir.join(format!(
+        "{:016x}",
+        fnv1a(url.as_os_str().as_encoded_bytes())
+    ));
+
+    match download(url, &cached) {
+        Ok(()) => Ok(cached),
+        Err(err) if cached.exists() => {
+            warn!("{err:?}, showing the copy downloaded previously");
+            Ok(cached)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Download into a temporary file first, so that a failed download does not replace the copy
+/// in the cache
+#[cfg(feature = "http")]
+fn download(url: &Path, cached: &Path) -> Result<()> {
+    let url = url
+        .to_str()
+        .ok_or_else(|| eyre!("{url:?} is not a valid URL"))?;
+    let partial = cached.with_extension("part");
+    let res = ureq::AgentBuilder::new()
+        .timeout(TIMEOUT)
+        .build()
+        .get(url)
+        .call()
+        .with_context(|| format!("downloading {url} failed"))
+        .and_then(|response| {
+            let mut file =
+                File::create(&partial).with_context(|| format!("creating {partial:?}"))?;
+            // One more byte than allowed, to know that the limit has been exceeded
+            let size = io::copy(
+                &mut response.into_reader().take(MAX_DOWNLOAD_SIZE + 1),
+                &mut file,
+            )
+            .with_context(|| format!("downloading {url} failed"))?;
+            ensure!(
+                size <= MAX_DOWNLOAD_SIZE,
+                "{url} is bigger than {MAX_DOWNLOAD_SIZE} bytes"
+            );
+            Ok(())
+        });
+    if let Err(err) = res {
+        let _ = fs::remove_file(&partial);
+        return Err(err);
+    }
+    fs::rename(&partial, cached).with_context(|| format!("moving {partial:?} to {cached:?}"))?;
+    Ok(())
+}
+
+/// A hash that does not change between releases, used to name the copies in the cache
+#[cfg(feature = "http")]
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_url() {
+        assert!(is_url(Path::new("https://example.org/image.jpg")));
+        assert!(is_url(Path::new("http://example.org/image")));
+        assert!(!is_url(Path::new("/home/user/https://image.jpg")));
+        assert!(!is_url(Path::new("~/Pictures")));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_download() {
+        use std::{io::Write, net::TcpListener, thread};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/image.png", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for (stream, status) in listener.incoming().zip(["200 OK", "404 Not Found"]) {
+                let mut stream = stream.unwrap();
+                // The request is short enough to be read at once
+                let _ = stream.read(&mut [0; 4096]).unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: 5\r\nConnection: close\r\n\r\nimage"
+                )
+                .unwrap();
+            }
+        });
+
+        let dir = std::env::temp_dir().join("wpaperd-download");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cached = dir.join("image");
+        download(Path::new(&url), &cached).unwrap();
+        assert_eq!(fs::read(&cached).unwrap(), b"image");
+
+        // The copy in the cache is kept when the download fails
+        assert!(download(Path::new(&url), &cached).is_err());
+        assert_eq!(fs::read(&cached).unwrap(), b"image");
+        assert!(!cached.with_extension("part").exists());
+    }
+}
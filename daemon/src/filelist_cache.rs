@@ -1,100 +1,330 @@
 use std::{
+    collections::{HashMap, HashSet},
+    fs,
     path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex,
     },
+    thread,
+    time::SystemTime,
 };
 
 use color_eyre::eyre::{anyhow, Context, Result};
 use hotwatch::Hotwatch;
-use log::error;
-use smithay_client_toolkit::reexports::calloop::{self, ping::Ping, LoopHandle};
+use image::image_dimensions;
+use log::{debug, error};
+use smithay_client_toolkit::reexports::calloop::{
+    self,
+    channel::{Event, Sender},
+    ping::Ping,
+    LoopHandle,
+};
+use smithay_client_toolkit::reexports::client::QueueHandle;
 use walkdir::WalkDir;
 
 use crate::wpaperd::Wpaperd;
 
+/// Whether an image or a display is wider than it's tall, see
+/// [`WallpaperInfo::orientation_preference`](crate::wallpaper_info::WallpaperInfo::orientation_preference)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Landscape,
+    Portrait,
+}
+
+impl Orientation {
+    /// `None` when the width and the height are the same, which matches both
+    pub fn from_ratio(ratio: f32) -> Option<Self> {
+        if ratio > 1.0 {
+            Some(Orientation::Landscape)
+        } else if ratio < 1.0 {
+            Some(Orientation::Portrait)
+        } else {
+            None
+        }
+    }
+}
+
+/// The size of the images read so far, by their modification time, `None` when the size can't
+/// be read
+type Dimensions = HashMap<PathBuf, (SystemTime, Option<(u32, u32)>)>;
+
+/// The images of a directory matching each orientation, sent back to the main loop by the
+/// thread that has read their size, see [`Filelist::oriented`]
+struct Oriented {
+    path: PathBuf,
+    generation: u64,
+    max_depth: usize,
+    files: Vec<(Orientation, Arc<Vec<PathBuf>>)>,
+}
+
 #[derive(Debug)]
 struct Filelist {
     path: PathBuf,
-    filelist: Arc<Vec<PathBuf>>,
+    /// The images found up to each maximum depth requested, see
+    /// [`Recursive::max_depth`](crate::wallpaper_info::Recursive::max_depth)
+    filelists: Vec<(usize, Arc<Vec<PathBuf>>)>,
+    /// The images of `filelists` matching an orientation, filtered when first requested
+    oriented: Vec<(usize, Orientation, Arc<Vec<PathBuf>>)>,
+    /// The depths whose images are being filtered by another thread
+    orienting: Vec<usize>,
+    /// Incremented each time the directory is walked, to discard the images filtered before
+    generation: u64,
+    /// Kept across the walks, so that the size of each image is only read once
+    dimensions: Arc<Mutex<Dimensions>>,
     outdated: Arc<AtomicBool>,
 }
 
 impl Filelist {
-    fn new(path: &Path) -> Self {
+    fn new(path: &Path, max_depths: &[usize]) -> Self {
         let mut res = Self {
             path: path.to_path_buf(),
-            filelist: Arc::new(Vec::new()),
+            filelists: max_depths
+                .iter()
+                .map(|max_depth| (*max_depth, Arc::new(Vec::new())))
+                .collect(),
+            oriented: Vec::new(),
+            orienting: Vec::new(),
+            generation: 0,
+            dimensions: Arc::new(Mutex::new(HashMap::new())),
             outdated: Arc::new(AtomicBool::new(true)),
         };
         res.populate();
         res
     }
+
+    /// Walk the directory once, up to the deepest depth requested
     fn populate(&mut self) {
-        self.filelist = Arc::new(
-            WalkDir::new(&self.path)
-                .follow_links(true)
-                .sort_by_file_name()
-                .into_iter()
-                .filter_map(|e| e.ok())
-                .filter(|e| {
-                    if let Some(guess) = new_mime_guess::from_path(e.path()).first() {
-                        guess.type_() == "image"
-                    } else {
-                        false
-                    }
-                })
-                .map(|e| e.path().to_path_buf())
-                .collect(),
-        );
+        let max_depth = self
+            .filelists
+            .iter()
+            .map(|(max_depth, _)| *max_depth)
+            .max()
+            .unwrap_or(1);
+        let images: Vec<(usize, PathBuf)> = WalkDir::new(&self.path)
+            .follow_links(true)
+            .max_depth(max_depth)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| {
+                if let Some(guess) = new_mime_guess::from_path(e.path()).first() {
+                    guess.type_() == "image"
+                } else {
+                    // Without the feature they are skipped, as they cannot be decoded
+                    cfg!(feature = "jxl") && crate::jxl::is_jxl(e.path())
+                }
+            })
+            .map(|e| (e.depth(), e.path().to_path_buf()))
+            .collect();
+        debug!("found {} images in {:?}", images.len(), self.path);
+        for (max_depth, filelist) in &mut self.filelists {
+            *filelist = Arc::new(
+                images
+                    .iter()
+                    .filter(|(depth, _)| depth <= max_depth)
+                    .map(|(_, path)| path.clone())
+                    .collect(),
+            );
+        }
+        self.oriented.clear();
+        self.orienting.clear();
+        self.generation += 1;
+        // Forget the images that have been removed
+        let images: HashSet<&PathBuf> = images.iter().map(|(_, path)| path).collect();
+        self.dimensions
+            .lock()
+            .expect("lock to not be poisoned")
+            .retain(|path, _| images.contains(path));
         self.outdated.store(false, Ordering::Relaxed);
     }
+
+    /// Only keep the depths in `max_depths`, return false if a new one must be populated
+    fn update_depths(&mut self, max_depths: &[usize]) -> bool {
+        self.filelists
+            .retain(|(max_depth, _)| max_depths.contains(max_depth));
+        self.oriented
+            .retain(|(max_depth, _, _)| max_depths.contains(max_depth));
+        self.orienting
+            .retain(|max_depth| max_depths.contains(max_depth));
+        let mut up_to_date = true;
+        for max_depth in max_depths {
+            if !self.filelists.iter().any(|(depth, _)| depth == max_depth) {
+                self.filelists.push((*max_depth, Arc::new(Vec::new())));
+                up_to_date = false;
+            }
+        }
+        up_to_date
+    }
+
+    /// The images found up to `max_depth` matching `orientation`. Reading their size is left to
+    /// another thread, which sends the result to `sender`; until then all of them are returned
+    fn oriented(
+        &mut self,
+        max_depth: usize,
+        orientation: Orientation,
+        sender: Option<&Sender<Oriented>>,
+    ) -> Arc<Vec<PathBuf>> {
+        if let Some((_, _, files)) = self
+            .oriented
+            .iter()
+            .find(|(depth, o, _)| *depth == max_depth && *o == orientation)
+        {
+            return files.clone();
+        }
+        let files = self
+            .filelists
+            .iter()
+            .find(|(depth, _)| *depth == max_depth)
+            .expect("max_depth passed to Filelist::oriented has been populated")
+            .1
+            .clone();
+        if let Some(sender) = sender.filter(|_| !self.orienting.contains(&max_depth)) {
+            let path = self.path.clone();
+            let generation = self.generation;
+            let dimensions = self.dimensions.clone();
+            let sender = sender.clone();
+            let all_files = files.clone();
+            let spawned = thread::Builder::new()
+                .name("wpaperd-orientation".to_string())
+                .spawn(move || {
+                    let files = orient(&all_files, &dimensions);
+                    // The main loop is not running anymore otherwise
+                    let _ = sender.send(Oriented {
+                        path,
+                        generation,
+                        max_depth,
+                        files,
+                    });
+                });
+            match spawned {
+                Ok(_) => self.orienting.push(max_depth),
+                Err(err) => error!("spawning the orientation thread: {err:?}"),
+            }
+        }
+        files
+    }
+
+    /// Store the images filtered by [`orient`], unless the directory has been walked again since
+    fn store_oriented(&mut self, oriented: Oriented) {
+        if oriented.generation != self.generation || !self.orienting.contains(&oriented.max_depth) {
+            return;
+        }
+        self.orienting.retain(|depth| *depth != oriented.max_depth);
+        for (orientation, files) in oriented.files {
+            debug!(
+                "{} images in {:?} match the {orientation:?} orientation",
+                files.len(),
+                self.path
+            );
+            self.oriented.push((oriented.max_depth, orientation, files));
+        }
+    }
+
+    /// Number of images found, at the deepest depth requested
+    fn len(&self) -> usize {
+        self.filelists
+            .iter()
+            .map(|(_, filelist)| filelist.len())
+            .max()
+            .unwrap_or(0)
+    }
 }
 
+#[derive(Default)]
 pub struct FilelistCache {
     cache: Vec<Filelist>,
+    /// Where the threads send the images they have filtered by orientation
+    oriented_sender: Option<Sender<Oriented>>,
 }
 
 impl FilelistCache {
     pub fn new(
-        paths: Vec<PathBuf>,
+        paths: Vec<(PathBuf, usize)>,
         hotwatch: &mut Hotwatch,
         event_loop_handle: LoopHandle<Wpaperd>,
+        qh: QueueHandle<Wpaperd>,
     ) -> Result<(Ping, Self)> {
         let (ping, ping_source) =
             calloop::ping::make_ping().context("Unable to create a calloop::ping::Ping")?;
 
-        let mut filelist_cache = Self { cache: Vec::new() };
+        let (oriented_sender, oriented_channel) = calloop::channel::channel();
+        let mut filelist_cache = Self {
+            cache: Vec::new(),
+            oriented_sender: Some(oriented_sender),
+        };
         filelist_cache.update_paths(paths, hotwatch, ping.clone());
         event_loop_handle
             .insert_source(ping_source, move |_, _, wpaperd| {
                 wpaperd.filelist_cache.borrow_mut().update_cache();
+                // Do not keep showing the images that have been deleted
+                for surface in &mut wpaperd.surfaces {
+                    surface.check_current_image(&qh);
+                    surface.check_startup_hold(&qh);
+                }
             })
             .map_err(|e| anyhow!("inserting the filelist event listener in the event loop: {e}"))?;
+        event_loop_handle
+            .insert_source(oriented_channel, |event, _, wpaperd| {
+                if let Event::Msg(oriented) = event {
+                    wpaperd.filelist_cache.borrow_mut().store_oriented(oriented);
+                }
+            })
+            .map_err(|e| anyhow!("inserting the orientation channel in the event loop: {e}"))?;
 
         Ok((ping, filelist_cache))
     }
 
-    pub fn get(&self, path: &Path) -> Arc<Vec<PathBuf>> {
+    pub fn get(&self, path: &Path, max_depth: usize) -> Arc<Vec<PathBuf>> {
         self.cache
             .iter()
             .find(|filelist| filelist.path == path)
+            .and_then(|filelist| {
+                filelist
+                    .filelists
+                    .iter()
+                    .find(|(depth, _)| *depth == max_depth)
+            })
             .expect("path passed to Filelist::get has been cached")
-            .filelist
+            .1
             .clone()
     }
 
-    /// paths must be sorted
+    /// Like [`FilelistCache::get`], preferring the images that match `orientation`
+    pub fn get_oriented(
+        &mut self,
+        path: &Path,
+        max_depth: usize,
+        orientation: Orientation,
+    ) -> Arc<Vec<PathBuf>> {
+        self.cache
+            .iter_mut()
+            .find(|filelist| filelist.path == path)
+            .expect("path passed to Filelist::get_oriented has been cached")
+            .oriented(max_depth, orientation, self.oriented_sender.as_ref())
+    }
+
+    fn store_oriented(&mut self, oriented: Oriented) {
+        if let Some(filelist) = self
+            .cache
+            .iter_mut()
+            .find(|filelist| filelist.path == oriented.path)
+        {
+            filelist.store_oriented(oriented);
+        }
+    }
+
+    /// paths must be sorted, each one comes with the maximum depth it is searched to
     pub fn update_paths(
         &mut self,
-        paths: Vec<PathBuf>,
+        paths: Vec<(PathBuf, usize)>,
         hotwatch: &mut Hotwatch,
         event_loop_ping: Ping,
     ) {
         self.cache.retain(|filelist| {
             let path_exists = filelist.path.exists();
-            if paths.contains(&filelist.path) && path_exists {
+            if paths.iter().any(|(path, _)| path == &filelist.path) && path_exists {
                 true
             } else {
                 // Stop watching paths that have been removed
@@ -111,18 +341,34 @@ impl FilelistCache {
             }
         });
 
-        for path in paths {
-            if !self.cache.iter().any(|filelist| filelist.path == path) {
+        let max_depths = |path: &Path| -> Vec<usize> {
+            paths
+                .iter()
+                .filter(|(p, _)| p == path)
+                .map(|(_, max_depth)| *max_depth)
+                .collect()
+        };
+        for (path, _) in &paths {
+            if let Some(filelist) = self
+                .cache
+                .iter_mut()
+                .find(|filelist| &filelist.path == path)
+            {
+                // The new depths are populated by update_cache
+                if !filelist.update_depths(&max_depths(path)) {
+                    filelist.outdated.store(true, Ordering::Relaxed);
+                }
+            } else {
                 // Skip paths that don't exists and files
                 if !path.exists() || !path.is_dir() {
                     continue;
                 }
-                let filelist = Filelist::new(&path);
+                let filelist = Filelist::new(path, &max_depths(path));
                 let outdated = filelist.outdated.clone();
                 self.cache.push(filelist);
                 let ping_clone = event_loop_ping.clone();
                 if let Err(err) = hotwatch
-                    .watch(&path, move |event| match event.kind {
+                    .watch(path, move |event| match event.kind {
                         hotwatch::EventKind::Create(_)
                         | hotwatch::EventKind::Remove(_)
                         | hotwatch::EventKind::Modify(_) => {
@@ -146,6 +392,35 @@ impl FilelistCache {
         self.update_cache();
     }
 
+    /// Walk again the directories matching `path`, or all of them, for the changes that have not
+    /// been reported, e.g. on network filesystems. Return how many images each one contains
+    pub fn rescan(&mut self, path: Option<&Path>) -> Vec<(PathBuf, usize)> {
+        // The path can be written differently from the configuration
+        let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let path = path.map(canonical);
+        for filelist in &mut self.cache {
+            if path
+                .as_ref()
+                .map_or(true, |path| &canonical(&filelist.path) == path)
+            {
+                filelist.outdated.store(true, Ordering::Relaxed);
+            }
+        }
+        // The outdated flag is cleared once populated, only the ones marked above are left
+        let outdated: Vec<PathBuf> = self
+            .cache
+            .iter()
+            .filter(|filelist| filelist.outdated.load(Ordering::Relaxed))
+            .map(|filelist| filelist.path.clone())
+            .collect();
+        self.update_cache();
+        self.cache
+            .iter()
+            .filter(|filelist| outdated.contains(&filelist.path))
+            .map(|filelist| (filelist.path.clone(), filelist.len()))
+            .collect()
+    }
+
     pub fn update_cache(&mut self) {
         for filelist in &mut self.cache {
             if filelist.outdated.load(std::sync::atomic::Ordering::Relaxed) {
@@ -154,3 +429,205 @@ impl FilelistCache {
         }
     }
 }
+
+/// The images of `files` matching each orientation, or all of them if none does. Square images
+/// and the ones whose size can't be read match both orientations. It reads the header of the
+/// images, so it must not be called by the main loop
+fn orient(
+    files: &Arc<Vec<PathBuf>>,
+    dimensions: &Mutex<Dimensions>,
+) -> Vec<(Orientation, Arc<Vec<PathBuf>>)> {
+    let orientations: Vec<Option<Orientation>> = files
+        .iter()
+        .map(|file| {
+            // Only the header is read, and only once for each version of the file
+            let modified = fs::metadata(file).and_then(|metadata| metadata.modified());
+            let cached = dimensions
+                .lock()
+                .expect("lock to not be poisoned")
+                .get(file)
+                .copied();
+            let size = match (cached, modified) {
+                (Some((cached, size)), Ok(modified)) if cached == modified => size,
+                (_, modified) => {
+                    let size = image_dimensions(file).ok();
+                    if let Ok(modified) = modified {
+                        dimensions
+                            .lock()
+                            .expect("lock to not be poisoned")
+                            .insert(file.clone(), (modified, size));
+                    }
+                    size
+                }
+            };
+            size.and_then(|(width, height)| Orientation::from_ratio(width as f32 / height as f32))
+        })
+        .collect();
+    [Orientation::Landscape, Orientation::Portrait]
+        .into_iter()
+        .map(|orientation| {
+            let matching: Vec<PathBuf> = files
+                .iter()
+                .zip(&orientations)
+                .filter(|(_, o)| o.map_or(true, |o| o == orientation))
+                .map(|(file, _)| file.clone())
+                .collect();
+            // A soft preference, the display is not left without images
+            let matching = if matching.is_empty() {
+                files.clone()
+            } else {
+                Arc::new(matching)
+            };
+            (orientation, matching)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_filelist_depths() {
+        let dir = std::env::temp_dir().join("wpaperd-filelist-depths");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+        for image in ["a.png", "sub/b.png", "sub/deeper/c.png"] {
+            fs::write(dir.join(image), []).unwrap();
+        }
+
+        let depths = |filelist: &Filelist| -> Vec<(usize, usize)> {
+            filelist
+                .filelists
+                .iter()
+                .map(|(max_depth, files)| (*max_depth, files.len()))
+                .collect()
+        };
+        let mut filelist = Filelist::new(&dir, &[1, usize::MAX]);
+        assert_eq!(depths(&filelist), [(1, 1), (usize::MAX, 3)]);
+
+        // Only one level of subdirectories
+        assert!(!filelist.update_depths(&[2]));
+        filelist.populate();
+        assert_eq!(depths(&filelist), [(2, 2)]);
+        assert!(filelist.update_depths(&[2]));
+    }
+
+    #[test]
+    fn test_rescan() {
+        let dir = std::env::temp_dir().join("wpaperd-filelist-rescan");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::create_dir_all(dir.join("b")).unwrap();
+        fs::write(dir.join("a/1.png"), []).unwrap();
+
+        let mut cache = FilelistCache {
+            cache: vec![
+                Filelist::new(&dir.join("a"), &[1]),
+                Filelist::new(&dir.join("b"), &[1]),
+            ],
+            oriented_sender: None,
+        };
+        // Not reported, as nothing is watched here
+        fs::write(dir.join("a/2.png"), []).unwrap();
+        fs::write(dir.join("b/1.png"), []).unwrap();
+        assert_eq!(cache.get(&dir.join("a"), 1).len(), 1);
+
+        // Written differently from the cached path
+        let rescanned = cache.rescan(Some(&dir.join("b/../a/")));
+        assert_eq!(rescanned, [(dir.join("a"), 2)]);
+        assert_eq!(cache.get(&dir.join("a"), 1).len(), 2);
+        assert!(cache.get(&dir.join("b"), 1).is_empty());
+
+        assert_eq!(cache.rescan(None), [(dir.join("a"), 2), (dir.join("b"), 1)]);
+        assert!(cache.rescan(Some(&dir.join("c"))).is_empty());
+    }
+
+    #[test]
+    fn test_oriented() {
+        let dir = std::env::temp_dir().join("wpaperd-filelist-oriented");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, width, height) in [("wide.png", 4, 2), ("tall.png", 2, 4), ("square.png", 2, 2)]
+        {
+            image::RgbaImage::new(width, height)
+                .save(dir.join(name))
+                .unwrap();
+        }
+        // Its size can't be read
+        fs::write(dir.join("broken.png"), []).unwrap();
+
+        let mut filelist = Filelist::new(&dir, &[1]);
+        let names = |files: &[PathBuf]| -> Vec<String> {
+            files
+                .iter()
+                .map(|file| file.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        let oriented = |filelist: &Filelist| -> Oriented {
+            Oriented {
+                path: dir.clone(),
+                generation: filelist.generation,
+                max_depth: 1,
+                files: orient(&filelist.filelists[0].1, &filelist.dimensions),
+            }
+        };
+        let files = oriented(&filelist).files;
+        assert_eq!(files[0].0, Orientation::Landscape);
+        assert_eq!(names(&files[0].1), ["broken.png", "square.png", "wide.png"]);
+        assert_eq!(files[1].0, Orientation::Portrait);
+        assert_eq!(names(&files[1].1), ["broken.png", "square.png", "tall.png"]);
+
+        // All the images until the thread is done, then only the matching ones
+        let (sender, _channel) = calloop::channel::channel();
+        assert_eq!(
+            filelist
+                .oriented(1, Orientation::Landscape, Some(&sender))
+                .len(),
+            4
+        );
+        assert_eq!(filelist.orienting, [1]);
+        filelist.store_oriented(oriented(&filelist));
+        assert!(filelist.orienting.is_empty());
+        let landscape = filelist.oriented(1, Orientation::Landscape, Some(&sender));
+        assert_eq!(names(&landscape), ["broken.png", "square.png", "wide.png"]);
+        // Filtered only once
+        assert!(Arc::ptr_eq(
+            &landscape,
+            &filelist.oriented(1, Orientation::Landscape, Some(&sender))
+        ));
+
+        // Filtered before the directory has been walked again
+        let outdated = oriented(&filelist);
+        filelist.populate();
+        filelist.oriented(1, Orientation::Landscape, Some(&sender));
+        filelist.store_oriented(outdated);
+        assert!(filelist.oriented.is_empty());
+
+        // No image matches, all of them are used
+        fs::remove_file(dir.join("wide.png")).unwrap();
+        fs::remove_file(dir.join("square.png")).unwrap();
+        fs::remove_file(dir.join("broken.png")).unwrap();
+        filelist.populate();
+        assert_eq!(filelist.dimensions.lock().unwrap().len(), 1);
+        assert_eq!(names(&oriented(&filelist).files[0].1), ["tall.png"]);
+    }
+
+    #[test]
+    fn test_filelist_jxl() {
+        let dir = std::env::temp_dir().join("wpaperd-filelist-jxl");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for image in ["a.png", "b.jxl", "c.txt"] {
+            fs::write(dir.join(image), []).unwrap();
+        }
+
+        let filelist = Filelist::new(&dir, &[1]);
+        let files = &filelist.filelists[0].1;
+        assert!(files.contains(&dir.join("a.png")));
+        assert_eq!(files.contains(&dir.join("b.jxl")), cfg!(feature = "jxl"));
+        assert!(!files.contains(&dir.join("c.txt")));
+    }
+}
@@ -1,11 +1,87 @@
-use std::{collections::HashMap, path::PathBuf, thread::JoinHandle};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    num::NonZeroUsize,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Instant, SystemTime},
+};
 
-use image::{open, RgbaImage};
-use log::warn;
+#[cfg(feature = "http")]
+use image::ImageReader;
+use image::{
+    image_dimensions,
+    imageops::{self, FilterType},
+    open, DynamicImage, ImageResult, RgbaImage,
+};
+use log::{debug, info, warn};
+use smithay_client_toolkit::reexports::calloop::channel::Sender;
+
+use crate::render::TextureFormat;
+
+/// How much an image can be downscaled when it's decoded, to not waste memory on pixels that
+/// will never be visible
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SizeLimit {
+    /// Keep enough pixels to cover a display of this size
+    Display(u32, u32),
+    /// Maximum length of the longest side
+    Pixels(u32),
+    /// Resize the image to exactly this size, cropping the parts that don't fit like the cover
+    /// mode. Used for the images of the mosaic mode
+    Fill(u32, u32),
+    /// Resize the image to fit in this size like the contain mode, over a blurred copy that
+    /// covers the borders. Used for the contain-blur mode
+    Letterbox(u32, u32),
+}
+
+impl SizeLimit {
+    /// Leave some room for the transitions that zoom into the image
+    const DISPLAY_FACTOR: f32 = 1.5;
+
+    /// The size to downscale an image of `width`x`height` to, if it exceeds the limit
+    fn target_size(&self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let scale = match *self {
+            // Both the sides have to be big enough, otherwise the cover mode would upscale it
+            SizeLimit::Display(display_width, display_height) => {
+                f32::max(
+                    display_width as f32 / width as f32,
+                    display_height as f32 / height as f32,
+                ) * Self::DISPLAY_FACTOR
+            }
+            SizeLimit::Pixels(pixels) => pixels as f32 / width.max(height) as f32,
+            // The image is resized and cropped at the same time, see [`decode`]
+            SizeLimit::Fill(..) | SizeLimit::Letterbox(..) => return None,
+        };
+        (scale < 1.0).then(|| {
+            (
+                ((width as f32 * scale).round() as u32).max(1),
+                ((height as f32 * scale).round() as u32).max(1),
+            )
+        })
+    }
+}
+
+/// An image that has been requested, the same path is decoded once for each size limit and
+/// order of the channels
+type ImageKey = (PathBuf, Option<SizeLimit>, TextureFormat);
+
+/// The result of decoding the image requested, sent back to the main loop by the workers
+pub type DecodedImage = (ImageKey, Option<RgbaImage>);
+
+enum ImageState {
+    Decoding,
+    Decoded(RgbaImage),
+    Failed,
+}
 
 struct Image {
-    data: Option<RgbaImage>,
-    thread_handle: Option<JoinHandle<Option<RgbaImage>>>,
+    state: ImageState,
     requesters: Vec<String>,
 }
 
@@ -15,81 +91,723 @@ pub enum ImageLoaderStatus {
     Error,
 }
 
+/// Decode the images in a pool of worker threads, so that the render loop never waits for them.
+/// Each decoded image is sent back via a calloop channel, then the main loop calls
+/// [`ImageLoader::decoded`] and asks the requesters to draw again
 pub struct ImageLoader {
-    images: HashMap<PathBuf, Image>,
+    images: HashMap<ImageKey, Image>,
+    jobs: mpsc::Sender<ImageKey>,
+    /// The images skipped because of `max_file_size`, so that they are only reported once
+    oversized: HashSet<PathBuf>,
+    /// Longest side of the textures the GPU accepts, `0` until a renderer has been created.
+    /// Shared with the workers, see [`ImageLoader::limit_texture_size`]
+    max_texture_size: Arc<AtomicU32>,
+    /// The focal points computed so far, by path and modification time of the file
+    focal_points: HashMap<(PathBuf, SystemTime), (f32, f32)>,
 }
 
 impl ImageLoader {
-    pub fn new() -> Self {
+    /// Upper bound for the number of workers, decoding is mostly limited by the memory bandwidth
+    const MAX_WORKERS: usize = 4;
+
+    pub fn new(decoded_sender: Sender<DecodedImage>) -> Self {
+        let (jobs, receiver) = mpsc::channel::<ImageKey>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let max_texture_size = Arc::new(AtomicU32::new(0));
+        let workers = thread::available_parallelism()
+            .map(NonZeroUsize::get)
+            .unwrap_or(1)
+            .min(Self::MAX_WORKERS);
+        for _ in 0..workers {
+            let receiver = receiver.clone();
+            let decoded_sender = decoded_sender.clone();
+            let max_texture_size = max_texture_size.clone();
+            thread::Builder::new()
+                .name("wpaperd-decoder".to_string())
+                .spawn(move || loop {
+                    // Release the lock before decoding, so that the other workers can pick a job
+                    let job = receiver.lock().expect("lock to not be poisoned").recv();
+                    let Ok((path, size_limit, texture_format)) = job else {
+                        // The loader has been dropped
+                        break;
+                    };
+                    let started = Instant::now();
+                    let max_texture_size =
+                        Some(max_texture_size.load(Ordering::Relaxed)).filter(|max| *max != 0);
+                    let image = catch_unwind(AssertUnwindSafe(|| {
+                        let mut image = decode(&path, size_limit, max_texture_size)?;
+                        texture_format.arrange(&mut image);
+                        Some(image)
+                    }))
+                    .unwrap_or_else(|_| {
+                        warn!("decoding image {path:?} panicked");
+                        None
+                    });
+                    if let Some(image) = &image {
+                        debug!(
+                            "decoded image {path:?} at {}x{} in {:?}",
+                            image.width(),
+                            image.height(),
+                            started.elapsed()
+                        );
+                    }
+                    if decoded_sender
+                        .send(((path, size_limit, texture_format), image))
+                        .is_err()
+                    {
+                        // The main loop is not running anymore
+                        break;
+                    }
+                })
+                .expect("spawning the image decoder thread to work");
+        }
+
         Self {
             images: HashMap::new(),
+            jobs,
+            oversized: HashSet::new(),
+            max_texture_size,
+            focal_points: HashMap::new(),
         }
     }
 
-    pub fn background_load(&mut self, path: PathBuf, requester_name: String) -> ImageLoaderStatus {
-        if let Some(image) = self.images.get_mut(&path) {
-            if let Some(handle) = image.thread_handle.take() {
-                if handle.is_finished() {
-                    match handle.join() {
-                        Ok(thread_result) => match thread_result {
-                            Some(image_data) => {
-                                image.data = Some(image_data);
-                            }
-                            None => {
-                                self.images.remove(&path);
-                                return ImageLoaderStatus::Error;
-                            }
-                        },
-                        Err(err) => {
-                            warn!("{err:?}");
-                            self.images.remove(&path);
-                            return ImageLoaderStatus::Error;
-                        }
-                    }
-                } else {
-                    // the thread is still running
-                    // reassign the handle
-                    image.thread_handle = Some(handle);
-                    return ImageLoaderStatus::Waiting;
-                }
-            }
-            if let Some(data) = &image.data {
+    /// Downscale the images decoded from now on, so that their longest side is at most
+    /// `max_texture_size`. With several renderers, the smallest limit is kept
+    pub fn limit_texture_size(&self, max_texture_size: u32) {
+        let _ =
+            self.max_texture_size
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                    Some(match current {
+                        0 => max_texture_size,
+                        current => current.min(max_texture_size),
+                    })
+                });
+    }
+
+    pub fn background_load(
+        &mut self,
+        path: PathBuf,
+        size_limit: Option<SizeLimit>,
+        texture_format: TextureFormat,
+        max_file_size: Option<u64>,
+        requester_name: String,
+    ) -> ImageLoaderStatus {
+        let key = (path, size_limit, texture_format);
+        let Some(image) = self.request(&key, max_file_size, &requester_name) else {
+            return ImageLoaderStatus::Error;
+        };
+        match &image.state {
+            // The decoded image is not ready yet
+            ImageState::Decoding => ImageLoaderStatus::Waiting,
+            ImageState::Decoded(data) => {
                 // If the requesters is only one and it's the same as the current
-                if image.requesters.len() == 1
-                    && image.requesters.first().unwrap() == &requester_name
-                {
+                if image.requesters.len() == 1 {
                     // Just send it up and remove it from the map
-                    let image = self.images.remove(&path);
-                    ImageLoaderStatus::Loaded(image.unwrap().data.unwrap())
+                    match self.images.remove(&key).map(|image| image.state) {
+                        Some(ImageState::Decoded(data)) => ImageLoaderStatus::Loaded(data),
+                        _ => unreachable!(),
+                    }
                 } else {
                     // otherwise this image has been requested by multiple surfaces
-                    let requesters = &mut image.requesters;
-                    if let Some(index) = requesters.iter().position(|name| name == &requester_name)
-                    {
-                        requesters.remove(index);
-                    }
-                    ImageLoaderStatus::Loaded(data.clone())
+                    let data = data.clone();
+                    image.requesters.retain(|name| name != &requester_name);
+                    ImageLoaderStatus::Loaded(data)
                 }
-            } else {
-                // The decoded image is not ready yet
-                ImageLoaderStatus::Waiting
             }
-        } else {
-            let path_clone = path.clone();
-            let handle = std::thread::spawn(|| match open(path_clone) {
-                Ok(image) => Some(image.into_rgba8()),
-                Err(err) => {
-                    warn!("{err:?}");
-                    None
+            ImageState::Failed => {
+                image.requesters.retain(|name| name != &requester_name);
+                if image.requesters.is_empty() {
+                    self.images.remove(&key);
+                }
+                ImageLoaderStatus::Error
+            }
+        }
+    }
+
+    /// Start decoding the image at `path` without waiting for it, a later call to
+    /// [`ImageLoader::background_load`] for the same path will return it as soon as it's ready
+    pub fn prefetch(
+        &mut self,
+        path: PathBuf,
+        size_limit: Option<SizeLimit>,
+        texture_format: TextureFormat,
+        max_file_size: Option<u64>,
+        requester_name: String,
+    ) {
+        self.request(
+            &(path, size_limit, texture_format),
+            max_file_size,
+            &requester_name,
+        );
+    }
+
+    /// Register the request of `requester_name` for the image `key`, sending it to the
+    /// workers if it's not being decoded yet. The files bigger than `max_file_size` are not
+    /// decoded at all
+    fn request(
+        &mut self,
+        key: &ImageKey,
+        max_file_size: Option<u64>,
+        requester_name: &str,
+    ) -> Option<&mut Image> {
+        if let Some(max_file_size) = max_file_size {
+            // When the size is unknown, decoding will fail anyway
+            let size = fs::metadata(&key.0).map_or(0, |metadata| metadata.len());
+            if size > max_file_size {
+                if self.oversized.insert(key.0.clone()) {
+                    warn!(
+                        "skipping image {:?}, its size of {size} bytes exceeds max_file_size",
+                        key.0
+                    );
                 }
-            });
-            let image = Image {
-                requesters: vec![requester_name],
-                thread_handle: Some(handle),
-                data: None,
-            };
-            self.images.insert(path, image);
+                return None;
+            }
+        }
+
+        // Only the latest request of each requester is honored, forget the previous ones.
+        // The images that are still being decoded will be discarded once they are done
+        self.images.retain(|image_key, image| {
+            if image_key != key {
+                image.requesters.retain(|name| name != requester_name);
+            }
+            !image.requesters.is_empty()
+        });
+
+        if !self.images.contains_key(key) {
+            debug!("decoding image {:?} for {requester_name}", key.0);
+            if let Err(err) = self.jobs.send(key.clone()) {
+                warn!("unable to decode image {:?}: {err:?}", key.0);
+                return None;
+            }
+            self.images.insert(
+                key.clone(),
+                Image {
+                    state: ImageState::Decoding,
+                    requesters: Vec::new(),
+                },
+            );
+        }
+
+        let image = self
+            .images
+            .get_mut(key)
+            .expect("image to have been inserted");
+        if !image.requesters.iter().any(|name| name == requester_name) {
+            image.requesters.push(requester_name.to_string());
+        }
+        Some(image)
+    }
+
+    /// The [`focal_point`] of `image`, decoded from the file at `path`. It is computed only once
+    /// for each version of the file, unless its modification time can't be read
+    pub fn focal_point(
+        &mut self,
+        path: &Path,
+        image: &RgbaImage,
+        texture_format: TextureFormat,
+    ) -> (f32, f32) {
+        let Ok(modified) = fs::metadata(path).and_then(|metadata| metadata.modified()) else {
+            return focal_point(image, texture_format);
+        };
+        let key = (path.to_path_buf(), modified);
+        if let Some(focal_point) = self.focal_points.get(&key) {
+            return *focal_point;
+        }
+        // The file has changed, forget its previous version
+        self.focal_points.retain(|(cached, _), _| cached != path);
+        *self
+            .focal_points
+            .entry(key)
+            .or_insert_with(|| focal_point(image, texture_format))
+    }
+
+    /// Store an image decoded by the workers and return the requesters waiting for it
+    pub fn decoded(&mut self, (key, data): DecodedImage) -> Vec<String> {
+        match self.images.get_mut(&key) {
+            Some(image) => {
+                image.state = match data {
+                    Some(data) => ImageState::Decoded(data),
+                    None => ImageState::Failed,
+                };
+                image.requesters.clone()
+            }
+            // Nobody is waiting for this image anymore
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Decode the image at `path`, downscaled to `size_limit` and so that its longest side fits in
+/// `max_texture_size`. Whatever the color type of the file, e.g. 16-bit or grayscale, the image
+/// is converted to 8-bit RGBA. The errors are logged
+pub fn decode(
+    path: &Path,
+    size_limit: Option<SizeLimit>,
+    max_texture_size: Option<u32>,
+) -> Option<RgbaImage> {
+    // The URLs are downloaded first, their format cannot be guessed from the extension
+    #[cfg(feature = "http")]
+    let image = if crate::download::is_url(path) {
+        crate::download::fetch(path).and_then(|file| open_downloaded(&file))
+    } else {
+        open_file(path)
+    };
+    #[cfg(not(feature = "http"))]
+    let image = open_file(path);
+    let image = match image {
+        Ok(image) => image,
+        Err(err) => {
+            warn!("{err:?}");
+            return None;
+        }
+    };
+    if let Some(SizeLimit::Fill(width, height)) = size_limit {
+        return Some(
+            image
+                .resize_to_fill(width, height, FilterType::Lanczos3)
+                .into_rgba8(),
+        );
+    }
+    if let Some(SizeLimit::Letterbox(width, height)) = size_limit {
+        return Some(letterbox_blur(&image, width, height));
+    }
+    let image = image.into_rgba8();
+    let (width, height) = size_limit
+        .and_then(|size_limit| size_limit.target_size(image.width(), image.height()))
+        .unwrap_or(image.dimensions());
+    // Uploading a bigger texture would fail
+    let (width, height) = match max_texture_size
+        .and_then(|max_texture_size| fit_texture_size(width, height, max_texture_size))
+    {
+        Some((fitted_width, fitted_height)) => {
+            info!(
+                "downscaling image {path:?} from {width}x{height} to {fitted_width}x{fitted_height}, the maximum texture size of the GPU"
+            );
+            (fitted_width, fitted_height)
+        }
+        None => (width, height),
+    };
+    if (width, height) == image.dimensions() {
+        Some(image)
+    } else {
+        Some(imageops::resize(
+            &image,
+            width,
+            height,
+            FilterType::Lanczos3,
+        ))
+    }
+}
+
+/// The size to downscale `width`x`height` to, keeping the aspect ratio, if any of its sides is
+/// longer than `max_texture_size`
+fn fit_texture_size(width: u32, height: u32, max_texture_size: u32) -> Option<(u32, u32)> {
+    if width <= max_texture_size && height <= max_texture_size {
+        return None;
+    }
+    // Rounded down, so that the longest side is exactly the limit and the other one never
+    // exceeds it
+    let shrink = |side: u32, longest: u32| {
+        ((side as u64 * max_texture_size as u64 / longest as u64) as u32).max(1)
+    };
+    Some(if width >= height {
+        (max_texture_size, shrink(height, width))
+    } else {
+        (shrink(width, height), max_texture_size)
+    })
+}
+
+/// The center of the detailed parts of `image`, from `(0.0, 0.0)` at its top left corner to
+/// `(1.0, 1.0)` at the bottom right. The luminance is sampled on a coarse grid and each sample
+/// is weighted by its contrast with the neighbours, so that flat areas like the sky don't move
+/// the point. Uniform images are focused in the middle. `texture_format` is the order of the
+/// channels of `image`
+pub fn focal_point(image: &RgbaImage, texture_format: TextureFormat) -> (f32, f32) {
+    const GRID: u32 = 64;
+    let (width, height) = image.dimensions();
+    let columns = GRID.min(width).max(1);
+    let rows = GRID.min(height).max(1);
+    let luminance: Vec<f32> = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .map(|(column, row)| {
+            // The middle of each cell of the grid
+            let x = (2 * column + 1) * width / (2 * columns);
+            let y = (2 * row + 1) * height / (2 * rows);
+            let [red, green, blue, alpha] = texture_format.rgba(image.get_pixel(x, y).0);
+            (0.2126 * f32::from(red) + 0.7152 * f32::from(green) + 0.0722 * f32::from(blue))
+                * f32::from(alpha)
+                / (255.0 * 255.0)
+        })
+        .collect();
+    let at = |column: u32, row: u32| luminance[(row * columns + column) as usize];
+
+    let (mut total, mut x_sum, mut y_sum) = (0.0, 0.0, 0.0);
+    for row in 0..rows {
+        for column in 0..columns {
+            let horizontal =
+                at((column + 1).min(columns - 1), row) - at(column.saturating_sub(1), row);
+            let vertical = at(column, (row + 1).min(rows - 1)) - at(column, row.saturating_sub(1));
+            // Squared, so that a few strong edges weigh more than a lot of faint noise
+            let weight = horizontal * horizontal + vertical * vertical;
+            total += weight;
+            x_sum += weight * (column as f32 + 0.5) / columns as f32;
+            y_sum += weight * (row as f32 + 0.5) / rows as f32;
+        }
+    }
+    if total < f32::EPSILON {
+        return (0.5, 0.5);
+    }
+    (x_sum / total, y_sum / total)
+}
+
+/// Where to place an image that is cropped to `visible` of its side, from `0.0` to `1.0` like
+/// [`Gravity::position`](crate::wallpaper_info::Gravity::position), so that the part shown is
+/// centered on `focus` as much as possible. None when the side is not cropped
+pub fn focal_position(focus: f32, visible: f32) -> Option<f32> {
+    (visible < 1.0).then(|| ((focus - visible / 2.0) / (1.0 - visible)).clamp(0.0, 1.0))
+}
+
+/// Compose `image` at exactly `width`x`height`, contained in the middle of a blurred copy of
+/// itself that covers the whole size
+pub fn letterbox_blur(image: &DynamicImage, width: u32, height: u32) -> RgbaImage {
+    // Blurring a smaller copy is much faster, and it looks the same once it's scaled up
+    const DOWNSCALE: u32 = 8;
+    const SIGMA: f32 = 4.0;
+    let background = image.resize_to_fill(
+        (width / DOWNSCALE).max(1),
+        (height / DOWNSCALE).max(1),
+        FilterType::Triangle,
+    );
+    let background = imageops::blur(&background.into_rgba8(), SIGMA);
+    let mut canvas = imageops::resize(&background, width, height, FilterType::Triangle);
+
+    let foreground = image
+        .resize(width, height, FilterType::Lanczos3)
+        .into_rgba8();
+    let x = (width - foreground.width()) / 2;
+    let y = (height - foreground.height()) / 2;
+    imageops::overlay(&mut canvas, &foreground, x.into(), y.into());
+    canvas
+}
+
+/// Decode the image at `path`, guessing its format from the extension
+fn open_file(path: &Path) -> color_eyre::Result<DynamicImage> {
+    #[cfg(feature = "jxl")]
+    if crate::jxl::is_jxl(path) {
+        return crate::jxl::decode(path);
+    }
+    Ok(open(path)?)
+}
+
+/// Decode a downloaded image, guessing its format from the content as the copies in the cache
+/// have no extension
+#[cfg(feature = "http")]
+fn open_downloaded(file: &Path) -> color_eyre::Result<DynamicImage> {
+    #[cfg(feature = "jxl")]
+    if crate::jxl::check(file).is_ok() {
+        return crate::jxl::decode(file);
+    }
+    Ok(ImageReader::open(file)?.with_guessed_format()?.decode()?)
+}
+
+/// Check that `path` is an image that can be decoded, by only reading its header
+pub fn check_image(path: &Path) -> ImageResult<()> {
+    #[cfg(feature = "jxl")]
+    if crate::jxl::is_jxl(path) {
+        return crate::jxl::check(path).map_err(image::ImageError::IoError);
+    }
+    image_dimensions(path).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, LumaA, Rgba};
+    use smithay_client_toolkit::reexports::calloop::channel::channel;
+
+    use super::*;
+
+    #[test]
+    fn test_focal_point() {
+        assert_eq!(
+            focal_point(&RgbaImage::new(100, 50), TextureFormat::Rgba),
+            (0.5, 0.5)
+        );
+
+        // A white square in the bottom right corner of a black image
+        let mut image = RgbaImage::from_pixel(200, 100, Rgba([0, 0, 0, 255]));
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            if (140..180).contains(&x) && (60..90).contains(&y) {
+                *pixel = Rgba([255, 255, 255, 255]);
+            }
+        }
+        let (x, y) = focal_point(&image, TextureFormat::Rgba);
+        assert!((x - 0.8).abs() < 0.05, "{x}");
+        assert!((y - 0.75).abs() < 0.05, "{y}");
+        // The channels swapped by the decoder threads are read back in the same order
+        let mut image = RgbaImage::from_pixel(200, 100, Rgba([0, 0, 0, 255]));
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            if (20..60).contains(&x) && (10..40).contains(&y) {
+                *pixel = Rgba([255, 0, 0, 255]);
+            } else if (140..180).contains(&x) && (60..90).contains(&y) {
+                *pixel = Rgba([0, 0, 255, 255]);
+            }
+        }
+        let rgba = focal_point(&image, TextureFormat::Rgba);
+        TextureFormat::Bgra.arrange(&mut image);
+        assert_eq!(focal_point(&image, TextureFormat::Bgra), rgba);
+        assert_ne!(focal_point(&image, TextureFormat::Rgba), rgba);
+
+        // Half of the image is shown, centered on the focus unless it's too close to the edges
+        assert_eq!(focal_position(0.5, 0.5), Some(0.5));
+        assert_eq!(focal_position(0.8, 0.5), Some(1.0));
+        assert_eq!(focal_position(0.1, 0.5), Some(0.0));
+        assert_eq!(focal_position(0.625, 0.5), Some(0.75));
+        assert_eq!(focal_position(0.2, 1.0), None);
+    }
+
+    #[test]
+    fn test_only_latest_request_is_honored() {
+        let (sender, _channel) = channel();
+        let mut loader = ImageLoader::new(sender);
+        let image = || Some(RgbaImage::new(1, 1));
+
+        assert!(matches!(
+            loader.background_load(
+                PathBuf::from("a.png"),
+                None,
+                TextureFormat::Rgba,
+                None,
+                "DP-1".to_string()
+            ),
             ImageLoaderStatus::Waiting
+        ));
+        assert!(matches!(
+            loader.background_load(
+                PathBuf::from("b.png"),
+                None,
+                TextureFormat::Rgba,
+                None,
+                "DP-1".to_string()
+            ),
+            ImageLoaderStatus::Waiting
+        ));
+        // Nobody is waiting for the first image anymore
+        assert!(loader
+            .decoded(((PathBuf::from("a.png"), None, TextureFormat::Rgba), image()))
+            .is_empty());
+        assert_eq!(
+            loader.decoded(((PathBuf::from("b.png"), None, TextureFormat::Rgba), image())),
+            ["DP-1".to_string()]
+        );
+        assert!(matches!(
+            loader.background_load(
+                PathBuf::from("b.png"),
+                None,
+                TextureFormat::Rgba,
+                None,
+                "DP-1".to_string()
+            ),
+            ImageLoaderStatus::Loaded(_)
+        ));
+    }
+
+    #[test]
+    fn test_prefetched_image_is_loaded() {
+        let (sender, _channel) = channel();
+        let mut loader = ImageLoader::new(sender);
+
+        loader.prefetch(
+            PathBuf::from("a.png"),
+            None,
+            TextureFormat::Rgba,
+            None,
+            "DP-1".to_string(),
+        );
+        assert_eq!(
+            loader.decoded((
+                (PathBuf::from("a.png"), None, TextureFormat::Rgba),
+                Some(RgbaImage::new(1, 1))
+            )),
+            ["DP-1".to_string()]
+        );
+        assert!(matches!(
+            loader.background_load(
+                PathBuf::from("a.png"),
+                None,
+                TextureFormat::Rgba,
+                None,
+                "DP-1".to_string()
+            ),
+            ImageLoaderStatus::Loaded(_)
+        ));
+    }
+
+    #[test]
+    fn test_max_file_size() {
+        let (sender, _channel) = channel();
+        let mut loader = ImageLoader::new(sender);
+        let path = std::env::temp_dir().join("wpaperd-max-file-size.png");
+        fs::write(&path, [0; 100]).unwrap();
+
+        for _ in 0..2 {
+            assert!(matches!(
+                loader.background_load(
+                    path.clone(),
+                    None,
+                    TextureFormat::Rgba,
+                    Some(99),
+                    "DP-1".to_string()
+                ),
+                ImageLoaderStatus::Error
+            ));
         }
+        assert_eq!(loader.oversized.len(), 1);
+        assert!(matches!(
+            loader.background_load(
+                path,
+                None,
+                TextureFormat::Rgba,
+                Some(100),
+                "DP-1".to_string()
+            ),
+            ImageLoaderStatus::Waiting
+        ));
+    }
+
+    #[test]
+    fn test_mosaic_requests_are_independent() {
+        let (sender, _channel) = channel();
+        let mut loader = ImageLoader::new(sender);
+        let size_limit = Some(SizeLimit::Fill(960, 540));
+
+        loader.prefetch(
+            PathBuf::from("a.png"),
+            size_limit,
+            TextureFormat::Rgba,
+            None,
+            "DP-1#0".to_string(),
+        );
+        loader.prefetch(
+            PathBuf::from("b.png"),
+            size_limit,
+            TextureFormat::Rgba,
+            None,
+            "DP-1#1".to_string(),
+        );
+        // Each cell of the mosaic is a different requester
+        assert_eq!(
+            loader.decoded((
+                (PathBuf::from("a.png"), size_limit, TextureFormat::Rgba),
+                Some(RgbaImage::new(1, 1))
+            )),
+            ["DP-1#0".to_string()]
+        );
+        assert_eq!(
+            loader.decoded((
+                (PathBuf::from("b.png"), size_limit, TextureFormat::Rgba),
+                Some(RgbaImage::new(1, 1))
+            )),
+            ["DP-1#1".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_letterbox_blur() {
+        let image = RgbaImage::from_pixel(40, 20, [200, 0, 0, 255].into());
+        let letterbox = letterbox_blur(&image.into(), 40, 40);
+        assert_eq!(letterbox.dimensions(), (40, 40));
+        // The image is in the middle and the borders are filled
+        assert_eq!(letterbox.get_pixel(20, 20).0, [200, 0, 0, 255]);
+        assert_eq!(letterbox.get_pixel(0, 0).0[3], 255);
+        assert_ne!(letterbox.get_pixel(0, 0).0[0], 0);
+    }
+
+    #[test]
+    fn test_size_limit() {
+        // Cover a 1920x1080 display, with some room for zooming
+        assert_eq!(
+            SizeLimit::Display(1920, 1080).target_size(12000, 6000),
+            Some((3240, 1620))
+        );
+        assert_eq!(SizeLimit::Display(1920, 1080).target_size(2560, 1440), None);
+        assert_eq!(
+            SizeLimit::Pixels(4096).target_size(6000, 8192),
+            Some((3000, 4096))
+        );
+        // Images are never upscaled
+        assert_eq!(SizeLimit::Pixels(4096).target_size(1000, 1000), None);
+    }
+
+    #[test]
+    fn test_fit_texture_size() {
+        assert_eq!(fit_texture_size(8192, 8192, 8192), None);
+        assert_eq!(fit_texture_size(20000, 5000, 8192), Some((8192, 2048)));
+        assert_eq!(fit_texture_size(3000, 16385, 16384), Some((2999, 16384)));
+        // A very thin image keeps at least one pixel
+        assert_eq!(fit_texture_size(100_000, 1, 4096), Some((4096, 1)));
+
+        let path = std::env::temp_dir().join("wpaperd-max-texture-size.png");
+        RgbaImage::new(300, 100).save(&path).unwrap();
+        assert_eq!(
+            decode(&path, None, Some(60)).unwrap().dimensions(),
+            (60, 20)
+        );
+        // Applied after the size limit
+        let image = decode(&path, Some(SizeLimit::Pixels(150)), Some(60)).unwrap();
+        assert_eq!(image.dimensions(), (60, 20));
+        let image = decode(&path, Some(SizeLimit::Pixels(30)), Some(60)).unwrap();
+        assert_eq!(image.dimensions(), (30, 10));
+    }
+
+    #[test]
+    fn test_16_bit_images_are_decoded_as_8_bit_rgba() {
+        let dir = std::env::temp_dir();
+        let rgba = dir.join("wpaperd-16-bit-rgba.png");
+        ImageBuffer::from_pixel(64, 32, Rgba([0xffff_u16, 0x8080, 0, 0xffff]))
+            .save(&rgba)
+            .unwrap();
+        let gray_alpha = dir.join("wpaperd-16-bit-gray-alpha.png");
+        ImageBuffer::from_pixel(64, 32, LumaA([0x8080_u16, 0xffff]))
+            .save(&gray_alpha)
+            .unwrap();
+
+        for (path, pixel) in [
+            (rgba, [255, 128, 0, 255]),
+            (gray_alpha, [128, 128, 128, 255]),
+        ] {
+            // Four bytes for each pixel, the layout uploaded to the textures
+            let image = decode(&path, None, None).unwrap();
+            assert_eq!(image.dimensions(), (64, 32));
+            assert_eq!(image.as_raw().len(), 64 * 32 * 4);
+            assert_eq!(image.get_pixel(10, 10).0, pixel);
+            let image = decode(&path, Some(SizeLimit::Pixels(16)), None).unwrap();
+            assert_eq!(image.dimensions(), (16, 8));
+            assert_eq!(image.as_raw().len(), 16 * 8 * 4);
+            let image = decode(&path, Some(SizeLimit::Letterbox(20, 20)), None).unwrap();
+            assert_eq!(image.as_raw().len(), 20 * 20 * 4);
+        }
+    }
+
+    #[cfg(all(feature = "http", feature = "jxl"))]
+    #[test]
+    fn test_open_downloaded() {
+        let dir = std::env::temp_dir().join("wpaperd-open-downloaded");
+        fs::create_dir_all(&dir).unwrap();
+        // Named like the copies in the cache, without extension
+        let jxl = dir.join("0123456789abcdef");
+        fs::copy(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/image.jxl"),
+            &jxl,
+        )
+        .unwrap();
+        let image = open_downloaded(&jxl).unwrap();
+        assert_eq!((image.width(), image.height()), (3, 2));
+
+        let png = dir.join("fedcba9876543210");
+        RgbaImage::new(4, 2)
+            .save_with_format(&png, image::ImageFormat::Png)
+            .unwrap();
+        let image = open_downloaded(&png).unwrap();
+        assert_eq!((image.width(), image.height()), (4, 2));
     }
 }
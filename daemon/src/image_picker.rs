@@ -1,16 +1,24 @@
 use std::{
     cell::RefCell,
-    collections::VecDeque,
+    collections::{HashSet, VecDeque},
     path::{Path, PathBuf},
     rc::Rc,
+    sync::Arc,
     time::Instant,
 };
 
+use chrono::{Datelike, Local};
 use log::warn;
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    rngs::StdRng,
+    seq::SliceRandom,
+    Rng, SeedableRng,
+};
 
 use crate::{
-    filelist_cache::FilelistCache,
-    wallpaper_info::{Sorting, WallpaperInfo},
+    filelist_cache::{FilelistCache, Orientation},
+    wallpaper_info::{Sorting, WallpaperInfo, Weights},
 };
 
 #[derive(Debug)]
@@ -92,10 +100,31 @@ impl Queue {
         }
     }
 
+    /// Push the images of a previous session, oldest first, skipping the ones that no longer
+    /// exist. Only the most recent `size` images are kept
+    fn restore(&mut self, history: impl IntoIterator<Item = PathBuf>) {
+        for path in history {
+            if path.exists() {
+                self.push(path);
+            }
+        }
+    }
+
     fn has_reached_end(&self) -> bool {
         self.current == self.tail
     }
 
+    /// Forget every image but the current one, return how many have been forgotten
+    fn clear(&mut self) -> usize {
+        let Some(current) = self.buffer.get(self.current).cloned() else {
+            return 0;
+        };
+        let cleared = self.buffer.len() - 1;
+        *self = Self::with_capacity(self.size);
+        self.push(current);
+        cleared
+    }
+
     fn resize(&mut self, new_size: usize) {
         if !self.is_full() {
             self.buffer.reserve_exact(new_size);
@@ -116,6 +145,78 @@ impl Queue {
     }
 }
 
+/// A random permutation of the files, played through without repeats
+#[derive(Debug, Default)]
+struct Shuffle {
+    order: Vec<PathBuf>,
+    /// Index in `order` of the image currently shown
+    current: Option<usize>,
+}
+
+impl Shuffle {
+    /// Keep `order` in sync with the files available: the files that have been removed are
+    /// dropped and the new ones are appended in random order, so that the images already shown
+    /// won't be shown again until the next reshuffle
+    fn sync(&mut self, files: &[PathBuf], rng: &mut StdRng) {
+        let files_set: HashSet<&PathBuf> = files.iter().collect();
+        if let Some(current) = self.current {
+            // Point to the last image shown that is still available
+            self.current = self.order[..=current]
+                .iter()
+                .filter(|p| files_set.contains(p))
+                .count()
+                .checked_sub(1);
+        }
+        self.order.retain(|p| files_set.contains(p));
+
+        let known: HashSet<&PathBuf> = self.order.iter().collect();
+        let mut new_files: Vec<PathBuf> = files
+            .iter()
+            .filter(|p| !known.contains(p))
+            .cloned()
+            .collect();
+        new_files.shuffle(rng);
+        self.order.extend(new_files);
+    }
+
+    fn next(
+        &mut self,
+        files: &[PathBuf],
+        current_img: &Path,
+        rng: &mut StdRng,
+    ) -> (usize, PathBuf) {
+        self.sync(files, rng);
+        let next = self.current.map_or(0, |current| current + 1);
+        if next < self.order.len() {
+            return (next, self.order[next].clone());
+        }
+
+        // Every image has been shown, start a new permutation
+        self.order.shuffle(rng);
+        // Avoid showing the same image twice in a row
+        if self.order.len() > 1 && self.order[0] == current_img {
+            let last = self.order.len() - 1;
+            self.order.swap(0, last);
+        }
+        self.current = None;
+        (0, self.order[0].clone())
+    }
+
+    fn previous(
+        &mut self,
+        files: &[PathBuf],
+        current_img: &Path,
+        rng: &mut StdRng,
+    ) -> (usize, PathBuf) {
+        self.sync(files, rng);
+        match self.current {
+            Some(current) if current > 0 => (current - 1, self.order[current - 1].clone()),
+            // There is nothing before the first image of the permutation
+            current => (current.unwrap_or(0), current_img.to_path_buf()),
+        }
+    }
+}
+
 enum ImagePickerAction {
     Next,
     Previous,
@@ -125,6 +226,70 @@ enum ImagePickerSorting {
     Random(Queue),
     Ascending(usize),
     Descending(usize),
+    Shuffle(Shuffle),
+    Daily,
+}
+
+/// Position of `current_img` in the sorted `files`, starting from the index it had before the
+/// files changed. When the image has been removed, `Err` contains the index of the file that
+/// followed it, so that the rotation doesn't go back to the start
+fn sorted_position(
+    files: &[PathBuf],
+    current_img: &Path,
+    current_index: usize,
+) -> Result<usize, usize> {
+    if files
+        .get(current_index)
+        .is_some_and(|file| file == current_img)
+    {
+        Ok(current_index)
+    } else {
+        files.binary_search_by(|file| file.as_path().cmp(current_img))
+    }
+}
+
+/// Index of the file after the one at `position`, wrapping around at the end
+fn following_index(position: Result<usize, usize>, len: usize) -> usize {
+    let index = match position {
+        Ok(index) => index + 1,
+        Err(index) => index,
+    };
+    if index < len {
+        index
+    } else {
+        0
+    }
+}
+
+/// Index of the file before the one at `position`, wrapping around at the start
+fn preceding_index(position: Result<usize, usize>, len: usize) -> usize {
+    let (Ok(index) | Err(index)) = position;
+    index.checked_sub(1).unwrap_or(len - 1)
+}
+
+/// Index of the image shown on `day`, the number of days since the start of the common era.
+/// The same day always maps to the same image, as long as the files do not change
+fn daily_index(day: i32, len: usize) -> usize {
+    StdRng::seed_from_u64(day as u64).gen_range(0..len)
+}
+
+/// Seeded deterministically when `seed` is set, so that the same images are picked in the same
+/// order on each run
+fn new_rng(seed: Option<u64>) -> StdRng {
+    seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64)
+}
+
+/// The weight of each file in `path`, `None` when they all have the same one
+fn file_weights(weights: &Weights, path: &Path, files: &[PathBuf]) -> Option<Vec<f64>> {
+    if weights.is_empty() {
+        return None;
+    }
+    Some(
+        files
+            .iter()
+            .map(|file| weights.weight(file.strip_prefix(path).unwrap_or(file)))
+            .collect(),
+    )
 }
 
 impl ImagePickerSorting {
@@ -133,13 +298,48 @@ impl ImagePickerSorting {
     }
 }
 
+/// The next image, picked in advance so that it can be decoded before it is shown
+struct Prefetched {
+    path: PathBuf,
+    index: usize,
+    /// The files it has been picked from, a different list means that it might be stale
+    files: Arc<Vec<PathBuf>>,
+}
+
+/// Path reported for images received in memory via IPC, which are not stored on disk
+pub const IN_MEMORY_IMAGE_PATH: &str = "-";
+
 pub struct ImagePicker {
     current_img: PathBuf,
     pub image_changed_instant: Instant,
     action: Option<ImagePickerAction>,
     sorting: ImagePickerSorting,
     filelist_cache: Rc<RefCell<FilelistCache>>,
+    /// See [`Recursive::max_depth`](crate::wallpaper_info::Recursive::max_depth)
+    max_depth: usize,
     reload: bool,
+    /// Image explicitly requested via IPC, it will be shown next regardless of the sorting
+    requested_img: Option<PathBuf>,
+    /// True when the current image has been requested via IPC
+    showing_requested_img: bool,
+    /// Incremented each time a different image is requested
+    generation: u32,
+    prefetched: Option<Prefetched>,
+    /// See [`WallpaperInfo::selector`]
+    #[cfg(feature = "script")]
+    selector: Option<PathBuf>,
+    /// Used by the `random` and `shuffle` sortings
+    rng: StdRng,
+    /// See [`WallpaperInfo::seed`]
+    seed: Option<u64>,
+    /// Drawn once, so that a reload doesn't move the start of the rotation.
+    /// See [`WallpaperInfo::random_start`]
+    random_start: Option<u64>,
+    /// See [`WallpaperInfo::weights`]
+    weights: Weights,
+    /// Orientation of the display when `orientation_preference` is set, see
+    /// [`WallpaperInfo::orientation_preference`]
+    orientation: Option<Orientation>,
 }
 
 impl ImagePicker {
@@ -155,16 +355,68 @@ impl ImagePicker {
                 }
                 Sorting::Ascending => ImagePickerSorting::Ascending(usize::MAX),
                 Sorting::Descending => ImagePickerSorting::Descending(usize::MAX),
+                Sorting::Shuffle => ImagePickerSorting::Shuffle(Shuffle::default()),
+                Sorting::Daily => ImagePickerSorting::Daily,
             },
             filelist_cache,
+            max_depth: wallpaper_info.recursive.max_depth(),
             reload: false,
+            requested_img: None,
+            showing_requested_img: false,
+            generation: 0,
+            prefetched: None,
+            #[cfg(feature = "script")]
+            selector: wallpaper_info.selector.clone(),
+            rng: new_rng(wallpaper_info.seed),
+            seed: wallpaper_info.seed,
+            // Not taken from `rng`, which would change the sequence of the other sortings
+            random_start: wallpaper_info
+                .random_start
+                .then(|| new_rng(wallpaper_info.seed).gen()),
+            weights: wallpaper_info.weights.clone(),
+            orientation: None,
+        }
+    }
+
+    /// The images that can be picked in the directory `path`
+    fn files(&self, path: &Path) -> Arc<Vec<PathBuf>> {
+        match self.orientation {
+            Some(orientation) => {
+                self.filelist_cache
+                    .borrow_mut()
+                    .get_oriented(path, self.max_depth, orientation)
+            }
+            None => self.filelist_cache.borrow().get(path, self.max_depth),
         }
     }
 
     /// Get the next image based on the sorting method
-    fn get_image_path(&mut self, files: &[PathBuf]) -> (usize, PathBuf) {
+    fn get_image_path(&mut self, path: &Path, files: &[PathBuf]) -> (usize, PathBuf) {
+        #[cfg(feature = "script")]
+        if let Some(picked) = self.pick_with_selector(files) {
+            return picked;
+        }
+
+        // Nothing has been shown yet, the rotation goes on in order from there
+        if let (
+            Some(random_start),
+            ImagePickerSorting::Ascending(_) | ImagePickerSorting::Descending(_),
+        ) = (self.random_start, &self.sorting)
+        {
+            if self.current_img.as_os_str().is_empty() {
+                let index = (random_start % files.len() as u64) as usize;
+                return (index, files[index].to_path_buf());
+            }
+        }
+
         match (&self.action, &mut self.sorting) {
             (None, _) if self.current_img.exists() => unreachable!(),
+            // The files are sorted by path, so the index is stable across restarts. Use the
+            // local date, so that the image changes at the local midnight
+            (_, ImagePickerSorting::Daily) => {
+                let index = daily_index(Local::now().date_naive().num_days_from_ce(), files.len());
+                (index, files[index].to_path_buf())
+            }
             (None | Some(ImagePickerAction::Next), ImagePickerSorting::Random(queue)) => {
                 // Use the next images in the queue, if any
                 while let Some((next, index)) = queue.next() {
@@ -177,12 +429,22 @@ impl ImagePicker {
                     return (0, files[0].to_path_buf());
                 }
 
+                // Use the uniform distribution when every weight is 0
+                let file_weights = file_weights(&self.weights, path, files);
+                let weighted = file_weights
+                    .as_ref()
+                    .and_then(|file_weights| WeightedIndex::new(file_weights).ok());
+                let pick = |rng: &mut StdRng| match &weighted {
+                    Some(weighted) => weighted.sample(rng),
+                    None => rng.gen_range(0..files.len()),
+                };
+
                 // Otherwise pick a new random image that has not been drawn before
                 // Try 5 times, then get a random image. We do this because it might happen
                 // that the queue is bigger than the amount of available wallpapers
                 let mut tries = 5;
                 loop {
-                    let index = rand::random::<usize>() % files.len();
+                    let index = pick(&mut self.rng);
                     // search for an image that has not been drawn yet
                     // fail after 5 tries
                     if !queue.contains(&files[index]) {
@@ -193,8 +455,20 @@ impl ImagePicker {
                     // We still need a new image, get the first one that is different than
                     // the current one. We also know that there is more than one image
                     if tries == 0 {
+                        // The weights might leave only the current image, pick any other one
+                        // in that case
+                        if let Some(file_weights) = &file_weights {
+                            let others: Vec<usize> = (0..files.len())
+                                .filter(|&index| files[index] != self.current_img)
+                                .collect();
+                            if let Ok(&index) =
+                                others.choose_weighted(&mut self.rng, |&index| file_weights[index])
+                            {
+                                break (index, files[index].to_path_buf());
+                            }
+                        }
                         break loop {
-                            let index = rand::random::<usize>() % files.len();
+                            let index = self.rng.gen_range(0..files.len());
                             if files[index] != self.current_img {
                                 break (index, files[index].to_path_buf());
                             }
@@ -204,6 +478,12 @@ impl ImagePicker {
                     tries -= 1;
                 }
             }
+            (None | Some(ImagePickerAction::Next), ImagePickerSorting::Shuffle(shuffle)) => {
+                shuffle.next(files, &self.current_img, &mut self.rng)
+            }
+            (Some(ImagePickerAction::Previous), ImagePickerSorting::Shuffle(shuffle)) => {
+                shuffle.previous(files, &self.current_img, &mut self.rng)
+            }
             (Some(ImagePickerAction::Previous), ImagePickerSorting::Random(queue)) => {
                 while let Some((prev, index)) = queue.previous() {
                     if prev.exists() {
@@ -215,26 +495,6 @@ impl ImagePicker {
                 queue.set_current_to(&self.current_img.to_path_buf());
                 (usize::MAX, self.current_image())
             }
-            // The current image is still in the same place
-            (Some(ImagePickerAction::Next), ImagePickerSorting::Descending(current_index))
-            | (Some(ImagePickerAction::Previous), ImagePickerSorting::Ascending(current_index))
-                if files.get(*current_index) == Some(&self.current_img) =>
-            {
-                // Start from the end
-                files
-                    .get(*current_index - 1)
-                    .map(|p| (*current_index - 1, p.to_path_buf()))
-                    .unwrap_or_else(|| {
-                        (
-                            files.len(),
-                            files
-                                .last()
-                                .expect("files vec to not be empty")
-                                .to_path_buf(),
-                        )
-                    })
-            }
-            // The image index is different
             (
                 None | Some(ImagePickerAction::Next),
                 ImagePickerSorting::Descending(current_index),
@@ -242,63 +502,91 @@ impl ImagePicker {
             | (
                 None | Some(ImagePickerAction::Previous),
                 ImagePickerSorting::Ascending(current_index),
-            ) => match files.binary_search(&self.current_img) {
-                Ok(new_index) => files
-                    .get(new_index - 1)
-                    .map(|p| (new_index - 1, p.to_path_buf()))
-                    .unwrap_or_else(|| (files.len(), files.last().unwrap().to_path_buf())),
-                Err(_err) => files
-                    .get(*current_index - 1)
-                    .map(|p| (*current_index - 1, p.to_path_buf()))
-                    .unwrap_or_else(|| (files.len(), files.last().unwrap().to_path_buf())),
-            },
-            // The current image is still in the same place
-            (Some(ImagePickerAction::Previous), ImagePickerSorting::Descending(current_index))
-            | (Some(ImagePickerAction::Next), ImagePickerSorting::Ascending(current_index))
-                if files.get(*current_index) == Some(&self.current_img) =>
-            {
-                // Start from the end
-                files
-                    .get(*current_index + 1)
-                    .map(|p| (*current_index + 1, p.to_path_buf()))
-                    .unwrap_or_else(|| (0, files.first().unwrap().to_path_buf()))
+            ) => {
+                let position = sorted_position(files, &self.current_img, *current_index);
+                let index = preceding_index(position, files.len());
+                (index, files[index].to_path_buf())
             }
-            // The image index is different
             (Some(ImagePickerAction::Previous), ImagePickerSorting::Descending(current_index))
             | (Some(ImagePickerAction::Next), ImagePickerSorting::Ascending(current_index)) => {
-                match files.binary_search(&self.current_img) {
-                    Ok(new_index) => files
-                        .get(new_index + 1)
-                        .map(|p| (new_index + 1, p.to_path_buf()))
-                        .unwrap_or_else(|| (0, files.first().unwrap().to_path_buf())),
-                    Err(_err) => files
-                        .get(*current_index + 1)
-                        .map(|p| (*current_index + 1, p.to_path_buf()))
-                        .unwrap_or_else(|| (0, files.first().unwrap().to_path_buf())),
-                }
+                let position = sorted_position(files, &self.current_img, *current_index);
+                let index = following_index(position, files.len());
+                (index, files[index].to_path_buf())
             }
         }
     }
 
+    /// Let the selector choose the next image, `None` when it is not set or it fails and the
+    /// sorting has to be used instead. The sorting stays where it was, apart from `ascending`
+    /// and `descending` that continue from the image chosen
+    #[cfg(feature = "script")]
+    fn pick_with_selector(&self, files: &[PathBuf]) -> Option<(usize, PathBuf)> {
+        if !matches!(self.action, Some(ImagePickerAction::Next)) {
+            return None;
+        }
+        let selector = self.selector.as_deref()?;
+        let file_index = crate::selector::select(selector, files, &self.current_img)
+            .map_err(|err| warn!("{err:?}, using the sorting instead"))
+            .ok()?;
+        let index = match &self.sorting {
+            ImagePickerSorting::Ascending(_) | ImagePickerSorting::Descending(_) => file_index,
+            // Not a position in the queue, so that the image is pushed to the history
+            ImagePickerSorting::Random(_) => usize::MAX,
+            ImagePickerSorting::Shuffle(shuffle) => shuffle.current.unwrap_or_default(),
+            ImagePickerSorting::Daily => 0,
+        };
+        Some((index, files[file_index].to_path_buf()))
+    }
+
     pub fn get_image_from_path(&mut self, path: &Path) -> Option<(PathBuf, usize)> {
+        if let Some(requested_img) = &self.requested_img {
+            if requested_img == &self.current_img {
+                self.requested_img = None;
+                return None;
+            }
+            let requested_img = requested_img.clone();
+            self.discard_prefetch();
+            return Some((requested_img, usize::MAX));
+        }
+
+        // Load the current image again, e.g. after the GPU lost its texture
+        if self.reload
+            && !self.current_img.as_os_str().is_empty()
+            && self.current_img != Path::new(IN_MEMORY_IMAGE_PATH)
+        {
+            return Some((self.current_img.clone(), 0));
+        }
+
         if path.is_dir() {
             self.action.as_ref()?;
 
-            let files = self.filelist_cache.borrow().get(path);
+            let files = self.files(path);
+
+            if let Some(prefetched) = self.prefetched.take() {
+                // The sorting has already moved to the prefetched image
+                if matches!(self.action, Some(ImagePickerAction::Next))
+                    && Arc::ptr_eq(&prefetched.files, &files)
+                {
+                    return (prefetched.path != self.current_img)
+                        .then_some((prefetched.path, prefetched.index));
+                }
+                self.prefetched = Some(prefetched);
+                self.discard_prefetch();
+            }
 
             // There are no images, forcefully break out of the loop
             if files.is_empty() {
                 warn!("Directory {path:?} does not contain any valid image files.");
                 None
             } else {
-                let (index, img_path) = self.get_image_path(&files);
+                let (index, img_path) = self.get_image_path(path, &files);
                 if img_path == self.current_img {
                     None
                 } else {
                     Some((img_path, index))
                 }
             }
-        } else if path == self.current_img && !self.reload {
+        } else if (path == self.current_img || self.showing_requested_img) && !self.reload {
             None
         } else {
             // path is not a directory and it's not the current image
@@ -306,7 +594,159 @@ impl ImagePicker {
         }
     }
 
+    /// Pick up to `count` different images for the mosaic mode, moving the sorting past all of
+    /// them. Return `None` if there is no need to change the images shown
+    pub fn get_images_from_path(
+        &mut self,
+        path: &Path,
+        count: usize,
+    ) -> Option<Vec<(PathBuf, usize)>> {
+        if !path.is_dir() {
+            return None;
+        }
+        let previous = matches!(self.action.as_ref()?, ImagePickerAction::Previous);
+        // Single images are not shown in the mosaic mode
+        self.requested_img = None;
+        self.discard_prefetch();
+
+        let files = self.files(path);
+        if files.is_empty() {
+            warn!("Directory {path:?} does not contain any valid image files.");
+            self.action = None;
+            return None;
+        }
+
+        let count = count.min(files.len());
+        let mut images: Vec<(PathBuf, usize)> = Vec::with_capacity(count);
+        // The random sorting might pick an image twice, do not try forever
+        let mut tries = count * 5;
+        while images.len() < count && tries > 0 {
+            tries -= 1;
+            self.action = Some(if previous {
+                ImagePickerAction::Previous
+            } else {
+                ImagePickerAction::Next
+            });
+            let (index, img_path) = self.get_image_path(path, &files);
+            self.update_current_image(img_path.clone(), index);
+            if !images.iter().any(|(path, _)| path == &img_path) {
+                images.push((img_path, index));
+            }
+        }
+        Some(images)
+    }
+
+    /// Pick the image that will be shown by the next call to [`ImagePicker::next_image`], without
+    /// showing it yet. Return `None` if it cannot be known in advance
+    pub fn pick_next(&mut self, path: &Path) -> Option<PathBuf> {
+        if self.requested_img.is_some() || !path.is_dir() {
+            return None;
+        }
+
+        let files = self.files(path);
+        if self
+            .prefetched
+            .as_ref()
+            .is_some_and(|prefetched| !Arc::ptr_eq(&prefetched.files, &files))
+        {
+            self.discard_prefetch();
+        }
+        if self.prefetched.is_none() {
+            if files.is_empty() {
+                return None;
+            }
+            let action = self.action.replace(ImagePickerAction::Next);
+            let (index, img_path) = self.get_image_path(path, &files);
+            self.action = action;
+            self.prefetched = Some(Prefetched {
+                path: img_path,
+                index,
+                files,
+            });
+        }
+        self.prefetched
+            .as_ref()
+            .map(|prefetched| prefetched.path.clone())
+    }
+
+    /// Pick the next image like [`ImagePicker::pick_next`], so that it can be decoded in advance.
+    /// Return `None` if there is nothing to prefetch
+    pub fn prefetch_next(&mut self, path: &Path) -> Option<PathBuf> {
+        if self.prefetched.is_some() {
+            return None;
+        }
+        // Keep the prefetched image to not pick again, even if there is nothing to decode
+        self.pick_next(path)
+            .filter(|img_path| img_path != &self.current_img)
+    }
+
+    /// Forget the prefetched image, because the state it has been picked from has changed
+    pub fn discard_prefetch(&mut self) {
+        if self.prefetched.take().is_some() {
+            // Picking the image moved through the queue, go back to the current image
+            if let ImagePickerSorting::Random(queue) = &mut self.sorting {
+                queue.set_current_to(&self.current_img);
+            }
+        }
+    }
+
+    /// Move past an image that could not be loaded, so that the next try picks another one in
+    /// the same direction instead of the same image
+    pub fn skip_image(&mut self, img_path: PathBuf, index: usize) {
+        let image_changed_instant = self.image_changed_instant;
+        let action = self.action.as_ref().map(|action| match action {
+            ImagePickerAction::Next => ImagePickerAction::Next,
+            ImagePickerAction::Previous => ImagePickerAction::Previous,
+        });
+        self.update_current_image(img_path, index);
+        self.image_changed_instant = image_changed_instant;
+        self.action = action;
+    }
+
+    /// Look for a file with the same name as the current image in the new `path`, and make it
+    /// the current image without changing the wallpaper. Return false if there is none
+    pub fn keep_current_image(&mut self, path: &Path) -> bool {
+        if self.current_img == Path::new(IN_MEMORY_IMAGE_PATH) {
+            return false;
+        }
+        let Some(name) = self.current_img.file_name() else {
+            return false;
+        };
+        let found = if path.is_dir() {
+            let files = self.filelist_cache.borrow().get(path, self.max_depth);
+            files
+                .iter()
+                .position(|file| file.file_name() == Some(name))
+                .map(|index| (files[index].clone(), index))
+        } else {
+            (path.file_name() == Some(name)).then(|| (path.to_path_buf(), 0))
+        };
+        let Some((img_path, index)) = found else {
+            return false;
+        };
+        // The image has already been shown for a while
+        let image_changed_instant = self.image_changed_instant;
+        self.update_current_image(img_path, index);
+        self.image_changed_instant = image_changed_instant;
+        true
+    }
+
     pub fn update_current_image(&mut self, img_path: PathBuf, index: usize) {
+        self.image_changed_instant = Instant::now();
+        self.showing_requested_img = self.requested_img.as_ref() == Some(&img_path);
+        if self.showing_requested_img {
+            self.requested_img = None;
+            self.action = None;
+            // Keep the image in the history, but do not change the position in the sorting
+            if let ImagePickerSorting::Random(queue) = &mut self.sorting {
+                if queue.has_reached_end() || !queue.is_full() {
+                    queue.push(img_path.clone());
+                }
+            }
+            self.current_img = img_path;
+            return;
+        }
+
         match (self.action.take(), &mut self.sorting) {
             (Some(ImagePickerAction::Next), ImagePickerSorting::Random(queue)) => {
                 if queue.has_reached_end() || queue.buffer.get(index).is_none() {
@@ -314,6 +754,8 @@ impl ImagePicker {
                 }
             }
             (None | Some(ImagePickerAction::Previous), ImagePickerSorting::Random { .. }) => {}
+            (_, ImagePickerSorting::Daily) => {}
+            (_, ImagePickerSorting::Shuffle(shuffle)) => shuffle.current = Some(index),
             (
                 _,
                 ImagePickerSorting::Ascending(current_index)
@@ -324,21 +766,73 @@ impl ImagePicker {
         self.current_img = img_path;
     }
 
+    /// Show `img_path` as the next image, even if it's not part of the configured path
+    pub fn set_image(&mut self, img_path: PathBuf) {
+        self.requested_img = Some(img_path);
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// Show the image received via IPC as the next image, the surface holds its data
+    pub fn set_in_memory_image(&mut self) {
+        // Every image received is a new one, even if the current image was also in memory
+        if self.current_img == Path::new(IN_MEMORY_IMAGE_PATH) {
+            self.current_img = PathBuf::new();
+        }
+        self.requested_img = Some(PathBuf::from(IN_MEMORY_IMAGE_PATH));
+        self.generation = self.generation.wrapping_add(1);
+    }
+
     /// Update wallpaper by going down 1 index through the cached image paths
     /// Expiry timer reset even if already at the first cached image
     pub fn previous_image(&mut self) {
         self.action = Some(ImagePickerAction::Previous);
+        self.generation = self.generation.wrapping_add(1);
     }
 
     /// Update wallpaper by going up 1 index through the cached image paths
     pub fn next_image(&mut self) {
         self.action = Some(ImagePickerAction::Next);
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// Changes each time a different image is requested, so that an image that was being loaded
+    /// can be discarded
+    #[inline]
+    pub fn generation(&self) -> u32 {
+        self.generation
     }
 
     pub fn current_image(&self) -> PathBuf {
         self.current_img.clone()
     }
 
+    /// True if the directory `path` contains images, or if `path` is a file that exists
+    pub fn has_images(&self, path: &Path) -> bool {
+        if path.is_dir() {
+            !self
+                .filelist_cache
+                .borrow()
+                .get(path, self.max_depth)
+                .is_empty()
+        } else {
+            path.exists()
+        }
+    }
+
+    /// Whether the current image has been removed from the directory `path`. Images that
+    /// have been requested explicitly are not part of it, they are never considered removed
+    pub fn current_image_removed(&self, path: &Path) -> bool {
+        if !path.is_dir() || self.showing_requested_img || self.current_img.as_os_str().is_empty() {
+            return false;
+        }
+        let files = self.filelist_cache.borrow().get(path, self.max_depth);
+        // There is no other image to show
+        if files.is_empty() {
+            return false;
+        }
+        !files.contains(&self.current_img)
+    }
+
     /// Return true if the path changed
     pub fn update_sorting(
         &mut self,
@@ -348,15 +842,35 @@ impl ImagePicker {
     ) {
         match (&mut self.sorting, new_sorting) {
             (
-                ImagePickerSorting::Random { .. } | ImagePickerSorting::Descending(_),
+                ImagePickerSorting::Random { .. }
+                | ImagePickerSorting::Descending(_)
+                | ImagePickerSorting::Shuffle(_)
+                | ImagePickerSorting::Daily,
                 Sorting::Ascending,
             ) => self.sorting = ImagePickerSorting::Ascending(usize::MAX),
             (
-                ImagePickerSorting::Random { .. } | ImagePickerSorting::Ascending(_),
+                ImagePickerSorting::Random { .. }
+                | ImagePickerSorting::Ascending(_)
+                | ImagePickerSorting::Shuffle(_)
+                | ImagePickerSorting::Daily,
                 Sorting::Descending,
             ) => self.sorting = ImagePickerSorting::Descending(usize::MAX),
             (
-                ImagePickerSorting::Descending(_) | ImagePickerSorting::Ascending(_),
+                ImagePickerSorting::Random { .. }
+                | ImagePickerSorting::Ascending(_)
+                | ImagePickerSorting::Descending(_)
+                | ImagePickerSorting::Daily,
+                Sorting::Shuffle,
+            ) => self.sorting = ImagePickerSorting::Shuffle(Shuffle::default()),
+            // The path has changed, start a new permutation
+            (ImagePickerSorting::Shuffle(_), Sorting::Shuffle) if path_changed => {
+                self.sorting = ImagePickerSorting::Shuffle(Shuffle::default());
+            }
+            (
+                ImagePickerSorting::Descending(_)
+                | ImagePickerSorting::Ascending(_)
+                | ImagePickerSorting::Shuffle(_)
+                | ImagePickerSorting::Daily,
                 Sorting::Random,
             ) if path_changed => {
                 // If the path was changed, use a new random sorting
@@ -368,7 +882,10 @@ impl ImagePicker {
                 self.sorting = ImagePickerSorting::new_random(drawn_images_queue_size);
             }
             (
-                ImagePickerSorting::Descending(_) | ImagePickerSorting::Ascending(_),
+                ImagePickerSorting::Descending(_)
+                | ImagePickerSorting::Ascending(_)
+                | ImagePickerSorting::Shuffle(_)
+                | ImagePickerSorting::Daily,
                 Sorting::Random,
             ) => {
                 // if the path was not changed, use the current image as the first image of
@@ -377,23 +894,99 @@ impl ImagePicker {
                 queue.push(self.current_image());
                 self.sorting = ImagePickerSorting::Random(queue);
             }
+            (
+                ImagePickerSorting::Random { .. }
+                | ImagePickerSorting::Ascending(_)
+                | ImagePickerSorting::Descending(_)
+                | ImagePickerSorting::Shuffle(_),
+                Sorting::Daily,
+            ) => self.sorting = ImagePickerSorting::Daily,
             // No need to update the sorting if it's the same
             (_, _) => {}
         }
     }
 
+    #[cfg(feature = "script")]
+    #[inline]
+    pub fn update_selector(&mut self, selector: Option<PathBuf>) {
+        self.selector = selector;
+    }
+
+    /// Start the sequence of the new seed from the beginning
+    pub fn update_seed(&mut self, seed: Option<u64>) {
+        if self.seed != seed {
+            self.seed = seed;
+            self.rng = new_rng(seed);
+        }
+    }
+
+    pub fn update_weights(&mut self, weights: Weights) {
+        self.weights = weights;
+    }
+
+    /// The prefetched image is discarded by [`ImagePicker::pick_next`] when the images change
+    pub fn update_orientation(&mut self, orientation: Option<Orientation>) {
+        self.orientation = orientation;
+    }
+
+    pub fn update_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
     pub fn update_queue_size(&mut self, drawn_images_queue_size: usize) {
         match &mut self.sorting {
             ImagePickerSorting::Random(queue) => {
                 queue.resize(drawn_images_queue_size);
             }
-            ImagePickerSorting::Ascending(_) | ImagePickerSorting::Descending(_) => {}
+            ImagePickerSorting::Ascending(_)
+            | ImagePickerSorting::Descending(_)
+            | ImagePickerSorting::Shuffle(_)
+            | ImagePickerSorting::Daily => {}
+        }
+    }
+
+    /// Images drawn recently, oldest first. Only available for the `random` sorting, which uses
+    /// them to avoid repeating the same images
+    pub fn history(&self) -> Option<impl Iterator<Item = &Path>> {
+        match &self.sorting {
+            ImagePickerSorting::Random(queue) => Some(queue.buffer.iter().map(PathBuf::as_path)),
+            ImagePickerSorting::Ascending(_)
+            | ImagePickerSorting::Descending(_)
+            | ImagePickerSorting::Shuffle(_)
+            | ImagePickerSorting::Daily => None,
+        }
+    }
+
+    /// Forget the images drawn recently but the current one, so that the next ones can be any
+    /// image. Return how many have been forgotten, always `0` for the sortings other than
+    /// `random`
+    pub fn clear_history(&mut self) -> usize {
+        // It has been picked avoiding the images that are forgotten now
+        self.discard_prefetch();
+        match &mut self.sorting {
+            ImagePickerSorting::Random(queue) => queue.clear(),
+            ImagePickerSorting::Ascending(_)
+            | ImagePickerSorting::Descending(_)
+            | ImagePickerSorting::Shuffle(_)
+            | ImagePickerSorting::Daily => 0,
+        }
+    }
+
+    /// Restore the images drawn in a previous session, see [`ImagePicker::history`]
+    pub fn restore_history(&mut self, history: impl IntoIterator<Item = PathBuf>) {
+        // The images avoided would depend on the previous runs
+        if self.seed.is_some() {
+            return;
+        }
+        if let ImagePickerSorting::Random(queue) = &mut self.sorting {
+            queue.restore(history);
         }
     }
 
     #[inline]
     pub fn reload(&mut self) {
         self.reload = true;
+        self.generation = self.generation.wrapping_add(1);
     }
 
     #[inline]
@@ -412,6 +1005,116 @@ mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
 
+    #[test]
+    fn test_daily_index() {
+        // The same day always shows the same image
+        assert_eq!(daily_index(739_000, 20), daily_index(739_000, 20));
+        assert!((0..1000).all(|day| daily_index(day, 7) < 7));
+        // Not every day shows the same image
+        assert!((1..30).any(|day| daily_index(day, 20) != daily_index(0, 20)));
+    }
+
+    #[test]
+    fn test_keep_current_image() {
+        let mut image_picker = ImagePicker::new(
+            &WallpaperInfo::default(),
+            Rc::new(RefCell::new(FilelistCache::default())),
+        );
+        image_picker.update_current_image(PathBuf::from("/old/a.png"), 0);
+        let image_changed_instant = image_picker.image_changed_instant;
+
+        assert!(!image_picker.keep_current_image(Path::new("/new/b.png")));
+        assert!(image_picker.keep_current_image(Path::new("/new/a.png")));
+        assert_eq!(image_picker.current_image(), Path::new("/new/a.png"));
+        // The timer goes on from when the image has been shown
+        assert_eq!(image_picker.image_changed_instant, image_changed_instant);
+    }
+
+    #[test]
+    fn test_weights() {
+        let files: Vec<PathBuf> = ["a", "b"]
+            .iter()
+            .flat_map(|dir| {
+                (0..5).map(move |index| PathBuf::from(format!("/dir/{dir}/{index}.png")))
+            })
+            .collect();
+        let info = WallpaperInfo {
+            weights: toml::from_str(r#""a/*" = 0.0"#).unwrap(),
+            ..WallpaperInfo::default()
+        };
+        let mut image_picker =
+            ImagePicker::new(&info, Rc::new(RefCell::new(FilelistCache::default())));
+        // Even once the queue is full, the images with no weight are never picked
+        for _ in 0..50 {
+            image_picker.action = Some(ImagePickerAction::Next);
+            let (index, path) = image_picker.get_image_path(Path::new("/dir"), &files);
+            assert!(path.starts_with("/dir/b"));
+            image_picker.update_current_image(path, index);
+        }
+    }
+
+    #[test]
+    fn test_seed() {
+        let files: Vec<PathBuf> = (0..10)
+            .map(|index| PathBuf::from(format!("/{index}.png")))
+            .collect();
+        let sequence = |sorting: Sorting, seed: Option<u64>| -> Vec<PathBuf> {
+            let info = WallpaperInfo {
+                sorting,
+                seed,
+                ..WallpaperInfo::default()
+            };
+            let mut image_picker =
+                ImagePicker::new(&info, Rc::new(RefCell::new(FilelistCache::default())));
+            (0..20)
+                .map(|_| {
+                    image_picker.action = Some(ImagePickerAction::Next);
+                    let (index, path) = image_picker.get_image_path(Path::new("/"), &files);
+                    image_picker.update_current_image(path.clone(), index);
+                    path
+                })
+                .collect()
+        };
+        for sorting in [Sorting::Random, Sorting::Shuffle] {
+            assert_eq!(sequence(sorting, Some(42)), sequence(sorting, Some(42)));
+            assert_ne!(sequence(sorting, Some(42)), sequence(sorting, Some(43)));
+        }
+    }
+
+    #[test]
+    fn test_random_start() {
+        let files: Vec<PathBuf> = (0..10)
+            .map(|index| PathBuf::from(format!("/{index}.png")))
+            .collect();
+        let sequence = |sorting: Sorting, random_start: bool, seed: Option<u64>| {
+            let info = WallpaperInfo {
+                sorting,
+                random_start,
+                seed,
+                ..WallpaperInfo::default()
+            };
+            let mut image_picker =
+                ImagePicker::new(&info, Rc::new(RefCell::new(FilelistCache::default())));
+            (0..3)
+                .map(|_| {
+                    image_picker.action = Some(ImagePickerAction::Next);
+                    let (index, path) = image_picker.get_image_path(Path::new("/"), &files);
+                    image_picker.update_current_image(path.clone(), index);
+                    index
+                })
+                .collect::<Vec<usize>>()
+        };
+        assert_eq!(sequence(Sorting::Ascending, false, None), [0, 1, 2]);
+        assert_eq!(sequence(Sorting::Descending, false, None), [9, 8, 7]);
+
+        // The same seed starts at the same image, then the images follow in order
+        let ascending = sequence(Sorting::Ascending, true, Some(42));
+        assert_eq!(ascending, sequence(Sorting::Ascending, true, Some(42)));
+        assert_eq!(ascending[1], (ascending[0] + 1) % 10);
+        let descending = sequence(Sorting::Descending, true, Some(42));
+        assert_eq!(descending[1], (descending[0] + 9) % 10);
+    }
+
     #[test]
     fn test_push() {
         let mut queue = Queue::with_capacity(2);
@@ -466,4 +1169,122 @@ mod tests {
         assert_eq!(Some((Path::new("mypath8"), 1)), queue.next());
         assert_eq!(None, queue.next());
     }
+
+    #[test]
+    fn test_clear() {
+        let mut queue = Queue::with_capacity(3);
+        assert_eq!(queue.clear(), 0);
+        for path in ["mypath", "mypath2", "mypath3", "mypath4"] {
+            queue.push(PathBuf::from(path));
+        }
+        assert_eq!(Some((Path::new("mypath3"), 1)), queue.previous());
+
+        // Only the current image is kept
+        assert_eq!(queue.clear(), 2);
+        assert_eq!(queue.buffer, [PathBuf::from("mypath3")]);
+        assert_eq!(Path::new("mypath3"), queue.current());
+        assert_eq!(None, queue.previous());
+        assert_eq!(None, queue.next());
+        queue.push(PathBuf::from("mypath"));
+        assert_eq!(Path::new("mypath"), queue.current());
+    }
+
+    #[test]
+    fn test_restore() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let mut queue = Queue::with_capacity(2);
+        queue.restore([
+            dir.join("main.rs"),
+            dir.join("does-not-exist.png"),
+            dir.join("config.rs"),
+            dir.join("surface.rs"),
+        ]);
+        // Missing paths are pruned and only the most recent ones are kept
+        assert_eq!(
+            queue.buffer,
+            [dir.join("config.rs"), dir.join("surface.rs")]
+        );
+        assert!(queue.has_reached_end());
+    }
+
+    fn files(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn test_sorted_position() {
+        let current = Path::new("d");
+        // A file has been added before the current one
+        let position = sorted_position(&files(&["a", "b", "d", "f"]), current, 1);
+        assert_eq!(position, Ok(2));
+        assert_eq!(following_index(position, 4), 3);
+        assert_eq!(preceding_index(position, 4), 1);
+
+        // A file has been added after the current one
+        let position = sorted_position(&files(&["b", "d", "e", "f"]), current, 1);
+        assert_eq!(position, Ok(1));
+        assert_eq!(following_index(position, 4), 2);
+
+        // The current file has been removed, continue from where it was
+        let position = sorted_position(&files(&["b", "f"]), current, 1);
+        assert_eq!(position, Err(1));
+        assert_eq!(following_index(position, 2), 1);
+        assert_eq!(preceding_index(position, 2), 0);
+
+        // Wrap around at both ends
+        assert_eq!(following_index(Ok(3), 4), 0);
+        assert_eq!(following_index(Err(4), 4), 0);
+        assert_eq!(preceding_index(Ok(0), 4), 3);
+        // Nothing has been shown yet
+        let position = sorted_position(&files(&["b", "d"]), Path::new(""), usize::MAX);
+        assert_eq!(following_index(position, 2), 0);
+    }
+
+    #[test]
+    fn test_shuffle_no_repeats() {
+        let files = files(&["a", "b", "c", "d"]);
+        let mut shuffle = Shuffle::default();
+        let mut rng = StdRng::from_entropy();
+        let mut current = PathBuf::new();
+        let mut shown = HashSet::new();
+        for _ in 0..files.len() {
+            let (index, path) = shuffle.next(&files, &current, &mut rng);
+            shuffle.current = Some(index);
+            assert!(shown.insert(path.clone()));
+            current = path;
+        }
+        assert_eq!(shown.len(), files.len());
+
+        // A new permutation starts, without repeating the last image
+        let (index, path) = shuffle.next(&files, &current, &mut rng);
+        assert_eq!(index, 0);
+        assert_ne!(path, current);
+    }
+
+    #[test]
+    fn test_shuffle_sync() {
+        let mut shuffle = Shuffle::default();
+        let mut rng = StdRng::from_entropy();
+        let (index, first) = shuffle.next(&files(&["a", "b", "c"]), Path::new(""), &mut rng);
+        shuffle.current = Some(index);
+        let (index, second) = shuffle.next(&files(&["a", "b", "c"]), &first, &mut rng);
+        shuffle.current = Some(index);
+
+        // The first image has been removed and a new one has been added
+        let mut new_files: Vec<PathBuf> = files(&["a", "b", "c", "e"]);
+        new_files.retain(|p| p != &first);
+        let (_, prev) = shuffle.previous(&new_files, &second, &mut rng);
+        // There is nothing else before the second image
+        assert_eq!(prev, second);
+
+        let mut shown = HashSet::from([second.clone()]);
+        let mut current = second;
+        for _ in 0..2 {
+            let (index, path) = shuffle.next(&new_files, &current, &mut rng);
+            shuffle.current = Some(index);
+            assert!(shown.insert(path.clone()));
+            current = path;
+        }
+        assert_eq!(shown, new_files.into_iter().collect());
+    }
 }
@@ -3,19 +3,41 @@
 
 use std::collections::HashSet;
 use std::fs;
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::io::{self, BufWriter, Read, Write};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
 
-use color_eyre::eyre::{ensure, Context};
+use color_eyre::eyre::{bail, ensure, Context};
 use color_eyre::{Result, Section};
+use image::{ImageFormat, RgbaImage};
+use smithay_client_toolkit::reexports::calloop::channel::Sender;
 use smithay_client_toolkit::reexports::client::QueueHandle;
-use wpaperd_ipc::{IpcError, IpcMessage, IpcResponse};
+use wpaperd_ipc::{
+    format_outputs, ConfigInfo, IpcError, IpcEvent, IpcMessage, IpcResponse, OutputInfo,
+};
 
+use crate::image_loader::check_image;
+use crate::image_picker::IN_MEMORY_IMAGE_PATH;
 use crate::socket::SocketSource;
 use crate::surface::Surface;
 use crate::Wpaperd;
 
+/// Upper limit for the images sent over IPC, to avoid allocating an unbounded buffer
+const MAX_IMAGE_DATA_SIZE: usize = 512 * 1024 * 1024;
+/// A client that stops sending the image data for this long is answered with an error
+const IMAGE_DATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An image sent over IPC, read and decoded by another thread. The main loop shows it and
+/// answers the client, see [`handle_received_image`]
+pub struct ReceivedImage {
+    stream: UnixStream,
+    monitor: String,
+    pin: bool,
+    image: Result<RgbaImage, IpcError>,
+}
+
 /// Create an IPC socket.
 pub fn listen_on_ipc_socket(socket_path: &Path) -> Result<SocketSource> {
     // Try to delete the socket if it exists already.
@@ -29,6 +51,65 @@ pub fn listen_on_ipc_socket(socket_path: &Path) -> Result<SocketSource> {
     Ok(socket)
 }
 
+/// Ask the running daemon for its outputs and print them, for `wpaperd --list-outputs`.
+/// Returns `false` when no daemon is listening on the socket
+pub fn list_outputs(socket_path: &Path) -> Result<bool> {
+    let mut conn = match UnixStream::connect(socket_path) {
+        Ok(conn) => conn,
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(false)
+        }
+        Err(err) => {
+            return Err(err).with_context(|| format!("unable to connect to {socket_path:?}"))
+        }
+    };
+    conn.write_all(&serde_json::to_vec(&IpcMessage::Outputs)?)?;
+    let mut buf = String::new();
+    conn.read_to_string(&mut buf)?;
+    let res: Result<IpcResponse, IpcError> =
+        serde_json::from_str(&buf).context("the daemon did not return a valid response")?;
+    match res {
+        Ok(IpcResponse::Outputs { outputs }) => print!("{}", format_outputs(&outputs)),
+        Ok(_) => bail!("the daemon did not answer with the list of outputs"),
+        Err(err) => bail!("the daemon could not list the outputs: {err:?}"),
+    }
+    Ok(true)
+}
+
+/// The clients that subscribed to the events of the daemon, they all receive the same events
+#[derive(Default)]
+pub struct EventSubscribers {
+    streams: Vec<UnixStream>,
+}
+
+impl EventSubscribers {
+    pub fn subscribe(&mut self, stream: UnixStream) -> Result<()> {
+        // A client that doesn't read its events must not block the daemon
+        stream
+            .set_nonblocking(true)
+            .context("setting the subscriber socket as non-blocking")?;
+        self.streams.push(stream);
+        Ok(())
+    }
+
+    /// Send `event` to every subscriber. The clients that closed the connection, or whose
+    /// socket is full because they don't read the events, are dropped
+    pub fn emit(&mut self, event: IpcEvent) {
+        if self.streams.is_empty() {
+            return;
+        }
+        let mut line = serde_json::to_vec(&event).expect("json encoding to work");
+        line.push(b'\n');
+        self.streams
+            .retain_mut(|stream| stream.write_all(&line).is_ok());
+    }
+}
+
 fn check_monitors(wpaperd: &Wpaperd, monitors: &Vec<String>) -> Result<(), IpcError> {
     for monitor in monitors {
         if !wpaperd
@@ -58,29 +139,133 @@ fn collect_surfaces(wpaperd: &mut Wpaperd, monitors: Vec<String>) -> Vec<&mut Su
         .collect()
 }
 
+/// Read the `size` bytes of image data following a message, `received` contains the bytes that
+/// have already been read together with the message
+fn read_image_data(stream: &mut impl Read, received: &[u8], size: usize) -> Result<Vec<u8>> {
+    ensure!(
+        size <= MAX_IMAGE_DATA_SIZE,
+        "The image received is bigger than {MAX_IMAGE_DATA_SIZE} bytes"
+    );
+    ensure!(
+        received.len() <= size,
+        "Received more data than the size of the image"
+    );
+    let mut data = vec![0; size];
+    data[..received.len()].copy_from_slice(received);
+    stream
+        .read_exact(&mut data[received.len()..])
+        .context("error while reading the image data from IPC")?;
+
+    Ok(data)
+}
+
+/// Read and decode the image following a `SetWallpaperData` message in another thread, so that
+/// a slow client doesn't block the daemon. The result is sent to `sender` in any case
+fn receive_image(
+    stream: UnixStream,
+    received: Vec<u8>,
+    size: usize,
+    monitor: String,
+    pin: bool,
+    sender: Sender<ReceivedImage>,
+) -> Result<()> {
+    thread::Builder::new()
+        .name("wpaperd-ipc-image".to_string())
+        .spawn(move || {
+            let image = stream
+                .set_read_timeout(Some(IMAGE_DATA_TIMEOUT))
+                .context("setting the timeout of the IPC socket")
+                .and_then(|_| read_image_data(&mut &stream, &received, size))
+                .map_err(|err| format!("{err:#}"))
+                .and_then(|data| image::load_from_memory(&data).map_err(|err| err.to_string()))
+                .map(|image| image.into_rgba8())
+                .map_err(|error| IpcError::InvalidImage {
+                    path: PathBuf::from(IN_MEMORY_IMAGE_PATH),
+                    error,
+                });
+            // The main loop is not running anymore otherwise
+            let _ = sender.send(ReceivedImage {
+                stream,
+                monitor,
+                pin,
+                image,
+            });
+        })
+        .context("spawning the thread reading the image")?;
+    Ok(())
+}
+
+/// Show an image read by [`receive_image`] and answer the client
+pub fn handle_received_image(
+    received: ReceivedImage,
+    qh: &QueueHandle<Wpaperd>,
+    wpaperd: &mut Wpaperd,
+) -> Result<()> {
+    let ReceivedImage {
+        stream,
+        monitor,
+        pin,
+        image,
+    } = received;
+    let resp = image.and_then(|image| match wpaperd.surface_from_name(&monitor) {
+        Some(surface) => {
+            surface.set_in_memory_image(image);
+            if pin {
+                surface.pause();
+            }
+            surface.queue_draw(qh);
+            Ok(IpcResponse::Ok)
+        }
+        None => Err(IpcError::MonitorNotFound { monitor }),
+    });
+    send_response(stream, &resp)
+}
+
+fn send_response(ustream: UnixStream, resp: &Result<IpcResponse, IpcError>) -> Result<()> {
+    let mut stream = BufWriter::new(ustream);
+    stream
+        .write_all(&serde_json::to_vec(resp).unwrap())
+        .context("unable to write response to the IPC client")
+        .suggestion("Probably the client died, try running it again")?;
+
+    Ok(())
+}
+
 /// Handle IPC socket messages.
 pub fn handle_message(
     ustream: UnixStream,
     qh: QueueHandle<Wpaperd>,
     wpaperd: &mut Wpaperd,
+    image_sender: &Sender<ReceivedImage>,
 ) -> Result<()> {
     const SIZE: usize = 4096;
     let mut buffer = [0; SIZE];
 
-    // Read new content to buffer.
-    let mut stream = BufReader::new(&ustream);
-    let n = stream
+    // Read new content to buffer. It's not buffered further, the image data following a
+    // message is read afterwards from the same stream
+    let n = (&ustream)
         .read(&mut buffer)
         .context("error while reading line from IPC")?;
     // The message is empty
     if n == 0 {
         return Ok(());
     }
-    ensure!(n != SIZE, "The message received was too big");
 
     // Read pending events on socket.
-    let message: IpcMessage = serde_json::from_slice(&buffer[..n])
-        .with_context(|| format!("error while deserializing message {:?}", &buffer[..n]))?;
+    // The message could be followed by other data, only deserialize the first value
+    let mut messages = serde_json::Deserializer::from_slice(&buffer[..n]).into_iter();
+    let message: IpcMessage = match messages.next() {
+        Some(Ok(message)) => message,
+        Some(Err(err)) if n == SIZE => {
+            return Err(err).context("The message received was too big");
+        }
+        Some(Err(err)) => {
+            return Err(err)
+                .with_context(|| format!("error while deserializing message {:?}", &buffer[..n]));
+        }
+        None => return Ok(()),
+    };
+    let data_start = messages.byte_offset();
 
     // Handle IPC events.
     let resp: Result<IpcResponse, IpcError> = match message {
@@ -104,6 +289,27 @@ pub fn handle_message(
                 .collect::<Vec<(String, PathBuf)>>(),
         }),
 
+        IpcMessage::Outputs => Ok(IpcResponse::Outputs {
+            outputs: wpaperd
+                .surfaces
+                .iter()
+                .map(|surface| {
+                    let info = surface.display_info();
+                    let (width, height) = info.pixel_size().unwrap_or_default();
+                    OutputInfo {
+                        name: info.name.clone(),
+                        description: info.description.clone(),
+                        width,
+                        height,
+                        scale: info
+                            .fractional_scale
+                            .map_or(info.scale as f32, |scale| scale as f32 / 120.0),
+                        wallpaper: surface.image_picker.current_image(),
+                    }
+                })
+                .collect(),
+        }),
+
         IpcMessage::PreviousWallpaper { monitors } => {
             check_monitors(wpaperd, &monitors).map(|_| {
                 for surface in collect_surfaces(wpaperd, monitors) {
@@ -133,6 +339,39 @@ pub fn handle_message(
             IpcResponse::Ok
         }),
 
+        IpcMessage::ResetHistory { monitors } => {
+            check_monitors(wpaperd, &monitors).map(|_| IpcResponse::HistoryReset {
+                entries: collect_surfaces(wpaperd, monitors)
+                    .into_iter()
+                    .map(|surface| (surface.name(), surface.reset_history()))
+                    .collect(),
+            })
+        }
+
+        IpcMessage::InitialTransition { monitors, enabled } => {
+            check_monitors(wpaperd, &monitors).map(|_| {
+                let entries: Vec<(String, bool)> = collect_surfaces(wpaperd, monitors)
+                    .into_iter()
+                    .map(|surface| {
+                        if let Some(enabled) = enabled {
+                            surface.set_initial_transition(enabled);
+                        }
+                        (surface.name(), surface.wallpaper_info().initial_transition)
+                    })
+                    .collect();
+                // Kept for when the displays are connected again
+                if let Some(enabled) = enabled {
+                    for (monitor, _) in &entries {
+                        wpaperd
+                            .config
+                            .initial_transitions
+                            .insert(monitor.clone(), enabled);
+                    }
+                }
+                IpcResponse::InitialTransition { entries }
+            })
+        }
+
         IpcMessage::PauseWallpaper { monitors } => check_monitors(wpaperd, &monitors).map(|_| {
             for surface in collect_surfaces(wpaperd, monitors) {
                 surface.pause();
@@ -146,13 +385,169 @@ pub fn handle_message(
             }
             IpcResponse::Ok
         }),
+
+        IpcMessage::SetWallpaper { monitor, path, pin } => match check_image(&path) {
+            Ok(_) => match wpaperd.surface_from_name(&monitor) {
+                Some(surface) => {
+                    surface.image_picker.set_image(path);
+                    if pin {
+                        surface.pause();
+                    }
+                    surface.queue_draw(&qh);
+                    Ok(IpcResponse::Ok)
+                }
+                None => Err(IpcError::MonitorNotFound { monitor }),
+            },
+            Err(err) => Err(IpcError::InvalidImage {
+                path,
+                error: err.to_string(),
+            }),
+        },
+
+        IpcMessage::Rescan { target } => {
+            // A display is rescanned through the directory it shows
+            let path = target.as_ref().map(|target| {
+                wpaperd
+                    .surfaces
+                    .iter()
+                    .find(|surface| &surface.name() == target)
+                    .map_or_else(
+                        || PathBuf::from(target),
+                        |surface| surface.path().to_path_buf(),
+                    )
+            });
+            let entries = wpaperd.filelist_cache.borrow_mut().rescan(path.as_deref());
+            if let (true, Some(target)) = (entries.is_empty(), target) {
+                Err(IpcError::NothingToRescan { target })
+            } else {
+                // Same as when the changes are reported
+                for surface in &mut wpaperd.surfaces {
+                    surface.check_current_image(&qh);
+                    surface.check_startup_hold(&qh);
+                }
+                Ok(IpcResponse::Rescanned { entries })
+            }
+        }
+
+        IpcMessage::NextGroup { group } => {
+            if wpaperd.next_group_wallpaper(&group, &qh) {
+                Ok(IpcResponse::Ok)
+            } else {
+                Err(IpcError::GroupNotFound { group })
+            }
+        }
+
+        // The copy kept by the surface, it is the one being drawn
+        IpcMessage::Config { monitor } => {
+            match wpaperd
+                .surfaces
+                .iter()
+                .find(|surface| surface.name() == monitor)
+            {
+                Some(surface) => Ok(IpcResponse::Config(ConfigInfo {
+                    section: wpaperd
+                        .config
+                        .section_name_for_output(&surface.display_info()),
+                    config: serde_json::to_value(surface.wallpaper_info())
+                        .context("serializing the configuration")?,
+                })),
+                None => Err(IpcError::MonitorNotFound { monitor }),
+            }
+        }
+
+        IpcMessage::SwapWallpapers { first, second } => {
+            let image = |wpaperd: &Wpaperd, monitor: &str| match wpaperd
+                .surfaces
+                .iter()
+                .find(|surface| surface.name() == monitor)
+            {
+                Some(surface) => surface
+                    .swappable_image()
+                    .map_err(|reason| IpcError::CannotSwap {
+                        monitor: monitor.to_string(),
+                        reason,
+                    }),
+                None => Err(IpcError::MonitorNotFound {
+                    monitor: monitor.to_string(),
+                }),
+            };
+            image(wpaperd, &first)
+                .and_then(|first_image| Ok((first_image, image(wpaperd, &second)?)))
+                .map(|(first_image, second_image)| {
+                    if first != second {
+                        for (monitor, path) in [(&first, second_image), (&second, first_image)] {
+                            let surface = wpaperd
+                                .surface_from_name(monitor)
+                                .expect("the display to have been found");
+                            surface.image_picker.set_image(path);
+                            surface.queue_draw(&qh);
+                        }
+                    }
+                    IpcResponse::Ok
+                })
+        }
+
+        IpcMessage::Capture { monitor, path } => match wpaperd.surface_from_name(&monitor) {
+            Some(surface) => surface
+                .capture()
+                .and_then(|image| {
+                    image
+                        .save_with_format(&path, ImageFormat::Png)
+                        .with_context(|| format!("saving the capture to {path:?}"))
+                })
+                .map(|_| IpcResponse::Ok)
+                .map_err(|err| IpcError::CaptureFailed {
+                    monitor,
+                    error: format!("{err:?}"),
+                }),
+            None => Err(IpcError::MonitorNotFound { monitor }),
+        },
+
+        IpcMessage::Debug { monitor } => match wpaperd.surface_from_name(&monitor) {
+            Some(surface) => Ok(IpcResponse::Debug(surface.debug_info())),
+            None => Err(IpcError::MonitorNotFound { monitor }),
+        },
+
+        // The connection stays open, the response is the stream of events
+        IpcMessage::Subscribe => return wpaperd.events.borrow_mut().subscribe(ustream),
+
+        // Always read the data, so that the client can read the response
+        IpcMessage::SetWallpaperData { monitor, size, pin } => {
+            let received = buffer[data_start..n].to_vec();
+            return receive_image(ustream, received, size, monitor, pin, image_sender.clone());
+        }
     };
 
-    let mut stream = BufWriter::new(ustream);
-    stream
-        .write_all(&serde_json::to_vec(&resp).unwrap())
-        .context("unable to write response to the IPC client")
-        .suggestion("Probably the client died, try running it again")?;
+    send_response(ustream, &resp)
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+
+    use super::*;
+
+    #[test]
+    fn test_event_subscribers() {
+        let mut subscribers = EventSubscribers::default();
+        let (first, first_client) = UnixStream::pair().unwrap();
+        let (second, second_client) = UnixStream::pair().unwrap();
+        subscribers.subscribe(first).unwrap();
+        subscribers.subscribe(second).unwrap();
+        // The second client disconnected
+        drop(second_client);
+
+        subscribers.emit(IpcEvent::ConfigReloaded);
+        assert_eq!(subscribers.streams.len(), 1);
+
+        let event = IpcEvent::OutputAdded {
+            monitor: "DP-1".to_string(),
+        };
+        subscribers.emit(event.clone());
+        let mut lines = BufReader::new(first_client).lines();
+        let mut next_event =
+            || -> IpcEvent { serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap() };
+        assert_eq!(next_event(), IpcEvent::ConfigReloaded);
+        assert_eq!(next_event(), event);
+    }
 }
@@ -0,0 +1,97 @@
+//! JPEG XL wallpapers. The `image` crate cannot decode them, so they are decoded with
+//! jxl-oxide by the decoder threads.
+
+use std::path::Path;
+#[cfg(feature = "jxl")]
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+};
+
+#[cfg(feature = "jxl")]
+use color_eyre::{eyre::Context, Result};
+#[cfg(feature = "jxl")]
+use image::DynamicImage;
+#[cfg(feature = "jxl")]
+use jxl_oxide::integration::JxlDecoder;
+
+/// True if `path` has the `.jxl` extension, unknown to the mime database
+pub fn is_jxl(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("jxl"))
+}
+
+/// Signatures of a bare codestream and of the ISOBMFF container
+#[cfg(feature = "jxl")]
+const SIGNATURES: [&[u8]; 2] = [
+    &[0xff, 0x0a],
+    &[
+        0, 0, 0, 0x0c, b'J', b'X', b'L', b' ', 0x0d, 0x0a, 0x87, 0x0a,
+    ],
+];
+
+/// Check that `path` starts with a JPEG XL signature, without decoding it
+#[cfg(feature = "jxl")]
+pub fn check(path: &Path) -> io::Result<()> {
+    let mut header = Vec::with_capacity(12);
+    File::open(path)?.take(12).read_to_end(&mut header)?;
+    if SIGNATURES
+        .iter()
+        .any(|signature| header.starts_with(signature))
+    {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the file is not a JPEG XL image",
+        ))
+    }
+}
+
+/// Decode `path`. It blocks, so it must only be called by the decoder threads
+#[cfg(feature = "jxl")]
+pub fn decode(path: &Path) -> Result<DynamicImage> {
+    let file = File::open(path).with_context(|| format!("opening {path:?}"))?;
+    let decoder = JxlDecoder::new(BufReader::new(file))
+        .with_context(|| format!("reading the JPEG XL header of {path:?}"))?;
+    DynamicImage::from_decoder(decoder).with_context(|| format!("decoding {path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x2 image, lossless
+    #[cfg(feature = "jxl")]
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/image.jxl");
+
+    #[test]
+    fn test_is_jxl() {
+        assert!(is_jxl(Path::new("/home/user/Pictures/image.jxl")));
+        assert!(is_jxl(Path::new("image.JXL")));
+        assert!(!is_jxl(Path::new("image.jpg")));
+        assert!(!is_jxl(Path::new("jxl")));
+    }
+
+    #[cfg(feature = "jxl")]
+    #[test]
+    fn test_check() {
+        assert!(check(Path::new(FIXTURE)).is_ok());
+
+        let dir = std::env::temp_dir().join("wpaperd-jxl-check");
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("image.jxl");
+        std::fs::write(&image, [0xff, 0x0a, 0xfa, 0x1f]).unwrap();
+        assert!(check(&image).is_ok());
+        std::fs::write(&image, b"\x89PNG\r\n\x1a\n").unwrap();
+        assert!(check(&image).is_err());
+    }
+
+    #[cfg(feature = "jxl")]
+    #[test]
+    fn test_decode() {
+        let image = decode(Path::new(FIXTURE)).unwrap();
+        assert_eq!((image.width(), image.height()), (3, 2));
+        assert!(decode(Path::new("/nonexistent.jxl")).is_err());
+    }
+}
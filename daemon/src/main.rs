@@ -1,14 +1,23 @@
+mod color_scheme;
 mod config;
 mod display_info;
+mod download;
 mod filelist_cache;
 mod image_loader;
 mod image_picker;
 mod ipc_server;
+mod jxl;
 mod opts;
+mod preview;
 mod render;
+#[cfg(feature = "systemd")]
+mod sd_notify;
+#[cfg(feature = "script")]
+mod selector;
 mod socket;
 mod surface;
 mod wallpaper_info;
+mod workspace;
 mod wpaperd;
 
 extern crate khronos_egl as egl;
@@ -28,7 +37,7 @@ use std::{
 
 use clap::Parser;
 use color_eyre::{
-    eyre::{anyhow, ContextCompat, WrapErr},
+    eyre::{anyhow, WrapErr},
     Result, Section,
 };
 use config::Config;
@@ -36,18 +45,19 @@ use egl::API as egl;
 use filelist_cache::FilelistCache;
 use flexi_logger::{Duplicate, FileSpec, Logger};
 use hotwatch::Hotwatch;
-use ipc_server::{handle_message, listen_on_ipc_socket};
-use log::error;
+use ipc_server::{handle_message, handle_received_image, listen_on_ipc_socket};
+use log::{error, warn};
 use nix::unistd::fork;
-use opts::Opts;
+use opts::{Command, Opts};
 use smithay_client_toolkit::reexports::{
     calloop,
     calloop_wayland_source::WaylandSource,
     client::{globals::registry_queue_init, Connection, Proxy},
 };
-use wpaperd_ipc::socket_path;
+use wpaperd_ipc::{socket_path, IpcEvent};
 use xdg::BaseDirectories;
 
+use crate::surface::Surface;
 use crate::wpaperd::Wpaperd;
 
 fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
@@ -80,22 +90,31 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         }
     };
     config.reloaded = Some(reloaded);
-
-    // we use the OpenGL ES API because it's more widely supported
-    // and it's used by wlroots
-    egl.bind_api(egl::OPENGL_ES_API)
-        .context("unable to select OpenGL API")?;
+    config.oneshot = opts.oneshot;
 
     let conn = Connection::connect_to_env()
         .context("connecting to wayland")
         .suggestion("Are you running a wayland compositor?")?;
 
-    let egl_display = unsafe {
-        egl.get_display(conn.display().id().as_ptr() as *mut std::ffi::c_void)
-            .context("getting the display from the WlDisplay")?
+    // we use the OpenGL ES API because it's more widely supported
+    // and it's used by wlroots
+    let egl_display = egl
+        .bind_api(egl::OPENGL_ES_API)
+        .context("unable to select OpenGL API")
+        .and_then(|_| {
+            render::init_display(
+                conn.display().id().as_ptr() as *mut std::ffi::c_void,
+                opts.render_node.as_deref(),
+            )
+        });
+    // The wallpapers can still be drawn in shared memory, without transitions nor effects
+    let egl_display = match egl_display {
+        Ok(egl_display) => Some(egl_display),
+        Err(err) => {
+            warn!("EGL is not available, drawing in software: {err:?}");
+            None
+        }
     };
-    egl.initialize(egl_display)
-        .context("initializing the egl display")?;
 
     let (globals, event_queue) =
         registry_queue_init(&conn).context("initializing the wayland registry queue")?;
@@ -115,22 +134,124 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         .map_err(|e| anyhow!("inserting the hotwatch event listener in the event loop: {e}"))?;
 
     let mut hotwatch = Hotwatch::new().context("hotwatch failed to initialize")?;
-    config.listen_to_changes(&mut hotwatch, ping)?;
+    // The wallpapers are kept as they are, there is no need to reload the configuration
+    if !opts.oneshot {
+        config.listen_to_changes(&mut hotwatch, ping)?;
+    }
+    config.retry_missing_paths(&event_loop.handle())?;
 
-    let (ping, filelist_cache) =
-        FilelistCache::new(config.paths(), &mut hotwatch, event_loop.handle())?;
+    let (ping, filelist_cache) = FilelistCache::new(
+        config.paths(),
+        &mut hotwatch,
+        event_loop.handle(),
+        qh.clone(),
+    )?;
     let filelist_cache = Rc::new(RefCell::new(filelist_cache));
 
-    let mut wpaperd = Wpaperd::new(&qh, &globals, config, egl_display, filelist_cache.clone())?;
+    // Each display writes the path of its current wallpaper and the recently drawn images
+    // in these directories
+    xdg_dirs
+        .create_state_directory("wallpapers")
+        .context("unable to create the state directory for the wallpapers")?;
+    xdg_dirs
+        .create_state_directory("history")
+        .context("unable to create the state directory for the history")?;
+    let xdg_state_home_dir = xdg_dirs.get_state_home();
+
+    // The images are decoded in other threads, draw the surfaces waiting for them once they
+    // are ready
+    let (decoded_sender, decoded_channel) = calloop::channel::channel();
+    let qh_clone = qh.clone();
+    event_loop
+        .handle()
+        .insert_source(decoded_channel, move |event, _, wpaperd| {
+            if let calloop::channel::Event::Msg(decoded_image) = event {
+                let requesters = wpaperd.image_loader.borrow_mut().decoded(decoded_image);
+                for name in requesters {
+                    // The images of the mosaic mode are requested by each cell of the display
+                    let name = name
+                        .split_once(Surface::MOSAIC_REQUESTER_SEPARATOR)
+                        .map_or(name.as_str(), |(name, _)| name);
+                    if let Some(surface) = wpaperd.surface_from_name(name) {
+                        surface.image_decoded(&qh_clone);
+                    }
+                }
+            }
+        })
+        .map_err(|e| anyhow!("inserting the image decoder channel in the event loop: {e}"))?;
+
+    // The color scheme is read by another thread, which sends it on every change
+    let (color_scheme_sender, color_scheme_channel) = calloop::channel::channel();
+    let qh_clone = qh.clone();
+    let handle = event_loop.handle();
+    event_loop
+        .handle()
+        .insert_source(color_scheme_channel, move |event, _, wpaperd| {
+            if let calloop::channel::Event::Msg(color_scheme) = event {
+                if wpaperd.config.set_color_scheme(color_scheme) {
+                    wpaperd.update_surfaces(handle.clone(), &qh_clone);
+                }
+            }
+        })
+        .map_err(|e| anyhow!("inserting the color scheme channel in the event loop: {e}"))?;
+    let mut following_color_scheme = false;
+    follow_color_scheme(
+        &mut config,
+        &mut following_color_scheme,
+        &color_scheme_sender,
+    );
+
+    // The workspaces are followed by another thread as well
+    let (workspace_sender, workspace_channel) = calloop::channel::channel();
+    let qh_clone = qh.clone();
+    let handle = event_loop.handle();
+    event_loop
+        .handle()
+        .insert_source(workspace_channel, move |event, _, wpaperd| {
+            if let calloop::channel::Event::Msg(workspaces) = event {
+                if wpaperd.config.set_workspaces(workspaces) {
+                    wpaperd.update_surfaces(handle.clone(), &qh_clone);
+                }
+            }
+        })
+        .map_err(|e| anyhow!("inserting the workspace channel in the event loop: {e}"))?;
+    let mut following_workspaces = false;
+    follow_workspaces(&mut config, &mut following_workspaces, &workspace_sender);
+
+    let mut wpaperd = Wpaperd::new(
+        &qh,
+        &globals,
+        config,
+        egl_display,
+        filelist_cache.clone(),
+        xdg_state_home_dir,
+        decoded_sender,
+        event_loop.handle(),
+    )?;
 
     // Start listening on the IPC socket
     let socket = listen_on_ipc_socket(&socket_path()?).context("spawning the ipc socket")?;
 
+    // The images sent over IPC are read by other threads, show them once they are decoded
+    let (image_sender, image_channel) = calloop::channel::channel();
+    let qh_clone = qh.clone();
+    event_loop
+        .handle()
+        .insert_source(image_channel, move |event, _, wpaperd| {
+            if let calloop::channel::Event::Msg(received) = event {
+                if let Err(err) = handle_received_image(received, &qh_clone, wpaperd) {
+                    error!("{:?}", err);
+                }
+            }
+        })
+        .map_err(|e| anyhow!("inserting the IPC image channel in the event loop: {e}"))?;
+
     // Add source to calloop loop.
+    let qh_clone = qh.clone();
     event_loop
         .handle()
-        .insert_source(socket, |stream, _, wpaperd| {
-            if let Err(err) = handle_message(stream, qh.clone(), wpaperd) {
+        .insert_source(socket, move |stream, _, wpaperd| {
+            if let Err(err) = handle_message(stream, qh_clone.clone(), wpaperd, &image_sender) {
                 error!("{:?}", err);
             }
         })?;
@@ -142,6 +263,8 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
             error!("Could not write to FD {notify}: {err:?}");
         }
     }
+    #[cfg(feature = "systemd")]
+    let (mut ready, mut outputs) = (false, None);
 
     loop {
         // If the config has been modified, this value will return true
@@ -162,8 +285,44 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
                 ping.clone(),
             );
 
+            follow_color_scheme(
+                &mut wpaperd.config,
+                &mut following_color_scheme,
+                &color_scheme_sender,
+            );
+            follow_workspaces(
+                &mut wpaperd.config,
+                &mut following_workspaces,
+                &workspace_sender,
+            );
+
             // Read the config, update the paths in the surfaces
             wpaperd.update_surfaces(event_loop.handle(), &qh);
+            wpaperd.events.borrow_mut().emit(IpcEvent::ConfigReloaded);
+        }
+
+        // The compositor sent richer identifiers for some displays
+        if wpaperd.output_heads_changed {
+            wpaperd.output_heads_changed = false;
+            wpaperd.update_surfaces(event_loop.handle(), &qh);
+        }
+
+        wpaperd.update_groups(&event_loop.handle(), &qh);
+
+        // Ready once the surfaces of the displays found at startup have been configured
+        #[cfg(feature = "systemd")]
+        if !ready && wpaperd.surfaces_configured() {
+            ready = true;
+            if let Err(err) = sd_notify::notify("READY=1") {
+                error!("{err:?}");
+            }
+        }
+        #[cfg(feature = "systemd")]
+        if ready && outputs != Some(wpaperd.surfaces.len()) {
+            outputs = Some(wpaperd.surfaces.len());
+            if let Err(err) = sd_notify::notify_status(wpaperd.surfaces.len()) {
+                error!("{err:?}");
+            }
         }
 
         // Due to how LayerSurface works, we cannot attach the egl window right away.
@@ -177,6 +336,7 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
             };
 
             if !surface.drawn() {
+                surface.hold_startup(&event_loop.handle(), &qh);
                 surface.add_timer(None, &event_loop.handle(), qh.clone());
                 if let Err(err) = surface.draw(&qh, 0) {
                     error!("{err:?}");
@@ -195,6 +355,52 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
     }
 }
 
+/// Start listening to the color scheme of the desktop the first time the configuration uses
+/// `light_path` or `dark_path`
+fn follow_color_scheme(
+    config: &mut Config,
+    following: &mut bool,
+    sender: &calloop::channel::Sender<color_scheme::ColorScheme>,
+) {
+    if *following || !config.follows_color_scheme() {
+        return;
+    }
+    // Do not try again on every reload when there is no portal
+    *following = true;
+    match color_scheme::listen(sender.clone()) {
+        Ok(color_scheme) => {
+            config.set_color_scheme(color_scheme);
+        }
+        Err(err) => warn!(
+            "{:?}",
+            err.wrap_err("unable to follow the color scheme, using light_path")
+        ),
+    }
+}
+
+/// Start listening to the workspaces of the compositor the first time the configuration uses
+/// `workspace_paths`
+fn follow_workspaces(
+    config: &mut Config,
+    following: &mut bool,
+    sender: &calloop::channel::Sender<workspace::ActiveWorkspaces>,
+) {
+    if *following || !config.follows_workspaces() {
+        return;
+    }
+    // Do not try again on every reload when the compositor is not supported
+    *following = true;
+    match workspace::listen(sender.clone()) {
+        Ok(workspaces) => {
+            config.set_workspaces(workspaces);
+        }
+        Err(err) => warn!(
+            "{:?}",
+            err.wrap_err("unable to follow the workspaces, ignoring workspace_paths")
+        ),
+    }
+}
+
 fn main() -> Result<()> {
     color_eyre::install()?;
 
@@ -202,7 +408,24 @@ fn main() -> Result<()> {
 
     let opts = Opts::parse();
 
-    let mut logger = Logger::try_with_env_or_str(if opts.verbose { "debug" } else { "info" })?;
+    if opts.list_outputs {
+        if !ipc_server::list_outputs(&socket_path()?)? {
+            eprintln!("wpaperd is not running, start it first to list its outputs");
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    // The levels can be set for each module, e.g. `wpaperd::render=debug`
+    let mut logger = match &opts.log {
+        Some(spec) => Logger::try_with_str(spec).context("parsing the --log levels")?,
+        None => Logger::try_with_env_or_str(if opts.verbose { "debug" } else { "info" })?,
+    };
+
+    if let Some(Command::Preview(preview)) = &opts.command {
+        logger.start()?;
+        return preview::run(preview);
+    }
 
     if opts.daemon {
         // If wpaperd detach, then log to files
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 
 #[derive(Parser)]
 #[clap(
@@ -25,9 +25,97 @@ pub struct Opts {
     pub daemon: bool,
     #[clap(short, long, help = "Increase the verbosity of wpaperd")]
     pub verbose: bool,
+    #[clap(
+        long,
+        value_name = "SPEC",
+        help = "Log levels in the format of RUST_LOG, e.g. \"info,wpaperd::render=debug\" (overrides RUST_LOG and --verbose)"
+    )]
+    pub log: Option<String>,
     #[clap(
         long,
         help = "Readiness fd used by wpaperd to signal that it has started correctly"
     )]
     pub notify: Option<u8>,
+    #[clap(
+        long,
+        help = "Print the outputs handled by the running wpaperd instance and exit"
+    )]
+    pub list_outputs: bool,
+    #[clap(
+        long,
+        help = "Show one wallpaper on each display and keep it, without rotating the wallpapers or watching the configuration"
+    )]
+    pub oneshot: bool,
+    #[clap(
+        long,
+        help = "DRM node of the GPU drawing the wallpapers, e.g. /dev/dri/renderD128 (the GPU of the compositor by default)"
+    )]
+    pub render_node: Option<PathBuf>,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Render the transition between two images to a series of PNG frames, without a compositor
+    Preview(PreviewOpts),
+}
+
+#[derive(Args)]
+pub struct PreviewOpts {
+    #[clap(
+        long,
+        default_value = "fade",
+        help = "Name of the transition, as in the configuration"
+    )]
+    pub transition: String,
+    #[clap(long, help = "Image shown at the start of the transition")]
+    pub from: PathBuf,
+    #[clap(long, help = "Image shown at the end of the transition")]
+    pub to: PathBuf,
+    #[clap(
+        long = "set",
+        value_name = "KEY=VALUE",
+        help = "Parameter of the transition, with the value written as in the configuration, e.g. --set 'direction=[1.0, 0.0]'"
+    )]
+    pub parameters: Vec<String>,
+    #[clap(
+        long,
+        help = "Milliseconds the transition runs (the default of the transition otherwise)"
+    )]
+    pub transition_time: Option<u32>,
+    #[clap(
+        long,
+        default_value = "cover",
+        help = "How the images are drawn, as in the configuration"
+    )]
+    pub mode: String,
+    #[clap(long, default_value = "1280x720", value_parser = parse_size, help = "Size of the frames, in pixels")]
+    pub size: (i32, i32),
+    #[clap(
+        long,
+        default_value_t = 30,
+        value_parser = clap::value_parser!(u32).range(1..=1000),
+        help = "Frames rendered for each second of the transition"
+    )]
+    pub fps: u32,
+    #[clap(
+        short,
+        long,
+        default_value = "wpaperd-preview",
+        help = "Directory where the frames are written, as frame-0000.png, frame-0001.png..."
+    )]
+    pub output: PathBuf,
+}
+
+/// Parse a size in the form `<width>x<height>`
+fn parse_size(size: &str) -> Result<(i32, i32), String> {
+    let parse = |dimension: &str| match dimension.parse() {
+        Ok(dimension) if dimension > 0 => Ok(dimension),
+        _ => Err(format!("invalid size {size:?}, expected e.g. 1920x1080")),
+    };
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| format!("invalid size {size:?}, expected e.g. 1920x1080"))?;
+    Ok((parse(width)?, parse(height)?))
 }
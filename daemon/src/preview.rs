@@ -0,0 +1,160 @@
+//! `wpaperd preview`, render a transition offscreen with the same shaders as the daemon. It shows
+//! how a transition and its parameters look without changing the wallpaper of any display.
+
+use std::{cell::RefCell, fs, path::Path, rc::Rc};
+
+use color_eyre::{
+    eyre::{bail, Context, ContextCompat},
+    Result,
+};
+use egl::API as egl;
+use image::RgbaImage;
+use log::info;
+
+use crate::{
+    display_info::DisplayInfo,
+    image_loader::{decode, SizeLimit},
+    opts::PreviewOpts,
+    render::{init_offscreen_display, EglContext, Renderer, Transition},
+    wallpaper_info::{BackgroundMode, Gravity, TileSnap},
+};
+
+/// Draw each frame of the transition from `--from` to `--to` and write them as PNG files
+pub fn run(opts: &PreviewOpts) -> Result<()> {
+    let transition = parse_transition(&opts.transition, &opts.parameters)?;
+    let mode = parse_mode(&opts.mode)?;
+    let transition_time = opts
+        .transition_time
+        .unwrap_or_else(|| transition.default_transition_time());
+    let (width, height) = opts.size;
+
+    // The borders of contain-blur are composed when decoding, like in the daemon
+    let size_limit = match mode {
+        BackgroundMode::ContainBlur => SizeLimit::Letterbox(width as u32, height as u32),
+        _ => SizeLimit::Display(width as u32, height as u32),
+    };
+    let load = |path: &Path| -> Result<RgbaImage> {
+        // Already limited to the size of the frames
+        decode(path, Some(size_limit), None)
+            .with_context(|| format!("unable to decode the image {path:?}"))
+    };
+    let from = load(&opts.from)?;
+    let to = load(&opts.to)?;
+
+    egl.bind_api(egl::OPENGL_ES_API)
+        .context("unable to select OpenGL API")?;
+    let egl_context = EglContext::new_offscreen(init_offscreen_display()?, width, height)?;
+    egl_context.make_current()?;
+
+    let display_info = Rc::new(RefCell::new(DisplayInfo::offscreen(width, height)));
+    let mut renderer = unsafe {
+        Renderer::new(
+            from,
+            display_info,
+            0,
+            transition,
+            Gravity::default(),
+            (0, 0),
+            TileSnap::default(),
+        )
+        .context("unable to create the renderer")?
+    };
+    renderer.resize()?;
+    // Place the first image for the mode, as if it was already shown on a display
+    renderer.set_mode(mode, true)?;
+    renderer.load_wallpaper(to, None, mode)?;
+    renderer.start_transition(0, transition_time);
+
+    fs::create_dir_all(&opts.output)
+        .with_context(|| format!("creating the directory {:?}", opts.output))?;
+    let mut frame = 0;
+    loop {
+        let time = frame * 1000 / opts.fps;
+        let transition_going = unsafe { renderer.draw(time, mode)? };
+        let path = opts.output.join(format!("frame-{frame:04}.png"));
+        renderer
+            .read_pixels()?
+            .save(&path)
+            .with_context(|| format!("writing the frame {path:?}"))?;
+        frame += 1;
+        if !transition_going {
+            break;
+        }
+    }
+    info!("{frame} frames written in {:?}", opts.output);
+
+    Ok(())
+}
+
+/// Read the transition like a section of the configuration, each parameter being a `key=value`
+/// line of it
+fn parse_transition(name: &str, parameters: &[String]) -> Result<Transition> {
+    let mut section = toml::Table::new();
+    section.insert(
+        "transition".to_string(),
+        toml::Value::String(name.to_string()),
+    );
+    for parameter in parameters {
+        let (key, value) = parameter
+            .split_once('=')
+            .with_context(|| format!("the parameter {parameter:?} is not in the form KEY=VALUE"))?;
+        let mut line: toml::Table = toml::from_str(&format!("value = {value}"))
+            .with_context(|| format!("invalid value of the parameter {parameter:?}"))?;
+        let value = line.remove("value").expect("the value to be parsed");
+        section.insert(key.trim().to_string(), value);
+    }
+    toml::Value::Table(section)
+        .try_into()
+        .with_context(|| format!("invalid transition {name:?}"))
+}
+
+fn parse_mode(name: &str) -> Result<BackgroundMode> {
+    let mode = toml::Value::String(name.to_string())
+        .try_into()
+        .with_context(|| format!("invalid mode {name:?}"))?;
+    if mode == BackgroundMode::Mosaic {
+        bail!("the mosaic mode shows more than two images, it cannot be previewed");
+    }
+    Ok(mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_transition() {
+        let transition = parse_transition("fade", &[]).unwrap();
+        assert_eq!(transition, Transition::Fade {});
+
+        let parameters = ["clockwise=false".to_string()];
+        let transition = parse_transition("clock-wipe", &parameters).unwrap();
+        assert_eq!(
+            transition,
+            Transition::ClockWipe {
+                clockwise: Some(false)
+            }
+        );
+
+        let parameters = ["direction = [1.0, 0.0]".to_string()];
+        let transition = parse_transition("directional", &parameters).unwrap();
+        assert_eq!(
+            transition,
+            Transition::Directional {
+                direction: Some([1.0, 0.0])
+            }
+        );
+
+        assert!(parse_transition("unknown", &[]).is_err());
+        assert!(parse_transition("clock-wipe", &["clockwise".to_string()]).is_err());
+        assert!(parse_transition("clock-wipe", &["clockwise=maybe".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("contain").unwrap(), BackgroundMode::Contain);
+        assert_eq!(parse_mode("fit").unwrap(), BackgroundMode::Contain);
+        assert!(parse_mode("mosaic").is_err());
+        assert!(parse_mode("unknown").is_err());
+    }
+}
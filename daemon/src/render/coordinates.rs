@@ -25,6 +25,57 @@ impl Coordinates {
         }
     }
 
+    /// Move all the points by `x` and `y`
+    pub fn translate(self, (x, y): (f32, f32)) -> Self {
+        Self {
+            x_left: self.x_left + x,
+            x_right: self.x_right + x,
+            y_bottom: self.y_bottom + y,
+            y_top: self.y_top + y,
+        }
+    }
+
+    /// Grow the rectangle by `x` and `y` on each side
+    pub fn grow(self, (x, y): (f32, f32)) -> Self {
+        // Texture coordinates and vertices don't grow in the same direction on the y axis
+        let x = x * (self.x_right - self.x_left).signum();
+        let y = y * (self.y_bottom - self.y_top).signum();
+        Self {
+            x_left: self.x_left - x,
+            x_right: self.x_right + x,
+            y_bottom: self.y_bottom + y,
+            y_top: self.y_top - y,
+        }
+    }
+
+    /// Mirror the rectangle by swapping its sides, horizontally and/or vertically
+    pub fn flip(self, (horizontal, vertical): (bool, bool)) -> Self {
+        let (x_left, x_right) = if horizontal {
+            (self.x_right, self.x_left)
+        } else {
+            (self.x_left, self.x_right)
+        };
+        let (y_bottom, y_top) = if vertical {
+            (self.y_top, self.y_bottom)
+        } else {
+            (self.y_bottom, self.y_top)
+        };
+        Self {
+            x_left,
+            x_right,
+            y_bottom,
+            y_top,
+        }
+    }
+
+    /// Width and height of the rectangle
+    pub fn size(&self) -> (f32, f32) {
+        (
+            (self.x_right - self.x_left).abs(),
+            (self.y_bottom - self.y_top).abs(),
+        )
+    }
+
     pub const fn default_vec_coordinates() -> Self {
         Self {
             x_right: Self::VEC_X_RIGHT,
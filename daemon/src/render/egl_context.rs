@@ -1,35 +1,238 @@
+use std::{
+    ffi::{c_char, c_void, CStr},
+    os::unix::fs::FileTypeExt,
+    path::Path,
+};
+
+use log::warn;
 use smithay_client_toolkit::reexports::client::{protocol::wl_surface::WlSurface, Proxy};
 use wayland_egl::WlEglSurface;
 
 use egl::API as egl;
 
-use color_eyre::{eyre::Context, Result};
+use color_eyre::{
+    eyre::{ensure, Context, ContextCompat},
+    Report, Result,
+};
+
+// From EGL_EXT_platform_wayland, EGL_EXT_device_query, EGL_EXT_device_drm,
+// EGL_EXT_device_drm_render_node and EGL_EXT_explicit_device
+const PLATFORM_WAYLAND_EXT: egl::Enum = 0x31D8;
+const DEVICE_EXT: egl::Attrib = 0x322C;
+const DRM_DEVICE_FILE_EXT: egl::Int = 0x3233;
+const DRM_RENDER_NODE_FILE_EXT: egl::Int = 0x3377;
+// From EGL_MESA_platform_surfaceless
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+type QueryDevicesExt = unsafe extern "system" fn(
+    max_devices: egl::Int,
+    devices: *mut *mut c_void,
+    num_devices: *mut egl::Int,
+) -> egl::Boolean;
+type QueryDeviceStringExt =
+    unsafe extern "system" fn(device: *mut c_void, name: egl::Int) -> *const c_char;
+
+/// Get and initialize the EGL display of the wayland connection. With `render_node`, e.g.
+/// `/dev/dri/renderD129`, the display uses that GPU; if it can't be used, the default display
+/// is used instead
+pub fn init_display(
+    wl_display: egl::NativeDisplayType,
+    render_node: Option<&Path>,
+) -> Result<egl::Display> {
+    if let Some(render_node) = render_node {
+        match device_display(wl_display, render_node) {
+            Ok(egl_display) => return Ok(egl_display),
+            Err(err) => warn!("{err:?}, using the default GPU instead"),
+        }
+    }
+
+    let egl_display = unsafe {
+        egl.get_display(wl_display)
+            .context("getting the display from the WlDisplay")?
+    };
+    egl.initialize(egl_display)
+        .context("initializing the egl display")?;
+    Ok(egl_display)
+}
+
+fn device_display(wl_display: egl::NativeDisplayType, render_node: &Path) -> Result<egl::Display> {
+    let render_node = render_node
+        .canonicalize()
+        .with_context(|| format!("using the render node {render_node:?}"))?;
+    ensure!(
+        render_node.metadata()?.file_type().is_char_device(),
+        "the render node {render_node:?} is not a DRM device"
+    );
+
+    let extensions = egl
+        .query_string(None, egl::EXTENSIONS)
+        .context("querying the EGL client extensions")?
+        .to_string_lossy();
+    for extension in [
+        "EGL_EXT_device_enumeration",
+        "EGL_EXT_device_query",
+        "EGL_EXT_explicit_device",
+    ] {
+        ensure!(
+            extensions
+                .split(' ')
+                .any(|available| available == extension),
+            "the EGL implementation does not support {extension}, needed to choose the GPU"
+        );
+    }
+    let query_devices: QueryDevicesExt = unsafe {
+        std::mem::transmute(
+            egl.get_proc_address("eglQueryDevicesEXT")
+                .context("loading eglQueryDevicesEXT")?,
+        )
+    };
+    let query_device_string: QueryDeviceStringExt = unsafe {
+        std::mem::transmute(
+            egl.get_proc_address("eglQueryDeviceStringEXT")
+                .context("loading eglQueryDeviceStringEXT")?,
+        )
+    };
+
+    let mut num_devices = 0;
+    ensure!(
+        unsafe { query_devices(0, std::ptr::null_mut(), &mut num_devices) } == egl::TRUE,
+        "querying the number of EGL devices"
+    );
+    let mut devices = vec![std::ptr::null_mut(); num_devices.max(0) as usize];
+    ensure!(
+        unsafe { query_devices(num_devices, devices.as_mut_ptr(), &mut num_devices) } == egl::TRUE,
+        "querying the EGL devices"
+    );
+    devices.truncate(num_devices.max(0) as usize);
+
+    // Both the primary node and the render node of a GPU are accepted
+    let is_render_node = |device: *mut c_void, name: egl::Int| {
+        let file = unsafe { query_device_string(device, name) };
+        !file.is_null()
+            && Path::new(&*unsafe { CStr::from_ptr(file) }.to_string_lossy())
+                .canonicalize()
+                .is_ok_and(|file| file == render_node)
+    };
+    let device = devices
+        .into_iter()
+        .find(|&device| {
+            is_render_node(device, DRM_RENDER_NODE_FILE_EXT)
+                || is_render_node(device, DRM_DEVICE_FILE_EXT)
+        })
+        .with_context(|| format!("no EGL device uses the render node {render_node:?}"))?;
+
+    let egl_display = unsafe {
+        egl.get_platform_display(
+            PLATFORM_WAYLAND_EXT,
+            wl_display,
+            &[DEVICE_EXT, device as egl::Attrib, egl::ATTRIB_NONE],
+        )
+    }
+    .with_context(|| format!("getting the display of the render node {render_node:?}"))?;
+    egl.initialize(egl_display)
+        .with_context(|| format!("initializing the display of the render node {render_node:?}"))?;
+    Ok(egl_display)
+}
+
+/// Get and initialize an EGL display without a wayland connection, to draw offscreen. Mesa's
+/// surfaceless platform doesn't need any window system; otherwise the default display is used
+pub fn init_offscreen_display() -> Result<egl::Display> {
+    let egl_display = unsafe {
+        egl.get_platform_display(
+            PLATFORM_SURFACELESS_MESA,
+            egl::DEFAULT_DISPLAY,
+            &[egl::ATTRIB_NONE],
+        )
+    }
+    .ok()
+    .or_else(|| unsafe { egl.get_display(egl::DEFAULT_DISPLAY) })
+    .context("getting an offscreen EGL display")?;
+    egl.initialize(egl_display)
+        .context("initializing the offscreen EGL display")?;
+    Ok(egl_display)
+}
 
 pub struct EglContext {
     pub display: egl::Display,
     pub context: egl::Context,
     pub config: egl::Config,
-    wl_egl_surface: WlEglSurface,
+    /// Unset when drawing offscreen, in a pbuffer
+    wl_egl_surface: Option<WlEglSurface>,
     surface: khronos_egl::Surface,
 }
 
 impl EglContext {
-    pub fn new(egl_display: egl::Display, wl_surface: &WlSurface) -> Self {
-        const ATTRIBUTES: [i32; 7] = [
+    pub fn new(egl_display: egl::Display, wl_surface: &WlSurface) -> Result<Self> {
+        let config = Self::choose_config(egl_display, egl::WINDOW_BIT)?;
+        let context = Self::create_context(egl_display, config)?;
+
+        // First, create a small surface, we don't know the size of the output yet
+        let wl_egl_surface = WlEglSurface::new(wl_surface.id(), 10, 10)
+            .context("unable to create the wayland EGL surface")?;
+
+        let surface = unsafe {
+            egl.create_window_surface(
+                egl_display,
+                config,
+                wl_egl_surface.ptr() as egl::NativeWindowType,
+                None,
+            )
+            .context("unable to create an EGL surface")?
+        };
+
+        Ok(Self {
+            display: egl_display,
+            context,
+            config,
+            surface,
+            wl_egl_surface: Some(wl_egl_surface),
+        })
+    }
+
+    /// Draw in a pbuffer of `width`x`height` pixels instead of a wayland surface, the frames are
+    /// read back with [`Renderer::read_pixels`](super::Renderer::read_pixels)
+    pub fn new_offscreen(egl_display: egl::Display, width: i32, height: i32) -> Result<Self> {
+        let config = Self::choose_config(egl_display, egl::PBUFFER_BIT)?;
+        let context = Self::create_context(egl_display, config)?;
+        let surface = egl
+            .create_pbuffer_surface(
+                egl_display,
+                config,
+                &[egl::WIDTH, width, egl::HEIGHT, height, egl::NONE],
+            )
+            .context("unable to create an EGL pbuffer")?;
+
+        Ok(Self {
+            display: egl_display,
+            context,
+            config,
+            surface,
+            wl_egl_surface: None,
+        })
+    }
+
+    fn choose_config(egl_display: egl::Display, surface_type: egl::Int) -> Result<egl::Config> {
+        let attributes = [
             egl::RED_SIZE,
             8,
             egl::GREEN_SIZE,
             8,
             egl::BLUE_SIZE,
             8,
+            // Needed by `opacity`, the compositor blends the wallpaper with what is behind it
+            egl::ALPHA_SIZE,
+            8,
+            egl::SURFACE_TYPE,
+            surface_type,
             egl::NONE,
         ];
 
-        let config = egl
-            .choose_first_config(egl_display, &ATTRIBUTES)
-            .expect("unable to choose an EGL configuration")
-            .expect("no EGL configuration found");
+        egl.choose_first_config(egl_display, &attributes)
+            .context("unable to choose an EGL configuration")?
+            .context("no EGL configuration found")
+    }
 
+    fn create_context(egl_display: egl::Display, config: egl::Config) -> Result<egl::Context> {
         const CONTEXT_ATTRIBUTES: [i32; 5] = [
             egl::CONTEXT_MAJOR_VERSION,
             3,
@@ -38,30 +241,24 @@ impl EglContext {
             egl::NONE,
         ];
 
-        let context = egl
-            .create_context(egl_display, config, None, &CONTEXT_ATTRIBUTES)
-            .expect("unable to create an EGL context");
-
-        // First, create a small surface, we don't know the size of the output yet
-        let wl_egl_surface = WlEglSurface::new(wl_surface.id(), 10, 10).unwrap();
+        egl.create_context(egl_display, config, None, &CONTEXT_ATTRIBUTES)
+            .context("unable to create an EGL context")
+    }
 
-        let surface = unsafe {
-            egl.create_window_surface(
-                egl_display,
-                config,
-                wl_egl_surface.ptr() as egl::NativeWindowType,
-                None,
-            )
-            .expect("unable to create an EGL surface")
-        };
+    /// True if `err` has been caused by a power management event or a GPU reset, all the
+    /// openGL objects are gone and the context must be recreated
+    pub fn is_context_lost(err: &Report) -> bool {
+        err.chain()
+            .any(|cause| matches!(cause.downcast_ref(), Some(egl::Error::ContextLost)))
+    }
 
-        Self {
-            display: egl_display,
-            context,
-            config,
-            surface,
-            wl_egl_surface,
-        }
+    /// Replace a lost context with a new one, together with its surface
+    pub fn recreate(&mut self, wl_surface: &WlSurface, width: i32, height: i32) -> Result<()> {
+        // The old context cannot be used anymore, errors when destroying it do not matter
+        let _ = egl.make_current(self.display, None, None, None);
+        let _ = egl.destroy_context(self.display, self.context);
+        self.context = Self::create_context(self.display, self.config)?;
+        self.resize(wl_surface, width, height)
     }
 
     #[inline]
@@ -101,8 +298,25 @@ impl EglContext {
         };
 
         self.surface = surface;
-        self.wl_egl_surface = wl_egl_surface;
+        self.wl_egl_surface = Some(wl_egl_surface);
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::eyre::eyre;
+
+    use super::*;
+
+    #[test]
+    fn test_is_context_lost() {
+        let lost: Result<()> = Err(egl::Error::ContextLost).context("unable to post the surface");
+        assert!(EglContext::is_context_lost(&lost.unwrap_err()));
+        let lost =
+            Report::new(egl::Error::BadSurface).wrap_err("unable to make the context current");
+        assert!(!EglContext::is_context_lost(&lost));
+        assert!(!EglContext::is_context_lost(&eyre!("OpenGL error")));
+    }
+}
@@ -2,6 +2,7 @@ mod coordinates;
 mod egl_context;
 mod renderer;
 mod shader;
+mod shm;
 mod transition;
 mod wallpaper;
 
@@ -11,11 +12,14 @@ use color_eyre::{
     eyre::{bail, ensure},
     Result,
 };
-use image::DynamicImage;
+use image::RgbaImage;
 
-pub use egl_context::EglContext;
+pub use egl_context::{init_display, init_offscreen_display, EglContext};
 pub use renderer::Renderer;
+pub use shm::ShmRenderer;
 pub use transition::Transition;
+#[cfg(test)]
+pub use transition::{BarnDoorAxis, CellShape};
 
 pub mod gl {
     #![allow(clippy::all)]
@@ -113,7 +117,117 @@ fn initialize_objects(
     }
 }
 
-fn load_texture(gl: &gl::Gl, image: DynamicImage) -> Result<gl::types::GLuint> {
+/// Create the sampler used for both the wallpapers, it overrides the parameters of the textures
+fn initialize_sampler(gl: &gl::Gl) -> Result<gl::types::GLuint> {
+    unsafe {
+        let mut sampler = 0;
+        gl.GenSamplers(1, &mut sampler);
+        gl_check!(gl, "generating the sampler");
+        gl.SamplerParameteri(sampler, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl_check!(gl, "defining the sampler min filter");
+        gl.SamplerParameteri(sampler, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl_check!(gl, "defining the sampler mag filter");
+        for unit in 0..2 {
+            gl.BindSampler(unit, sampler);
+            gl_check!(gl, "binding the sampler");
+        }
+
+        Ok(sampler)
+    }
+}
+
+/// Order of the channels of the pixels given to `TexImage2D`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TextureFormat {
+    #[default]
+    Rgba,
+    /// Many drivers store the textures as BGRA and convert the RGBA pixels one by one while
+    /// uploading them, then the upload of BGRA pixels is a plain copy. Needs
+    /// `GL_EXT_texture_format_BGRA8888`
+    Bgra,
+}
+
+impl TextureFormat {
+    const BGRA_EXTENSION: &'static str = "GL_EXT_texture_format_BGRA8888";
+
+    /// BGRA when the driver supports it, RGBA otherwise
+    pub fn detect(gl: &gl::Gl) -> Self {
+        unsafe {
+            let mut count = 0;
+            gl.GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+            let supported = (0..count.max(0) as gl::types::GLuint).any(|index| {
+                let extension = gl.GetStringi(gl::EXTENSIONS, index);
+                !extension.is_null()
+                    && CStr::from_ptr(extension as _).to_bytes() == Self::BGRA_EXTENSION.as_bytes()
+            });
+            if supported {
+                Self::Bgra
+            } else {
+                Self::Rgba
+            }
+        }
+    }
+
+    /// Reorder the channels of `image`, decoded as RGBA. It goes through every pixel, so it
+    /// must only be called by the decoder threads
+    pub fn arrange(self, image: &mut RgbaImage) {
+        if self == Self::Bgra {
+            for pixel in image.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+    }
+
+    /// The red, green, blue and alpha channels of `pixel`, stored in this order
+    pub fn rgba(self, pixel: [u8; 4]) -> [u8; 4] {
+        match self {
+            Self::Rgba => pixel,
+            Self::Bgra => [pixel[2], pixel[1], pixel[0], pixel[3]],
+        }
+    }
+
+    /// The internal format and the format of the pixels, the extension requires them to match
+    fn formats(self) -> (gl::types::GLenum, gl::types::GLenum) {
+        match self {
+            Self::Rgba => (gl::RGBA8, gl::RGBA),
+            Self::Bgra => (gl::BGRA_EXT, gl::BGRA_EXT),
+        }
+    }
+}
+
+/// An 8-bit image to upload in a texture, with its channels stored in the order of `format`.
+/// The images drawn on the main thread, e.g. the colors, are RGBA
+pub struct TextureImage {
+    pub image: RgbaImage,
+    pub format: TextureFormat,
+}
+
+impl From<RgbaImage> for TextureImage {
+    fn from(image: RgbaImage) -> Self {
+        Self {
+            image,
+            format: TextureFormat::Rgba,
+        }
+    }
+}
+
+impl TextureImage {
+    /// The pixels in RGBA order, for the shared memory backend
+    pub fn into_rgba(mut self) -> RgbaImage {
+        if self.format == TextureFormat::Bgra {
+            // Swapping the channels again restores them
+            TextureFormat::Bgra.arrange(&mut self.image);
+        }
+        self.image
+    }
+}
+
+/// Upload `image` in a new texture, in the format its channels are stored in. The images are
+/// always 8-bit, whatever the format of the file they have been decoded from, so that their
+/// bytes match the layout given to `TexImage2D`
+fn load_texture(gl: &gl::Gl, image: TextureImage) -> Result<gl::types::GLuint> {
+    let (internal_format, pixel_format) = image.format.formats();
+    let image = image.image;
     Ok(unsafe {
         let mut texture = 0;
         gl.GenTextures(1, &mut texture);
@@ -125,13 +239,13 @@ fn load_texture(gl: &gl::Gl, image: DynamicImage) -> Result<gl::types::GLuint> {
         gl.TexImage2D(
             gl::TEXTURE_2D,
             0,
-            gl::RGBA8.try_into().unwrap(),
+            internal_format.try_into().unwrap(),
             image.width().try_into().unwrap(),
             image.height().try_into().unwrap(),
             0,
-            gl::RGBA,
+            pixel_format,
             gl::UNSIGNED_BYTE,
-            image.as_bytes().as_ptr() as *const c_void,
+            image.as_ptr() as *const c_void,
         );
         gl_check!(gl, "defining the texture");
         gl.GenerateMipmap(gl::TEXTURE_2D);
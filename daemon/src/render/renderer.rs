@@ -1,34 +1,214 @@
-use std::{cell::RefCell, ffi::CStr, ops::Deref, rc::Rc};
+use std::{
+    cell::RefCell,
+    ffi::CStr,
+    ops::Deref,
+    path::Path,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use color_eyre::{
-    eyre::{bail, ensure, Context},
+    eyre::{bail, ensure, Context, ContextCompat},
     Result,
 };
 use egl::API as egl;
 use image::{DynamicImage, RgbaImage};
-use log::error;
+use log::{debug, error};
 
 use crate::{
     display_info::DisplayInfo,
     gl_check,
+    image_loader::focal_position,
     render::{
-        initialize_objects, load_texture,
+        initialize_objects, initialize_sampler, load_texture,
         shader::{create_shader, FRAGMENT_SHADER_SOURCE, VERTEX_SHADER_SOURCE},
+        TextureFormat, TextureImage,
     },
-    wallpaper_info::BackgroundMode,
+    wallpaper_info::{BackgroundMode, Gravity, TileSnap},
 };
 
 use super::{
     coordinates::{get_opengl_point_coordinates, Coordinates},
     gl,
+    transition::MASK_TEXTURE_UNIT,
     wallpaper::Wallpaper,
     Transition,
 };
 
+/// Uploading a texture for longer than this delays the frame where the transition starts, see
+/// [`Renderer::restart_transition`]
+const UPLOAD_BUDGET: Duration = Duration::from_millis(16);
+
+/// The time passed to the shader for the grain wraps after this many milliseconds
+const GRAIN_PERIOD: u32 = 100_000;
+
+/// Scale applied to the texture coordinates of `wallpaper`, so that it is drawn according to
+/// `mode`. `display` is the size of the buffer of the display
+fn gen_texture_scale(
+    mode: BackgroundMode,
+    tile_snap: TileSnap,
+    wallpaper: &Wallpaper,
+    display: (i32, i32),
+) -> [f32; 2] {
+    let display_ratio = display.1 as f32 / display.0 as f32;
+    let image_ratio = wallpaper.image_height as f32 / wallpaper.image_width as f32;
+    let aspect = image_ratio / display_ratio;
+    match mode {
+        // The mosaic and the letterbox are composed at the size of the display
+        BackgroundMode::Stretch | BackgroundMode::Mosaic | BackgroundMode::ContainBlur => {
+            [1.0, 1.0]
+        }
+        // The image is taller than the display, crop the top and bottom
+        BackgroundMode::Cover if aspect > 1.0 => [1.0, 1.0 / aspect],
+        // The image is wider than the display, crop the left and right
+        BackgroundMode::Cover => [aspect, 1.0],
+        // Only one side matches the display, the other is cropped or letterboxed
+        BackgroundMode::FitWidth => [1.0, 1.0 / aspect],
+        BackgroundMode::FitHeight => [aspect, 1.0],
+        // The letterboxing is done by the vertices, see
+        // [`Wallpaper::generate_vertices_coordinates_for_fit_mode`]
+        BackgroundMode::Contain => [1.0, 1.0],
+        BackgroundMode::Tile => match tile_snap {
+            // The tiles are as tall as the display
+            TileSnap::None => [aspect, 1.0],
+            // The number of tiles that fit at the size of the image, at least one
+            TileSnap::Horizontal | TileSnap::Both => {
+                let columns = (display.0 as f32 / wallpaper.image_width as f32)
+                    .round()
+                    .max(1.0);
+                let rows = match tile_snap {
+                    TileSnap::Both => (display.1 as f32 / wallpaper.image_height as f32)
+                        .round()
+                        .max(1.0),
+                    // Keep the aspect ratio of the tiles
+                    _ => columns / aspect,
+                };
+                [columns, rows]
+            }
+        },
+    }
+}
+
+/// Scale of `wallpaper` in `contain` mode with blurred edges: the image is letterboxed by the
+/// texture coordinates like in the fit modes, instead of by the vertices, so that the shader can
+/// fill the borders
+fn contain_texture_scale(wallpaper: &Wallpaper, display: (i32, i32)) -> [f32; 2] {
+    let taller = wallpaper.image_height as f32 * display.0 as f32
+        > display.1 as f32 * wallpaper.image_width as f32;
+    let mode = if taller {
+        BackgroundMode::FitHeight
+    } else {
+        BackgroundMode::FitWidth
+    };
+    gen_texture_scale(mode, TileSnap::None, wallpaper, display)
+}
+
+/// Color of a blackbody at `kelvin`, approximated as in
+/// <https://tannerhelland.com/2012/09/18/convert-temperature-rgb-algorithm-code.html>
+fn blackbody(kelvin: u32) -> [f32; 3] {
+    let temp = kelvin as f32 / 100.0;
+    let red = if temp <= 66.0 {
+        255.0
+    } else {
+        329.698_73 * (temp - 60.0).powf(-0.133_204_76)
+    };
+    let green = if temp <= 66.0 {
+        99.470_8 * temp.ln() - 161.119_57
+    } else {
+        288.122_16 * (temp - 60.0).powf(-0.075_514_85)
+    };
+    let blue = if temp >= 66.0 {
+        255.0
+    } else if temp <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (temp - 10.0).ln() - 305.044_8
+    };
+    [red, green, blue].map(|channel| channel.clamp(0.0, 255.0) / 255.0)
+}
+
+/// Multiplier of the colors for `temperature`, relative to the daylight white at 6500K that
+/// leaves the colors unchanged. No channel goes above 1.0, so nothing gets clipped
+fn temperature_tint(temperature: u32) -> [f32; 3] {
+    let color = blackbody(temperature);
+    let white = blackbody(6500);
+    let tint = [0, 1, 2].map(|channel| color[channel] / white[channel]);
+    let max = tint.into_iter().fold(f32::MIN_POSITIVE, f32::max);
+    tint.map(|channel| channel / max)
+}
+
 fn transparent_image() -> RgbaImage {
     RgbaImage::from_raw(1, 1, vec![0, 0, 0, 0]).unwrap()
 }
 
+/// Offscreen framebuffer the wallpaper is drawn into at a higher resolution, then blitted with
+/// linear filtering to the surface. Each pixel of the display averages up to 2x2 pixels, which
+/// is why the factor is limited to `2.0`
+struct Supersampling {
+    framebuffer: gl::types::GLuint,
+    renderbuffer: gl::types::GLuint,
+    width: i32,
+    height: i32,
+}
+
+impl Supersampling {
+    unsafe fn new(gl: &gl::Gl, width: i32, height: i32) -> Result<Self> {
+        let mut max_size = 0;
+        gl.GetIntegerv(gl::MAX_RENDERBUFFER_SIZE, &mut max_size);
+        gl_check!(gl, "getting the maximum size of the renderbuffers");
+        ensure!(
+            width <= max_size && height <= max_size,
+            "the supersampled size {width}x{height} is bigger than the GPU supports ({max_size})"
+        );
+
+        let mut renderbuffer = 0;
+        gl.GenRenderbuffers(1, &mut renderbuffer);
+        gl_check!(gl, "generating the supersampling renderbuffer");
+        gl.BindRenderbuffer(gl::RENDERBUFFER, renderbuffer);
+        gl_check!(gl, "binding the supersampling renderbuffer");
+        gl.RenderbufferStorage(gl::RENDERBUFFER, gl::RGBA8, width, height);
+        gl_check!(gl, "allocating the supersampling renderbuffer");
+
+        let mut framebuffer = 0;
+        gl.GenFramebuffers(1, &mut framebuffer);
+        gl_check!(gl, "generating the supersampling framebuffer");
+        gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, framebuffer);
+        gl_check!(gl, "binding the supersampling framebuffer");
+        gl.FramebufferRenderbuffer(
+            gl::DRAW_FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            gl::RENDERBUFFER,
+            renderbuffer,
+        );
+        gl_check!(gl, "attaching the supersampling renderbuffer");
+        let status = gl.CheckFramebufferStatus(gl::DRAW_FRAMEBUFFER);
+        gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+        gl.BindRenderbuffer(gl::RENDERBUFFER, 0);
+        let supersampling = Self {
+            framebuffer,
+            renderbuffer,
+            width,
+            height,
+        };
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            supersampling.delete(gl);
+            bail!("the supersampling framebuffer is not complete: {status:#x}");
+        }
+        Ok(supersampling)
+    }
+
+    unsafe fn delete(&self, gl: &gl::Gl) {
+        gl.DeleteFramebuffers(1, &self.framebuffer);
+        gl.DeleteRenderbuffers(1, &self.renderbuffer);
+    }
+}
+
+/// Size of the offscreen framebuffer for a display of `width`x`height` pixels
+fn supersampled_size(width: i32, height: i32, factor: f32) -> (i32, i32) {
+    let scale = |dimension: i32| (dimension as f32 * factor).round() as i32;
+    (scale(width), scale(height))
+}
+
 pub struct Renderer {
     gl: gl::Gl,
     pub program: gl::types::GLuint,
@@ -38,25 +218,80 @@ pub struct Renderer {
     // milliseconds time for the transition
     transition_time: u32,
     pub time_started: u32,
+    /// Progress of the transition at the last draw, `1.0` when it has finished
+    progress: f32,
     display_info: Rc<RefCell<DisplayInfo>>,
     old_wallpaper: Wallpaper,
     current_wallpaper: Wallpaper,
     transparent_texture: gl::types::GLuint,
+    sampler: gl::types::GLuint,
+    /// Whether the shader shows `border_color` outside of the textures, for the current mode
+    clamp_to_border: bool,
+    border_color: [f32; 4],
+    /// Fill the borders of `contain` mode with a blurred copy of the edges of the image
+    blur_edges: bool,
     transition_fit_changed: bool,
+    /// The last texture took longer than [`UPLOAD_BUDGET`] to upload
+    slow_upload: bool,
+    /// The first frame of the transition has been presented late, because of a slow upload.
+    /// The transition starts again from the next frame instead of skipping ahead
+    restart_transition: bool,
+    /// `transition = "none"`, every wallpaper is shown at once
+    instant_transition: bool,
+    vignette: f32,
+    opacity: f32,
+    /// See [`temperature_tint`]
+    tint: [f32; 3],
+    brightness: f32,
+    /// Strength of the film grain, animated with the time of the frame
+    grain: f32,
+    gravity: Gravity,
+    /// See [`WallpaperInfo::offset`](crate::wallpaper_info::WallpaperInfo::offset)
+    offset: (i32, i32),
+    tile_snap: TileSnap,
+    /// Mirror the image horizontally and vertically
+    flip: (bool, bool),
+    /// Radius in logical pixels and opacity of the shadow around the image in `contain` mode
+    drop_shadow: Option<(u32, f32)>,
+    /// Width of the shadow relative to the size of the image, zero without a shadow
+    shadow_size: [f32; 2],
+    /// See [`Transition::mask`]
+    mask_texture: Option<gl::types::GLuint>,
+    supersampling_factor: f32,
+    /// Set when `supersampling_factor` is above `1.0` and the display has a size
+    supersampling: Option<Supersampling>,
+    /// Longest side of the textures the GPU accepts
+    max_texture_size: u32,
+    /// The order of the channels that uploads fastest, see [`Renderer::texture_format`]
+    texture_format: TextureFormat,
+    /// The mode of the last successful [`Renderer::set_mode`], which the vertex buffer and the
+    /// uniforms of the textures have been computed for
+    scene_mode: Option<BackgroundMode>,
 }
 
 impl Renderer {
     pub unsafe fn new(
-        image: DynamicImage,
+        image: RgbaImage,
         display_info: Rc<RefCell<DisplayInfo>>,
         transition_time: u32,
         transition: Transition,
+        gravity: Gravity,
+        offset: (i32, i32),
+        tile_snap: TileSnap,
     ) -> Result<Self> {
         let gl = gl::Gl::load_with(|name| {
             egl.get_proc_address(name)
                 .expect("egl.get_proc_address to work") as *const std::ffi::c_void
         });
 
+        let mut max_texture_size = 0;
+        gl.GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_texture_size);
+        gl_check!(gl, "getting the maximum size of the textures");
+
+        let instant_transition = transition == Transition::None {};
+        let mask_texture = transition
+            .mask()
+            .and_then(|mask| load_mask(&gl, mask).map_err(|err| error!("{err:?}")).ok());
         let program = create_program(&gl, transition)
             .context("unable to create program during openGL ES initialization")?;
 
@@ -65,7 +300,10 @@ impl Renderer {
         let old_wallpaper = Wallpaper::new(display_info.clone());
         let current_wallpaper = Wallpaper::new(display_info.clone());
 
+        let texture_format = TextureFormat::detect(&gl);
+        debug!("uploading the decoded images as {texture_format:?}");
         let transparent_texture = load_texture(&gl, transparent_image().into())?;
+        let sampler = initialize_sampler(&gl)?;
 
         let mut renderer = Self {
             gl,
@@ -74,19 +312,50 @@ impl Renderer {
             vbo,
             eab,
             time_started: 0,
+            progress: 1.0,
             transition_time,
             old_wallpaper,
             current_wallpaper,
             display_info,
             transparent_texture,
+            sampler,
+            clamp_to_border: false,
+            border_color: [0.0; 4],
+            blur_edges: false,
             transition_fit_changed: false,
+            slow_upload: false,
+            restart_transition: false,
+            instant_transition,
+            vignette: 0.0,
+            opacity: 1.0,
+            tint: [1.0; 3],
+            brightness: 1.0,
+            grain: 0.0,
+            gravity,
+            offset,
+            tile_snap,
+            flip: (false, false),
+            drop_shadow: None,
+            shadow_size: [0.0, 0.0],
+            mask_texture,
+            supersampling_factor: 1.0,
+            supersampling: None,
+            max_texture_size: max_texture_size as u32,
+            texture_format,
+            scene_mode: None,
         };
 
-        renderer.load_wallpaper(image, BackgroundMode::Stretch)?;
+        renderer.load_wallpaper(image, None, BackgroundMode::Stretch)?;
 
         Ok(renderer)
     }
 
+    /// True if the GPU has been reset, see [`EglContext::is_context_lost`](super::EglContext)
+    pub fn context_lost(&self) -> bool {
+        self.gl.GetGraphicsResetStatus.is_loaded()
+            && unsafe { self.gl.GetGraphicsResetStatus() } != gl::NO_ERROR
+    }
+
     #[inline]
     pub fn check_error(&self, msg: &str) -> Result<()> {
         unsafe {
@@ -96,17 +365,65 @@ impl Renderer {
     }
 
     pub unsafe fn draw(&mut self, time: u32, mode: BackgroundMode) -> Result<bool> {
+        let Some(supersampling) = &self.supersampling else {
+            return self.draw_scene(time, mode);
+        };
+        let (framebuffer, width, height) = (
+            supersampling.framebuffer,
+            supersampling.width,
+            supersampling.height,
+        );
+        self.gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, framebuffer);
+        self.check_error("binding the supersampling framebuffer")?;
+        let transition_going = self.draw_scene(time, mode)?;
+
+        let info = self.display_info.borrow();
+        self.gl.BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+        self.check_error("binding the supersampling framebuffer for reading")?;
+        self.gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+        self.check_error("binding the surface framebuffer")?;
+        self.gl.BlitFramebuffer(
+            0,
+            0,
+            width,
+            height,
+            0,
+            0,
+            info.adjusted_width(),
+            info.adjusted_height(),
+            gl::COLOR_BUFFER_BIT,
+            gl::LINEAR,
+        );
+        self.check_error("downsampling the supersampling framebuffer")?;
+        self.gl.BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        self.check_error("unbinding the supersampling framebuffer")?;
+
+        Ok(transition_going)
+    }
+
+    /// Draw the wallpaper in the framebuffer currently bound, the surface or the one of
+    /// [`Supersampling`]
+    unsafe fn draw_scene(&mut self, time: u32, mode: BackgroundMode) -> Result<bool> {
         self.gl.Clear(gl::COLOR_BUFFER_BIT);
         self.check_error("clearing the screen")?;
 
-        let mut progress = ((time.saturating_sub(self.time_started)) as f32
-            / self.transition_time as f32)
-            .min(1.0);
+        // The frame at `time_started` still shows the old wallpaper, so it can be drawn again
+        if self.restart_transition && time != self.time_started {
+            self.time_started = time;
+            self.restart_transition = false;
+        }
+
+        let mut progress = if self.transition_time == 0 {
+            1.0
+        } else {
+            ((time.saturating_sub(self.time_started)) as f32 / self.transition_time as f32).min(1.0)
+        };
         let transition_going = progress != 1.0;
+        self.progress = progress;
 
         match mode {
-            BackgroundMode::Stretch | BackgroundMode::Center | BackgroundMode::Tile => {}
-            BackgroundMode::Fit => {
+            // With blurred edges, both images cover the display during the whole transition
+            BackgroundMode::Contain if !self.blur_edges => {
                 if !self.transition_fit_changed && progress > 0.5 {
                     self.gl.ActiveTexture(gl::TEXTURE0);
                     self.check_error("activating gl::TEXTURE0")?;
@@ -119,21 +436,116 @@ impl Renderer {
                     self.transition_fit_changed = true;
                     // This will recalculate the vertices
                     self.set_mode(mode, true)?;
-                    return Ok(transition_going);
+                    // Without a transition, the current wallpaper is drawn in this same frame
+                    if transition_going {
+                        return Ok(transition_going);
+                    }
                 }
                 if transition_going {
                     progress = (progress % 0.5) * 2.0;
                 }
             }
+            BackgroundMode::Stretch
+            | BackgroundMode::Cover
+            | BackgroundMode::FitWidth
+            | BackgroundMode::FitHeight
+            | BackgroundMode::Contain
+            | BackgroundMode::Tile
+            | BackgroundMode::Mosaic
+            | BackgroundMode::ContainBlur => {}
         }
 
         let loc = self
             .gl
-            .GetUniformLocation(self.program, b"progress\0".as_ptr() as *const _);
+            .GetUniformLocation(self.program, c"progress".as_ptr() as *const _);
         self.check_error("getting the uniform location")?;
         self.gl.Uniform1f(loc, progress);
         self.check_error("calling Uniform1i")?;
 
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"vignette".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl.Uniform1f(loc, self.vignette);
+        self.check_error("calling Uniform1f on vignette")?;
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"opacity".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl.Uniform1f(loc, self.opacity);
+        self.check_error("calling Uniform1f on opacity")?;
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"tint".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        let [red, green, blue] = self.tint;
+        self.gl.Uniform3f(loc, red, green, blue);
+        self.check_error("calling Uniform3f on tint")?;
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"brightness".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl.Uniform1f(loc, self.brightness);
+        self.check_error("calling Uniform1f on brightness")?;
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"grain".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl.Uniform1f(loc, self.grain);
+        self.check_error("calling Uniform1f on grain")?;
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"time".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        // In seconds, the wrapping keeps the precision of the float
+        self.gl
+            .Uniform1f(loc, (time % GRAIN_PERIOD) as f32 / 1000.0);
+        self.check_error("calling Uniform1f on time")?;
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"clampToBorder".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl.Uniform1i(loc, self.clamp_to_border as i32);
+        self.check_error("calling Uniform1i on clampToBorder")?;
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"borderColor".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl
+            .Uniform4fv(loc, 1, self.border_color.as_ptr() as *const _);
+        self.check_error("calling Uniform4fv on borderColor")?;
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"blurEdges".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        let blur_edges = self.blur_edges && mode == BackgroundMode::Contain;
+        self.gl.Uniform1i(loc, blur_edges as i32);
+        self.check_error("calling Uniform1i on blurEdges")?;
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"shadowSize".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl
+            .Uniform2fv(loc, 1, self.shadow_size.as_ptr() as *const _);
+        self.check_error("calling Uniform2fv on shadowSize")?;
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"shadowOpacity".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        let shadow_opacity = self.drop_shadow.map_or(0.0, |(_, opacity)| opacity);
+        self.gl.Uniform1f(loc, shadow_opacity);
+        self.check_error("calling Uniform1f on shadowOpacity")?;
+
         self.gl
             .DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, std::ptr::null());
         self.check_error("drawing the triangles")?;
@@ -141,36 +553,81 @@ impl Renderer {
         Ok(transition_going)
     }
 
-    pub fn load_wallpaper(&mut self, image: DynamicImage, mode: BackgroundMode) -> Result<()> {
+    /// Load `image` as the wallpaper to transition to. In the modes that crop it, the part
+    /// shown is centered on `focal_point` if any, instead of following the gravity
+    pub fn load_wallpaper(
+        &mut self,
+        image: impl Into<TextureImage>,
+        focal_point: Option<(f32, f32)>,
+        mode: BackgroundMode,
+    ) -> Result<()> {
+        let image = match image.into() {
+            // Decoded for another renderer, e.g. before the context was lost
+            image if image.format != TextureFormat::Rgba && image.format != self.texture_format => {
+                TextureImage::from(image.into_rgba())
+            }
+            image => image,
+        };
+        // Without a transition the old wallpaper is never drawn, so only the current one
+        // matters: when it is placed exactly like the previous one, the scene can be kept
+        let same_scene = self.instant_transition
+            && self.scene_mode == Some(mode)
+            && image.image.dimensions()
+                == (
+                    self.current_wallpaper.image_width,
+                    self.current_wallpaper.image_height,
+                )
+            && focal_point == self.current_wallpaper.focal_point;
         std::mem::swap(&mut self.old_wallpaper, &mut self.current_wallpaper);
-        self.current_wallpaper.load_image(&self.gl, image)?;
+        let started = Instant::now();
+        self.current_wallpaper
+            .load_image(&self.gl, image, focal_point)?;
+        let elapsed = started.elapsed();
+        self.slow_upload = elapsed > UPLOAD_BUDGET;
+        debug!(
+            "uploaded an image of {}x{} for display {} in {elapsed:?}",
+            self.current_wallpaper.image_width,
+            self.current_wallpaper.image_height,
+            self.display_info.borrow().name
+        );
 
-        self.bind_wallpapers(mode)?;
+        self.bind_wallpapers(mode, same_scene)?;
 
         Ok(())
     }
 
-    fn bind_wallpapers(&mut self, mode: BackgroundMode) -> Result<()> {
+    /// Bind the textures of the wallpapers, computing their placement again unless
+    /// `same_scene` is set
+    fn bind_wallpapers(&mut self, mode: BackgroundMode, same_scene: bool) -> Result<()> {
         match mode {
-            BackgroundMode::Stretch | BackgroundMode::Center | BackgroundMode::Tile => unsafe {
-                self.set_mode(mode, false)?;
+            BackgroundMode::Contain if !self.blur_edges => unsafe {
+                // We don't change the vertices, we still use the previous ones for the first half
+                // of the transition
                 self.gl.ActiveTexture(gl::TEXTURE0);
                 self.check_error("activating gl::TEXTURE0")?;
                 self.old_wallpaper.bind(&self.gl)?;
                 self.gl.ActiveTexture(gl::TEXTURE1);
                 self.check_error("activating gl::TEXTURE1")?;
-                self.current_wallpaper.bind(&self.gl)?;
+                self.gl
+                    .BindTexture(gl::TEXTURE_2D, self.transparent_texture);
             },
-            BackgroundMode::Fit => unsafe {
-                // We don't change the vertices, we still use the previous ones for the first half
-                // of the transition
+            BackgroundMode::Stretch
+            | BackgroundMode::Cover
+            | BackgroundMode::FitWidth
+            | BackgroundMode::FitHeight
+            | BackgroundMode::Contain
+            | BackgroundMode::Tile
+            | BackgroundMode::Mosaic
+            | BackgroundMode::ContainBlur => unsafe {
+                if !same_scene {
+                    self.set_mode(mode, false)?;
+                }
                 self.gl.ActiveTexture(gl::TEXTURE0);
                 self.check_error("activating gl::TEXTURE0")?;
                 self.old_wallpaper.bind(&self.gl)?;
                 self.gl.ActiveTexture(gl::TEXTURE1);
                 self.check_error("activating gl::TEXTURE1")?;
-                self.gl
-                    .BindTexture(gl::TEXTURE_2D, self.transparent_texture);
+                self.current_wallpaper.bind(&self.gl)?;
             },
         };
 
@@ -182,53 +639,139 @@ impl Renderer {
         mode: BackgroundMode,
         current_vertices_for_fit_mode: bool,
     ) -> Result<()> {
+        // Computed again from scratch if anything below fails
+        self.scene_mode = None;
         let (vertices, texture_scale, prev_texture_scale) = match mode {
-            BackgroundMode::Stretch | BackgroundMode::Center | BackgroundMode::Tile => {
-                let ratio = self.display_info.borrow().ratio();
-                let current_image_ratio = self.current_wallpaper.image_height as f32
-                    / self.current_wallpaper.image_width as f32;
-                let aspect = current_image_ratio / ratio;
-                let texture_scale = Box::new(match mode {
-                    BackgroundMode::Stretch => [1.0, 1.0],
-                    BackgroundMode::Center => [1.0, 1.0 / aspect],
-                    BackgroundMode::Fit => unreachable!(),
-                    BackgroundMode::Tile => [aspect, 1.0],
-                });
-                let prev_image_ratio =
-                    self.old_wallpaper.image_height as f32 / self.old_wallpaper.image_width as f32;
-                let aspect = prev_image_ratio / ratio;
-                let prev_texture_scale = Box::new(match mode {
-                    BackgroundMode::Stretch => [1.0, 1.0],
-                    BackgroundMode::Center => [1.0, 1.0 / aspect],
-                    BackgroundMode::Fit => unreachable!(),
-                    BackgroundMode::Tile => [aspect, 1.0],
-                });
-
+            BackgroundMode::Contain if self.blur_edges => {
+                let display_info = self.display_info.borrow();
+                let display = (
+                    display_info.adjusted_width(),
+                    display_info.adjusted_height(),
+                );
+                (
+                    Coordinates::default_vec_coordinates(),
+                    contain_texture_scale(&self.current_wallpaper, display),
+                    contain_texture_scale(&self.old_wallpaper, display),
+                )
+            }
+            BackgroundMode::Stretch
+            | BackgroundMode::Cover
+            | BackgroundMode::FitWidth
+            | BackgroundMode::FitHeight
+            | BackgroundMode::Tile
+            | BackgroundMode::Mosaic
+            | BackgroundMode::ContainBlur => {
+                // adjusted_width and adjusted_height returns the rotated sizes in case
+                // the display is rotated. However, openGL is drawing in the same orientation
+                // as our display (i.e. we don't apply any transform here)
+                let display_info = self.display_info.borrow();
+                let display = (
+                    display_info.adjusted_width(),
+                    display_info.adjusted_height(),
+                );
                 (
                     Coordinates::default_vec_coordinates(),
-                    texture_scale,
-                    prev_texture_scale,
+                    gen_texture_scale(mode, self.tile_snap, &self.current_wallpaper, display),
+                    gen_texture_scale(mode, self.tile_snap, &self.old_wallpaper, display),
                 )
             }
-            BackgroundMode::Fit => {
+            BackgroundMode::Contain => {
                 let vec_coordinates = if current_vertices_for_fit_mode {
                     self.current_wallpaper
-                        .generate_vertices_coordinates_for_fit_mode()
+                        .generate_vertices_coordinates_for_fit_mode(self.gravity)
                 } else {
                     self.old_wallpaper
-                        .generate_vertices_coordinates_for_fit_mode()
+                        .generate_vertices_coordinates_for_fit_mode(self.gravity)
                 };
 
-                let texture_scale = Box::new([1.0, 1.0]);
+                (vec_coordinates, [1.0, 1.0], [1.0, 1.0])
+            }
+        };
+        // Nudge the image in the modes placing it inside the display
+        let vertices = match mode {
+            BackgroundMode::Cover
+            | BackgroundMode::FitWidth
+            | BackgroundMode::FitHeight
+            | BackgroundMode::Contain => {
+                vertices.translate(self.display_info.borrow().gl_offset(self.offset))
+            }
+            BackgroundMode::Stretch
+            | BackgroundMode::Tile
+            | BackgroundMode::Mosaic
+            | BackgroundMode::ContainBlur => vertices,
+        };
+        // In cover mode, move the part of the image shown inside the whole image, or the image
+        // inside the borders in the fit modes; contain mode moves the vertices instead, unless
+        // its edges are blurred. The sides that are cropped follow the focal point, if any
+        let offset = |texture_scale: &[f32; 2], focal_point: Option<(f32, f32)>| match mode {
+            BackgroundMode::Contain if !self.blur_edges => [0.0, 0.0],
+            BackgroundMode::Cover
+            | BackgroundMode::FitWidth
+            | BackgroundMode::FitHeight
+            | BackgroundMode::Contain => {
+                let (mut x_position, mut y_position) = self.gravity.position();
+                if let Some((x_focus, y_focus)) = focal_point {
+                    x_position = focal_position(x_focus, texture_scale[0]).unwrap_or(x_position);
+                    y_position = focal_position(y_focus, texture_scale[1]).unwrap_or(y_position);
+                }
+                [
+                    x_position * (1.0 - texture_scale[0]),
+                    y_position * (1.0 - texture_scale[1]),
+                ]
+            }
+            BackgroundMode::Stretch
+            | BackgroundMode::Tile
+            | BackgroundMode::Mosaic
+            | BackgroundMode::ContainBlur => [0.0, 0.0],
+        };
+        let texture_offset = offset(&texture_scale, self.current_wallpaper.focal_point);
+        let prev_texture_offset = offset(&prev_texture_scale, self.old_wallpaper.focal_point);
 
-                (vec_coordinates, texture_scale.clone(), texture_scale)
+        // The image doesn't cover the display in contain mode, grow the vertices to make room
+        // for the shadow; the texture coordinates outside of 0.0..1.0 are the shadow
+        let (vertices, tex_coordinates, shadow_size) = match (mode, self.drop_shadow) {
+            (BackgroundMode::Contain, Some((radius, _))) if radius > 0 && !self.blur_edges => {
+                let radius = radius as i32;
+                let (x, y) = self.display_info.borrow().gl_offset((radius, radius));
+                // gl_offset goes towards the bottom, i.e. -1.0
+                let margin = (x, -y);
+                let (width, height) = vertices.size();
+                let shadow_size = [margin.0 / width, margin.1 / height];
+                (
+                    vertices.grow(margin),
+                    Coordinates::default_texture_coordinates()
+                        .grow((shadow_size[0], shadow_size[1])),
+                    shadow_size,
+                )
             }
+            _ => (
+                vertices,
+                Coordinates::default_texture_coordinates(),
+                [0.0, 0.0],
+            ),
         };
+        self.shadow_size = shadow_size;
+        // The shader works on the mirrored coordinates, so the transitions are mirrored too
+        let tex_coordinates = tex_coordinates.flip(self.flip);
+
+        let vertex_data = get_opengl_point_coordinates(vertices, tex_coordinates);
 
-        let vertex_data =
-            get_opengl_point_coordinates(vertices, Coordinates::default_texture_coordinates());
+        // Tile mode repeats the image, the other modes show the border color outside of it.
+        // CLAMP_TO_BORDER is not used because it blends the border into the outermost half
+        // texel, which covers the whole display for the textures of the solid colors
+        self.clamp_to_border = mode != BackgroundMode::Tile;
+        let wrap = if self.clamp_to_border {
+            gl::CLAMP_TO_EDGE
+        } else {
+            gl::REPEAT
+        };
 
         unsafe {
+            for param in [gl::TEXTURE_WRAP_S, gl::TEXTURE_WRAP_T] {
+                self.gl.SamplerParameteri(self.sampler, param, wrap as i32);
+                self.check_error("defining the sampler wrap mode")?;
+            }
+
             // Update the vertex buffer
             self.gl.BufferSubData(
                 gl::ARRAY_BUFFER,
@@ -240,7 +783,7 @@ impl Renderer {
 
             let loc = self
                 .gl
-                .GetUniformLocation(self.program, b"textureScale\0".as_ptr() as *const _);
+                .GetUniformLocation(self.program, c"textureScale".as_ptr() as *const _);
             self.check_error("getting the uniform location")?;
             ensure!(loc > 0, "textureScale not found");
             self.gl
@@ -249,24 +792,41 @@ impl Renderer {
 
             let loc = self
                 .gl
-                .GetUniformLocation(self.program, b"prevTextureScale\0".as_ptr() as *const _);
+                .GetUniformLocation(self.program, c"prevTextureScale".as_ptr() as *const _);
             self.check_error("getting the uniform location")?;
             ensure!(loc > 0, "prevTextureScale not found");
             self.gl
                 .Uniform2fv(loc, 1, prev_texture_scale.as_ptr() as *const _);
             self.check_error("calling Uniform2fv on prevTextureScale")?;
 
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, c"textureOffset".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl
+                .Uniform2fv(loc, 1, texture_offset.as_ptr() as *const _);
+            self.check_error("calling Uniform2fv on textureOffset")?;
+
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, c"prevTextureOffset".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl
+                .Uniform2fv(loc, 1, prev_texture_offset.as_ptr() as *const _);
+            self.check_error("calling Uniform2fv on prevTextureOffset")?;
+
             let display_info = self.display_info.borrow();
             let ratio =
                 display_info.adjusted_width() as f32 / display_info.adjusted_height() as f32;
 
             let loc = self
                 .gl
-                .GetUniformLocation(self.program, b"ratio\0".as_ptr() as *const _);
+                .GetUniformLocation(self.program, c"ratio".as_ptr() as *const _);
             self.check_error("getting the uniform location")?;
             self.gl.Uniform1f(loc, ratio);
             self.check_error("calling Uniform1f")?;
         }
+        self.scene_mode = Some(mode);
 
         Ok(())
     }
@@ -274,8 +834,18 @@ impl Renderer {
     #[inline]
     pub fn start_transition(&mut self, time: u32, new_transition_time: u32) {
         self.time_started = time;
-        self.transition_time = new_transition_time;
+        // A transition time of 0 draws a single frame, no other frame is requested
+        self.transition_time = if self.instant_transition {
+            0
+        } else {
+            new_transition_time
+        };
         self.transition_fit_changed = false;
+        self.restart_transition = self.slow_upload && self.transition_time > 0;
+        if self.restart_transition {
+            debug!("the upload was slow, starting the transition again on the next frame");
+        }
+        self.slow_upload = false;
     }
 
     #[inline]
@@ -293,18 +863,197 @@ impl Renderer {
         Ok(())
     }
 
+    /// Read back what has been drawn on the surface, before the buffers are swapped
+    pub fn read_pixels(&self) -> Result<RgbaImage> {
+        let (width, height) = {
+            let info = self.display_info.borrow();
+            (info.adjusted_width(), info.adjusted_height())
+        };
+        let mut pixels = vec![0; width as usize * height as usize * 4];
+        unsafe {
+            self.gl.BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+            self.check_error("binding the surface framebuffer for reading")?;
+            self.gl.ReadPixels(
+                0,
+                0,
+                width,
+                height,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_mut_ptr() as *mut _,
+            );
+            self.check_error("reading the pixels of the surface")?;
+        }
+        let image = RgbaImage::from_raw(width as u32, height as u32, pixels)
+            .context("the pixels read do not match the size of the display")?;
+        // OpenGL starts from the bottom row
+        Ok(image::imageops::flip_vertical(&image))
+    }
+
+    /// Resize the viewport and the supersampling framebuffer to the size of the display
     pub fn resize(&mut self) -> Result<()> {
-        let info = self.display_info.borrow();
+        let (width, height) = {
+            let info = self.display_info.borrow();
+            (info.adjusted_width(), info.adjusted_height())
+        };
+        // The scale of the textures depends on the size of the display
+        self.scene_mode = None;
         unsafe {
-            self.gl
-                .Viewport(0, 0, info.adjusted_width(), info.adjusted_height());
+            if let Some(supersampling) = self.supersampling.take() {
+                supersampling.delete(&self.gl);
+            }
+            let (width, height) = if self.supersampling_factor > 1.0 && width > 0 && height > 0 {
+                let (width, height) = supersampled_size(width, height, self.supersampling_factor);
+                let supersampling = Supersampling::new(&self.gl, width, height)
+                    .context("creating the supersampling framebuffer")?;
+                self.supersampling = Some(supersampling);
+                (width, height)
+            } else {
+                (width, height)
+            };
+            debug!(
+                "resizing the viewport of display {} to {width}x{height}",
+                self.display_info.borrow().name
+            );
+            self.gl.Viewport(0, 0, width, height);
             self.check_error("resizing the viewport")
         }
     }
 
+    /// Draw at `factor` times the resolution of the display, `1.0` draws directly on it
+    pub fn set_supersampling(&mut self, factor: f32) -> Result<()> {
+        self.supersampling_factor = factor;
+        self.resize()
+    }
+
+    #[inline]
+    pub fn progress(&self) -> f32 {
+        self.progress
+    }
+
+    #[inline]
+    pub fn transition_time(&self) -> u32 {
+        self.transition_time
+    }
+
     #[inline]
     pub fn update_transition_time(&mut self, transition_time: u32) {
-        self.transition_time = transition_time;
+        if !self.instant_transition {
+            self.transition_time = transition_time;
+        }
+    }
+
+    #[inline]
+    pub fn set_vignette(&mut self, vignette: f32) {
+        self.vignette = vignette;
+    }
+
+    /// Shown around the textures in every mode but `tile`, in both the previous and the current
+    /// wallpaper during the transitions
+    #[inline]
+    pub fn set_border_color(&mut self, border_color: [f32; 4]) {
+        self.border_color = border_color;
+    }
+
+    #[inline]
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+
+    /// Tint the wallpaper according to a color temperature in Kelvin
+    #[inline]
+    pub fn set_temperature(&mut self, temperature: u32) {
+        self.tint = temperature_tint(temperature);
+    }
+
+    /// Move the image according to `gravity`, only `cover` and `contain` modes are affected
+    pub fn set_gravity(&mut self, gravity: Gravity, mode: BackgroundMode) -> Result<()> {
+        self.gravity = gravity;
+        // In contain mode, keep the vertices of the wallpaper currently shown
+        self.set_mode(mode, self.transition_fit_changed)
+    }
+
+    /// Move the image by `offset`, only `cover` and `contain` modes are affected
+    pub fn set_offset(&mut self, offset: (i32, i32), mode: BackgroundMode) -> Result<()> {
+        self.offset = offset;
+        self.set_mode(mode, self.transition_fit_changed)
+    }
+
+    /// Only the `tile` mode is affected
+    pub fn set_tile_snap(&mut self, tile_snap: TileSnap, mode: BackgroundMode) -> Result<()> {
+        self.tile_snap = tile_snap;
+        self.set_mode(mode, self.transition_fit_changed)
+    }
+
+    /// Only the `contain` mode is affected, the image covers the display in the other modes.
+    /// The shadow is drawn once the vertices are computed again, see [`Self::refresh_vertices`]
+    #[inline]
+    pub fn set_drop_shadow(&mut self, drop_shadow: Option<(u32, f32)>) {
+        self.drop_shadow = drop_shadow;
+    }
+
+    /// Only the `contain` mode is affected. The image is then drawn over the whole display and
+    /// the transitions blend the two images, instead of switching the vertices halfway. Applied
+    /// once the wallpapers are bound again, see [`Self::rebind_wallpapers`]
+    #[inline]
+    pub fn set_blur_edges(&mut self, blur_edges: bool) {
+        self.blur_edges = blur_edges;
+    }
+
+    /// Bind both wallpapers and compute the vertices of the current one. A transition in
+    /// `contain` mode is completed with the vertices of the current wallpaper
+    pub fn rebind_wallpapers(&mut self, mode: BackgroundMode) -> Result<()> {
+        // The current wallpaper might be waiting for the second half of the transition
+        self.transition_fit_changed = true;
+        unsafe {
+            self.gl.ActiveTexture(gl::TEXTURE0);
+            self.check_error("activating gl::TEXTURE0")?;
+            self.old_wallpaper.bind(&self.gl)?;
+            self.gl.ActiveTexture(gl::TEXTURE1);
+            self.check_error("activating gl::TEXTURE1")?;
+            self.current_wallpaper.bind(&self.gl)?;
+        }
+        self.set_mode(mode, true)
+    }
+
+    /// Mirror the image, applied once the vertices are computed again, see
+    /// [`Self::refresh_vertices`]
+    #[inline]
+    pub fn set_flip(&mut self, flip: (bool, bool)) {
+        self.flip = flip;
+    }
+
+    /// Compute the vertices again, keeping the ones of the wallpaper currently shown in contain
+    /// mode
+    pub fn refresh_vertices(&mut self, mode: BackgroundMode) -> Result<()> {
+        self.set_mode(mode, self.transition_fit_changed)
+    }
+
+    /// The images are downscaled to it when decoded, see
+    /// [`ImageLoader::limit_texture_size`](crate::image_loader::ImageLoader::limit_texture_size)
+    #[inline]
+    pub fn max_texture_size(&self) -> u32 {
+        self.max_texture_size
+    }
+
+    /// The decoder threads store the channels of the images in this order, so that uploading
+    /// them is a plain copy. The RGBA images are accepted as well
+    #[inline]
+    pub fn texture_format(&self) -> TextureFormat {
+        self.texture_format
+    }
+
+    #[inline]
+    pub fn set_brightness(&mut self, brightness: f32) {
+        self.brightness = brightness;
+    }
+
+    /// The grain only moves when the wallpaper is drawn again, see [`Surface::draw`]
+    ///
+    /// [`Surface::draw`]: crate::surface::Surface::draw
+    #[inline]
+    pub fn set_grain(&mut self, grain: f32) {
+        self.grain = grain;
     }
 
     #[inline]
@@ -312,7 +1061,7 @@ impl Renderer {
         // By loading a transparent pixel into the old wallpaper, we free space from GPU memory
         if let Err(err) = self
             .old_wallpaper
-            .load_image(&self.gl, transparent_image().into())
+            .load_image(&self.gl, transparent_image().into(), None)
             .context("unloading the previous wallpaper")
         {
             error!("{err:?}");
@@ -321,6 +1070,18 @@ impl Renderer {
 
     #[inline]
     pub fn update_transition(&mut self, transition: Transition) {
+        self.instant_transition = transition == Transition::None {};
+        if let Some(mask_texture) = self.mask_texture.take() {
+            unsafe {
+                self.gl.DeleteTextures(1, &mask_texture);
+            }
+        }
+        if let Some(mask) = transition.mask() {
+            match load_mask(&self.gl, mask) {
+                Ok(mask_texture) => self.mask_texture = Some(mask_texture),
+                Err(err) => error!("{err:?}"),
+            }
+        }
         match create_program(&self.gl, transition) {
             Ok(program) => {
                 unsafe {
@@ -333,6 +1094,51 @@ impl Renderer {
     }
 }
 
+/// Load the grayscale mask of [`Transition::Mask`] in [`MASK_TEXTURE_UNIT`]. The shader samples
+/// it in normalized coordinates, so its size doesn't need to match the display
+fn load_mask(gl: &gl::Gl, path: &Path) -> Result<gl::types::GLuint> {
+    let image = DynamicImage::ImageLuma8(
+        image::open(path)
+            .with_context(|| format!("opening the transition mask {path:?}"))?
+            .into_luma8(),
+    )
+    .into_rgba8();
+    unsafe {
+        let mut texture = 0;
+        gl.GenTextures(1, &mut texture);
+        gl_check!(gl, "generating the mask texture");
+        // Leave the textures of the wallpapers bound to the other units
+        gl.ActiveTexture(gl::TEXTURE0 + MASK_TEXTURE_UNIT);
+        gl_check!(gl, "activating the mask texture unit");
+        gl.BindTexture(gl::TEXTURE_2D, texture);
+        gl_check!(gl, "binding the mask texture");
+        gl.TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGBA8.try_into().unwrap(),
+            image.width().try_into().unwrap(),
+            image.height().try_into().unwrap(),
+            0,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            image.as_ptr() as *const std::ffi::c_void,
+        );
+        gl_check!(gl, "defining the mask texture");
+        for (param, value) in [
+            (gl::TEXTURE_MIN_FILTER, gl::LINEAR),
+            (gl::TEXTURE_MAG_FILTER, gl::LINEAR),
+            (gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE),
+            (gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE),
+        ] {
+            gl.TexParameteri(gl::TEXTURE_2D, param, value as i32);
+            gl_check!(gl, "defining the mask texture parameters");
+        }
+        gl.ActiveTexture(gl::TEXTURE0);
+        gl_check!(gl, "activating gl::TEXTURE0");
+        Ok(texture)
+    }
+}
+
 fn create_program(gl: &gl::Gl, transition: Transition) -> Result<gl::types::GLuint> {
     unsafe {
         let program = gl.CreateProgram();
@@ -371,12 +1177,12 @@ fn create_program(gl: &gl::Gl, transition: Transition) -> Result<gl::types::GLui
         gl_check!(gl, "calling UseProgram");
 
         // We need to setup the uniform each time we create a program
-        let loc = gl.GetUniformLocation(program, b"u_prev_texture\0".as_ptr() as *const _);
+        let loc = gl.GetUniformLocation(program, c"u_prev_texture".as_ptr() as *const _);
         gl_check!(gl, "getting the uniform location for u_prev_texture");
         ensure!(loc > 0, "u_prev_texture not found");
         gl.Uniform1i(loc, 0);
         gl_check!(gl, "calling Uniform1i");
-        let loc = gl.GetUniformLocation(program, b"u_texture\0".as_ptr() as *const _);
+        let loc = gl.GetUniformLocation(program, c"u_texture".as_ptr() as *const _);
         gl_check!(gl, "getting the uniform location for u_texture");
         ensure!(loc > 0, "u_texture not found");
         gl.Uniform1i(loc, 1);
@@ -404,7 +1210,100 @@ impl Drop for Renderer {
             self.gl.DeleteBuffers(1, &self.eab);
             self.gl.DeleteBuffers(1, &self.vbo);
             self.gl.DeleteBuffers(1, &self.vao);
+            self.gl.DeleteSamplers(1, &self.sampler);
+            if let Some(mask_texture) = self.mask_texture {
+                self.gl.DeleteTextures(1, &mask_texture);
+            }
+            if let Some(supersampling) = &self.supersampling {
+                supersampling.delete(&self.gl);
+            }
             self.gl.DeleteProgram(self.program);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use smithay_client_toolkit::reexports::client::protocol::wl_output::Transform;
+
+    use super::*;
+
+    /// An image of 300x200 on a display of 1000x600
+    fn wallpaper() -> Wallpaper {
+        let display_info = Rc::new(RefCell::new(DisplayInfo {
+            name: "DP-1".to_string(),
+            make: "Unknown".to_string(),
+            model: "Unknown".to_string(),
+            serial: None,
+            description: None,
+            width: 1000,
+            height: 600,
+            scale: 1,
+            fractional_scale: None,
+            transform: Transform::Normal,
+            mode_dimensions: None,
+        }));
+        let mut wallpaper = Wallpaper::new(display_info);
+        wallpaper.image_width = 300;
+        wallpaper.image_height = 200;
+        wallpaper
+    }
+
+    #[test]
+    fn test_tile_snap() {
+        let wallpaper = wallpaper();
+        let scale =
+            |tile_snap| gen_texture_scale(BackgroundMode::Tile, tile_snap, &wallpaper, (1000, 600));
+        // 3.33 tiles horizontally, 3 whole ones when snapped
+        assert_eq!(scale(TileSnap::Horizontal)[0], 3.0);
+        assert_eq!(scale(TileSnap::Both), [3.0, 3.0]);
+        // The tiles are as tall as the display without snapping
+        assert_eq!(scale(TileSnap::None)[1], 1.0);
+    }
+
+    #[test]
+    fn test_fit_width_height() {
+        let wallpaper = wallpaper();
+        let scale = |mode| gen_texture_scale(mode, TileSnap::None, &wallpaper, (1000, 600));
+        // The image is slightly taller than the display, the top and bottom are cropped
+        let [x, y] = scale(BackgroundMode::FitWidth);
+        assert_eq!(x, 1.0);
+        assert!((y - 0.9).abs() < 1e-6);
+        // and there are borders at the left and right
+        let [x, y] = scale(BackgroundMode::FitHeight);
+        assert!((x - 10.0 / 9.0).abs() < 1e-6);
+        assert_eq!(y, 1.0);
+    }
+
+    #[test]
+    fn test_contain_texture_scale() {
+        // The image is slightly taller than the display, there are borders at the left and right
+        let [x, y] = contain_texture_scale(&wallpaper(), (1000, 600));
+        assert!((x - 10.0 / 9.0).abs() < 1e-6);
+        assert_eq!(y, 1.0);
+        // and at the top and bottom on a display taller than the image
+        let [x, y] = contain_texture_scale(&wallpaper(), (1000, 800));
+        assert_eq!(x, 1.0);
+        assert!((y - 1.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_temperature_tint() {
+        assert_eq!(temperature_tint(6500), [1.0; 3]);
+        // Warmer, blue is reduced the most
+        let [red, green, blue] = temperature_tint(3000);
+        assert_eq!(red, 1.0);
+        assert!(blue < green && green < 1.0);
+        // Cooler, red is reduced instead
+        let [red, _, blue] = temperature_tint(10000);
+        assert_eq!(blue, 1.0);
+        assert!(red < 1.0);
+    }
+
+    #[test]
+    fn test_supersampled_size() {
+        assert_eq!(supersampled_size(1920, 1080, 1.5), (2880, 1620));
+        assert_eq!(supersampled_size(1366, 768, 1.25), (1708, 960));
+        assert_eq!(supersampled_size(1920, 1080, 2.0), (3840, 2160));
+    }
+}
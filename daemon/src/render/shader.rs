@@ -78,21 +78,100 @@ layout (location = 3) uniform vec2 prevTextureScale;
 layout (location = 4) uniform sampler2D u_prev_texture;
 layout (location = 5) uniform sampler2D u_texture;
 
+uniform vec2 textureOffset;
+uniform vec2 prevTextureOffset;
+
 uniform float progress;
 uniform float ratio;
+uniform float vignette;
+uniform float opacity;
+uniform vec3 tint;
+uniform float brightness;
+// Strength of the film grain, animated by time in seconds
+uniform float grain;
+uniform float time;
+// Width of the shadow around the image, relative to its size
+uniform vec2 shadowSize;
+uniform float shadowOpacity;
+// Show borderColor outside of the textures, like CLAMP_TO_BORDER
+uniform bool clampToBorder;
+// Premultiplied, transparent by default
+uniform vec4 borderColor;
+// Show a blurred copy of the closest edge instead of borderColor, in contain mode
+uniform bool blurEdges;
 
 vec4 transition(vec2);
 
+// Pseudo-random value between 0.0 and 1.0
+float hash(highp vec3 p) {
+    p = fract(p * 0.1031);
+    p += dot(p, p.zyx + 31.32);
+    return fract((p.x + p.y) * p.z);
+}
+
+// Average of the taps around the closest point of the image, spread further apart the farther
+// uv is from it, so that the border gets blurrier towards the edges of the display
+vec4 blurredEdge(sampler2D tex, vec2 uv) {
+    vec2 edge = clamp(uv, 0.0, 1.0);
+    float spread = 0.01 + 0.1 * length(uv - edge);
+    vec4 color = vec4(0.0);
+    for (int x = -3; x <= 3; x++) {
+        for (int y = -3; y <= 3; y++) {
+            color += texture(tex, clamp(edge + vec2(x, y) * spread, 0.0, 1.0));
+        }
+    }
+    return color / 49.0;
+}
+
+vec4 sampleTexture(sampler2D tex, vec2 uv) {
+    if (clampToBorder && (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))))) {
+        if (blurEdges) {
+            return blurredEdge(tex, uv);
+        }
+        // The margin of the drop shadow stays transparent
+        bool inside = all(greaterThanEqual(v_texcoord, vec2(0.0)))
+            && all(lessThanEqual(v_texcoord, vec2(1.0)));
+        return inside ? borderColor : vec4(0.0);
+    }
+    return texture(tex, uv);
+}
+
 vec4 getFromColor(vec2 uv) {
-    uv = (uv - 0.5) * prevTextureScale + (0.5 * prevTextureScale);
-    return texture(u_prev_texture, uv);
+    uv = (uv - 0.5) * prevTextureScale + (0.5 * prevTextureScale) + prevTextureOffset;
+    return sampleTexture(u_prev_texture, uv);
 }
 
 vec4 getToColor(vec2 uv) {
-    uv = (uv - 0.5) * textureScale + (0.5 * textureScale);
-    return texture(u_texture, uv);
+    uv = (uv - 0.5) * textureScale + (0.5 * textureScale) + textureOffset;
+    return sampleTexture(u_texture, uv);
 }
 
 void main() {
-    FragColor = transition(v_texcoord);
+    vec4 color = transition(v_texcoord);
+    if (shadowOpacity > 0.0 && shadowSize.x > 0.0 && shadowSize.y > 0.0) {
+        // Distance from the image, 1.0 where the shadow ends
+        vec2 outside = max(max(-v_texcoord, v_texcoord - 1.0), 0.0);
+        float shadowDistance = length(outside / shadowSize);
+        if (shadowDistance > 0.0) {
+            // The shadow fades along with the closest edge of the image during the transitions
+            float edge = transition(clamp(v_texcoord, 0.0, 1.0)).a;
+            float shadow = shadowOpacity * edge * (1.0 - smoothstep(0.0, 1.0, shadowDistance));
+            // Draw the image over the black shadow
+            color += vec4(0.0, 0.0, 0.0, shadow) * (1.0 - color.a);
+        }
+    }
+    // Distance from the center, corrected by the ratio so that the vignette stays round;
+    // it is 1.0 in the corners
+    vec2 position = (v_texcoord - 0.5) * vec2(ratio, 1.0);
+    float distance = length(position) / length(vec2(ratio, 1.0) * 0.5);
+    color.rgb *= 1.0 - vignette * smoothstep(0.0, 1.0, distance);
+    color.rgb *= tint * brightness;
+    if (grain > 0.0) {
+        // A new pattern every 1/24 of a second, like the frames of a film
+        float noise = hash(vec3(gl_FragCoord.xy, floor(time * 24.0))) - 0.5;
+        color.rgb = clamp(color.rgb + noise * grain * 0.5 * color.a, 0.0, color.a);
+    }
+    // The compositor expects premultiplied alpha
+    color *= opacity;
+    FragColor = color;
 }";
@@ -0,0 +1,14 @@
+// License: MIT
+
+uniform bool vertical; // = false
+uniform float smoothness; // = 0.05
+
+vec4 transition(vec2 uv) {
+  // Distance from the line where the doors open, 1.0 at the edges of the display
+  float offset = abs((vertical ? uv.y : uv.x) - 0.5) * 2.0;
+  // The soft edge has gone past the edges when the transition ends
+  float opening = progress * (1.0 + smoothness);
+
+  float m = smoothstep(opening - smoothness, opening, offset);
+  return mix(getToColor(uv), getFromColor(uv), m);
+}
@@ -0,0 +1,33 @@
+// License: MIT
+
+uniform bool hexagonal; // = false
+uniform float rows; // = 12.0
+
+// How long each cell takes to fade in, relative to the whole transition
+const float FADE = 0.1;
+const vec2 HEXAGON = vec2(1.0, 1.7320508);
+
+// Integer coordinates of the cell containing p, in units of cells
+vec2 cell(vec2 p) {
+  if (!hexagonal) {
+    return floor(p);
+  }
+  // The center of the closest hexagon, from the two rectangular grids shifted by half a cell
+  vec2 a = mod(p, HEXAGON) - HEXAGON * 0.5;
+  vec2 b = mod(p - HEXAGON * 0.5, HEXAGON) - HEXAGON * 0.5;
+  vec2 center = p - (dot(a, a) < dot(b, b) ? a : b);
+  // Rounded, so that every fragment of a cell gets exactly the same threshold
+  return floor(center * 2.0 / HEXAGON + 0.5);
+}
+
+float threshold(highp vec2 id) {
+  return fract(sin(dot(id, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+vec4 transition(vec2 uv) {
+  // Square cells on the display, in units of cells
+  vec2 p = vec2(uv.x * ratio, uv.y) * rows;
+  float start = threshold(cell(p)) * (1.0 - FADE);
+  float m = smoothstep(start, start + FADE, progress);
+  return mix(getFromColor(uv), getToColor(uv), m);
+}
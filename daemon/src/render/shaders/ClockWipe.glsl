@@ -0,0 +1,26 @@
+// License: MIT
+
+uniform bool clockwise; // = true
+
+const float PI = 3.141592653589;
+// Angle of the soft edge between the wallpapers
+const float edge = 0.05;
+
+vec4 transition(vec2 uv) {
+  // Correct the horizontal distance by the ratio, so that the hand sweeps at a constant
+  // angular speed around the center of the display
+  vec2 dir = (uv - 0.5) * vec2(ratio, 1.0);
+  // Start from twelve o'clock, the texture coordinates grow downwards
+  float angle = atan(dir.x, -dir.y);
+  if (angle < 0.0) {
+    angle += 2.0 * PI;
+  }
+  if (!clockwise) {
+    angle = 2.0 * PI - angle;
+  }
+  // The soft edge has gone past the whole circle when the transition ends
+  float sweep = progress * (2.0 * PI + edge);
+
+  float m = smoothstep(sweep - edge, sweep, angle);
+  return mix(getToColor(uv), getFromColor(uv), m);
+}
@@ -0,0 +1,21 @@
+// License: MIT
+
+uniform float strength; // = 0.3
+
+// Luminance of the old wallpaper, its edges push the new one around
+float luminance(vec2 uv) {
+  return dot(getFromColor(uv).rgb, vec3(0.299, 0.587, 0.114));
+}
+
+vec4 transition(vec2 uv) {
+  // The same distance on both axes of the display
+  vec2 delta = vec2(0.01 / ratio, 0.01);
+  vec2 gradient = vec2(
+    luminance(uv + vec2(delta.x, 0.0)) - luminance(uv - vec2(delta.x, 0.0)),
+    luminance(uv + vec2(0.0, delta.y)) - luminance(uv - vec2(0.0, delta.y))
+  );
+  // The distortion eases out, the new wallpaper is in place when the transition ends
+  float remaining = 1.0 - progress;
+  vec2 offset = gradient * strength * remaining * remaining;
+  return mix(getFromColor(uv - offset * progress), getToColor(uv + offset), smoothstep(0.0, 1.0, progress));
+}
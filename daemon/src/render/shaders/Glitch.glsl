@@ -0,0 +1,36 @@
+// License: MIT
+
+uniform float intensity; // = 1.0
+
+// Number of horizontal bands along the height of the display
+const float bands = 24.0;
+// The bands jump to a new position this many times during the transition
+const float steps = 20.0;
+
+float hash(vec2 p) {
+  return fract(sin(dot(p, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+vec4 getColor(vec2 uv, float m) {
+  return mix(getFromColor(uv), getToColor(uv), m);
+}
+
+vec4 transition(vec2 uv) {
+  // Strongest at the midpoint, nothing left at the start and at the end
+  float strength = intensity * sin(progress * 3.14159265);
+  float frame = floor(progress * steps);
+  float band = floor(uv.y * bands);
+  // Only some of the bands are displaced at each step
+  float noise = hash(vec2(band, frame));
+  float displaced = step(1.0 - 0.4 * strength, noise);
+  float offset = displaced * (hash(vec2(frame, band)) - 0.5) * 0.2 * strength;
+  vec2 p = vec2(fract(uv.x + offset), uv.y);
+
+  // The bands swap to the new wallpaper at different times, every one has swapped at the end
+  float m = step(hash(vec2(band, 1.0)) * 0.5 + 0.25, progress);
+  float split = 0.02 * strength;
+  vec4 color = getColor(p, m);
+  color.r = getColor(vec2(fract(p.x + split), p.y), m).r;
+  color.b = getColor(vec2(fract(p.x - split), p.y), m).b;
+  return color;
+}
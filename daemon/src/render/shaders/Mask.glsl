@@ -0,0 +1,13 @@
+// License: MIT
+
+// Bound to the texture unit 2 by the renderer, in the same coordinates as the wallpapers
+uniform sampler2D u_mask;
+uniform float smoothness; // = 0.1
+
+vec4 transition(vec2 uv) {
+  // The darker parts of the mask show the new wallpaper first
+  float threshold = texture(u_mask, uv).r;
+  // Even the white parts have shown the new wallpaper when the transition ends
+  float m = smoothstep(threshold, threshold + smoothness, progress * (1.0 + smoothness));
+  return mix(getFromColor(uv), getToColor(uv), m);
+}
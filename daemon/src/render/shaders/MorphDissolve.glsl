@@ -0,0 +1,19 @@
+// License: MIT
+
+uniform float scale; // = 300.0
+
+// Width of the crossover of each grain, from the old wallpaper to the new one
+const float softness = 0.1;
+
+// Only depends on the coordinates, so that the grains do not shimmer between the frames
+float hash(vec2 p) {
+  return fract(sin(dot(p, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+vec4 transition(vec2 uv) {
+  // Square grains, `scale` of them along the height of the display
+  float threshold = hash(floor(uv * vec2(ratio, 1.0) * scale));
+  // Every grain has crossed over when the transition ends
+  float m = smoothstep(threshold, threshold + softness, progress * (1.0 + softness));
+  return mix(getFromColor(uv), getToColor(uv), m);
+}
@@ -0,0 +1,4 @@
+// The new wallpaper is shown right away, see Renderer::start_transition
+vec4 transition(vec2 uv) {
+    return getToColor(uv);
+}
@@ -0,0 +1,26 @@
+// License: MIT
+
+uniform vec2 center; // = vec2(0.5, 0.5)
+uniform float amplitude; // = 0.03
+
+// Width of the soft edge between the wallpapers
+const float edge = 0.1;
+const float frequency = 40.0;
+const float speed = 30.0;
+
+vec4 transition(vec2 uv) {
+  // Correct the distances by the ratio, so that the ripple stays round
+  vec2 aspect = vec2(ratio, 1.0);
+  vec2 dir = (uv - center) * aspect;
+  float dist = length(dir);
+  // The new wallpaper has covered the farthest corner when the transition ends
+  float radius = progress * (length(max(center, 1.0 - center) * aspect) + edge);
+
+  // The ripple grows quickly and then settles as the transition ends
+  float envelope = smoothstep(0.0, 0.1, progress) * (1.0 - progress);
+  float wave = sin(dist * frequency - progress * speed) * amplitude * envelope;
+  vec2 offset = dist > 0.0 ? dir / dist * wave / aspect : vec2(0.0);
+
+  float m = smoothstep(radius - edge, radius, dist);
+  return mix(getToColor(uv + offset), getFromColor(uv + offset), m);
+}
@@ -0,0 +1,29 @@
+// License: MIT
+
+uniform bool zoomIn; // = true
+
+// How much the wallpapers are scaled at the start and at the end of the transition
+const float zoom = 0.5;
+
+vec2 scaleFromCenter(vec2 uv, float scale) {
+  return (uv - 0.5) / scale + 0.5;
+}
+
+float inside(vec2 uv) {
+  return step(0.0, uv.x) * step(uv.x, 1.0) * step(0.0, uv.y) * step(uv.y, 1.0);
+}
+
+vec4 transition(vec2 uv) {
+  float p = smoothstep(0.0, 1.0, progress);
+  // The scale is the same on both axes, so the wallpapers keep their aspect ratio
+  float fromScale = zoomIn ? mix(1.0, 1.0 + zoom, p) : mix(1.0, 1.0 / (1.0 + zoom), p);
+  float toScale = zoomIn ? mix(1.0 / (1.0 + zoom), 1.0, p) : mix(1.0 + zoom, 1.0, p);
+
+  vec2 fromUv = scaleFromCenter(uv, fromScale);
+  vec2 toUv = scaleFromCenter(uv, toScale);
+  return mix(
+    getFromColor(fromUv) * inside(fromUv),
+    getToColor(toUv) * inside(toUv),
+    p
+  );
+}
@@ -0,0 +1,207 @@
+//! Software rendering, used when EGL is not available. The wallpaper is scaled on the CPU and
+//! copied into a shared memory buffer, without transitions nor effects.
+
+use std::{cell::RefCell, rc::Rc};
+
+use color_eyre::{eyre::Context, Result};
+use image::{imageops, Rgba, RgbaImage};
+use smithay_client_toolkit::{
+    reexports::client::protocol::{wl_shm, wl_surface::WlSurface},
+    shm::{
+        slot::{Buffer, SlotPool},
+        Shm,
+    },
+};
+
+use crate::{
+    display_info::DisplayInfo,
+    image_loader::focal_position,
+    wallpaper_info::{BackgroundMode, Gravity},
+};
+
+pub struct ShmRenderer {
+    pool: SlotPool,
+    /// The wallpaper loaded last, at its own size
+    image: RgbaImage,
+    /// Where the crop of the image is centered, see [`crate::image_loader::focal_point`]
+    focal_point: Option<(f32, f32)>,
+    /// Attached to the surface, it is kept until the next one replaces it
+    buffer: Option<Buffer>,
+    display_info: Rc<RefCell<DisplayInfo>>,
+}
+
+impl ShmRenderer {
+    pub fn new(shm: &Shm, display_info: Rc<RefCell<DisplayInfo>>) -> Result<Self> {
+        // The pool grows when the first buffer is created, once the size of the display is known
+        let pool = SlotPool::new(4, shm).context("creating the shared memory pool")?;
+        Ok(Self {
+            pool,
+            image: RgbaImage::new(1, 1),
+            focal_point: None,
+            buffer: None,
+            display_info,
+        })
+    }
+
+    pub fn load_wallpaper(&mut self, image: RgbaImage, focal_point: Option<(f32, f32)>) {
+        self.image = image;
+        self.focal_point = focal_point;
+    }
+
+    /// The wallpaper composed at the size of the display, see [`compose`]
+    pub fn frame(&self, mode: BackgroundMode, gravity: Gravity, border: Rgba<u8>) -> RgbaImage {
+        let size = {
+            let info = self.display_info.borrow();
+            (info.adjusted_width() as u32, info.adjusted_height() as u32)
+        };
+        compose(&self.image, size, mode, gravity, self.focal_point, border)
+    }
+
+    /// Copy the frame into a new buffer and attach it to `surface`, the caller commits it
+    pub fn draw(
+        &mut self,
+        surface: &WlSurface,
+        mode: BackgroundMode,
+        gravity: Gravity,
+        border: Rgba<u8>,
+    ) -> Result<()> {
+        let frame = self.frame(mode, gravity, border);
+        let (width, height) = (frame.width() as i32, frame.height() as i32);
+        let (buffer, canvas) = self
+            .pool
+            .create_buffer(width, height, width * 4, wl_shm::Format::Argb8888)
+            .context("creating the shared memory buffer")?;
+        // Argb8888 is little endian and premultiplied
+        for (pixel, bytes) in frame.pixels().zip(canvas.chunks_exact_mut(4)) {
+            let [red, green, blue, alpha] = pixel.0;
+            let premultiply = |channel: u8| (u16::from(channel) * u16::from(alpha) / 255) as u8;
+            bytes.copy_from_slice(&[
+                premultiply(blue),
+                premultiply(green),
+                premultiply(red),
+                alpha,
+            ]);
+        }
+        buffer
+            .attach_to(surface)
+            .context("attaching the shared memory buffer")?;
+        self.buffer = Some(buffer);
+        Ok(())
+    }
+}
+
+/// Scale `image` to a canvas of `size` filled with `border`, like the renderer does for `mode`.
+/// The modes composed before being drawn, `mosaic` and `contain-blur`, are stretched
+fn compose(
+    image: &RgbaImage,
+    (width, height): (u32, u32),
+    mode: BackgroundMode,
+    gravity: Gravity,
+    focal_point: Option<(f32, f32)>,
+    border: Rgba<u8>,
+) -> RgbaImage {
+    let (image_width, image_height) = (image.width() as f32, image.height() as f32);
+    let horizontal = width as f32 / image_width;
+    let vertical = height as f32 / image_height;
+    let scale = match mode {
+        BackgroundMode::Stretch | BackgroundMode::Mosaic | BackgroundMode::ContainBlur => {
+            return imageops::resize(image, width, height, imageops::FilterType::Triangle);
+        }
+        BackgroundMode::Cover => horizontal.max(vertical),
+        BackgroundMode::Contain => horizontal.min(vertical),
+        BackgroundMode::FitWidth => horizontal,
+        // The tiles are as tall as the display
+        BackgroundMode::FitHeight | BackgroundMode::Tile => vertical,
+    };
+    let scaled_width = ((image_width * scale).round() as u32).max(1);
+    let scaled_height = ((image_height * scale).round() as u32).max(1);
+    let scaled = imageops::resize(
+        image,
+        scaled_width,
+        scaled_height,
+        imageops::FilterType::Triangle,
+    );
+
+    let mut canvas = RgbaImage::from_pixel(width, height, border);
+    if mode == BackgroundMode::Tile {
+        for x in (0..width).step_by(scaled_width as usize) {
+            imageops::replace(&mut canvas, &scaled, x.into(), 0);
+        }
+    } else {
+        // The free space, or the part cropped when negative, is split according to the gravity.
+        // The sides that are cropped follow the focal point, if any
+        let (mut x_position, mut y_position) = gravity.position();
+        if let Some((x_focus, y_focus)) = focal_point {
+            let visible = |side: u32, scaled: u32| side as f32 / scaled as f32;
+            x_position =
+                focal_position(x_focus, visible(width, scaled_width)).unwrap_or(x_position);
+            y_position =
+                focal_position(y_focus, visible(height, scaled_height)).unwrap_or(y_position);
+        }
+        let x = ((width as f32 - scaled_width as f32) * x_position).round() as i64;
+        let y = ((height as f32 - scaled_height as f32) * y_position).round() as i64;
+        imageops::replace(&mut canvas, &scaled, x, y);
+    }
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BORDER: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+    #[test]
+    fn test_compose() {
+        // Twice as wide as tall, on a square display
+        let image = RgbaImage::from_pixel(20, 10, RED);
+        let compose = |mode, gravity| compose(&image, (40, 40), mode, gravity, None, BORDER);
+
+        let frame = compose(BackgroundMode::Cover, Gravity::Center);
+        assert_eq!(frame.dimensions(), (40, 40));
+        assert!(frame.pixels().all(|pixel| *pixel == RED));
+
+        // 40x20 in the middle of the display
+        let frame = compose(BackgroundMode::Contain, Gravity::Center);
+        assert_eq!(*frame.get_pixel(20, 5), BORDER);
+        assert_eq!(*frame.get_pixel(20, 20), RED);
+        assert_eq!(*frame.get_pixel(20, 35), BORDER);
+        // At the top
+        let frame = compose(BackgroundMode::Contain, Gravity::Top);
+        assert_eq!(*frame.get_pixel(20, 5), RED);
+        assert_eq!(*frame.get_pixel(20, 35), BORDER);
+
+        let frame = compose(BackgroundMode::Stretch, Gravity::Center);
+        assert!(frame.pixels().all(|pixel| *pixel == RED));
+    }
+
+    #[test]
+    fn test_compose_focal_point() {
+        // Red on the left half and blue on the right half, cropped to a square by cover mode
+        let blue = Rgba([0, 0, 255, 255]);
+        let mut image = RgbaImage::from_pixel(40, 20, RED);
+        for x in 20..40 {
+            for y in 0..20 {
+                image.put_pixel(x, y, blue);
+            }
+        }
+        let compose = |focal_point| {
+            compose(
+                &image,
+                (20, 20),
+                BackgroundMode::Cover,
+                Gravity::Center,
+                focal_point,
+                BORDER,
+            )
+        };
+
+        let frame = compose(None);
+        assert_eq!(*frame.get_pixel(2, 10), RED);
+        assert_eq!(*frame.get_pixel(17, 10), blue);
+        // Only the right half is shown
+        let frame = compose(Some((0.9, 0.5)));
+        assert!(frame.pixels().all(|pixel| *pixel == blue));
+    }
+}
@@ -1,10 +1,13 @@
-use std::ffi::CStr;
+use std::{
+    ffi::CStr,
+    path::{Path, PathBuf},
+};
 
 use color_eyre::{
     eyre::{bail, ensure},
     Result,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 //use wpaperd_transitions_proc_macro::Transitions;
 
 use crate::gl_check;
@@ -95,12 +98,82 @@ impl UniformSetter for [f32; 4] {
     }
 }
 
+/// Direction of the [`Transition::Zoom`] transition
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ZoomDirection {
+    /// The old wallpaper grows and the new one emerges from the center
+    In,
+    /// The old wallpaper shrinks and the new one comes in from a bigger scale
+    Out,
+}
+
+impl UniformSetter for ZoomDirection {
+    unsafe fn set_uniform(&self, gl: &gl::Gl, loc: gl::types::GLint) {
+        unsafe {
+            gl.Uniform1i(loc, (*self == ZoomDirection::In).into());
+        }
+    }
+}
+
+/// Direction the doors of [`Transition::BarnDoor`] slide to
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BarnDoorAxis {
+    /// The doors open from a vertical line in the middle towards the left and the right
+    Horizontal,
+    /// The doors open from a horizontal line in the middle towards the top and the bottom
+    Vertical,
+}
+
+impl UniformSetter for BarnDoorAxis {
+    unsafe fn set_uniform(&self, gl: &gl::Gl, loc: gl::types::GLint) {
+        unsafe {
+            gl.Uniform1i(loc, (*self == BarnDoorAxis::Vertical).into());
+        }
+    }
+}
+
+/// Shape of the cells of [`Transition::Cells`]
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CellShape {
+    Square,
+    /// Hexagons with a vertex at the top, in staggered rows
+    Hex,
+}
+
+impl UniformSetter for CellShape {
+    unsafe fn set_uniform(&self, gl: &gl::Gl, loc: gl::types::GLint) {
+        unsafe {
+            gl.Uniform1i(loc, (*self == CellShape::Hex).into());
+        }
+    }
+}
+
+/// Texture unit of the mask of [`Transition::Mask`]
+pub const MASK_TEXTURE_UNIT: gl::types::GLenum = 2;
+
+/// Path of the grayscale image of [`Transition::Mask`]
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(transparent)]
+pub struct TransitionMask(pub PathBuf);
+
+impl UniformSetter for TransitionMask {
+    /// The renderer loads the image in [`MASK_TEXTURE_UNIT`], only the sampler is set here
+    unsafe fn set_uniform(&self, gl: &gl::Gl, loc: gl::types::GLint) {
+        unsafe {
+            gl.Uniform1i(loc, MASK_TEXTURE_UNIT as i32);
+        }
+    }
+}
+
 macro_rules! transition_shader {
     ($enum:ident { $($variant:ident { $($field_name:ident: $field_ty:ty = ($glsl_name:literal, $default_value:expr)),* } => $default_time:expr),* }) => {
-        #[derive(Deserialize, Clone, Debug, PartialEq)]
+        #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
         #[serde(rename_all = "kebab-case", tag = "transition")]
         pub enum $enum {
-            $($variant { $($field_name: Option<$field_ty>),* }),*
+            $($variant { $(#[serde(skip_serializing_if = "Option::is_none")] $field_name: Option<$field_ty>),* }),*
         }
 
         impl $enum {
@@ -118,7 +191,7 @@ macro_rules! transition_shader {
                                     let loc = gl.GetUniformLocation(program, format_bytes!(b"{}\0", $glsl_name.as_bytes()).as_ptr() as *const _);
                                     gl_check!(gl, format!("getting the uniform location for {}", $glsl_name));
                                     ensure!(loc >= 0, "uniform {} cannot be found", $glsl_name);
-                                    $field_name.unwrap_or($default_value).set_uniform(gl, loc);
+                                    $field_name.as_ref().unwrap_or(&$default_value).set_uniform(gl, loc);
                                     gl_check!(gl, format!("calling Uniform on {}", $glsl_name));
                                 }
                             )*
@@ -140,6 +213,10 @@ macro_rules! transition_shader {
 
 transition_shader! {
     Transition {
+        BarnDoor {
+            axis: BarnDoorAxis = ("vertical", BarnDoorAxis::Horizontal),
+            smoothness: f32 = ("smoothness", 0.05)
+        } => 1500,
         BookFlip{} => 2000,
         Bounce {
             shadow_colour: [f32; 4] = ("shadow_colour", [0.0, 0.0, 0.0, 0.6]),
@@ -153,7 +230,12 @@ transition_shader! {
             waves: f32 = ("waves", 30.0),
             color_separation: f32 = ("colorSeparation", 0.3)
         } => 2000,
+        Cells {
+            shape: CellShape = ("hexagonal", CellShape::Square),
+            rows: f32 = ("rows", 12.0)
+        } => 2000,
         Circle{} => 3000,
+        ClockWipe { clockwise: bool = ("clockwise", true) } => 1500,
         CircleCrop{
             bgcolor: [f32; 4] = ("bgcolor", [0.0, 0.0, 0.0, 1.0])
         } => 3000,
@@ -173,6 +255,7 @@ transition_shader! {
             direction: [f32; 2] = ("direction", [1.0, -1.0]),
             smoothness: f32 = ("smoothness", 0.5)
         } => 1000,
+        Displace { strength: f32 = ("strength", 0.3) } => 1500,
         Dissolve {
             line_width: f32 = ("uLineWidth", 0.1),
             spread_clr: [f32; 3] = ("uSpreadClr", [1.0, 0.0, 0.0]),
@@ -198,6 +281,7 @@ transition_shader! {
         } => 1500,
         Fade{} => 300,
         FilmBurn { seed: f32 = ("Seed", 2.31) } => 2000,
+        Glitch { intensity: f32 = ("intensity", 1.0) } => 1000,
         GlitchDisplace{} => 1500,
         GlitchMemories{} => 1500,
         GridFlip {
@@ -216,10 +300,16 @@ transition_shader! {
         InvertedPageCurl{} => 2000,
         LeftRight{} => 2000,
         LinearBlur { intensity: f32 = ("intensity", 0.1) } => 800,
+        Mask {
+            mask: TransitionMask = ("u_mask", TransitionMask::default()),
+            smoothness: f32 = ("smoothness", 0.1)
+        } => 2000,
         Mosaic{
             endx: i32 = ("endx", 2),
             endy: i32 = ("endy", -1)
         } => 2000,
+        MorphDissolve { scale: f32 = ("scale", 300.0) } => 1500,
+        None {} => 0,
         Overexposure{} => 2000,
         Pixelize {
             squares_min: [i32; 2] = ("squaresMin", [20, 20]),
@@ -235,6 +325,10 @@ transition_shader! {
             amplitude: f32 = ("amplitude", 100.0),
             speed: f32 = ("speed", 50.0)
         } => 1500,
+        RippleReveal {
+            center: [f32; 2] = ("center", [0.5, 0.5]),
+            amplitude: f32 = ("amplitude", 0.03)
+        } => 2500,
         Rolls {
             rolls_type: i32 = ("type", 0),
             rot_down: bool = ("RotDown", false)
@@ -244,6 +338,17 @@ transition_shader! {
             rotations: f32 = ("rotations", 1.0),
             scale: f32 = ("scale", 8.0),
             back_color: [f32; 4] = ("backColor", [0.15, 0.15, 0.15, 1.0])
-        } => 1500
+        } => 1500,
+        Zoom { direction: ZoomDirection = ("zoomIn", ZoomDirection::In) } => 1000
+    }
+}
+
+impl Transition {
+    /// The image loaded in [`MASK_TEXTURE_UNIT`], if the transition needs one
+    pub fn mask(&self) -> Option<&Path> {
+        match self {
+            Transition::Mask { mask, .. } => mask.as_ref().map(|mask| mask.0.as_path()),
+            _ => None,
+        }
     }
 }
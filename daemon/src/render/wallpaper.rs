@@ -4,16 +4,17 @@ use color_eyre::{
     eyre::{bail, ensure},
     Result,
 };
-use image::DynamicImage;
 
-use crate::{display_info::DisplayInfo, gl_check, render::gl};
+use crate::{display_info::DisplayInfo, gl_check, render::gl, wallpaper_info::Gravity};
 
-use super::{coordinates::Coordinates, load_texture};
+use super::{coordinates::Coordinates, load_texture, TextureImage};
 
 pub struct Wallpaper {
     pub texture: gl::types::GLuint,
     pub image_width: u32,
     pub image_height: u32,
+    /// Where the crop of the image is centered, see [`crate::image_loader::focal_point`]
+    pub focal_point: Option<(f32, f32)>,
     display_info: Rc<RefCell<DisplayInfo>>, // transparent_texture: gl::types::GLuint,
 }
 
@@ -23,6 +24,7 @@ impl Wallpaper {
             texture: 0,
             image_width: 10,
             image_height: 10,
+            focal_point: None,
             display_info,
         }
     }
@@ -36,9 +38,15 @@ impl Wallpaper {
         Ok(())
     }
 
-    pub fn load_image(&mut self, gl: &gl::Gl, image: DynamicImage) -> Result<()> {
-        self.image_width = image.width();
-        self.image_height = image.height();
+    pub fn load_image(
+        &mut self,
+        gl: &gl::Gl,
+        image: TextureImage,
+        focal_point: Option<(f32, f32)>,
+    ) -> Result<()> {
+        self.image_width = image.image.width();
+        self.image_height = image.image.height();
+        self.focal_point = focal_point;
 
         let texture = load_texture(gl, image)?;
 
@@ -51,24 +59,24 @@ impl Wallpaper {
         Ok(())
     }
 
-    pub fn generate_vertices_coordinates_for_fit_mode(&self) -> Coordinates {
+    pub fn generate_vertices_coordinates_for_fit_mode(&self, gravity: Gravity) -> Coordinates {
         let display_width = self.display_info.borrow().scaled_width();
         let display_height = self.display_info.borrow().scaled_height();
         let display_ratio = display_width as f32 / display_height as f32;
         let image_ratio = self.image_width as f32 / self.image_height as f32;
-        if display_ratio == image_ratio {
-            Coordinates::default_vec_coordinates()
+        // Half of the width and height of the image, in openGL coordinates
+        let (x, y) = if display_ratio == image_ratio {
+            return Coordinates::default_vec_coordinates();
         } else if display_ratio > image_ratio {
-            let x = image_ratio / display_ratio;
-            Coordinates::new(-x, x, Coordinates::VEC_Y_BOTTOM, Coordinates::VEC_Y_TOP)
+            (image_ratio / display_ratio, 1.0)
         } else {
-            let y = 1.0 - display_ratio / image_ratio;
-            Coordinates::new(
-                Coordinates::VEC_X_LEFT,
-                Coordinates::VEC_X_RIGHT,
-                Coordinates::VEC_Y_BOTTOM - y,
-                Coordinates::VEC_Y_TOP + y,
-            )
-        }
+            (1.0, display_ratio / image_ratio)
+        };
+        // Move the center of the image inside the free space, the left of the display is at
+        // -1.0 and the top is at 1.0
+        let (x_position, y_position) = gravity.position();
+        let x_center = (1.0 - x) * (2.0 * x_position - 1.0);
+        let y_center = (1.0 - y) * (1.0 - 2.0 * y_position);
+        Coordinates::new(x_center - x, x_center + x, y_center + y, y_center - y)
     }
 }
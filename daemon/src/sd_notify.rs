@@ -0,0 +1,31 @@
+//! Notify systemd about the state of the daemon, for the services with `Type=notify`.
+//! See sd_notify(3), the messages are sent as datagrams to the socket in `NOTIFY_SOCKET`.
+
+use std::{env, os::unix::net::UnixDatagram};
+
+use color_eyre::{eyre::Context, Result};
+
+/// Send `state` (e.g. `READY=1`), do nothing when not started by systemd
+pub fn notify(state: &str) -> Result<()> {
+    let Some(socket_path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let socket = UnixDatagram::unbound()?;
+    let path = socket_path.to_string_lossy();
+    // Socket paths starting with @ are in the abstract namespace
+    if let Some(name) = path.strip_prefix('@') {
+        use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+        socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)
+    } else {
+        socket.send_to(state.as_bytes(), &*socket_path)
+    }
+    .with_context(|| format!("sending {state:?} to the systemd notification socket"))?;
+    Ok(())
+}
+
+/// Tell systemd how many displays have a wallpaper
+pub fn notify_status(outputs: usize) -> Result<()> {
+    notify(&format!(
+        "STATUS=Showing the wallpaper on {outputs} displays"
+    ))
+}
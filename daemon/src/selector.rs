@@ -0,0 +1,138 @@
+//! Custom selection of the next wallpaper, enabled by the `script` feature. The `selector` is a
+//! [Rhai](https://rhai.rs) script run by an embedded engine: it can't access the files, the
+//! network or the state of the daemon, and it's stopped after [`TIMEOUT`]. It receives the
+//! candidate images in `files`, the current wallpaper in `current` and the local time in `hour`,
+//! `minute` and `weekday`, then returns the chosen image, either its path or its index in
+//! `files`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use chrono::{Datelike, Local, Timelike};
+use color_eyre::{
+    eyre::{bail, eyre, Context, ContextCompat},
+    Result,
+};
+use log::{debug, info};
+use rhai::{Array, Dynamic, Engine, Scope, INT};
+
+/// The script runs on the main thread, it is stopped when it takes longer than this
+const TIMEOUT: Duration = Duration::from_millis(200);
+/// Limits on the values the script can build, so that it can't exhaust the memory
+const MAX_STRING_SIZE: usize = 64 * 1024;
+const MAX_ARRAY_SIZE: usize = 1024 * 1024;
+const MAX_MAP_SIZE: usize = 64 * 1024;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_EXPR_DEPTH: usize = 64;
+
+/// Run the script `selector` and return the index in `files` of the image it has chosen
+pub fn select(selector: &Path, files: &[PathBuf], current_img: &Path) -> Result<usize> {
+    let script = fs::read_to_string(selector)
+        .with_context(|| format!("reading the selector {selector:?}"))?;
+    let engine = engine(Instant::now() + TIMEOUT, files.len());
+    let ast = engine
+        .compile(script)
+        .map_err(|err| eyre!("the selector {selector:?} is not a valid script: {err}"))?;
+
+    let now = Local::now();
+    let mut scope = Scope::new();
+    scope.push_constant(
+        "files",
+        files
+            .iter()
+            .map(|file| Dynamic::from(file.to_string_lossy().into_owned()))
+            .collect::<Array>(),
+    );
+    scope.push_constant("current", current_img.to_string_lossy().into_owned());
+    scope.push_constant("hour", INT::from(now.hour()));
+    scope.push_constant("minute", INT::from(now.minute()));
+    // From 0 on Monday to 6 on Sunday
+    scope.push_constant("weekday", INT::from(now.weekday().num_days_from_monday()));
+
+    let chosen: Dynamic = engine
+        .eval_ast_with_scope(&mut scope, &ast)
+        .map_err(|err| eyre!("the selector {selector:?} failed: {err}"))?;
+    let index = if let Some(index) = chosen.clone().try_cast::<INT>() {
+        usize::try_from(index)
+            .ok()
+            .filter(|index| *index < files.len())
+    } else if let Some(chosen) = chosen.clone().try_cast::<String>() {
+        // The order of the candidates depends on the sorting, search all of them
+        let chosen = Path::new(chosen.trim());
+        files.iter().position(|file| file == chosen)
+    } else {
+        bail!(
+            "the selector {selector:?} returned a {}, instead of a path or an index",
+            chosen.type_name()
+        );
+    };
+    index.with_context(|| {
+        format!("the selector {selector:?} chose {chosen}, which is not one of the candidates")
+    })
+}
+
+/// An engine without access to the system, that stops the scripts running after `deadline`
+fn engine(deadline: Instant, files: usize) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_string_size(MAX_STRING_SIZE)
+        .set_max_array_size(MAX_ARRAY_SIZE.max(files))
+        .set_max_map_size(MAX_MAP_SIZE)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH)
+        .on_progress(move |_| (Instant::now() >= deadline).then_some(Dynamic::UNIT))
+        .on_print(|text| info!("selector: {text}"))
+        .on_debug(|text, _, position| debug!("selector at {position}: {text}"));
+    // Scripts can't be evaluated from strings built at runtime
+    engine.disable_symbol("eval");
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selector(name: &str, script: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("wpaperd-selector-{name}.rhai"));
+        fs::write(&path, script).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_select() {
+        // Not sorted, like the images of the shuffle sorting
+        let files = [PathBuf::from("/b.png"), PathBuf::from("/a.png")];
+        let last = selector("last", "files[files.len() - 1]");
+        assert_eq!(select(&last, &files, Path::new("/b.png")).unwrap(), 1);
+        let index = selector("index", "if hour < 24 { 0 } else { 1 }");
+        assert_eq!(select(&index, &files, Path::new("/b.png")).unwrap(), 0);
+
+        let current = selector("current", "current");
+        assert_eq!(select(&current, &files, Path::new("/a.png")).unwrap(), 1);
+        // The current image is not among the candidates
+        assert!(select(&current, &files, Path::new("/c.png")).is_err());
+        assert!(select(&selector("out-of-range", "2"), &files, Path::new("")).is_err());
+
+        assert!(select(&selector("invalid", "files["), &files, Path::new("")).is_err());
+        assert!(select(&selector("throw", "throw \"no\""), &files, Path::new("")).is_err());
+        assert!(select(&selector("unit", "let a = 1;"), &files, Path::new("")).is_err());
+    }
+
+    #[test]
+    fn test_select_sandbox() {
+        let files = [PathBuf::from("/a.png")];
+        let start = Instant::now();
+        assert!(select(&selector("loop", "loop {}"), &files, Path::new("")).is_err());
+        assert!(start.elapsed() < TIMEOUT * 5);
+
+        let eval = selector("eval", "eval(\"files[0]\")");
+        assert!(select(&eval, &files, Path::new("")).is_err());
+        let import = selector("import", "import \"other\" as other; files[0]");
+        assert!(select(&import, &files, Path::new("")).is_err());
+        let memory = selector("memory", "let s = \"a\"; loop { s += s; }");
+        assert!(select(&memory, &files, Path::new("")).is_err());
+    }
+}
@@ -1,31 +1,57 @@
 use std::{
-    cell::RefCell,
-    path::PathBuf,
+    cell::{Ref, RefCell},
+    ffi::OsStr,
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
     rc::Rc,
     time::{Duration, Instant},
 };
 
-use color_eyre::eyre::{Context, ContextCompat};
+use chrono::{DateTime, Days, Local};
+use color_eyre::eyre::{bail, Context, ContextCompat, Report};
 use color_eyre::Result;
-use image::RgbaImage;
-use log::{error, warn};
+use image::{imageops, Rgba, RgbaImage};
+use log::{debug, error, info, warn};
+use rand::seq::SliceRandom;
 use smithay_client_toolkit::reexports::calloop::{LoopHandle, RegistrationToken};
 use smithay_client_toolkit::reexports::client::protocol::wl_output::{Transform, WlOutput};
-use smithay_client_toolkit::reexports::client::protocol::wl_surface;
-use smithay_client_toolkit::reexports::client::QueueHandle;
-use smithay_client_toolkit::shell::wlr_layer::{LayerSurface, LayerSurfaceConfigure};
+use smithay_client_toolkit::reexports::client::protocol::wl_seat::WlSeat;
+use smithay_client_toolkit::reexports::client::protocol::{wl_buffer::WlBuffer, wl_surface};
+use smithay_client_toolkit::reexports::client::{Proxy, QueueHandle};
+use smithay_client_toolkit::reexports::protocols::ext::idle_notify::v1::client::{
+    ext_idle_notification_v1::ExtIdleNotificationV1, ext_idle_notifier_v1::ExtIdleNotifierV1,
+};
+use smithay_client_toolkit::reexports::protocols_wlr::output_power_management::v1::client::zwlr_output_power_v1::ZwlrOutputPowerV1;
+use smithay_client_toolkit::reexports::protocols::wp::fractional_scale::v1::client::wp_fractional_scale_v1::WpFractionalScaleV1;
+use smithay_client_toolkit::reexports::protocols::wp::single_pixel_buffer::v1::client::wp_single_pixel_buffer_manager_v1::WpSinglePixelBufferManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewport::WpViewport;
+use smithay_client_toolkit::shell::wlr_layer::{LayerSurface, LayerSurfaceConfigure, SurfaceKind};
 use smithay_client_toolkit::{
     reexports::calloop::timer::{TimeoutAction, Timer},
     shell::WaylandSurface,
+    shm::Shm,
 };
 
+use wpaperd_ipc::{DebugInfo, IpcEvent};
+
 use crate::wpaperd::Wpaperd;
-use crate::{display_info::DisplayInfo, wallpaper_info::WallpaperInfo};
 use crate::{
-    filelist_cache::FilelistCache,
-    render::{EglContext, Renderer},
+    display_info::{DisplayInfo, OutputHeadInfo},
+    download::is_url,
+    wallpaper_info::{
+        BackgroundMode, Color, Gradient, GroupMode, MaxTextureSize, Sorting, WallpaperInfo,
+    },
+};
+use crate::{
+    filelist_cache::{FilelistCache, Orientation},
+    ipc_server::EventSubscribers,
+    render::{EglContext, Renderer, ShmRenderer, TextureFormat, TextureImage},
+};
+use crate::{
+    image_loader::{letterbox_blur, ImageLoader, ImageLoaderStatus, SizeLimit},
+    image_picker::{ImagePicker, IN_MEMORY_IMAGE_PATH},
 };
-use crate::{image_loader::ImageLoader, image_picker::ImagePicker};
 
 #[derive(Debug)]
 pub enum EventSource {
@@ -35,20 +61,122 @@ pub enum EventSource {
     Paused(Duration),
 }
 
+/// A scale sent by the compositor, see [`Surface::debounce_scale`]
+#[derive(Clone, Copy)]
+enum Scale {
+    Integer(i32),
+    /// In 120ths, sent via wp_fractional_scale_v1
+    Fractional(u32),
+}
+
+/// See `startup_delay` in [`WallpaperInfo`]
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StartupHold {
+    /// The display hasn't been drawn yet
+    Pending,
+    /// The display stays black until the images are available or the delay expires
+    Holding,
+    Done,
+}
+
+/// How the wallpaper is drawn on the surface
+enum Backend {
+    Gl {
+        egl_context: EglContext,
+        renderer: Box<Renderer>,
+    },
+    /// EGL is not available, the wallpaper is drawn without transitions nor effects
+    Shm(ShmRenderer),
+}
+
+/// Rolling average of the interval between frames, used for diagnostics
+#[derive(Debug, Default)]
+struct FrameStats {
+    last_frame: Option<u32>,
+    average_interval: Option<f32>,
+}
+
+impl FrameStats {
+    /// Frames further apart than this belong to different sequences of draws
+    const MAX_INTERVAL: u32 = 1000;
+
+    fn record(&mut self, time: u32) {
+        if let Some(interval) = self
+            .last_frame
+            .map(|last_frame| time.wrapping_sub(last_frame))
+            .filter(|interval| *interval <= Self::MAX_INTERVAL)
+        {
+            let interval = interval as f32;
+            self.average_interval = Some(match self.average_interval {
+                Some(average) => average * 0.9 + interval * 0.1,
+                None => interval,
+            });
+        }
+        self.last_frame = Some(time);
+    }
+}
+
 pub struct Surface {
     pub surface: wl_surface::WlSurface,
     pub output: WlOutput,
     pub layer: LayerSurface,
-    egl_context: EglContext,
-    renderer: Renderer,
+    /// Set when the compositor supports fractional scaling
+    viewport: Option<WpViewport>,
+    fractional_scale: Option<WpFractionalScaleV1>,
+    /// The last scale received, waiting for the timer to apply it
+    pending_scale: Option<(Scale, RegistrationToken)>,
+    /// Set when the compositor supports single pixel buffers, the viewport is needed to scale
+    /// them to the whole display
+    single_pixel_buffer_manager: Option<WpSinglePixelBufferManagerV1>,
+    /// Attached in place of the EGL buffer while a solid color is shown
+    single_pixel_buffer: Option<WlBuffer>,
+    /// Notifies when the session has been idle for `idle_timeout`, together with the timeout
+    /// it has been created with
+    idle_notification: Option<(ExtIdleNotificationV1, Duration)>,
+    /// True when the session is idle and the wallpaper is dimmed
+    idle: bool,
+    /// Notifies when the output is turned off and on, when the compositor supports
+    /// wlr-output-power-management
+    output_power: Option<ZwlrOutputPowerV1>,
+    /// Nothing is drawn and the timer is paused while the output is off
+    powered_off: bool,
+    backend: Backend,
     pub image_picker: ImagePicker,
     pub event_source: EventSource,
     wallpaper_info: WallpaperInfo,
+    /// How long the current image will be shown, picked from the `duration` range each time
+    /// the wallpaper changes
+    current_duration: Option<Duration>,
+    /// The mode of the current image, picked from `modes` each time the wallpaper changes
+    image_mode: BackgroundMode,
     info: Rc<RefCell<DisplayInfo>>,
     image_loader: Rc<RefCell<ImageLoader>>,
+    events: Rc<RefCell<EventSubscribers>>,
+    /// Set when a transition starts, so that its end is only notified once
+    transition_running: bool,
+    /// Time of the frame last drawn, see `transition_fps` in [`WallpaperInfo`]
+    last_draw: Option<u32>,
+    /// Time of the frame that requested the next frame of the grain, so that only one callback
+    /// keeps it going
+    grain_queued_at: Option<u32>,
+    startup_hold: StartupHold,
+    /// Directory containing the state of each display, like the path of the current wallpaper
+    xdg_state_home_dir: PathBuf,
     drawn: bool,
     loading_image: Option<(PathBuf, usize)>,
     loading_image_tries: u8,
+    /// Images of the mosaic being loaded, see [`BackgroundMode::Mosaic`]
+    loading_mosaic: Option<Vec<(PathBuf, ImageLoaderStatus)>>,
+    /// Value of [`ImagePicker::generation`] when `loading_image` has been picked
+    loading_image_generation: u32,
+    /// Only measured after diagnostics have been requested via IPC
+    frame_stats: Option<FrameStats>,
+    /// Image received via IPC, it is loaded in place of [`IN_MEMORY_IMAGE_PATH`]
+    in_memory_image: Option<RgbaImage>,
+    /// The solid color currently drawn, see `color` in [`WallpaperInfo`]
+    shown_color: Option<Color>,
+    /// The gradient currently drawn and the size of the display it has been made for
+    shown_gradient: Option<(Gradient, (i32, i32))>,
     /// Determines whether we should skip the next transition. Used to skip
     /// the first transition when starting up.
     ///
@@ -58,118 +186,445 @@ pub struct Surface {
     /// Setting this to true will mean only an explicit next/previous wallpaper command will change
     /// the wallpaper.
     should_pause: bool,
+    /// Set when another display of the same group runs the timer, this surface then changes
+    /// wallpaper along with it
+    following_group: bool,
+    /// How many times the EGL context has been lost recently, see [`Surface::recover_context`]
+    context_losses: u32,
+    last_context_loss: Option<Instant>,
 }
 
 impl Surface {
+    /// How long before the duration expires the next image starts being decoded
+    const PREFETCH_AHEAD: Duration = Duration::from_secs(5);
+    /// Separates the name of the display from the index of the image in the requests of the
+    /// mosaic mode, so that each image is a different requester for the [`ImageLoader`]
+    pub const MOSAIC_REQUESTER_SEPARATOR: char = '#';
+    /// Losing the context more often than this means that the GPU keeps failing
+    const MAX_CONTEXT_LOSSES: u32 = 3;
+    const CONTEXT_LOSSES_WINDOW: Duration = Duration::from_secs(60);
+    /// How long a new scale has to stay the same before the surface is resized for it
+    const SCALE_DEBOUNCE: Duration = Duration::from_millis(250);
+
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         layer: LayerSurface,
         output: WlOutput,
         info: DisplayInfo,
         wallpaper_info: WallpaperInfo,
-        egl_display: egl::Display,
+        egl_display: Option<egl::Display>,
+        shm: &Shm,
         filelist_cache: Rc<RefCell<FilelistCache>>,
         image_loader: Rc<RefCell<ImageLoader>>,
-    ) -> Self {
+        events: Rc<RefCell<EventSubscribers>>,
+        xdg_state_home_dir: PathBuf,
+    ) -> Result<Self> {
         let surface = layer.wl_surface().clone();
-        let egl_context = EglContext::new(egl_display, &surface);
-        // Make the egl context as current to make the renderer creation work
-        egl_context
-            .make_current()
-            .expect("EGL context switching to work");
+        let info = Rc::new(RefCell::new(info));
+        let gl_backend = egl_display.map(|egl_display| {
+            Self::gl_backend(egl_display, &surface, info.clone(), &wallpaper_info)
+        });
+        let backend = match gl_backend {
+            Some(Ok(backend)) => {
+                info!("drawing display {} with OpenGL ES", info.borrow().name);
+                if let Backend::Gl { renderer, .. } = &backend {
+                    image_loader
+                        .borrow()
+                        .limit_texture_size(renderer.max_texture_size());
+                }
+                backend
+            }
+            gl_backend => {
+                if let Some(Err(err)) = gl_backend {
+                    warn!("{err:?}");
+                }
+                info!(
+                    "drawing display {} in shared memory, without transitions nor effects",
+                    info.borrow().name
+                );
+                Backend::Shm(ShmRenderer::new(shm, info.clone())?)
+            }
+        };
 
         // Commit the surface
         surface.commit();
 
-        let image_picker = ImagePicker::new(&wallpaper_info, filelist_cache);
-
-        let image = black_image();
-        let info = Rc::new(RefCell::new(info));
-
-        let renderer = unsafe {
-            Renderer::new(
-                image.into(),
-                info.clone(),
-                0,
-                wallpaper_info.transition.clone(),
-            )
-            .expect("unable to create the renderer")
-        };
+        let mut image_picker = ImagePicker::new(&wallpaper_info, filelist_cache);
+        // Keep avoiding the images drawn recently, even across restarts
+        match Self::read_history(&xdg_state_home_dir, &info.borrow().name) {
+            Ok(history) => image_picker.restore_history(history),
+            Err(err) => warn!("{err:?}"),
+        }
 
         let first_transition = !wallpaper_info.initial_transition;
         let mut surface = Self {
             output,
             layer,
+            viewport: None,
+            fractional_scale: None,
+            pending_scale: None,
+            single_pixel_buffer_manager: None,
+            single_pixel_buffer: None,
+            idle_notification: None,
+            idle: false,
+            output_power: None,
+            powered_off: false,
             info,
             surface,
-            egl_context,
-            renderer,
+            backend,
             image_picker,
             event_source: EventSource::NotSet,
+            following_group: false,
+            context_losses: 0,
+            last_context_loss: None,
+            current_duration: pick_duration(&wallpaper_info),
+            image_mode: pick_mode(&wallpaper_info),
             wallpaper_info,
             drawn: false,
             should_pause: false,
             image_loader,
+            events,
+            transition_running: false,
+            last_draw: None,
+            grain_queued_at: None,
+            startup_hold: StartupHold::Pending,
+            xdg_state_home_dir,
             loading_image: None,
             loading_image_tries: 0,
+            loading_mosaic: None,
+            loading_image_generation: 0,
+            frame_stats: None,
+            in_memory_image: None,
+            shown_color: None,
+            shown_gradient: None,
             skip_next_transition: first_transition,
         };
 
+        surface.warn_grain();
+
         // Start loading the wallpaper as soon as possible (i.e. surface creation)
         // It will still be loaded as a texture when we have an openGL context
         if let Err(err) = surface.load_wallpaper(0) {
             warn!("{err:?}");
         }
 
-        surface
+        Ok(surface)
+    }
+
+    /// Create the EGL context and the renderer, with the settings of `wallpaper_info`
+    fn gl_backend(
+        egl_display: egl::Display,
+        surface: &wl_surface::WlSurface,
+        info: Rc<RefCell<DisplayInfo>>,
+        wallpaper_info: &WallpaperInfo,
+    ) -> Result<Backend> {
+        let egl_context = EglContext::new(egl_display, surface)?;
+        // Make the egl context as current to make the renderer creation work
+        egl_context.make_current()?;
+
+        let mut renderer = unsafe {
+            Renderer::new(
+                black_image(),
+                info,
+                0,
+                wallpaper_info.transition.clone(),
+                wallpaper_info.gravity,
+                wallpaper_info.offset,
+                wallpaper_info.tile_snap,
+            )
+            .context("unable to create the renderer")?
+        };
+        configure_renderer(&mut renderer, wallpaper_info);
+
+        Ok(Backend::Gl {
+            egl_context,
+            renderer: Box::new(renderer),
+        })
+    }
+
+    /// Run `f` with the renderer, once its context is current. The shm backend has nothing to
+    /// update, the wallpaper is composed again on each draw
+    fn with_renderer(
+        &mut self,
+        f: impl FnOnce(&mut Renderer, &WallpaperInfo, BackgroundMode) -> Result<()>,
+    ) -> Result<()> {
+        let mode = self.mode();
+        match &mut self.backend {
+            Backend::Gl {
+                egl_context,
+                renderer,
+            } => {
+                egl_context.make_current()?;
+                f(renderer, &self.wallpaper_info, mode)
+            }
+            Backend::Shm(_) => Ok(()),
+        }
+    }
+
+    /// Load `image` as the wallpaper to draw next, its crop centered on `focal_point` if any
+    fn load_image(
+        &mut self,
+        image: impl Into<TextureImage>,
+        focal_point: Option<(f32, f32)>,
+    ) -> Result<()> {
+        let mode = self.mode();
+        match &mut self.backend {
+            Backend::Gl {
+                egl_context,
+                renderer,
+            } => {
+                egl_context.make_current()?;
+                renderer.load_wallpaper(image, focal_point, mode)
+            }
+            Backend::Shm(renderer) => {
+                renderer.load_wallpaper(image.into().into_rgba(), focal_point);
+                Ok(())
+            }
+        }
     }
 
-    /// Returns true if something has been drawn to the surface
+    /// Draw the surface, recreating the EGL context if it has been lost
     pub fn draw(&mut self, qh: &QueueHandle<Wpaperd>, time: u32) -> Result<()> {
+        // Nothing can be drawn on an empty buffer, the next configure will request a frame
+        if !self.is_configured() {
+            debug!("display {} has no size yet, not drawing", self.name());
+            return Ok(());
+        }
+        // Drawn again once the output is turned on
+        if self.powered_off {
+            return Ok(());
+        }
+        match self.draw_frame(qh, time) {
+            Err(err)
+                if EglContext::is_context_lost(&err)
+                    || matches!(&self.backend, Backend::Gl { renderer, .. } if renderer.context_lost()) =>
+            {
+                self.recover_context(qh, err)
+            }
+            res => res,
+        }
+    }
+
+    /// Draw the last frame again without showing it, and read it back. A running transition is
+    /// captured at the same progress as the frame on the display
+    pub fn capture(&mut self) -> Result<RgbaImage> {
+        let Some(time) = self.last_draw.filter(|_| self.drawn) else {
+            bail!("display {} has not been drawn yet", self.name());
+        };
+        let mode = self.mode();
+        match &mut self.backend {
+            Backend::Gl {
+                egl_context,
+                renderer,
+            } => {
+                egl_context.make_current()?;
+                let image =
+                    unsafe { renderer.draw(time, mode) }.and_then(|_| renderer.read_pixels());
+                renderer.clear_after_draw()?;
+                egl::API
+                    .make_current(egl_context.display, None, None, None)
+                    .context("Resetting the GL context")?;
+                image
+            }
+            Backend::Shm(renderer) => Ok(renderer.frame(
+                mode,
+                self.wallpaper_info.gravity,
+                shm_border(&self.wallpaper_info),
+            )),
+        }
+    }
+
+    /// Recreate the EGL context and the renderer, then load the current wallpaper again.
+    /// Give up after [`Self::MAX_CONTEXT_LOSSES`] in a short time, the GPU is not working
+    fn recover_context(&mut self, qh: &QueueHandle<Wpaperd>, err: Report) -> Result<()> {
+        if matches!(self.backend, Backend::Shm(_)) {
+            return Err(err);
+        }
+        let now = Instant::now();
+        self.context_losses = match self.last_context_loss {
+            Some(last) if now.duration_since(last) < Self::CONTEXT_LOSSES_WINDOW => {
+                self.context_losses + 1
+            }
+            _ => 1,
+        };
+        self.last_context_loss = Some(now);
+        if self.context_losses > Self::MAX_CONTEXT_LOSSES {
+            return Err(err.wrap_err(format!(
+                "the EGL context of display {} keeps being lost, not drawing anymore",
+                self.name()
+            )));
+        }
+        warn!(
+            "the EGL context of display {} has been lost, recreating it",
+            self.name()
+        );
+
+        let info = self.info.borrow();
+        let (width, height) = (info.adjusted_width(), info.adjusted_height());
+        drop(info);
+        let Backend::Gl {
+            egl_context,
+            renderer: lost_renderer,
+        } = &mut self.backend
+        else {
+            unreachable!("the shm backend has no context to lose");
+        };
+        egl_context
+            .recreate(&self.surface, width, height)
+            .context("recreating the EGL context")?;
+        egl_context.make_current()?;
+        let mut renderer = unsafe {
+            Renderer::new(
+                black_image(),
+                self.info.clone(),
+                0,
+                self.wallpaper_info.transition.clone(),
+                self.wallpaper_info.gravity,
+                self.wallpaper_info.offset,
+                self.wallpaper_info.tile_snap,
+            )?
+        };
+        renderer.resize()?;
+        configure_renderer(&mut renderer, &self.wallpaper_info);
+        if self.idle {
+            renderer.set_brightness(self.wallpaper_info.idle_brightness);
+        }
+        **lost_renderer = renderer;
+
+        // All the textures are gone, upload the current wallpaper without a transition
+        self.shown_color = None;
+        self.shown_gradient = None;
+        self.loading_image = None;
+        self.loading_mosaic = None;
+        self.skip_next_transition = true;
+        if self.wallpaper_info.mode == BackgroundMode::Mosaic
+            || self.image_picker.current_image() == Path::new(IN_MEMORY_IMAGE_PATH)
+        {
+            self.image_picker.next_image();
+        } else {
+            self.image_picker.reload();
+        }
+        self.queue_draw(qh);
+
+        Ok(())
+    }
+
+    fn draw_frame(&mut self, qh: &QueueHandle<Wpaperd>, time: u32) -> Result<()> {
         let info = self.info.borrow();
         let width = info.adjusted_width();
         let height = info.adjusted_height();
         // Drop the borrow to self
         drop(info);
 
+        // Skip the frames coming too early during a transition or while the grain moves, wait
+        // for the next one
+        if self.transition_running {
+            if too_early(self.last_draw, time, self.wallpaper_info.transition_fps) {
+                self.queue_draw(qh);
+                return Ok(());
+            }
+        } else if self.animates_grain() && too_early(self.last_draw, time, Some(GRAIN_FPS)) {
+            self.queue_grain_frame(qh, time);
+            return Ok(());
+        }
+
         // Only returns true when the wallpaper is loaded
         if self.load_wallpaper(time)? || !self.drawn {
-            // Use the correct context before loading the texture and drawing
-            self.egl_context.make_current()?;
+            let mode = self.mode();
+            let transition_going = match &mut self.backend {
+                Backend::Gl {
+                    egl_context,
+                    renderer,
+                } => {
+                    // Use the correct context before loading the texture and drawing
+                    egl_context.make_current()?;
+
+                    let transition_going = unsafe { renderer.draw(time, mode)? };
+                    if !transition_going {
+                        renderer.transition_finished();
+                    }
 
-            let transition_going = unsafe { self.renderer.draw(time, self.wallpaper_info.mode)? };
+                    renderer.clear_after_draw()?;
+                    egl_context.swap_buffers()?;
+
+                    // Reset the context
+                    egl::API
+                        .make_current(egl_context.display, None, None, None)
+                        .context("Resetting the GL context")?;
+                    transition_going
+                }
+                Backend::Shm(renderer) => {
+                    renderer.draw(
+                        &self.surface,
+                        mode,
+                        self.wallpaper_info.gravity,
+                        shm_border(&self.wallpaper_info),
+                    )?;
+                    false
+                }
+            };
+            self.last_draw = Some(time);
             if transition_going {
                 self.queue_draw(qh);
             } else {
-                self.renderer.transition_finished();
+                if self.transition_running {
+                    self.transition_running = false;
+                    self.emit(IpcEvent::TransitionFinished {
+                        monitor: self.name(),
+                    });
+                }
+                if self.animates_grain() {
+                    self.queue_grain_frame(qh, time);
+                }
             }
 
             self.drawn = true;
 
-            self.renderer.clear_after_draw()?;
-            self.egl_context.swap_buffers()?;
-
-            // Reset the context
-            egl::API
-                .make_current(self.egl_context.display, None, None, None)
-                .context("Resetting the GL context")?;
-
             // Mark the entire surface as damaged
             self.surface.damage_buffer(0, 0, width, height);
 
             // Finally, commit the surface
             self.surface.commit();
-        } else {
-            self.queue_draw(qh);
+
+            if !transition_going {
+                self.attach_single_pixel_buffer(qh);
+            }
         }
+        // Otherwise, the image is still being decoded: the surface will be drawn again as soon
+        // as it is ready
 
         Ok(())
     }
 
     // Call surface::frame when this return false
     pub fn load_wallpaper(&mut self, time: u32) -> Result<bool> {
+        if let Some(color) = self.wallpaper_info.color {
+            self.load_color(color, time)?;
+            return Ok(true);
+        }
+        if let Some(gradient) = self.wallpaper_info.gradient {
+            self.load_gradient(gradient, time)?;
+            return Ok(true);
+        }
+        // Keep the display black, the images are not there yet
+        if self.startup_hold == StartupHold::Holding {
+            return Ok(false);
+        }
+        if self.wallpaper_info.mode == BackgroundMode::Mosaic {
+            return self.load_mosaic(time);
+        }
+
         Ok(loop {
+            // Another image has been requested while the previous one was being loaded,
+            // only honor the latest request
+            if self.loading_image.is_some()
+                && self.loading_image_generation != self.image_picker.generation()
+            {
+                self.loading_image = None;
+            }
             // If we were not already trying to load an image
             if self.loading_image.is_none() {
+                self.loading_image_generation = self.image_picker.generation();
                 if let Some(item) = self
                     .image_picker
                     .get_image_from_path(&self.wallpaper_info.path)
@@ -186,45 +641,94 @@ impl Surface {
                 .as_ref()
                 .expect("loading image to be set")
                 .clone();
-            let res = self
-                .image_loader
-                .borrow_mut()
-                .background_load(image_path.to_owned(), self.name());
+            let in_memory = image_path == Path::new(IN_MEMORY_IMAGE_PATH);
+            // The image received in memory has been decoded on the main thread, keep it as RGBA
+            let texture_format = if in_memory {
+                TextureFormat::Rgba
+            } else {
+                self.texture_format()
+            };
+            let res = if in_memory {
+                // The image has already been decoded when it was received
+                let size_limit = self.size_limit();
+                self.in_memory_image
+                    .take()
+                    .map_or(ImageLoaderStatus::Error, |image| {
+                        ImageLoaderStatus::Loaded(match size_limit {
+                            Some(SizeLimit::Letterbox(width, height)) => {
+                                letterbox_blur(&image.into(), width, height)
+                            }
+                            _ => image,
+                        })
+                    })
+            } else {
+                self.image_loader.borrow_mut().background_load(
+                    image_path.to_owned(),
+                    self.size_limit(),
+                    texture_format,
+                    self.wallpaper_info.max_file_size,
+                    self.name(),
+                )
+            };
             match res {
-                crate::image_loader::ImageLoaderStatus::Loaded(data) => {
+                ImageLoaderStatus::Loaded(data) => {
+                    // The first frame of the transition already uses the new mode
+                    if !self.image_picker.is_reloading() {
+                        self.image_mode = pick_mode(&self.wallpaper_info);
+                    }
+                    let focal_point = self.wallpaper_info.focal_crop.then(|| {
+                        self.image_loader.borrow_mut().focal_point(
+                            &image_path,
+                            &data,
+                            texture_format,
+                        )
+                    });
                     // Renderer::load_wallpaper load the wallpaper in a openGL texture
-                    // Set the correct opengl context
-                    self.egl_context.make_current()?;
-                    self.renderer
-                        .load_wallpaper(data.into(), self.wallpaper_info.mode)?;
-
-                    let transition_time = if self.skip_next_transition {
-                        0
-                    } else {
-                        self.wallpaper_info.transition_time
-                    };
-                    self.skip_next_transition = false;
+                    self.load_image(
+                        TextureImage {
+                            image: data,
+                            format: texture_format,
+                        },
+                        focal_point,
+                    )?;
 
-                    self.renderer.start_transition(time, transition_time);
+                    self.current_duration = pick_duration(&self.wallpaper_info);
+                    self.shown_color = None;
+                    self.shown_gradient = None;
 
                     if self.image_picker.is_reloading() {
                         self.image_picker.reloaded();
                     } else {
                         self.image_picker.update_current_image(image_path, index);
                     }
+                    self.emit(IpcEvent::WallpaperChanged {
+                        monitor: self.name(),
+                        path: self.image_picker.current_image(),
+                    });
+                    self.start_transition(time);
+                    if let Err(err) = self.write_current_wallpaper() {
+                        warn!("{err:?}");
+                    }
+                    if let Err(err) = self.write_history() {
+                        warn!("{err:?}");
+                    }
                     // Restart the counter
                     self.loading_image_tries = 0;
                     self.loading_image = None;
                     break true;
                 }
-                crate::image_loader::ImageLoaderStatus::Waiting => {
+                ImageLoaderStatus::Waiting => {
                     // wait until the image has been loaded
                     break false;
                 }
-                crate::image_loader::ImageLoaderStatus::Error => {
+                ImageLoaderStatus::Error => {
                     // We don't want to try too many times
                     self.loading_image_tries += 1;
-                    // The image we were trying to load failed
+                    // The image we were trying to load failed, e.g. it is bigger than
+                    // max_file_size: pick another one next time
+                    if !self.image_picker.is_reloading() {
+                        self.image_picker.skip_image(image_path, index);
+                    }
                     self.loading_image = None;
                 }
             }
@@ -235,41 +739,380 @@ impl Surface {
         })
     }
 
+    /// Load a grid of different images as a single texture, each one cropped to fit its cell.
+    /// Return false while they are still being decoded
+    fn load_mosaic(&mut self, time: u32) -> Result<bool> {
+        let Some((width, height)) = self.info.borrow().pixel_size() else {
+            return Ok(true);
+        };
+        let (columns, rows) = self.wallpaper_info.mosaic_grid;
+        let gap = self.wallpaper_info.mosaic_gap;
+        let cell_width = (width.saturating_sub(gap * (columns - 1)) / columns).max(1);
+        let cell_height = (height.saturating_sub(gap * (rows - 1)) / rows).max(1);
+
+        // Only honor the latest request, like in [`Surface::load_wallpaper`]
+        if self.loading_mosaic.is_some()
+            && self.loading_image_generation != self.image_picker.generation()
+        {
+            self.loading_mosaic = None;
+        }
+        if self.loading_mosaic.is_none() {
+            self.loading_image_generation = self.image_picker.generation();
+            let Some(images) = self
+                .image_picker
+                .get_images_from_path(&self.wallpaper_info.path, (columns * rows) as usize)
+            else {
+                return Ok(true);
+            };
+            self.loading_mosaic = Some(
+                images
+                    .into_iter()
+                    .map(|(path, _)| (path, ImageLoaderStatus::Waiting))
+                    .collect(),
+            );
+        }
+
+        let name = self.name();
+        let max_file_size = self.wallpaper_info.max_file_size;
+        let texture_format = self.texture_format();
+        let cells = self
+            .loading_mosaic
+            .as_mut()
+            .expect("loading mosaic to be set");
+        let mut image_loader = self.image_loader.borrow_mut();
+        for (index, (path, status)) in cells.iter_mut().enumerate() {
+            if matches!(status, ImageLoaderStatus::Waiting) {
+                *status = image_loader.background_load(
+                    path.clone(),
+                    Some(SizeLimit::Fill(cell_width, cell_height)),
+                    texture_format,
+                    max_file_size,
+                    format!("{name}{}{index}", Self::MOSAIC_REQUESTER_SEPARATOR),
+                );
+            }
+        }
+        drop(image_loader);
+        if cells
+            .iter()
+            .any(|(_, status)| matches!(status, ImageLoaderStatus::Waiting))
+        {
+            return Ok(false);
+        }
+
+        // The cells without an image, because it failed or the directory has fewer images than
+        // cells, are left empty
+        let mut mosaic = RgbaImage::new(width, height);
+        let cells = self
+            .loading_mosaic
+            .take()
+            .expect("loading mosaic to be set");
+        for (index, (_, status)) in cells.into_iter().enumerate() {
+            if let ImageLoaderStatus::Loaded(image) = status {
+                let index = index as u32;
+                let x = (index % columns) * (cell_width + gap);
+                let y = (index / columns) * (cell_height + gap);
+                imageops::replace(&mut mosaic, &image, x.into(), y.into());
+            }
+        }
+
+        // The cells have been decoded in the order of the channels of the texture
+        self.load_image(
+            TextureImage {
+                image: mosaic,
+                format: texture_format,
+            },
+            None,
+        )?;
+        self.current_duration = pick_duration(&self.wallpaper_info);
+        self.shown_color = None;
+        self.shown_gradient = None;
+        self.image_picker.reloaded();
+        self.emit(IpcEvent::WallpaperChanged {
+            monitor: self.name(),
+            path: self.image_picker.current_image(),
+        });
+        self.start_transition(time);
+        if let Err(err) = self.write_current_wallpaper() {
+            warn!("{err:?}");
+        }
+        if let Err(err) = self.write_history() {
+            warn!("{err:?}");
+        }
+
+        Ok(true)
+    }
+
+    /// Draw `color` instead of an image, the images in `path` and the image picker are not used
+    fn load_color(&mut self, color: Color, time: u32) -> Result<()> {
+        if self.shown_color == Some(color) {
+            return Ok(());
+        }
+
+        self.load_image(color.image(), None)?;
+        self.start_transition(time);
+        self.shown_color = Some(color);
+        self.shown_gradient = None;
+
+        Ok(())
+    }
+
+    /// Draw `gradient` like a solid color, in an image made again when the display is resized
+    fn load_gradient(&mut self, gradient: Gradient, time: u32) -> Result<()> {
+        let info = self.info.borrow();
+        let size = (info.adjusted_width(), info.adjusted_height());
+        drop(info);
+        match self.shown_gradient {
+            Some(shown) if shown == (gradient, size) => return Ok(()),
+            // Only the size of the display changed
+            Some((shown, _)) if shown == gradient => self.skip_next_transition = true,
+            _ => {}
+        }
+
+        self.load_image(gradient.image(size.0, size.1), None)?;
+        self.start_transition(time);
+        self.shown_color = None;
+        self.shown_gradient = Some((gradient, size));
+
+        Ok(())
+    }
+
+    /// Start the transition to the wallpaper just loaded, unless it should be skipped
+    fn start_transition(&mut self, time: u32) {
+        let transition_time = if self.skip_next_transition {
+            0
+        } else {
+            self.wallpaper_info.transition_time
+        };
+        self.skip_next_transition = false;
+        if let Backend::Gl { renderer, .. } = &mut self.backend {
+            renderer.start_transition(time, transition_time);
+        }
+        self.transition_running = true;
+        self.emit(IpcEvent::TransitionStarted {
+            monitor: self.name(),
+        });
+    }
+
+    #[inline]
+    fn emit(&self, event: IpcEvent) {
+        self.events.borrow_mut().emit(event);
+    }
+
+    /// The mode used for drawing, a solid color or a gradient always covers the whole display
+    fn mode(&self) -> BackgroundMode {
+        if self.wallpaper_info.is_generated() {
+            BackgroundMode::Stretch
+        } else {
+            self.image_mode
+        }
+    }
+
+    /// Write the path of the current wallpaper to `XDG_STATE_HOME/wpaperd/wallpapers/<display>`
+    fn write_current_wallpaper(&self) -> Result<()> {
+        self.write_state_file(
+            "wallpapers",
+            self.image_picker.current_image().as_os_str().as_bytes(),
+        )
+    }
+
+    /// Write the images drawn recently to `XDG_STATE_HOME/wpaperd/history/<display>`, one per
+    /// line, so that they can be restored when wpaperd starts again
+    fn write_history(&self) -> Result<()> {
+        let Some(history) = self.image_picker.history() else {
+            return Ok(());
+        };
+        let content = history
+            .map(|path| path.as_os_str().as_bytes())
+            .collect::<Vec<_>>()
+            .join(&b'\n');
+        self.write_state_file("history", &content)
+    }
+
+    /// Forget the images drawn recently, also in the history file, so that the next ones can be
+    /// any image. Return how many have been forgotten
+    pub fn reset_history(&mut self) -> usize {
+        let cleared = self.image_picker.clear_history();
+        if let Err(err) = self.write_history() {
+            warn!("{err:?}");
+        }
+        cleared
+    }
+
+    /// Read the images drawn in a previous session, see [`Surface::write_history`]
+    fn read_history(xdg_state_home_dir: &Path, name: &str) -> Result<Vec<PathBuf>> {
+        let history_file = xdg_state_home_dir.join("history").join(name);
+        let content = match fs::read(&history_file) {
+            Ok(content) => content,
+            // Nothing has been drawn on this display yet
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("unable to read {history_file:?}"));
+            }
+        };
+        Ok(content
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| PathBuf::from(OsStr::from_bytes(line)))
+            .collect())
+    }
+
+    /// Write `content` to the file named as this display in the state directory `dir`.
+    /// The file is written to a temporary file first and then renamed, so that readers never
+    /// see a partial content
+    fn write_state_file(&self, dir: &str, content: &[u8]) -> Result<()> {
+        let name = self.name();
+        let dir = self.xdg_state_home_dir.join(dir);
+        let state_file = dir.join(&name);
+        let tmp_file = dir.join(format!(".{name}.tmp"));
+        fs::write(&tmp_file, content)
+            .with_context(|| format!("unable to write to {tmp_file:?}"))?;
+        fs::rename(&tmp_file, &state_file)
+            .with_context(|| format!("unable to move {tmp_file:?} to {state_file:?}"))?;
+
+        Ok(())
+    }
+
+    pub fn display_info(&self) -> Ref<'_, DisplayInfo> {
+        self.info.borrow()
+    }
+
+    /// Set `initial_transition` at runtime. It only matters before the first wallpaper has been
+    /// drawn, afterwards it is kept for the surface created the next time the display appears
+    pub fn set_initial_transition(&mut self, initial_transition: bool) {
+        self.wallpaper_info.initial_transition = initial_transition;
+        if !self.drawn {
+            self.skip_next_transition = !initial_transition;
+        }
+    }
+
+    /// The configuration currently applied to this display
+    pub fn wallpaper_info(&self) -> &WallpaperInfo {
+        &self.wallpaper_info
+    }
+
+    /// See [`DisplayInfo::merge_head`]
+    pub fn merge_output_head(&mut self, head: &OutputHeadInfo) -> bool {
+        self.info.borrow_mut().merge_head(head)
+    }
+
     pub fn name(&self) -> String {
         self.info.borrow().name.to_string()
     }
 
+    pub fn group(&self) -> Option<&str> {
+        self.wallpaper_info.group.as_deref()
+    }
+
+    #[inline]
+    pub fn group_mode(&self) -> GroupMode {
+        self.wallpaper_info.group_mode
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.wallpaper_info.path
+    }
+
+    /// Set whether the timer is run by another display of the same group, removing or adding
+    /// the timer of this surface accordingly
+    pub fn set_following_group(
+        &mut self,
+        following: bool,
+        handle: &LoopHandle<Wpaperd>,
+        qh: QueueHandle<Wpaperd>,
+    ) {
+        if self.following_group == following {
+            return;
+        }
+        self.following_group = following;
+        if following {
+            if let EventSource::Running(registration_token) = self.event_source {
+                handle.remove(registration_token);
+            }
+            self.event_source = EventSource::NotSet;
+        } else if self.drawn {
+            self.add_timer(None, handle, qh);
+        }
+    }
+
     /// Resize the surface
     pub fn resize(&mut self, qh: &QueueHandle<Wpaperd>) {
         let info = self.info.borrow();
         let width = info.adjusted_width();
         let height = info.adjusted_height();
+        // The scale can change before the first configure, wait for it
+        if width == 0 || height == 0 {
+            debug!(
+                "display {} has no size yet, it will be resized once configured",
+                info.name
+            );
+            return;
+        }
         // self.layer.set_size(width as u32, height as u32);
+        if let Some(viewport) = &self.viewport {
+            // The destination is the logical size of the surface, the buffer has been scaled
+            viewport.set_destination(info.width, info.height);
+        }
         let display_name = self.name();
-        let res = self
-            .egl_context
-            .resize(&self.surface, width, height)
-            .with_context(|| {
-                format!("unable to switch resize EGL context for display {display_name}",)
-            })
-            .and_then(|_| {
-                self.egl_context.make_current().with_context(|| {
-                    format!("unable to switch the openGL context for display {display_name}")
+        // The shm buffers are created at the size of the display on each draw
+        if let Backend::Gl {
+            egl_context,
+            renderer,
+        } = &mut self.backend
+        {
+            let res = egl_context
+                .resize(&self.surface, width, height)
+                .with_context(|| {
+                    format!("unable to switch resize EGL context for display {display_name}",)
                 })
-            })
-            .and_then(|_| {
-                self.renderer.resize().with_context(|| {
-                    format!("unable to resize the GL window for display {display_name}")
+                .and_then(|_| {
+                    egl_context.make_current().with_context(|| {
+                        format!("unable to switch the openGL context for display {display_name}")
+                    })
                 })
-            });
-        // Resize the gl viewport
-        if let Err(err) = res {
-            error!("{err:?}");
+                .and_then(|_| {
+                    renderer.resize().with_context(|| {
+                        format!("unable to resize the GL window for display {display_name}")
+                    })
+                });
+            // Resize the gl viewport
+            if let Err(err) = res {
+                error!("{err:?}");
+            }
+        }
+        drop(info);
+        // A rotated display shows images of the other orientation
+        self.image_picker.update_orientation(self.orientation());
+        // Compose the letterbox again at the new size
+        if self.drawn
+            && self.wallpaper_info.mode == BackgroundMode::ContainBlur
+            && self.image_picker.current_image() != Path::new(IN_MEMORY_IMAGE_PATH)
+        {
+            self.image_picker.reload();
         }
         self.surface.frame(qh, self.surface.clone());
     }
 
+    /// The orientation of the display, when the images matching it are preferred
+    fn orientation(&self) -> Option<Orientation> {
+        if !self.wallpaper_info.orientation_preference {
+            return None;
+        }
+        Orientation::from_ratio(self.info.borrow().ratio()?)
+    }
+
     pub fn change_size(&mut self, configure: LayerSurfaceConfigure, qh: &QueueHandle<Wpaperd>) {
+        // Some compositors send a transient empty size, keep the previous one until a valid
+        // size arrives
+        if let (0, _) | (_, 0) = configure.new_size {
+            debug!(
+                "ignoring the configure of size {}x{} for display {}",
+                configure.new_size.0,
+                configure.new_size.1,
+                self.name()
+            );
+            return;
+        }
         let mut info = self.info.borrow_mut();
         if info.change_size(configure) {
             drop(info);
@@ -286,91 +1129,258 @@ impl Surface {
         }
     }
 
-    pub fn change_scale_factor(&mut self, scale_factor: i32, qh: &QueueHandle<Wpaperd>) {
-        let mut info = self.info.borrow_mut();
-        if info.change_scale_factor(scale_factor) {
-            drop(info);
-            self.surface.set_buffer_scale(scale_factor);
-            self.resize(qh);
+    pub fn change_scale_factor(
+        &mut self,
+        scale_factor: i32,
+        handle: &LoopHandle<Wpaperd>,
+        qh: &QueueHandle<Wpaperd>,
+    ) {
+        // The fractional scale has precedence over the integer one
+        if self.fractional_scale.is_some() {
+            return;
         }
+        self.debounce_scale(Scale::Integer(scale_factor), handle, qh);
     }
 
-    /// Check that the dimensions are valid
-    pub fn is_configured(&self) -> bool {
-        let info = self.info.borrow();
-        info.width != 0 && info.height != 0
-    }
-
-    pub fn drawn(&self) -> bool {
+    pub fn change_fractional_scale(
+        &mut self,
+        scale: u32,
+        handle: &LoopHandle<Wpaperd>,
+        qh: &QueueHandle<Wpaperd>,
+    ) {
+        self.debounce_scale(Scale::Fractional(scale), handle, qh);
+    }
+
+    /// Apply `scale` once no other scale has been received for [`Self::SCALE_DEBOUNCE`]. Some
+    /// compositors switch back and forth while a display is plugged, and each resize reloads
+    /// the textures. The scale is applied right away until the surface has been drawn
+    fn debounce_scale(
+        &mut self,
+        scale: Scale,
+        handle: &LoopHandle<Wpaperd>,
+        qh: &QueueHandle<Wpaperd>,
+    ) {
+        if let Some((_, token)) = self.pending_scale.take() {
+            handle.remove(token);
+        }
+        if !self.drawn {
+            self.apply_scale(scale, qh);
+            return;
+        }
+        // The scale went back to the one in use, there is nothing to do
+        let info = self.info.borrow();
+        let changed = match scale {
+            Scale::Integer(scale_factor) => info.scale != scale_factor,
+            Scale::Fractional(scale) => info.fractional_scale != Some(scale),
+        };
+        drop(info);
+        if !changed {
+            return;
+        }
+
+        let name = self.name();
+        let qh = qh.clone();
+        let token = handle
+            .insert_source(
+                Timer::from_duration(Self::SCALE_DEBOUNCE),
+                move |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                    if let Some(surface) = wpaperd.surface_from_name(&name) {
+                        if let Some((scale, _)) = surface.pending_scale.take() {
+                            surface.apply_scale(scale, &qh);
+                        }
+                    }
+                    TimeoutAction::Drop
+                },
+            )
+            .expect("Failed to insert event source!");
+        self.pending_scale = Some((scale, token));
+    }
+
+    fn apply_scale(&mut self, scale: Scale, qh: &QueueHandle<Wpaperd>) {
+        let mut info = self.info.borrow_mut();
+        let changed = match scale {
+            Scale::Integer(scale_factor) => info.change_scale_factor(scale_factor),
+            Scale::Fractional(scale) => info.change_fractional_scale(scale),
+        };
+        drop(info);
+        if !changed {
+            return;
+        }
+        if let Scale::Integer(scale_factor) = scale {
+            self.surface.set_buffer_scale(scale_factor);
+        }
+        self.resize(qh);
+    }
+
+    /// Render at the fractional scale sent by the compositor instead of the integer one
+    pub fn enable_fractional_scale(
+        &mut self,
+        viewport: WpViewport,
+        fractional_scale: WpFractionalScaleV1,
+    ) {
+        self.viewport = Some(viewport);
+        self.fractional_scale = Some(fractional_scale);
+    }
+
+    /// Show the solid colors with single pixel buffers, see [`Surface::attach_single_pixel_buffer`]
+    pub fn enable_single_pixel_buffer(&mut self, manager: WpSinglePixelBufferManagerV1) {
+        self.single_pixel_buffer_manager = Some(manager);
+    }
+
+    /// Replace the EGL buffer with a single pixel of the color shown, scaled by the viewport.
+    /// The compositor can draw it without sampling a texture, and nothing is rendered until the
+    /// next draw attaches the EGL buffer again
+    fn attach_single_pixel_buffer(&mut self, qh: &QueueHandle<Wpaperd>) {
+        let (Some(manager), Some(Color(color))) =
+            (&self.single_pixel_buffer_manager, self.shown_color)
+        else {
+            return;
+        };
+        // The vignette, the dimming, the opacity, the tint and the grain are applied by the
+        // renderer
+        if self.viewport.is_none()
+            || self.idle
+            || self.wallpaper_info.vignette != 0.0
+            || self.wallpaper_info.grain != 0.0
+            || self.wallpaper_info.opacity != 1.0
+            || self.wallpaper_info.temperature != 6500
+        {
+            return;
+        }
+
+        // Each channel goes from 0 to u32::MAX
+        let [red, green, blue] = color.map(|channel| u32::from(channel) * (u32::MAX / 255));
+        let buffer = manager.create_u32_rgba_buffer(red, green, blue, u32::MAX, qh, ());
+        self.surface.attach(Some(&buffer), 0, 0);
+        self.surface.damage_buffer(0, 0, i32::MAX, i32::MAX);
+        self.surface.commit();
+        if let Some(previous_buffer) = self.single_pixel_buffer.replace(buffer) {
+            previous_buffer.destroy();
+        }
+    }
+
+    /// Check that the dimensions are valid
+    pub fn is_configured(&self) -> bool {
+        let info = self.info.borrow();
+        info.width != 0 && info.height != 0
+    }
+
+    pub fn drawn(&self) -> bool {
         self.drawn
     }
 
-    /// Update the wallpaper_info of this Surface
-    /// return true if the duration has changed
+    /// Update the wallpaper_info of this Surface, only the settings that differ are applied.
+    /// Return true if anything has changed
     pub fn update_wallpaper_info(
         &mut self,
         handle: &LoopHandle<Wpaperd>,
         qh: &QueueHandle<Wpaperd>,
         mut wallpaper_info: WallpaperInfo,
-    ) {
+    ) -> bool {
         if self.wallpaper_info == wallpaper_info {
-            return;
+            return false;
         }
 
         // Put the new value in place
         std::mem::swap(&mut self.wallpaper_info, &mut wallpaper_info);
-        let path_changed = self.wallpaper_info.path != wallpaper_info.path;
+        // Searching the directory to a different depth changes the images too
+        let path_changed = self.wallpaper_info.path != wallpaper_info.path
+            || self.wallpaper_info.recursive != wallpaper_info.recursive;
+        // Keep the prefetched image when only the way it is drawn has changed, otherwise the next
+        // wallpaper would be decoded when the timer expires and the transition would start late
+        let next_image_changed = path_changed
+            || self.wallpaper_info.sorting != wallpaper_info.sorting
+            || self.wallpaper_info.seed != wallpaper_info.seed
+            || self.wallpaper_info.weights != wallpaper_info.weights
+            || self.wallpaper_info.selector != wallpaper_info.selector
+            || self.wallpaper_info.mode != wallpaper_info.mode
+            || self.wallpaper_info.max_texture_size != wallpaper_info.max_texture_size
+            || self.wallpaper_info.supersampling != wallpaper_info.supersampling
+            || self.wallpaper_info.max_file_size != wallpaper_info.max_file_size;
+        if next_image_changed {
+            self.image_picker.discard_prefetch();
+        }
+        self.image_picker
+            .update_max_depth(self.wallpaper_info.recursive.max_depth());
         self.image_picker.update_sorting(
             self.wallpaper_info.sorting,
             path_changed,
             wallpaper_info.drawn_images_queue_size,
         );
+        self.image_picker.update_seed(self.wallpaper_info.seed);
+        self.image_picker
+            .update_weights(self.wallpaper_info.weights.clone());
+        self.image_picker.update_orientation(self.orientation());
+        #[cfg(feature = "script")]
+        self.image_picker
+            .update_selector(self.wallpaper_info.selector.clone());
+        if !self.wallpaper_info.is_generated()
+            && wallpaper_info.is_generated()
+            && self.wallpaper_info.path == self.image_picker.current_image()
+        {
+            // Show the image from before the color or the gradient again
+            self.image_picker.reload();
+        }
+        // Keep showing the current image until the timer expires, if it's also in the new path
+        let path_changed = path_changed
+            && !(self.wallpaper_info.preserve_image
+                && self.wallpaper_info.mode != BackgroundMode::Mosaic
+                && self
+                    .image_picker
+                    .keep_current_image(&self.wallpaper_info.path));
         if path_changed {
             // ask the image_picker to pick a new a image
             self.image_picker.next_image();
             self.queue_draw(qh);
+        } else if self.wallpaper_info.color != wallpaper_info.color
+            || self.wallpaper_info.gradient != wallpaper_info.gradient
+        {
+            self.queue_draw(qh);
         }
-        if self.wallpaper_info.duration != wallpaper_info.duration {
-            match (self.wallpaper_info.duration, wallpaper_info.duration) {
-                (None, None) => {
-                    unreachable!()
-                }
-                // There was a duration before but now it has been removed
-                (None, Some(_)) => {
-                    if let EventSource::Running(registration_token) = self.event_source {
-                        handle.remove(registration_token);
-                    }
-                }
-                // There wasn't a duration before but now it has been added or it has changed
-                (Some(new_duration), None) | (Some(new_duration), Some(_)) => {
-                    if let EventSource::Running(registration_token) = self.event_source {
-                        handle.remove(registration_token);
-                    }
-
-                    // if the path has not changed or the duration has changed
-                    // and the remaining time is great than 0
-                    let timer = if let (false, Some(remaining_time)) = (
-                        path_changed,
-                        remaining_duration(new_duration, self.image_picker.image_changed_instant),
-                    ) {
-                        Some(Timer::from_duration(remaining_time))
-                    } else {
-                        // otherwise draw the image immediately, the next timer
-                        // will be set to the new duration
-                        Some(Timer::immediate())
-                    };
-
-                    self.event_source = EventSource::NotSet;
-                    self.add_timer(timer, handle, qh.clone());
-                }
+        // The new path or color might be ready to be shown
+        self.check_startup_hold(qh);
+        let is_mosaic = |info: &WallpaperInfo| {
+            (info.mode == BackgroundMode::Mosaic).then_some((info.mosaic_grid, info.mosaic_gap))
+        };
+        let is_contain_blur = |info: &WallpaperInfo| info.mode == BackgroundMode::ContainBlur;
+        if !path_changed && is_mosaic(&self.wallpaper_info) != is_mosaic(&wallpaper_info) {
+            // Pick the images again, for the new grid
+            self.image_picker.next_image();
+            self.queue_draw(qh);
+        } else if !path_changed
+            && is_contain_blur(&self.wallpaper_info) != is_contain_blur(&wallpaper_info)
+        {
+            // The letterbox is part of the decoded image
+            self.image_picker.reload();
+            self.queue_draw(qh);
+        }
+        let is_daily = |info: &WallpaperInfo| info.sorting == Sorting::Daily;
+        if is_daily(&self.wallpaper_info) != is_daily(&wallpaper_info) {
+            // The daily sorting changes image at midnight instead of after `duration`
+            if let EventSource::Running(registration_token) = self.event_source {
+                handle.remove(registration_token);
             }
+            self.event_source = EventSource::NotSet;
+            self.current_duration = pick_duration(&self.wallpaper_info);
+            self.add_timer(None, handle, qh.clone());
+        }
+        // Only the timer is moved, the current image stays and nothing is drawn again. The
+        // downloaded wallpapers follow `refresh` instead
+        if !is_daily(&self.wallpaper_info)
+            && !self.wallpaper_info.oneshot
+            && !is_url(&self.wallpaper_info.path)
+            && (self.wallpaper_info.duration != wallpaper_info.duration
+                || self.wallpaper_info.align != wallpaper_info.align)
+        {
+            self.pick_current_duration();
+            self.reschedule_timer(path_changed, handle, qh);
         }
 
-        if self.wallpaper_info.mode != wallpaper_info.mode {
-            if let Err(err) = self
-                .egl_context
-                .make_current()
-                .and_then(|_| self.renderer.set_mode(self.wallpaper_info.mode, false))
+        if self.wallpaper_info.mode != wallpaper_info.mode
+            || self.wallpaper_info.modes != wallpaper_info.modes
+        {
+            self.image_mode = pick_mode(&self.wallpaper_info);
+            if let Err(err) = self.with_renderer(|renderer, _, mode| renderer.set_mode(mode, false))
             {
                 error!("{err:?}");
             }
@@ -380,25 +1390,166 @@ impl Surface {
                     warn!("{err:?}");
                 }
             }
-            if self.wallpaper_info.transition != wallpaper_info.transition {
-                match self.egl_context.make_current() {
-                    Ok(_) => {
-                        self.renderer
-                            .update_transition(self.wallpaper_info.transition.clone());
-                    }
-                    Err(err) => {
-                        error!("{err:?}");
-                    }
-                }
+        }
+        if self.wallpaper_info.transition != wallpaper_info.transition {
+            if let Err(err) = self.with_renderer(|renderer, info, _| {
+                renderer.update_transition(info.transition.clone());
+                Ok(())
+            }) {
+                error!("{err:?}");
             }
         }
         if self.wallpaper_info.drawn_images_queue_size != wallpaper_info.drawn_images_queue_size {
             self.image_picker
                 .update_queue_size(self.wallpaper_info.drawn_images_queue_size);
         }
+        if self.wallpaper_info.gravity != wallpaper_info.gravity {
+            if let Err(err) =
+                self.with_renderer(|renderer, info, mode| renderer.set_gravity(info.gravity, mode))
+            {
+                error!("{err:?}");
+            }
+            self.queue_draw(qh);
+        }
+        if self.wallpaper_info.offset != wallpaper_info.offset {
+            if let Err(err) =
+                self.with_renderer(|renderer, info, mode| renderer.set_offset(info.offset, mode))
+            {
+                error!("{err:?}");
+            }
+            self.queue_draw(qh);
+        }
+        if self.wallpaper_info.tile_snap != wallpaper_info.tile_snap {
+            if let Err(err) = self
+                .with_renderer(|renderer, info, mode| renderer.set_tile_snap(info.tile_snap, mode))
+            {
+                error!("{err:?}");
+            }
+            self.queue_draw(qh);
+        }
+        if self.wallpaper_info.vignette != wallpaper_info.vignette
+            || self.wallpaper_info.opacity != wallpaper_info.opacity
+            || self.wallpaper_info.temperature != wallpaper_info.temperature
+            || self.wallpaper_info.border_color != wallpaper_info.border_color
+        {
+            if let Err(err) = self.with_renderer(|renderer, info, _| {
+                renderer.set_vignette(info.vignette);
+                renderer.set_opacity(info.opacity);
+                renderer.set_temperature(info.temperature);
+                renderer.set_border_color(border_color(info));
+                Ok(())
+            }) {
+                error!("{err:?}");
+            }
+            self.queue_draw(qh);
+        }
+        if self.wallpaper_info.grain != wallpaper_info.grain {
+            if wallpaper_info.grain == 0.0 {
+                self.warn_grain();
+            }
+            if let Err(err) = self.with_renderer(|renderer, info, _| {
+                renderer.set_grain(info.grain);
+                Ok(())
+            }) {
+                error!("{err:?}");
+            }
+            // Starts drawing the grain again on every frame, or draws the last frame without it
+            self.queue_draw(qh);
+        }
+        if self.wallpaper_info.drop_shadow() != wallpaper_info.drop_shadow()
+            || flip(&self.wallpaper_info) != flip(&wallpaper_info)
+        {
+            if let Err(err) = self.with_renderer(|renderer, info, mode| {
+                renderer.set_drop_shadow(info.drop_shadow());
+                renderer.set_flip(flip(info));
+                renderer.refresh_vertices(mode)
+            }) {
+                error!("{err:?}");
+            }
+            self.queue_draw(qh);
+        }
+        if self.wallpaper_info.blur_edges != wallpaper_info.blur_edges {
+            if let Err(err) = self.with_renderer(|renderer, info, mode| {
+                renderer.set_blur_edges(info.blur_edges);
+                renderer.rebind_wallpapers(mode)
+            }) {
+                error!("{err:?}");
+            }
+            self.queue_draw(qh);
+        }
+        if self.wallpaper_info.focal_crop != wallpaper_info.focal_crop
+            && !self.wallpaper_info.is_generated()
+            && !path_changed
+        {
+            // The focal point is stored together with the image
+            self.image_picker.reload();
+            self.queue_draw(qh);
+        }
+        if self.wallpaper_info.supersampling != wallpaper_info.supersampling {
+            if let Err(err) = self
+                .with_renderer(|renderer, info, _| renderer.set_supersampling(info.supersampling))
+            {
+                error!("{err:?}");
+            }
+            self.queue_draw(qh);
+        }
+        if self.idle && self.wallpaper_info.idle_brightness != wallpaper_info.idle_brightness {
+            if let Err(err) = self.with_renderer(|renderer, info, _| {
+                renderer.set_brightness(info.idle_brightness);
+                Ok(())
+            }) {
+                error!("{err:?}");
+            }
+            self.queue_draw(qh);
+        }
+        // A transition that is running uses the new time right away
         if self.wallpaper_info.transition_time != wallpaper_info.transition_time {
-            self.renderer
-                .update_transition_time(self.wallpaper_info.transition_time);
+            if let Err(err) = self.with_renderer(|renderer, info, _| {
+                renderer.update_transition_time(info.transition_time);
+                Ok(())
+            }) {
+                error!("{err:?}");
+            }
+        }
+        if self.wallpaper_info.layer != wallpaper_info.layer {
+            // Moving the surface to another layer has been added in the version 2
+            if matches!(self.layer.kind(), SurfaceKind::Wlr(wlr_layer) if wlr_layer.version() >= 2)
+            {
+                self.layer.set_layer(self.wallpaper_info.layer.into());
+            } else {
+                warn!(
+                    "The compositor cannot move display {} to another layer, restart wpaperd to apply it",
+                    self.name()
+                );
+            }
+            self.surface.commit();
+        }
+        // The compositor configures the surface again with its new size
+        if self.wallpaper_info.exclusive_zone != wallpaper_info.exclusive_zone
+            || self.wallpaper_info.anchor != wallpaper_info.anchor
+            || self.wallpaper_info.margin != wallpaper_info.margin
+            || self.wallpaper_info.size != wallpaper_info.size
+        {
+            Self::set_placement(&self.layer, &self.wallpaper_info, &self.info.borrow());
+            self.surface.commit();
+        }
+        true
+    }
+
+    /// Set the edges the layer surface is attached to, its margin, its exclusive zone and its
+    /// size accordingly, see `anchor`, `margin`, `size` and `exclusive_zone` in [`WallpaperInfo`]
+    pub fn set_placement(layer: &LayerSurface, wallpaper_info: &WallpaperInfo, info: &DisplayInfo) {
+        layer.set_anchor(wallpaper_info.anchor());
+        let (top, right, bottom, left) = wallpaper_info.margin;
+        layer.set_margin(top, right, bottom, left);
+        layer.set_exclusive_zone(wallpaper_info.exclusive_zone);
+        if wallpaper_info.exclusive_zone == -1 && wallpaper_info.covers_display() {
+            layer.set_size(info.adjusted_width() as u32, info.adjusted_height() as u32);
+        } else {
+            // Let the compositor fit the surface in the space left by the other surfaces and
+            // the margin, along the sides without a size
+            let (width, height) = wallpaper_info.size;
+            layer.set_size(width, height);
         }
     }
 
@@ -410,14 +1561,14 @@ impl Surface {
         handle: &LoopHandle<Wpaperd>,
         qh: QueueHandle<Wpaperd>,
     ) {
-        if matches!(self.event_source, EventSource::Running(_)) {
+        if self.following_group || matches!(self.event_source, EventSource::Running(_)) {
             return;
         }
-        let Some(duration) = self.wallpaper_info.duration else {
+        let Some(duration) = self.current_duration else {
             return;
         };
 
-        let timer = timer.unwrap_or(Timer::from_duration(duration));
+        let timer = timer.unwrap_or(Timer::from_duration(self.timer_delay(duration)));
 
         let name = self.name().clone();
         let registration_token = handle
@@ -435,7 +1586,7 @@ impl Surface {
                         }
                     };
 
-                    if let Some(duration) = surface.wallpaper_info.duration {
+                    if let Some(duration) = surface.current_duration {
                         // Check that the timer has expired
                         // if the daemon received a next or previous image command
                         // the timer will be reset and we need to account that here
@@ -445,12 +1596,30 @@ impl Surface {
                         if let Some(remaining_time) =
                             remaining_duration(duration, surface.image_picker.image_changed_instant)
                         {
-                            TimeoutAction::ToDuration(remaining_time)
+                            if remaining_time <= Self::PREFETCH_AHEAD {
+                                surface.prefetch_next_image();
+                            }
+                            TimeoutAction::ToDuration(surface.timer_delay(remaining_time))
                         } else {
+                            // A new duration is picked once the image has been loaded
+                            let delay = surface.timer_delay(duration);
                             // Change the drawn image
-                            surface.image_picker.next_image();
-                            surface.queue_draw(&qh);
-                            TimeoutAction::ToDuration(duration)
+                            match surface.group().map(str::to_string) {
+                                Some(group) => {
+                                    wpaperd.next_group_wallpaper(&group, &qh);
+                                }
+                                None if is_url(&surface.wallpaper_info.path) => {
+                                    surface.image_picker.reload();
+                                    // The refresh interval starts again from now
+                                    surface.image_picker.image_changed_instant = Instant::now();
+                                    surface.queue_draw(&qh);
+                                }
+                                None => {
+                                    surface.image_picker.next_image();
+                                    surface.queue_draw(&qh);
+                                }
+                            }
+                            TimeoutAction::ToDuration(delay)
                         }
                     } else {
                         TimeoutAction::Drop
@@ -462,11 +1631,43 @@ impl Surface {
         self.event_source = EventSource::Running(registration_token);
     }
 
+    /// Replace the timer with one for `current_duration`, counted from when the current image was
+    /// shown. It fires right away if that time has already passed or if `image_changed`. While
+    /// paused, only the time left is updated and the timer starts again once resumed
+    fn reschedule_timer(
+        &mut self,
+        image_changed: bool,
+        handle: &LoopHandle<Wpaperd>,
+        qh: &QueueHandle<Wpaperd>,
+    ) {
+        if let EventSource::Running(registration_token) = self.event_source {
+            handle.remove(registration_token);
+        }
+        self.event_source = EventSource::NotSet;
+        let Some(duration) = self.current_duration else {
+            return;
+        };
+        let remaining_time = if image_changed {
+            None
+        } else {
+            remaining_duration(duration, self.image_picker.image_changed_instant)
+        };
+        if self.should_pause || self.powered_off {
+            self.event_source = EventSource::Paused(remaining_time.unwrap_or_default());
+        } else {
+            let timer = match remaining_time {
+                Some(remaining_time) => Timer::from_duration(self.timer_delay(remaining_time)),
+                None => Timer::immediate(),
+            };
+            self.add_timer(Some(timer), handle, qh.clone());
+        }
+    }
+
     /// Handle updating the timer based on the pause state of the automatic wallpaper sequence.
     /// Remove the timer if pausing, and add a new timer with the remaining duration of the old
     /// timer when resuming.
     pub fn handle_pause_state(&mut self, handle: &LoopHandle<Wpaperd>, qh: QueueHandle<Wpaperd>) {
-        match (self.should_pause, &self.event_source) {
+        match (self.should_pause || self.powered_off, &self.event_source) {
             // Should pause, but timer is still currently running
             (true, EventSource::Running(registration_token)) => {
                 let remaining_duration = self.get_remaining_duration().unwrap_or_default();
@@ -476,26 +1677,345 @@ impl Surface {
             }
             // Should resume, but timer is not currently running
             (false, EventSource::Paused(duration)) => {
-                self.add_timer(Some(Timer::from_duration(*duration)), handle, qh.clone());
+                let mut duration = *duration;
+                // Go back on the grid, the time spent paused has moved the next image away from it
+                if self.wallpaper_info.align {
+                    duration = self.pick_current_duration().unwrap_or(duration);
+                }
+                let timer = Timer::from_duration(self.timer_delay(duration));
+                self.add_timer(Some(timer), handle, qh.clone());
             }
             // Otherwise no update is necessary
             (_, _) => {}
         }
     }
 
-    #[inline]
-    pub fn queue_draw(&mut self, qh: &QueueHandle<Wpaperd>) {
-        // Start loading the next image immediately
-        if let Err(err) = self.load_wallpaper(0) {
-            warn!("{err:?}");
+    /// The grain is animated by drawing the wallpaper again on every frame, only with OpenGL ES
+    fn animates_grain(&self) -> bool {
+        self.wallpaper_info.grain > 0.0 && matches!(self.backend, Backend::Gl { .. })
+    }
+
+    /// Keep drawing the grain. Other callbacks can be queued for the same frame, e.g. when the
+    /// wallpaper changes: only the first one requests the next frame, so that they don't add up
+    fn queue_grain_frame(&mut self, qh: &QueueHandle<Wpaperd>, time: u32) {
+        if self.grain_queued_at != Some(time) {
+            self.grain_queued_at = Some(time);
+            self.queue_draw(qh);
         }
+    }
+
+    fn warn_grain(&self) {
+        if self.animates_grain() {
+            warn!(
+                "display {} is drawn again on every frame to animate the grain, which uses more power",
+                self.name()
+            );
+        }
+    }
+
+    /// Ask the compositor for a frame callback, where the next image is loaded and the surface
+    /// drawn. Loading it here would start the transition without a frame time
+    #[inline]
+    pub fn queue_draw(&self, qh: &QueueHandle<Wpaperd>) {
         self.surface.frame(qh, self.surface.clone());
         self.surface.commit();
     }
 
+    /// Show the next image right away if the current one has been deleted, instead of waiting
+    /// for `duration` to expire
+    pub fn check_current_image(&mut self, qh: &QueueHandle<Wpaperd>) {
+        if self.wallpaper_info.is_generated()
+            || self.loading_image.is_some()
+            || self.loading_mosaic.is_some()
+        {
+            return;
+        }
+        if self
+            .image_picker
+            .current_image_removed(&self.wallpaper_info.path)
+        {
+            self.image_picker.next_image();
+            self.queue_draw(qh);
+        }
+    }
+
+    /// Hold the first wallpaper for `startup_delay` when `path` has no images yet, e.g. a
+    /// network mount that is still being populated. Only applied before the first draw
+    pub fn hold_startup(&mut self, handle: &LoopHandle<Wpaperd>, qh: &QueueHandle<Wpaperd>) {
+        if self.startup_hold != StartupHold::Pending {
+            return;
+        }
+        self.startup_hold = StartupHold::Done;
+        let Some(delay) = self.wallpaper_info.startup_delay else {
+            return;
+        };
+        if self.wallpaper_info.is_generated()
+            || self.image_picker.has_images(&self.wallpaper_info.path)
+        {
+            return;
+        }
+
+        info!(
+            "waiting up to {delay:?} for the images of display {}",
+            self.name()
+        );
+        self.startup_hold = StartupHold::Holding;
+        let name = self.name();
+        let qh = qh.clone();
+        handle
+            .insert_source(
+                Timer::from_duration(delay),
+                move |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                    if let Some(surface) = wpaperd.surface_from_name(&name) {
+                        surface.end_startup_hold(&qh);
+                    }
+                    TimeoutAction::Drop
+                },
+            )
+            .expect("Failed to insert event source!");
+    }
+
+    /// End the hold of [`Surface::hold_startup`] early, once `path` contains images
+    pub fn check_startup_hold(&mut self, qh: &QueueHandle<Wpaperd>) {
+        if self.startup_hold == StartupHold::Holding
+            && (self.wallpaper_info.is_generated()
+                || self.image_picker.has_images(&self.wallpaper_info.path))
+        {
+            self.end_startup_hold(qh);
+        }
+    }
+
+    fn end_startup_hold(&mut self, qh: &QueueHandle<Wpaperd>) {
+        if self.startup_hold == StartupHold::Holding {
+            self.startup_hold = StartupHold::Done;
+            // The first wallpaper comes with the initial transition
+            self.queue_draw(qh);
+        }
+    }
+
+    /// Called when an image requested by this surface has been decoded
+    pub fn image_decoded(&self, qh: &QueueHandle<Wpaperd>) {
+        // Prefetched images are only drawn once the timer expires
+        if self.loading_image.is_some() || self.loading_mosaic.is_some() {
+            self.queue_draw(qh);
+        }
+    }
+
+    /// Start decoding the image that will be shown next, see `prefetch` in
+    /// [`WallpaperInfo`]
+    fn prefetch_next_image(&mut self) {
+        if !self.wallpaper_info.prefetch
+            || self.wallpaper_info.mode == BackgroundMode::Mosaic
+            || self.loading_image.is_some()
+        {
+            return;
+        }
+        if let Some(path) = self.image_picker.prefetch_next(&self.wallpaper_info.path) {
+            self.image_loader.borrow_mut().prefetch(
+                path,
+                self.size_limit(),
+                self.texture_format(),
+                self.wallpaper_info.max_file_size,
+                self.name(),
+            );
+        }
+    }
+
+    /// The order of the channels the images are decoded in, the one the renderer uploads
+    /// fastest
+    fn texture_format(&self) -> TextureFormat {
+        match &self.backend {
+            Backend::Gl { renderer, .. } => renderer.texture_format(),
+            Backend::Shm(_) => TextureFormat::Rgba,
+        }
+    }
+
+    /// How much the images can be downscaled when decoded, see `max_texture_size` in
+    /// [`WallpaperInfo`]
+    fn size_limit(&self) -> Option<SizeLimit> {
+        if self.wallpaper_info.mode == BackgroundMode::ContainBlur {
+            if let Some((width, height)) = self.info.borrow().pixel_size() {
+                return Some(SizeLimit::Letterbox(width, height));
+            }
+        }
+        match self.wallpaper_info.max_texture_size {
+            // Keep the details that supersampling can show
+            MaxTextureSize::Auto => self.info.borrow().pixel_size().map(|(width, height)| {
+                let scale = |dimension: u32| {
+                    (dimension as f32 * self.wallpaper_info.supersampling).round() as u32
+                };
+                SizeLimit::Display(scale(width), scale(height))
+            }),
+            MaxTextureSize::Unlimited => None,
+            MaxTextureSize::Pixels(pixels) => Some(SizeLimit::Pixels(pixels)),
+        }
+    }
+
+    /// How long to wait before the timer fires again, when `remaining` is left on the current
+    /// image. With `prefetch` enabled it fires [`Surface::PREFETCH_AHEAD`] earlier, so that the
+    /// next image can be decoded in the meantime
+    fn timer_delay(&self, remaining: Duration) -> Duration {
+        if self.wallpaper_info.prefetch && remaining > Self::PREFETCH_AHEAD {
+            remaining - Self::PREFETCH_AHEAD
+        } else {
+            remaining
+        }
+    }
+
+    /// Ask the compositor to notify when the session has been idle for `idle_timeout`, replacing
+    /// the previous notification if the timeout has changed
+    pub fn update_idle_notification(
+        &mut self,
+        idle_notifier: &ExtIdleNotifierV1,
+        seat: &WlSeat,
+        qh: &QueueHandle<Wpaperd>,
+    ) {
+        let timeout = self.wallpaper_info.idle_timeout;
+        if self.idle_notification.as_ref().map(|(_, timeout)| *timeout) == timeout {
+            return;
+        }
+
+        if let Some((idle_notification, _)) = self.idle_notification.take() {
+            idle_notification.destroy();
+        }
+        self.set_idle(false, qh);
+        self.idle_notification = timeout.map(|timeout| {
+            let idle_notification = idle_notifier.get_idle_notification(
+                timeout.as_millis().try_into().unwrap_or(u32::MAX),
+                seat,
+                qh,
+                self.surface.clone(),
+            );
+            (idle_notification, timeout)
+        });
+    }
+
+    pub fn enable_output_power(&mut self, output_power: ZwlrOutputPowerV1) {
+        self.output_power = Some(output_power);
+    }
+
+    /// Called when the compositor cannot report the power mode of the output anymore, it is
+    /// then considered always on
+    pub fn disable_output_power(&mut self, qh: &QueueHandle<Wpaperd>) {
+        if let Some(output_power) = self.output_power.take() {
+            output_power.destroy();
+        }
+        self.set_powered(true, qh);
+    }
+
+    /// Stop drawing while the output is off, the timer is paused by
+    /// [`Surface::handle_pause_state`]. When it's turned on again, the wallpaper changes right
+    /// away if its duration expired in the meantime, skipping the images it missed
+    pub fn set_powered(&mut self, powered: bool, qh: &QueueHandle<Wpaperd>) {
+        if self.powered_off != powered {
+            return;
+        }
+        self.powered_off = !powered;
+        if !powered {
+            return;
+        }
+        if !self.should_pause {
+            let remaining = self.get_remaining_duration().unwrap_or_default();
+            if let EventSource::Paused(duration) = &mut self.event_source {
+                *duration = remaining;
+            }
+        }
+        self.queue_draw(qh);
+    }
+
+    /// Dim the wallpaper when the session is idle and restore it on activity.
+    /// A transition that is running goes on, just dimmed
+    pub fn set_idle(&mut self, idle: bool, qh: &QueueHandle<Wpaperd>) {
+        if self.idle == idle {
+            return;
+        }
+        self.idle = idle;
+        if let Err(err) = self.with_renderer(|renderer, info, _| {
+            renderer.set_brightness(if idle { info.idle_brightness } else { 1.0 });
+            Ok(())
+        }) {
+            error!("{err:?}");
+        }
+        self.queue_draw(qh);
+    }
+
+    /// Called on each frame callback, with the timestamp given by the compositor
+    #[inline]
+    pub fn record_frame(&mut self, time: u32) {
+        if let Some(frame_stats) = &mut self.frame_stats {
+            frame_stats.record(time);
+        }
+    }
+
+    /// The image that `wpaperctl swap` can show on another display, or the reason why there is
+    /// none
+    pub fn swappable_image(&self) -> Result<PathBuf, String> {
+        if self.transition_running {
+            return Err("a transition is running".to_string());
+        }
+        if self.wallpaper_info.color.is_some() {
+            return Err("it shows a solid color".to_string());
+        }
+        if self.wallpaper_info.gradient.is_some() {
+            return Err("it shows a gradient".to_string());
+        }
+        if self.wallpaper_info.mode == BackgroundMode::Mosaic {
+            return Err("it shows a mosaic of images".to_string());
+        }
+        let path = self.image_picker.current_image();
+        if path == Path::new(IN_MEMORY_IMAGE_PATH) {
+            return Err("its image has been received over IPC".to_string());
+        }
+        Ok(path)
+    }
+
+    /// Return the diagnostics about the rendering, and start measuring the frame interval
+    pub fn debug_info(&mut self) -> DebugInfo {
+        // The shm backend shows each wallpaper at once
+        let (progress, time_started, transition_time) = match &self.backend {
+            Backend::Gl { renderer, .. } => (
+                renderer.progress(),
+                renderer.time_started,
+                renderer.transition_time(),
+            ),
+            Backend::Shm(_) => (1.0, self.last_draw.unwrap_or_default(), 0),
+        };
+        DebugInfo {
+            transition_going: progress != 1.0,
+            progress,
+            time_started,
+            transition_time,
+            frame_interval: self
+                .frame_stats
+                .get_or_insert_with(FrameStats::default)
+                .average_interval,
+        }
+    }
+
+    /// Show an image that has been decoded from memory, instead of being read from a file
+    pub fn set_in_memory_image(&mut self, image: RgbaImage) {
+        self.in_memory_image = Some(image);
+        self.image_picker.set_in_memory_image();
+    }
+
+    /// Pick how long the current image is shown, counted from when it was shown like
+    /// `current_duration`. Return the duration picked, which is the time left with `align`
+    fn pick_current_duration(&mut self) -> Option<Duration> {
+        let remaining = pick_duration(&self.wallpaper_info);
+        self.current_duration = remaining.map(|remaining| {
+            if self.wallpaper_info.align {
+                // The next boundary is counted from now
+                self.image_picker.image_changed_instant.elapsed() + remaining
+            } else {
+                remaining
+            }
+        });
+        remaining
+    }
+
     #[inline]
     fn get_remaining_duration(&self) -> Option<Duration> {
-        let duration = self.wallpaper_info.duration?;
+        let duration = self.current_duration?;
         remaining_duration(duration, self.image_picker.image_changed_instant)
     }
 
@@ -515,10 +2035,154 @@ impl Surface {
     }
 }
 
+impl Drop for Surface {
+    fn drop(&mut self) {
+        if let Some(viewport) = self.viewport.take() {
+            viewport.destroy();
+        }
+        if let Some(fractional_scale) = self.fractional_scale.take() {
+            fractional_scale.destroy();
+        }
+        if let Some(single_pixel_buffer) = self.single_pixel_buffer.take() {
+            single_pixel_buffer.destroy();
+        }
+        if let Some((idle_notification, _)) = self.idle_notification.take() {
+            idle_notification.destroy();
+        }
+        if let Some(output_power) = self.output_power.take() {
+            output_power.destroy();
+        }
+    }
+}
+
+/// Apply the settings of `wallpaper_info` that are not passed to [`Renderer::new`], both to a new
+/// renderer and to the one replacing a lost context
+fn configure_renderer(renderer: &mut Renderer, wallpaper_info: &WallpaperInfo) {
+    renderer.set_vignette(wallpaper_info.vignette);
+    renderer.set_opacity(wallpaper_info.opacity);
+    renderer.set_temperature(wallpaper_info.temperature);
+    renderer.set_border_color(border_color(wallpaper_info));
+    renderer.set_drop_shadow(wallpaper_info.drop_shadow());
+    renderer.set_flip(flip(wallpaper_info));
+    renderer.set_blur_edges(wallpaper_info.blur_edges);
+    renderer.set_grain(wallpaper_info.grain);
+    if let Err(err) = renderer.set_supersampling(wallpaper_info.supersampling) {
+        error!("{err:?}");
+    }
+}
+
 fn black_image() -> RgbaImage {
     RgbaImage::from_raw(1, 1, vec![0, 0, 0, 255]).unwrap()
 }
 
+/// The mirroring of `flip_horizontal`/`flip_vertical`, passed to `Renderer::set_flip`
+#[inline]
+fn flip(wallpaper_info: &WallpaperInfo) -> (bool, bool) {
+    (wallpaper_info.flip_horizontal, wallpaper_info.flip_vertical)
+}
+
+/// The border is transparent unless `border_color` is set
+fn border_color(wallpaper_info: &WallpaperInfo) -> [f32; 4] {
+    wallpaper_info
+        .border_color
+        .map_or([0.0; 4], |border_color| border_color.rgba())
+}
+
+/// Filled around the image by the shm backend, transparent unless `border_color` is set
+fn shm_border(wallpaper_info: &WallpaperInfo) -> Rgba<u8> {
+    wallpaper_info
+        .border_color
+        .map_or(Rgba([0; 4]), |Color([red, green, blue])| {
+            Rgba([red, green, blue, 255])
+        })
+}
+
+/// Frame rate of the grain, like the one of a film
+const GRAIN_FPS: u32 = 24;
+
+/// True if less than `1 / fps` seconds have passed since the frame `last_draw`, in milliseconds
+fn too_early(last_draw: Option<u32>, time: u32, fps: Option<u32>) -> bool {
+    match (last_draw, fps) {
+        (Some(last_draw), Some(fps)) => time.wrapping_sub(last_draw) < 1000 / fps,
+        _ => false,
+    }
+}
+
+/// One of `modes` at random, or `mode` when they are not set
+fn pick_mode(wallpaper_info: &WallpaperInfo) -> BackgroundMode {
+    wallpaper_info
+        .modes
+        .choose(&mut rand::thread_rng())
+        .copied()
+        .unwrap_or(wallpaper_info.mode)
+}
+
+/// How long the next image is shown: until the local midnight for the `daily` sorting,
+/// otherwise a value from the `duration` range
+fn pick_duration(wallpaper_info: &WallpaperInfo) -> Option<Duration> {
+    if wallpaper_info.oneshot {
+        return None;
+    }
+    // The same image is downloaded again, see [`crate::download`]
+    if is_url(&wallpaper_info.path) {
+        return wallpaper_info.refresh;
+    }
+    let now = Local::now();
+    if wallpaper_info.sorting == Sorting::Daily {
+        // Check again in a while, the image only changes once the date does
+        return Some(
+            local_midnight(now, 1)
+                .and_then(|midnight| (midnight - now).to_std().ok())
+                .unwrap_or(Duration::from_secs(60 * 60)),
+        );
+    }
+    let duration = wallpaper_info.duration?.pick();
+    if !wallpaper_info.align {
+        return Some(duration);
+    }
+    let since_midnight = local_midnight(now, 0).and_then(|midnight| (now - midnight).to_std().ok());
+    let until_midnight = local_midnight(now, 1).and_then(|midnight| (midnight - now).to_std().ok());
+    Some(match (since_midnight, until_midnight) {
+        (Some(since_midnight), Some(until_midnight)) => {
+            aligned_duration(duration, since_midnight, until_midnight)
+        }
+        _ => duration,
+    })
+}
+
+/// The local midnight starting the day `days` after the one of `now`
+fn local_midnight(now: DateTime<Local>, days: u64) -> Option<DateTime<Local>> {
+    now.date_naive()
+        .checked_add_days(Days::new(days))
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        // The midnight might be skipped or repeated when the daylight saving time changes
+        .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+}
+
+/// Time left until the next multiple of `duration` counted from the local midnight, so that
+/// e.g. `15m` changes the image on every quarter of an hour. The grid starts again at each
+/// midnight. A boundary closer than [`ALIGN_MARGIN`] is skipped, in case the timer fired a bit
+/// early, instead of showing the next image right away
+fn aligned_duration(
+    duration: Duration,
+    since_midnight: Duration,
+    until_midnight: Duration,
+) -> Duration {
+    let duration_nanos = duration.as_nanos().max(1);
+    let elapsed = Duration::from_nanos((since_midnight.as_nanos() % duration_nanos) as u64);
+    let mut remaining = duration.saturating_sub(elapsed);
+    if remaining < ALIGN_MARGIN {
+        remaining += duration;
+    }
+    if until_midnight >= ALIGN_MARGIN {
+        remaining = remaining.min(until_midnight);
+    }
+    remaining
+}
+
+/// See [`aligned_duration`]
+const ALIGN_MARGIN: Duration = Duration::from_secs(1);
+
 fn remaining_duration(duration: Duration, image_changed: Instant) -> Option<Duration> {
     // The timer has already expired
     let diff = image_changed.elapsed();
@@ -528,3 +2192,28 @@ fn remaining_duration(duration: Duration, image_changed: Instant) -> Option<Dura
         Some(duration - diff)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aligned_duration() {
+        let minutes = |minutes: u64| Duration::from_secs(minutes * 60);
+        let day = minutes(24 * 60);
+        let aligned = |duration, since_midnight: Duration| {
+            aligned_duration(duration, since_midnight, day - since_midnight)
+        };
+
+        // At 10:07, the next quarter of an hour is 10:15
+        assert_eq!(aligned(minutes(15), minutes(607)), minutes(8));
+        // Right on a boundary, or a bit before it, the next one is a whole duration away
+        assert_eq!(aligned(minutes(15), minutes(600)), minutes(15));
+        assert_eq!(
+            aligned(minutes(15), minutes(600) - Duration::from_millis(5)),
+            minutes(15) + Duration::from_millis(5)
+        );
+        // 7 hours don't divide the day, the grid starts again at midnight
+        assert_eq!(aligned(minutes(7 * 60), minutes(22 * 60)), minutes(2 * 60));
+    }
+}
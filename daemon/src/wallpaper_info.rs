@@ -1,57 +1,814 @@
-use std::{path::PathBuf, time::Duration};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 
-use serde::Deserialize;
+use glob::Pattern;
+
+use humantime_serde::re::humantime::{format_duration, parse_duration};
+use image::{Rgba, RgbaImage};
+use log::warn;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use smithay_client_toolkit::shell::wlr_layer::{Anchor, Layer};
 
 use crate::{image_picker::ImagePicker, render::Transition};
 
-#[derive(PartialEq, Debug)]
+/// Serialized for `wpaperctl config`, with the same names as the configuration when possible
+#[derive(PartialEq, Debug, Serialize)]
 pub struct WallpaperInfo {
     pub path: PathBuf,
-    pub duration: Option<Duration>,
+    /// Shown in place of the images, `path` is empty when it's set
+    pub color: Option<Color>,
+    pub gradient: Option<Gradient>,
+    pub duration: Option<DurationRange>,
+    /// Change the image on the multiples of `duration` counted from the local midnight
+    pub align: bool,
+    /// How often the image is downloaded again, when `path` is an URL
+    #[serde(with = "humantime_serde")]
+    pub refresh: Option<Duration>,
+    /// Program choosing the next image of a directory, see [`crate::selector`]
+    pub selector: Option<PathBuf>,
+    #[serde(rename = "apply-shadow")]
     pub apply_shadow: bool,
     pub sorting: Sorting,
+    /// Seed of the `random` and `shuffle` sortings, `None` picks different images on each run
+    pub seed: Option<u64>,
+    /// Start the `ascending` and `descending` sortings at a random image instead of the first one
+    pub random_start: bool,
+    /// How often the images are picked by the `random` sorting
+    pub weights: Weights,
+    /// Prefer the images with the same orientation as the display, falling back to the other
+    /// ones when none matches
+    pub orientation_preference: bool,
+    pub recursive: Recursive,
+    /// When `path` changes, keep the current image if a file with the same name is in the new one
+    pub preserve_image: bool,
     pub mode: BackgroundMode,
+    /// Each image is drawn with one of these modes, picked at random when it is loaded. When set,
+    /// `mode` is the first of them; empty when only `mode` is used
+    pub modes: Vec<BackgroundMode>,
+    pub gravity: Gravity,
+    /// Logical pixels the image is moved by in `cover` and `contain` modes, towards the right
+    /// and the bottom
+    pub offset: (i32, i32),
+    pub tile_snap: TileSnap,
+    /// Mirror the image from left to right, in every mode
+    pub flip_horizontal: bool,
+    /// Mirror the image from top to bottom, in every mode
+    pub flip_vertical: bool,
+    /// Number of columns and rows of the `mosaic` mode
+    pub mosaic_grid: (u32, u32),
+    /// Pixels between the images of the `mosaic` mode
+    pub mosaic_gap: u32,
+    #[serde(rename = "queue_size")]
     pub drawn_images_queue_size: usize,
     pub transition_time: u32,
+    /// Frame rate cap of the transitions, `None` draws on every frame of the display
+    pub transition_fps: Option<u32>,
 
     /// Determines if we should show the transition between black and first
     /// wallpaper. `false` means we instantly cut to the first wallpaper,
     /// `true` means we fade from black to the first wallpaper.
     pub initial_transition: bool,
+    /// Strength of the darkening applied to the edges of the wallpaper, `0.0` disables it
+    pub vignette: f32,
+    /// Multiplies the alpha of the whole wallpaper, `1.0` keeps it opaque
+    pub opacity: f32,
+    /// Color temperature in Kelvin, `6500` leaves the colors unchanged
+    pub temperature: u32,
+    /// Strength of the animated noise drawn over the wallpaper, `0.0` disables it. The display
+    /// is drawn again on every frame while it's enabled
+    pub grain: f32,
+    /// Shown around the images that don't cover the display, `None` leaves it transparent
+    pub border_color: Option<Color>,
+    /// Fill the borders of `contain` mode with a blurred copy of the edges of the image,
+    /// instead of `border_color`
+    pub blur_edges: bool,
+    /// Center the part of the image cropped by `cover`, `fit-width` and `fit-height` on its
+    /// focal point, see [`crate::image_loader::focal_point`]
+    pub focal_crop: bool,
+    /// Draw a shadow around the image in `contain` mode, the other modes cover the display
+    pub drop_shadow: bool,
+    /// How far the shadow spreads from the image, in logical pixels
+    pub shadow_radius: u32,
+    /// Opacity of the shadow next to the image
+    pub shadow_opacity: f32,
+    /// Dim the wallpaper after the session has been idle for this long, `None` disables it
+    #[serde(with = "humantime_serde")]
+    pub idle_timeout: Option<Duration>,
+    /// Brightness of the wallpaper while the session is idle
+    pub idle_brightness: f32,
+    /// Decode the next image in advance, so that it's ready when `duration` expires
+    pub prefetch: bool,
+    /// Keep the display black for up to this long when wpaperd starts, until `path` contains
+    /// images. `None` shows the first image as soon as possible
+    #[serde(with = "humantime_serde")]
+    pub startup_delay: Option<Duration>,
+    pub max_texture_size: MaxTextureSize,
+    /// The images bigger than this many bytes are skipped without being decoded
+    pub max_file_size: Option<u64>,
+    /// Factor of the resolution the wallpaper is drawn at before being downsampled, `1.0`
+    /// draws directly on the display
+    pub supersampling: f32,
+    /// The displays of the same group can be changed together
+    pub group: Option<String>,
+    /// Only the mode of the first display of the group is used
+    pub group_mode: GroupMode,
+    pub layer: WallpaperLayer,
+    /// `-1` covers the whole display, `0` leaves the space reserved by panels and bars
+    pub exclusive_zone: i32,
+    /// Edges of the display the wallpaper is attached to
+    pub anchor: Vec<LayerAnchor>,
+    /// Logical pixels between the wallpaper and the edges it is attached to, in the order top,
+    /// right, bottom and left
+    pub margin: (i32, i32, i32, i32),
+    /// Logical width and height of the wallpaper, `0` stretches it between the opposite edges
+    pub size: (u32, u32),
+    #[serde(flatten)]
     pub transition: Transition,
+    /// Set by `wpaperd --oneshot`: the first wallpaper is kept and no timer is added
+    pub oneshot: bool,
 }
 
 impl Default for WallpaperInfo {
     fn default() -> Self {
         Self {
             path: PathBuf::new(),
+            color: None,
+            gradient: None,
             duration: None,
+            refresh: None,
+            selector: None,
             apply_shadow: false,
             sorting: Sorting::default(),
+            seed: None,
+            weights: Weights::default(),
+            orientation_preference: false,
+            recursive: Recursive::default(),
+            preserve_image: false,
+            random_start: false,
+            align: false,
             mode: BackgroundMode::default(),
+            modes: Vec::new(),
+            gravity: Gravity::default(),
+            offset: (0, 0),
+            tile_snap: TileSnap::default(),
+            flip_horizontal: false,
+            flip_vertical: false,
+            mosaic_grid: (2, 2),
+            mosaic_gap: 0,
             drawn_images_queue_size: ImagePicker::DEFAULT_DRAWN_IMAGES_QUEUE_SIZE,
             transition_time: Transition::Fade {}.default_transition_time(),
+            transition_fps: None,
             initial_transition: true,
+            vignette: 0.0,
+            opacity: 1.0,
+            temperature: 6500,
+            grain: 0.0,
+            border_color: None,
+            blur_edges: false,
+            focal_crop: false,
+            drop_shadow: false,
+            shadow_radius: 32,
+            shadow_opacity: 0.5,
+            idle_timeout: None,
+            idle_brightness: 0.5,
+            prefetch: true,
+            startup_delay: None,
+            max_texture_size: MaxTextureSize::default(),
+            max_file_size: None,
+            supersampling: 1.0,
+            group: None,
+            group_mode: GroupMode::default(),
+            layer: WallpaperLayer::default(),
+            exclusive_zone: -1,
+            anchor: LayerAnchor::ALL.to_vec(),
+            margin: (0, 0, 0, 0),
+            size: (0, 0),
             transition: Transition::Fade {},
+            oneshot: false,
+        }
+    }
+}
+
+impl WallpaperInfo {
+    /// Radius and opacity of the shadow, when it's drawn
+    pub fn drop_shadow(&self) -> Option<(u32, f32)> {
+        self.drop_shadow
+            .then_some((self.shadow_radius, self.shadow_opacity))
+    }
+
+    /// The edges in `anchor`, as sent to the compositor
+    pub fn anchor(&self) -> Anchor {
+        self.anchor
+            .iter()
+            .fold(Anchor::empty(), |anchor, &edge| anchor | edge.into())
+    }
+
+    /// True when the wallpaper is attached to every edge of the display, without any margin
+    pub fn covers_display(&self) -> bool {
+        self.anchor() == Anchor::all() && self.margin == (0, 0, 0, 0) && self.size == (0, 0)
+    }
+
+    /// True when `color` or `gradient` is drawn instead of the images in `path`
+    pub fn is_generated(&self) -> bool {
+        self.color.is_some() || self.gradient.is_some()
+    }
+}
+
+/// How long an image is shown, a new value is picked randomly between `min` and `max` each
+/// time the wallpaper changes. A single duration is a range where `min` and `max` coincide.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DurationRange {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl DurationRange {
+    pub fn pick(&self) -> Duration {
+        if self.min >= self.max {
+            self.min
+        } else {
+            rand::thread_rng().gen_range(self.min..=self.max)
+        }
+    }
+}
+
+impl From<Duration> for DurationRange {
+    fn from(duration: Duration) -> Self {
+        Self {
+            min: duration,
+            max: duration,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for DurationRange {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        let parse =
+            |duration: &str| parse_duration(duration.trim()).map_err(serde::de::Error::custom);
+        match value.split_once("..") {
+            Some((min, max)) => Ok(Self {
+                min: parse(min)?,
+                max: parse(max)?,
+            }),
+            None => parse(&value).map(Self::from),
+        }
+    }
+}
+
+impl Serialize for DurationRange {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        if self.min == self.max {
+            serializer.collect_str(&format_duration(self.min))
+        } else {
+            serializer.collect_str(&format_args!(
+                "{}..{}",
+                format_duration(self.min),
+                format_duration(self.max)
+            ))
         }
     }
 }
 
-#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Sorting {
     #[default]
     Random,
     Ascending,
     Descending,
+    /// Show every image once in a random order, then reshuffle
+    Shuffle,
+    /// Show the same image for the whole day, a different one each day
+    Daily,
 }
 
-#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
-#[serde(rename_all = "lowercase")]
+/// How deep the directory in `path` is searched for images
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum Recursive {
+    /// Only the images directly inside the directory
+    Off,
+    /// Up to this many levels below the directory, `1` is the same as `Off`
+    Depth(usize),
+    /// The whole tree of subdirectories
+    #[default]
+    On,
+}
+
+impl Recursive {
+    /// The depth passed to [`walkdir::WalkDir::max_depth`], the directory itself is at depth 0
+    pub fn max_depth(self) -> usize {
+        match self {
+            Recursive::Off => 1,
+            Recursive::Depth(depth) => depth,
+            Recursive::On => usize::MAX,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Recursive {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Enabled(bool),
+            Name(String),
+            Depth(usize),
+        }
+
+        match Value::deserialize(deserializer)? {
+            Value::Enabled(true) => Ok(Self::On),
+            Value::Enabled(false) => Ok(Self::Off),
+            Value::Name(name) => match name.as_str() {
+                "on" => Ok(Self::On),
+                "off" => Ok(Self::Off),
+                _ => Err(serde::de::Error::unknown_variant(&name, &["on", "off"])),
+            },
+            Value::Depth(0) => Err(serde::de::Error::custom(
+                "the recursive depth must be at least 1",
+            )),
+            Value::Depth(depth) => Ok(Self::Depth(depth)),
+        }
+    }
+}
+
+impl Serialize for Recursive {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Recursive::Off => serializer.serialize_bool(false),
+            Recursive::Depth(depth) => serializer.serialize_u64(*depth as u64),
+            Recursive::On => serializer.serialize_bool(true),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum BackgroundMode {
     Stretch,
+    /// Fill the display keeping the aspect ratio, cropping the parts of the image that don't fit
     #[default]
-    Center,
-    Fit,
+    Cover,
+    /// Show the entire image keeping the aspect ratio, leaving empty borders
+    Contain,
+    /// Match the width of the display keeping the aspect ratio, cropping or leaving empty borders
+    /// at the top and bottom
+    FitWidth,
+    /// Match the height of the display keeping the aspect ratio, cropping or leaving empty
+    /// borders at the left and right
+    FitHeight,
+    /// Like `Contain`, filling the borders with a blurred copy of the image. The image is
+    /// composed at the size of the display when it's decoded
+    ContainBlur,
     Tile,
+    /// Show a grid of different images from `path`, see `mosaic_grid` and `mosaic_gap`
+    Mosaic,
+}
+
+impl BackgroundMode {
+    /// Name of the mode in the configuration
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Stretch => "stretch",
+            Self::Cover => "cover",
+            Self::Contain => "contain",
+            Self::FitWidth => "fit-width",
+            Self::FitHeight => "fit-height",
+            Self::ContainBlur => "contain-blur",
+            Self::Tile => "tile",
+            Self::Mosaic => "mosaic",
+        }
+    }
+
+    /// Whether the image can be bigger than the display, so that `focal_crop` applies
+    pub fn crops_image(self) -> bool {
+        matches!(self, Self::Cover | Self::FitWidth | Self::FitHeight)
+    }
+
+    /// Whether the image is placed inside the display, so that `gravity` and the offsets apply
+    pub fn places_image(self) -> bool {
+        matches!(
+            self,
+            Self::Cover | Self::Contain | Self::FitWidth | Self::FitHeight
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for BackgroundMode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        let deprecated = |new_mode: BackgroundMode, new_name: &str| {
+            warn!("mode {value:?} is deprecated, use {new_name:?} instead");
+            Ok(new_mode)
+        };
+        match value.as_str() {
+            "stretch" => Ok(Self::Stretch),
+            "cover" => Ok(Self::Cover),
+            "contain" => Ok(Self::Contain),
+            "fit-width" => Ok(Self::FitWidth),
+            "fit-height" => Ok(Self::FitHeight),
+            "contain-blur" => Ok(Self::ContainBlur),
+            "tile" => Ok(Self::Tile),
+            "mosaic" => Ok(Self::Mosaic),
+            "center" => deprecated(Self::Cover, "cover"),
+            "fit" => deprecated(Self::Contain, "contain"),
+            _ => Err(serde::de::Error::unknown_variant(
+                &value,
+                &[
+                    "stretch",
+                    "cover",
+                    "contain",
+                    "fit-width",
+                    "fit-height",
+                    "contain-blur",
+                    "tile",
+                    "mosaic",
+                ],
+            )),
+        }
+    }
+}
+
+/// How the `tile` mode scales the image so that only whole tiles are shown
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TileSnap {
+    /// Keep the size of the tiles, those at the edges can be cut
+    #[default]
+    None,
+    /// Fit a whole number of tiles horizontally, keeping their aspect ratio
+    Horizontal,
+    /// Fit a whole number of tiles on both axes, their aspect ratio can change slightly
+    Both,
+}
+
+/// Where the image is placed when it doesn't cover the whole display (in `contain` mode) or
+/// which part of the image is shown when it has been cropped (in `cover` mode)
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Gravity {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    #[default]
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Gravity {
+    /// Position of the image inside the free space, for both the horizontal and vertical axes:
+    /// `0.0` is the left/top edge and `1.0` is the right/bottom edge
+    pub fn position(&self) -> (f32, f32) {
+        match self {
+            Gravity::TopLeft => (0.0, 0.0),
+            Gravity::Top => (0.5, 0.0),
+            Gravity::TopRight => (1.0, 0.0),
+            Gravity::Left => (0.0, 0.5),
+            Gravity::Center => (0.5, 0.5),
+            Gravity::Right => (1.0, 0.5),
+            Gravity::BottomLeft => (0.0, 1.0),
+            Gravity::Bottom => (0.5, 1.0),
+            Gravity::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+/// What the displays of a `group` share when they change wallpaper together. In both modes they
+/// follow the timer of the first display
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GroupMode {
+    /// The displays with the same `path` show the same image
+    #[default]
+    Image,
+    /// Every display picks the next image from its own `path`
+    Timer,
+}
+
+/// Layer of the compositor where the wallpaper is drawn, the higher ones are drawn above
+/// the windows
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WallpaperLayer {
+    #[default]
+    Background,
+    Bottom,
+    Top,
+    Overlay,
+}
+
+impl From<WallpaperLayer> for Layer {
+    fn from(layer: WallpaperLayer) -> Self {
+        match layer {
+            WallpaperLayer::Background => Layer::Background,
+            WallpaperLayer::Bottom => Layer::Bottom,
+            WallpaperLayer::Top => Layer::Top,
+            WallpaperLayer::Overlay => Layer::Overlay,
+        }
+    }
+}
+
+/// Edge of the display the wallpaper is attached to, see `anchor` in [`WallpaperInfo`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LayerAnchor {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+impl LayerAnchor {
+    pub const ALL: [Self; 4] = [Self::Top, Self::Bottom, Self::Left, Self::Right];
+
+    /// Name of the edge in the configuration
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Top => "top",
+            Self::Bottom => "bottom",
+            Self::Left => "left",
+            Self::Right => "right",
+        }
+    }
+}
+
+impl From<LayerAnchor> for Anchor {
+    fn from(anchor: LayerAnchor) -> Self {
+        match anchor {
+            LayerAnchor::Top => Anchor::TOP,
+            LayerAnchor::Bottom => Anchor::BOTTOM,
+            LayerAnchor::Left => Anchor::LEFT,
+            LayerAnchor::Right => Anchor::RIGHT,
+        }
+    }
+}
+
+/// Largest size of the images loaded as textures, bigger images are downscaled when decoded
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum MaxTextureSize {
+    /// Keep enough pixels to cover the display
+    #[default]
+    Auto,
+    /// Load the images at their full size
+    Unlimited,
+    /// Maximum length of the longest side, in pixels
+    Pixels(u32),
+}
+
+impl<'de> Deserialize<'de> for MaxTextureSize {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Name(String),
+            Pixels(u32),
+        }
+
+        match Value::deserialize(deserializer)? {
+            Value::Name(name) => match name.as_str() {
+                "auto" => Ok(Self::Auto),
+                "none" => Ok(Self::Unlimited),
+                _ => Err(serde::de::Error::unknown_variant(&name, &["auto", "none"])),
+            },
+            Value::Pixels(pixels) => Ok(Self::Pixels(pixels)),
+        }
+    }
+}
+
+impl Serialize for MaxTextureSize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            MaxTextureSize::Auto => serializer.serialize_str("auto"),
+            MaxTextureSize::Unlimited => serializer.serialize_str("none"),
+            MaxTextureSize::Pixels(pixels) => serializer.serialize_u32(*pixels),
+        }
+    }
+}
+
+/// Size of a file in bytes, either a number of bytes or a number followed by `K`, `M` or `G`,
+/// as multiples of 1024
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FileSize(pub u64);
+
+impl FromStr for FileSize {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid file size {value:?}, expected a number like \"20M\"");
+        let digits = value.trim_end_matches(|c: char| !c.is_ascii_digit());
+        let multiplier: u64 = match value[digits.len()..].trim() {
+            "" | "B" => 1,
+            "K" | "KB" | "KiB" => 1 << 10,
+            "M" | "MB" | "MiB" => 1 << 20,
+            "G" | "GB" | "GiB" => 1 << 30,
+            _ => return Err(error()),
+        };
+        digits
+            .parse::<u64>()
+            .ok()
+            .and_then(|size| size.checked_mul(multiplier))
+            .map(Self)
+            .ok_or_else(error)
+    }
+}
+
+impl<'de> Deserialize<'de> for FileSize {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Bytes(u64),
+            Name(String),
+        }
+
+        match Value::deserialize(deserializer)? {
+            Value::Bytes(bytes) => Ok(Self(bytes)),
+            Value::Name(name) => name.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Weights of the images in the `random` sorting, from glob patterns matched against the path
+/// of the images relative to `path`. When several patterns match, the longest one is used; the
+/// images not matched by any pattern have a weight of `1.0`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Weights(Vec<(Pattern, f64)>);
+
+impl Weights {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn weight(&self, relative_path: &Path) -> f64 {
+        self.0
+            .iter()
+            .find(|(pattern, _)| pattern.matches_path(relative_path))
+            .map_or(1.0, |(_, weight)| *weight)
+    }
+}
+
+impl<'de> Deserialize<'de> for Weights {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let mut weights = BTreeMap::<String, f64>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(pattern, weight)| {
+                if !(weight >= 0.0 && weight.is_finite()) {
+                    return Err(serde::de::Error::custom(format!(
+                        "invalid weight {weight} for {pattern:?}, expected a positive number"
+                    )));
+                }
+                Pattern::new(&pattern)
+                    .map(|pattern| (pattern, weight))
+                    .map_err(serde::de::Error::custom)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // The most specific pattern comes first
+        weights.sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.as_str().len()));
+        Ok(Self(weights))
+    }
+}
+
+impl Serialize for Weights {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_map(
+            self.0
+                .iter()
+                .map(|(pattern, weight)| (pattern.as_str(), weight)),
+        )
+    }
+}
+
+/// Solid color shown in place of an image, in the form `#rrggbb`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Color(pub [u8; 3]);
+
+impl Color {
+    /// A single pixel of this color, drawn over the whole display like any other wallpaper
+    pub fn image(&self) -> RgbaImage {
+        let [r, g, b] = self.0;
+        RgbaImage::from_raw(1, 1, vec![r, g, b, 255]).unwrap()
+    }
+
+    /// Opaque color, with each channel from `0.0` to `1.0`
+    pub fn rgba(&self) -> [f32; 4] {
+        let [r, g, b] = self.0.map(|channel| f32::from(channel) / 255.0);
+        [r, g, b, 1.0]
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let hex = value
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| format!("invalid color {value:?}, expected the form \"#rrggbb\""))?;
+        let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).unwrap();
+        Ok(Self([channel(0), channel(2), channel(4)]))
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Linear gradient shown in place of an image, e.g.
+/// `{ from = "#000000", to = "#1e90ff", angle = 45 }`
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Gradient {
+    pub from: Color,
+    pub to: Color,
+    /// Degrees clockwise like in CSS: `0` goes from the bottom to the top, `90` from the left
+    /// to the right
+    #[serde(default = "Gradient::default_angle")]
+    pub angle: f32,
+}
+
+impl Gradient {
+    /// The longest side of the images, a gradient stays smooth once scaled to the display
+    const IMAGE_SIZE: f32 = 256.0;
+
+    fn default_angle() -> f32 {
+        180.0
+    }
+
+    /// The gradient with the aspect ratio of a display of `width`x`height` pixels, so that the
+    /// angle is the same once the image is stretched over it. Like in CSS, the line of the
+    /// gradient is long enough for the corners to get `from` and `to`
+    pub fn image(&self, width: i32, height: i32) -> RgbaImage {
+        let scale = (Self::IMAGE_SIZE / width.max(height).max(1) as f32).min(1.0);
+        let width = ((width as f32 * scale).round() as u32).max(1);
+        let height = ((height as f32 * scale).round() as u32).max(1);
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        let length = (width as f32 * sin).abs() + (height as f32 * cos).abs();
+        let [from, to] = [self.from.0, self.to.0].map(|color| color.map(f32::from));
+        RgbaImage::from_fn(width, height, |x, y| {
+            let x = x as f32 + 0.5 - width as f32 / 2.0;
+            let y = y as f32 + 0.5 - height as f32 / 2.0;
+            // The y axis points to the bottom
+            let progress = ((x * sin - y * cos) / length + 0.5).clamp(0.0, 1.0);
+            let [red, green, blue] = std::array::from_fn(|channel| {
+                (from[channel] + (to[channel] - from[channel]) * progress).round() as u8
+            });
+            Rgba([red, green, blue, 255])
+        })
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let [r, g, b] = self.0;
+        serializer.collect_str(&format_args!("#{r:02x}{g:02x}{b:02x}"))
+    }
 }
@@ -0,0 +1,288 @@
+//! Follow the workspaces shown on each display, used by `workspace_paths`. There is no common
+//! protocol for them among the compositors, so their own IPC is used: Sway (and i3) and
+//! Hyprland are supported.
+
+use std::{
+    collections::HashMap,
+    env,
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use color_eyre::{
+    eyre::{bail, ensure, Context},
+    Result,
+};
+use log::{debug, warn};
+use serde::Deserialize;
+use smithay_client_toolkit::reexports::calloop::channel::Sender;
+
+/// Name of the workspace shown on each display, by the name of the display
+pub type ActiveWorkspaces = HashMap<String, String>;
+
+const SWAY_MAGIC: &[u8] = b"i3-ipc";
+const SWAY_GET_WORKSPACES: u32 = 1;
+const SWAY_SUBSCRIBE: u32 = 2;
+/// Set in the type of the events, to tell them apart from the replies
+const SWAY_EVENT: u32 = 1 << 31;
+
+/// The events of Hyprland after which the workspaces of the displays are read again
+const HYPRLAND_EVENTS: &[&str] = &[
+    "workspace",
+    "workspacev2",
+    "focusedmon",
+    "moveworkspace",
+    "moveworkspacev2",
+    "renameworkspace",
+    "monitoradded",
+    "monitoraddedv2",
+];
+
+/// Read the workspaces currently shown, then listen to their changes in another thread and
+/// send them to the main loop. Fails when the compositor is not supported
+pub fn listen(sender: Sender<ActiveWorkspaces>) -> Result<ActiveWorkspaces> {
+    let Some(compositor) = Compositor::from_env() else {
+        bail!("the compositor is not supported, only Sway, i3 and Hyprland are");
+    };
+    let mut events = compositor
+        .subscribe()
+        .context("subscribing to the workspace events")?;
+    let mut workspaces = compositor
+        .active_workspaces()
+        .context("reading the active workspaces")?;
+    debug!("active workspaces: {workspaces:?}");
+
+    let active = workspaces.clone();
+    thread::Builder::new()
+        .name("wpaperd-workspace".to_string())
+        .spawn(move || loop {
+            let changed = events.wait().and_then(|_| compositor.active_workspaces());
+            match changed {
+                Ok(changed) if changed == workspaces => {}
+                Ok(changed) => {
+                    debug!("active workspaces: {changed:?}");
+                    workspaces = changed;
+                    if sender.send(workspaces.clone()).is_err() {
+                        // The main loop is not running anymore
+                        break;
+                    }
+                }
+                Err(err) => {
+                    warn!("stopped following the workspaces: {err:?}");
+                    break;
+                }
+            }
+        })
+        .context("spawning the workspace thread")?;
+
+    Ok(active)
+}
+
+enum Compositor {
+    /// Path of the IPC socket, which i3 shares
+    Sway(PathBuf),
+    /// Directory containing the sockets of the running instance
+    Hyprland(PathBuf),
+}
+
+impl Compositor {
+    fn from_env() -> Option<Self> {
+        if let Some(socket) = env::var_os("SWAYSOCK").or_else(|| env::var_os("I3SOCK")) {
+            return Some(Compositor::Sway(socket.into()));
+        }
+        let signature = env::var_os("HYPRLAND_INSTANCE_SIGNATURE")?;
+        // The sockets were in /tmp before Hyprland 0.40
+        let dir = env::var_os("XDG_RUNTIME_DIR")
+            .map(|dir| PathBuf::from(dir).join("hypr").join(&signature))
+            .filter(|dir| dir.exists())
+            .unwrap_or_else(|| Path::new("/tmp/hypr").join(&signature));
+        Some(Compositor::Hyprland(dir))
+    }
+
+    fn subscribe(&self) -> Result<Events> {
+        match self {
+            Compositor::Sway(socket) => {
+                let mut stream = UnixStream::connect(socket)?;
+                stream.write_all(&sway_message(SWAY_SUBSCRIBE, br#"["workspace","output"]"#))?;
+                let (_, reply) = read_sway_message(&mut stream)?;
+                let reply: SwaySubscribeReply = serde_json::from_slice(&reply)?;
+                ensure!(reply.success, "sway refused the subscription");
+                Ok(Events::Sway(stream))
+            }
+            Compositor::Hyprland(dir) => {
+                let stream = UnixStream::connect(dir.join(".socket2.sock"))?;
+                Ok(Events::Hyprland(BufReader::new(stream)))
+            }
+        }
+    }
+
+    /// Ask the compositor, using a new connection each time
+    fn active_workspaces(&self) -> Result<ActiveWorkspaces> {
+        match self {
+            Compositor::Sway(socket) => {
+                let mut stream = UnixStream::connect(socket)?;
+                stream.write_all(&sway_message(SWAY_GET_WORKSPACES, b""))?;
+                let (_, reply) = read_sway_message(&mut stream)?;
+                parse_sway_workspaces(&reply)
+            }
+            Compositor::Hyprland(dir) => {
+                let mut stream = UnixStream::connect(dir.join(".socket.sock"))?;
+                stream.write_all(b"j/monitors")?;
+                // Hyprland closes the connection after the reply
+                let mut reply = Vec::new();
+                stream.read_to_end(&mut reply)?;
+                parse_hyprland_monitors(&reply)
+            }
+        }
+    }
+}
+
+enum Events {
+    Sway(UnixStream),
+    Hyprland(BufReader<UnixStream>),
+}
+
+impl Events {
+    /// Block until an event that can change the active workspaces arrives
+    fn wait(&mut self) -> Result<()> {
+        match self {
+            Events::Sway(stream) => loop {
+                let (kind, _) = read_sway_message(stream)?;
+                if kind & SWAY_EVENT != 0 {
+                    return Ok(());
+                }
+            },
+            Events::Hyprland(stream) => loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line)? == 0 {
+                    bail!("hyprland closed the event socket");
+                }
+                if is_hyprland_workspace_event(&line) {
+                    return Ok(());
+                }
+            },
+        }
+    }
+}
+
+/// The header is the magic string followed by the length of the payload and the type of the
+/// message, in native endianness
+fn sway_message(kind: u32, payload: &[u8]) -> Vec<u8> {
+    let mut message = SWAY_MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(payload);
+    message
+}
+
+/// Return the type and the payload of the message
+fn read_sway_message(stream: &mut impl Read) -> Result<(u32, Vec<u8>)> {
+    let mut header = [0; 14];
+    stream.read_exact(&mut header)?;
+    ensure!(
+        &header[..6] == SWAY_MAGIC,
+        "invalid magic string in the sway message"
+    );
+    let len = u32::from_ne_bytes(header[6..10].try_into()?) as usize;
+    let kind = u32::from_ne_bytes(header[10..].try_into()?);
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload)?;
+    Ok((kind, payload))
+}
+
+#[derive(Deserialize)]
+struct SwaySubscribeReply {
+    success: bool,
+}
+
+#[derive(Deserialize)]
+struct SwayWorkspace {
+    name: String,
+    output: String,
+    visible: bool,
+}
+
+/// Each display shows only one of its workspaces, the visible one
+fn parse_sway_workspaces(reply: &[u8]) -> Result<ActiveWorkspaces> {
+    let workspaces: Vec<SwayWorkspace> =
+        serde_json::from_slice(reply).context("parsing the sway workspaces")?;
+    Ok(workspaces
+        .into_iter()
+        .filter(|workspace| workspace.visible)
+        .map(|workspace| (workspace.output, workspace.name))
+        .collect())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HyprlandMonitor {
+    name: String,
+    active_workspace: HyprlandWorkspace,
+}
+
+#[derive(Deserialize)]
+struct HyprlandWorkspace {
+    name: String,
+}
+
+fn parse_hyprland_monitors(reply: &[u8]) -> Result<ActiveWorkspaces> {
+    let monitors: Vec<HyprlandMonitor> =
+        serde_json::from_slice(reply).context("parsing the hyprland monitors")?;
+    Ok(monitors
+        .into_iter()
+        .map(|monitor| (monitor.name, monitor.active_workspace.name))
+        .collect())
+}
+
+/// The events are lines in the form `EVENT>>DATA`
+fn is_hyprland_workspace_event(line: &str) -> bool {
+    line.split_once(">>")
+        .is_some_and(|(event, _)| HYPRLAND_EVENTS.contains(&event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sway_message_is_read_back() {
+        let message = sway_message(SWAY_SUBSCRIBE, br#"["workspace"]"#);
+        let (kind, payload) = read_sway_message(&mut message.as_slice()).unwrap();
+        assert_eq!(kind, SWAY_SUBSCRIBE);
+        assert_eq!(payload, br#"["workspace"]"#);
+    }
+
+    #[test]
+    fn test_parse_sway_workspaces() {
+        let reply = br#"[
+            {"num": 1, "name": "1", "output": "DP-1", "visible": true, "focused": false},
+            {"num": 2, "name": "2: web", "output": "DP-1", "visible": false, "focused": false},
+            {"num": 3, "name": "3", "output": "HDMI-A-1", "visible": true, "focused": true}
+        ]"#;
+        let workspaces = parse_sway_workspaces(reply).unwrap();
+        assert_eq!(workspaces.len(), 2);
+        assert_eq!(workspaces["DP-1"], "1");
+        assert_eq!(workspaces["HDMI-A-1"], "3");
+    }
+
+    #[test]
+    fn test_parse_hyprland_monitors() {
+        let reply = br#"[
+            {"id": 0, "name": "DP-1", "activeWorkspace": {"id": 2, "name": "2"}},
+            {"id": 1, "name": "HDMI-A-1", "activeWorkspace": {"id": 4, "name": "web"}}
+        ]"#;
+        let workspaces = parse_hyprland_monitors(reply).unwrap();
+        assert_eq!(workspaces["DP-1"], "2");
+        assert_eq!(workspaces["HDMI-A-1"], "web");
+    }
+
+    #[test]
+    fn test_hyprland_workspace_event() {
+        assert!(is_hyprland_workspace_event("workspacev2>>2,2\n"));
+        assert!(is_hyprland_workspace_event("focusedmon>>DP-1,web\n"));
+        assert!(!is_hyprland_workspace_event("activewindow>>kitty,~\n"));
+        assert!(!is_hyprland_workspace_event("workspace"));
+    }
+}
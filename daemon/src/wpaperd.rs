@@ -1,31 +1,58 @@
 use std::cell::RefCell;
+use std::path::PathBuf;
 use std::rc::Rc;
 
 use color_eyre::owo_colors::OwoColorize;
 use color_eyre::Result;
-use log::{error, warn};
+use log::{error, info, warn};
 use smithay_client_toolkit::compositor::{CompositorHandler, CompositorState, Region};
 use smithay_client_toolkit::output::{OutputHandler, OutputState};
-use smithay_client_toolkit::reexports::calloop::LoopHandle;
+use smithay_client_toolkit::reexports::calloop::{channel::Sender, LoopHandle};
 use smithay_client_toolkit::reexports::client::globals::GlobalList;
-use smithay_client_toolkit::reexports::client::protocol::{wl_output, wl_surface};
-use smithay_client_toolkit::reexports::client::{Connection, QueueHandle};
+use smithay_client_toolkit::reexports::client::protocol::wl_seat::WlSeat;
+use smithay_client_toolkit::reexports::client::protocol::{wl_buffer, wl_output, wl_surface};
+use smithay_client_toolkit::reexports::client::{
+    event_created_child, Connection, Dispatch, Proxy, QueueHandle, WEnum,
+};
+use smithay_client_toolkit::reexports::protocols::ext::idle_notify::v1::client::{
+    ext_idle_notification_v1::{self, ExtIdleNotificationV1},
+    ext_idle_notifier_v1::ExtIdleNotifierV1,
+};
+use smithay_client_toolkit::reexports::protocols::wp::fractional_scale::v1::client::{
+    wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+    wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+};
+use smithay_client_toolkit::reexports::protocols::wp::single_pixel_buffer::v1::client::wp_single_pixel_buffer_manager_v1::WpSinglePixelBufferManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::{
+    wp_viewport::WpViewport, wp_viewporter::WpViewporter,
+};
+use smithay_client_toolkit::reexports::protocols_wlr::output_power_management::v1::client::{
+    zwlr_output_power_manager_v1::ZwlrOutputPowerManagerV1,
+    zwlr_output_power_v1::{self, ZwlrOutputPowerV1},
+};
+use smithay_client_toolkit::reexports::protocols_wlr::output_management::v1::client::{
+    zwlr_output_head_v1::{self, ZwlrOutputHeadV1},
+    zwlr_output_manager_v1::{self, ZwlrOutputManagerV1},
+    zwlr_output_mode_v1::{self, ZwlrOutputModeV1},
+};
 use smithay_client_toolkit::registry::{ProvidesRegistryState, RegistryState};
 use smithay_client_toolkit::shell::wlr_layer::{
-    Anchor, Layer, LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure,
+    LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure,
 };
 use smithay_client_toolkit::shm::{Shm, ShmHandler};
 use smithay_client_toolkit::{
     delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_shm,
     registry_handlers,
 };
+use wpaperd_ipc::IpcEvent;
 
 use crate::config::Config;
-use crate::display_info::DisplayInfo;
+use crate::display_info::{DisplayInfo, OutputHeadInfo};
 use crate::filelist_cache::FilelistCache;
-use crate::image_loader::ImageLoader;
+use crate::image_loader::{DecodedImage, ImageLoader};
+use crate::ipc_server::EventSubscribers;
 use crate::surface::Surface;
-use crate::wallpaper_info::WallpaperInfo;
+use crate::wallpaper_info::GroupMode;
 
 pub struct Wpaperd {
     pub compositor_state: CompositorState,
@@ -33,24 +60,52 @@ pub struct Wpaperd {
     pub shm_state: Shm,
     pub layer_state: LayerShell,
     pub registry_state: RegistryState,
+    /// Both are needed to use fractional scaling, when the compositor doesn't support them we
+    /// fall back to the integer buffer scale
+    fractional_scale_manager: Option<WpFractionalScaleManagerV1>,
+    viewporter: Option<WpViewporter>,
+    /// Shows the solid colors without using the GPU, it needs the viewporter to scale them
+    single_pixel_buffer_manager: Option<WpSinglePixelBufferManagerV1>,
+    /// Both are needed to dim the wallpapers when the session is idle
+    idle_notifier: Option<ExtIdleNotifierV1>,
+    seat: Option<WlSeat>,
+    /// Richer identifiers for the outputs, when the compositor supports wlr-output-management
+    output_manager: Option<ZwlrOutputManagerV1>,
+    output_heads: Vec<(ZwlrOutputHeadV1, OutputHeadInfo)>,
+    /// Reports when the outputs are turned off, so that their wallpaper is not drawn
+    output_power_manager: Option<ZwlrOutputPowerManagerV1>,
+    /// Set when the identifiers of a display changed, its configuration has to be looked up
+    /// again
+    pub output_heads_changed: bool,
     pub surfaces: Vec<Surface>,
+    /// The outputs whose section sets `ignore`, they have no surface
+    ignored_outputs: Vec<wl_output::WlOutput>,
     pub config: Config,
-    egl_display: egl::Display,
+    egl_display: Option<egl::Display>,
     pub filelist_cache: Rc<RefCell<FilelistCache>>,
-    image_loader: Rc<RefCell<ImageLoader>>,
+    pub image_loader: Rc<RefCell<ImageLoader>>,
+    /// Shared with the surfaces, which send the events about their wallpaper
+    pub events: Rc<RefCell<EventSubscribers>>,
+    xdg_state_home_dir: PathBuf,
+    /// Used by the wayland handlers to add timers
+    event_loop_handle: LoopHandle<'static, Wpaperd>,
 }
 
 impl Wpaperd {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         qh: &QueueHandle<Self>,
         globals: &GlobalList,
         config: Config,
-        egl_display: egl::Display,
+        egl_display: Option<egl::Display>,
         filelist_cache: Rc<RefCell<FilelistCache>>,
+        xdg_state_home_dir: PathBuf,
+        decoded_sender: Sender<DecodedImage>,
+        event_loop_handle: LoopHandle<'static, Wpaperd>,
     ) -> Result<Self> {
         let shm_state = Shm::bind(globals, qh)?;
 
-        let image_loader = Rc::new(RefCell::new(ImageLoader::new()));
+        let image_loader = Rc::new(RefCell::new(ImageLoader::new(decoded_sender)));
 
         Ok(Self {
             compositor_state: CompositorState::bind(globals, qh)?,
@@ -58,20 +113,164 @@ impl Wpaperd {
             shm_state,
             layer_state: LayerShell::bind(globals, qh)?,
             registry_state: RegistryState::new(globals),
+            fractional_scale_manager: globals.bind(qh, 1..=1, ()).ok(),
+            viewporter: globals.bind(qh, 1..=1, ()).ok(),
+            single_pixel_buffer_manager: globals.bind(qh, 1..=1, ()).ok(),
+            idle_notifier: globals.bind(qh, 1..=1, ()).ok(),
+            seat: globals.bind(qh, 1..=1, ()).ok(),
+            output_manager: globals.bind(qh, 1..=3, ()).ok(),
+            output_heads: Vec::new(),
+            output_power_manager: globals.bind(qh, 1..=1, ()).ok(),
+            output_heads_changed: false,
             surfaces: Vec::new(),
+            ignored_outputs: Vec::new(),
             config,
             egl_display,
             filelist_cache,
             image_loader,
+            events: Rc::new(RefCell::new(EventSubscribers::default())),
+            xdg_state_home_dir,
+            event_loop_handle,
         })
     }
 
+    /// Create the surface of `output`, unless the configuration ignores it
+    fn add_output(&mut self, qh: &QueueHandle<Self>, output: wl_output::WlOutput) {
+        let info = match self.output_state.info(&output) {
+            Some(info) => info,
+            None => {
+                error!("could not get info about output");
+                return;
+            }
+        };
+        let name = info
+            .name
+            .as_ref()
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| "unnamed".to_string());
+        let (scale_factor, transform) = (info.scale_factor, info.transform);
+        let mut display_info = DisplayInfo::new(info);
+        for (_, head) in &self.output_heads {
+            display_info.merge_head(head);
+        }
+
+        if self.config.is_ignored(&display_info) {
+            info!("ignoring display {}", name.bold().magenta());
+            self.ignored_outputs.push(output);
+            return;
+        }
+
+        let surface = self.compositor_state.create_surface(qh);
+        // When using fractional scaling, the buffer scale must stay 1
+        let fractional_scale = match (&self.fractional_scale_manager, &self.viewporter) {
+            (Some(fractional_scale_manager), Some(viewporter)) => Some((
+                viewporter.get_viewport(&surface, qh, ()),
+                fractional_scale_manager.get_fractional_scale(&surface, qh, surface.clone()),
+            )),
+            _ => {
+                surface.set_buffer_scale(scale_factor);
+                None
+            }
+        };
+        surface.set_buffer_transform(transform);
+
+        let wallpaper_info = match self.config.get_info_for_output(&display_info) {
+            Ok(wallpaper_info) => wallpaper_info,
+            Err(err) => {
+                warn!(
+                    "Configuration error on display {}: {err:?}",
+                    name.bold().magenta()
+                );
+                self.config.get_fallback_for_output(&display_info)
+            }
+        };
+
+        let layer = self.layer_state.create_layer_surface(
+            qh,
+            surface.clone(),
+            wallpaper_info.layer.into(),
+            Some(format!("wpaperd-{}", name)),
+            Some(&output),
+        );
+        Surface::set_placement(&layer, &wallpaper_info, &display_info);
+
+        match Region::new(&self.compositor_state) {
+            Ok(region) => {
+                // Wayland clients are expected to render the cursor on their input region. By setting the
+                // input region to an empty region, the compositor renders the default cursor. Without
+                // this, and empty desktop won't render a cursor.
+                surface.set_input_region(Some(region.wl_region()));
+
+                // From `wl_surface::set_opaque_region`:
+                // > Setting the pending opaque region has copy semantics, and the
+                // > wl_region object can be destroyed immediately.
+                region.wl_region().destroy();
+            }
+
+            Err(_) => {
+                warn!("could not create region, cursor won't be shown for display {name}");
+                return;
+            }
+        };
+
+        let output_power = self
+            .output_power_manager
+            .as_ref()
+            .map(|manager| manager.get_output_power(&output, qh, surface.clone()));
+        let surface = Surface::new(
+            layer,
+            output,
+            display_info,
+            wallpaper_info,
+            self.egl_display,
+            &self.shm_state,
+            self.filelist_cache.clone(),
+            self.image_loader.clone(),
+            self.events.clone(),
+            self.xdg_state_home_dir.clone(),
+        );
+        let mut surface = match surface {
+            Ok(surface) => surface,
+            Err(err) => {
+                error!(
+                    "could not draw on display {}: {err:?}",
+                    name.bold().magenta()
+                );
+                return;
+            }
+        };
+        if let Some((viewport, fractional_scale)) = fractional_scale {
+            surface.enable_fractional_scale(viewport, fractional_scale);
+            if let Some(single_pixel_buffer_manager) = &self.single_pixel_buffer_manager {
+                surface.enable_single_pixel_buffer(single_pixel_buffer_manager.clone());
+            }
+        }
+        if let (Some(idle_notifier), Some(seat)) = (&self.idle_notifier, &self.seat) {
+            surface.update_idle_notification(idle_notifier, seat, qh);
+        }
+        if let Some(output_power) = output_power {
+            surface.enable_output_power(output_power);
+        }
+        self.events
+            .borrow_mut()
+            .emit(IpcEvent::OutputAdded { monitor: name });
+        self.surfaces.push(surface);
+    }
+
+    /// Apply the configuration to every surface. The surfaces whose settings are unchanged are
+    /// left alone, so that editing the section of a display doesn't affect the others
     pub fn update_surfaces(&mut self, ev_handle: LoopHandle<Wpaperd>, qh: &QueueHandle<Wpaperd>) {
+        self.update_ignored_outputs(qh);
         for surface in &mut self.surfaces {
-            let res = self.config.get_output_by_name(&surface.name());
+            let res = self.config.get_info_for_output(&surface.display_info());
             match res {
                 Ok(wallpaper_info) => {
-                    surface.update_wallpaper_info(&ev_handle, qh, wallpaper_info);
+                    if !surface.update_wallpaper_info(&ev_handle, qh, wallpaper_info) {
+                        continue;
+                    }
+                    if let (Some(idle_notifier), Some(seat)) = (&self.idle_notifier, &self.seat) {
+                        surface.update_idle_notification(idle_notifier, seat, qh);
+                    }
                 }
                 Err(err) => warn!(
                     "Configuration error for display {}: {err:?}",
@@ -81,6 +280,95 @@ impl Wpaperd {
         }
     }
 
+    /// Remove the surfaces of the displays that the configuration now ignores, and create the
+    /// ones of the displays that are not ignored anymore
+    fn update_ignored_outputs(&mut self, qh: &QueueHandle<Wpaperd>) {
+        let mut index = 0;
+        while index < self.surfaces.len() {
+            if self.config.is_ignored(&self.surfaces[index].display_info()) {
+                // Keep the order of the others, the first display of a group runs its timer
+                let surface = self.surfaces.remove(index);
+                info!("ignoring display {}", surface.name().bold().magenta());
+                self.events.borrow_mut().emit(IpcEvent::OutputRemoved {
+                    monitor: surface.name(),
+                });
+                self.ignored_outputs.push(surface.output.clone());
+            } else {
+                index += 1;
+            }
+        }
+
+        for output in std::mem::take(&mut self.ignored_outputs) {
+            let ignored = self.output_state.info(&output).map_or(true, |info| {
+                let mut display_info = DisplayInfo::new(info);
+                for (_, head) in &self.output_heads {
+                    display_info.merge_head(head);
+                }
+                self.config.is_ignored(&display_info)
+            });
+            if ignored {
+                self.ignored_outputs.push(output);
+            } else {
+                self.add_output(qh, output);
+            }
+        }
+    }
+
+    /// Only the first display of each group runs a timer, the others change wallpaper when it
+    /// expires. Called in each iteration of the event loop, so that the group keeps its timer
+    /// when displays are added, removed or reconfigured
+    pub fn update_groups(&mut self, handle: &LoopHandle<Wpaperd>, qh: &QueueHandle<Wpaperd>) {
+        for i in 0..self.surfaces.len() {
+            let group = self.surfaces[i].group();
+            let following = group.is_some()
+                && self.surfaces[..i]
+                    .iter()
+                    .any(|surface| surface.group() == group);
+            self.surfaces[i].set_following_group(following, handle, qh.clone());
+        }
+    }
+
+    /// Show the next wallpaper on every display of `group` at the same time. The displays with
+    /// the same path as the first one show the image it picked, unless its `group_mode` is
+    /// `timer`. Return false if no display belongs to the group
+    pub fn next_group_wallpaper(&mut self, group: &str, qh: &QueueHandle<Wpaperd>) -> bool {
+        let mut members = self
+            .surfaces
+            .iter_mut()
+            .filter(|surface| surface.group() == Some(group));
+        let Some(first) = members.next() else {
+            return false;
+        };
+        let path = first.path().to_path_buf();
+        let next_img = match first.group_mode() {
+            GroupMode::Image => first.image_picker.pick_next(&path),
+            GroupMode::Timer => None,
+        };
+        first.image_picker.next_image();
+        first.queue_draw(qh);
+
+        for surface in members {
+            match &next_img {
+                Some(img_path) if surface.path() == path => {
+                    surface.image_picker.set_image(img_path.clone())
+                }
+                _ => surface.image_picker.next_image(),
+            }
+            surface.queue_draw(qh);
+        }
+        true
+    }
+
+    /// True once the compositor has described every display and each surface has been
+    /// configured; the displays that are ignored or can't be drawn on have no surface
+    #[cfg(feature = "systemd")]
+    pub fn surfaces_configured(&self) -> bool {
+        self.output_state
+            .outputs()
+            .all(|output| self.output_state.info(&output).is_some())
+            && self.surfaces.iter().all(Surface::is_configured)
+    }
+
     pub fn surface_from_name(&mut self, name: &str) -> Option<&mut Surface> {
         self.surfaces
             .iter_mut()
@@ -103,8 +391,9 @@ impl CompositorHandler for Wpaperd {
         surface: &wl_surface::WlSurface,
         new_factor: i32,
     ) {
+        let handle = self.event_loop_handle.clone();
         self.surface_from_wl_surface(surface)
-            .change_scale_factor(new_factor, qh);
+            .change_scale_factor(new_factor, &handle, qh);
     }
 
     fn frame(
@@ -115,6 +404,7 @@ impl CompositorHandler for Wpaperd {
         time: u32,
     ) {
         let surface = self.surface_from_wl_surface(surface);
+        surface.record_frame(time);
 
         match surface.draw(qh, time) {
             Ok(_) => {}
@@ -147,78 +437,7 @@ impl OutputHandler for Wpaperd {
         qh: &QueueHandle<Self>,
         output: wl_output::WlOutput,
     ) {
-        let surface = self.compositor_state.create_surface(qh);
-
-        let info = match self.output_state.info(&output) {
-            Some(info) => info,
-            None => {
-                error!("could not get info about output");
-                return;
-            }
-        };
-        surface.set_buffer_scale(info.scale_factor);
-        surface.set_buffer_transform(info.transform);
-
-        let name = info
-            .name
-            .as_ref()
-            .map(|name| name.to_string())
-            .unwrap_or_else(|| "unnamed".to_string());
-        let display_info = DisplayInfo::new(info);
-
-        let layer = self.layer_state.create_layer_surface(
-            qh,
-            surface.clone(),
-            Layer::Background,
-            Some(format!("wpaperd-{}", name)),
-            Some(&output),
-        );
-        layer.set_anchor(Anchor::TOP | Anchor::LEFT | Anchor::RIGHT | Anchor::BOTTOM);
-        layer.set_exclusive_zone(-1);
-        layer.set_size(
-            display_info.adjusted_width() as u32,
-            display_info.adjusted_height() as u32,
-        );
-
-        match Region::new(&self.compositor_state) {
-            Ok(region) => {
-                // Wayland clients are expected to render the cursor on their input region. By setting the
-                // input region to an empty region, the compositor renders the default cursor. Without
-                // this, and empty desktop won't render a cursor.
-                surface.set_input_region(Some(region.wl_region()));
-
-                // From `wl_surface::set_opaque_region`:
-                // > Setting the pending opaque region has copy semantics, and the
-                // > wl_region object can be destroyed immediately.
-                region.wl_region().destroy();
-            }
-
-            Err(_) => {
-                warn!("could not create region, cursor won't be shown for display {name}");
-                return;
-            }
-        };
-
-        let wallpaper_info = match self.config.get_output_by_name(&name) {
-            Ok(wallpaper_info) => wallpaper_info,
-            Err(err) => {
-                warn!(
-                    "Configuration error on display {}: {err:?}",
-                    name.bold().magenta()
-                );
-                WallpaperInfo::default()
-            }
-        };
-
-        self.surfaces.push(Surface::new(
-            layer,
-            output,
-            display_info,
-            wallpaper_info,
-            self.egl_display,
-            self.filelist_cache.clone(),
-            self.image_loader.clone(),
-        ));
+        self.add_output(qh, output);
     }
 
     fn update_output(
@@ -245,9 +464,21 @@ impl OutputHandler for Wpaperd {
             .find(|(_, surface)| surface.output == output)
         {
             Some((index, _)) => {
-                self.surfaces.swap_remove(index);
+                let surface = self.surfaces.swap_remove(index);
+                self.events.borrow_mut().emit(IpcEvent::OutputRemoved {
+                    monitor: surface.name(),
+                });
             }
-            None => error!("could not find display while handling output_destroyed"),
+            None => match self
+                .ignored_outputs
+                .iter()
+                .position(|ignored| ignored == &output)
+            {
+                Some(index) => {
+                    self.ignored_outputs.swap_remove(index);
+                }
+                None => error!("could not find display while handling output_destroyed"),
+            },
         }
     }
 }
@@ -280,6 +511,263 @@ impl ShmHandler for Wpaperd {
     }
 }
 
+impl Dispatch<WpFractionalScaleManagerV1, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpFractionalScaleManagerV1,
+        _event: <WpFractionalScaleManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // wp_fractional_scale_manager_v1 has no events
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, wl_surface::WlSurface> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        surface: &wl_surface::WlSurface,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            let handle = state.event_loop_handle.clone();
+            state
+                .surface_from_wl_surface(surface)
+                .change_fractional_scale(scale, &handle, qh);
+        }
+    }
+}
+
+impl Dispatch<WpViewporter, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewporter,
+        _event: <WpViewporter as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // wp_viewporter has no events
+    }
+}
+
+impl Dispatch<WpViewport, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewport,
+        _event: <WpViewport as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // wp_viewport has no events
+    }
+}
+
+impl Dispatch<WpSinglePixelBufferManagerV1, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpSinglePixelBufferManagerV1,
+        _event: <WpSinglePixelBufferManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // wp_single_pixel_buffer_manager_v1 has no events
+    }
+}
+
+impl Dispatch<wl_buffer::WlBuffer, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _proxy: &wl_buffer::WlBuffer,
+        _event: wl_buffer::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // Only the single pixel buffers are created with this data, they are destroyed once
+        // they are replaced and never reused, so there is no need to wait for the release
+    }
+}
+
+impl Dispatch<ExtIdleNotifierV1, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ExtIdleNotifierV1,
+        _event: <ExtIdleNotifierV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // ext_idle_notifier_v1 has no events
+    }
+}
+
+impl Dispatch<ExtIdleNotificationV1, wl_surface::WlSurface> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _proxy: &ExtIdleNotificationV1,
+        event: ext_idle_notification_v1::Event,
+        surface: &wl_surface::WlSurface,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let surface = state.surface_from_wl_surface(surface);
+        match event {
+            ext_idle_notification_v1::Event::Idled => surface.set_idle(true, qh),
+            ext_idle_notification_v1::Event::Resumed => surface.set_idle(false, qh),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZwlrOutputPowerManagerV1, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZwlrOutputPowerManagerV1,
+        _event: <ZwlrOutputPowerManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // zwlr_output_power_manager_v1 has no events
+    }
+}
+
+impl Dispatch<ZwlrOutputPowerV1, wl_surface::WlSurface> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZwlrOutputPowerV1,
+        event: zwlr_output_power_v1::Event,
+        surface: &wl_surface::WlSurface,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let surface = state.surface_from_wl_surface(surface);
+        match event {
+            zwlr_output_power_v1::Event::Mode { mode } => {
+                surface.set_powered(mode != WEnum::Value(zwlr_output_power_v1::Mode::Off), qh)
+            }
+            // Another client controls the power mode, or the output is gone
+            zwlr_output_power_v1::Event::Failed => surface.disable_output_power(qh),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZwlrOutputManagerV1, ()> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZwlrOutputManagerV1,
+        event: zwlr_output_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_output_manager_v1::Event::Head { head } => {
+                state.output_heads.push((head, OutputHeadInfo::default()));
+            }
+            // All the heads have sent their properties
+            zwlr_output_manager_v1::Event::Done { .. } => {
+                for surface in &mut state.surfaces {
+                    for (_, head) in &state.output_heads {
+                        state.output_heads_changed |= surface.merge_output_head(head);
+                    }
+                }
+                // The ignored displays might match another section now
+                state.output_heads_changed |= !state.ignored_outputs.is_empty();
+            }
+            // The compositor stopped sending the heads, keep the identifiers merged so far
+            zwlr_output_manager_v1::Event::Finished => state.output_manager = None,
+            _ => {}
+        }
+    }
+
+    event_created_child!(Wpaperd, ZwlrOutputManagerV1, [
+        zwlr_output_manager_v1::EVT_HEAD_OPCODE => (ZwlrOutputHeadV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrOutputHeadV1, ()> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrOutputHeadV1,
+        event: zwlr_output_head_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(index) = state
+            .output_heads
+            .iter()
+            .position(|(head, _)| head == proxy)
+        else {
+            return;
+        };
+        let info = &mut state.output_heads[index].1;
+        match event {
+            zwlr_output_head_v1::Event::Name { name } => info.name = Some(name),
+            zwlr_output_head_v1::Event::Description { description } => {
+                info.description = Some(description)
+            }
+            zwlr_output_head_v1::Event::Make { make } => info.make = Some(make),
+            zwlr_output_head_v1::Event::Model { model } => info.model = Some(model),
+            zwlr_output_head_v1::Event::SerialNumber { serial_number } => {
+                info.serial = Some(serial_number)
+            }
+            // The output has been disconnected
+            zwlr_output_head_v1::Event::Finished => {
+                let (head, _) = state.output_heads.swap_remove(index);
+                if head.version() >= 3 {
+                    head.release();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    event_created_child!(Wpaperd, ZwlrOutputHeadV1, [
+        zwlr_output_head_v1::EVT_MODE_OPCODE => (ZwlrOutputModeV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrOutputModeV1, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        proxy: &ZwlrOutputModeV1,
+        event: zwlr_output_mode_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The modes are not needed, they are only released
+        if let zwlr_output_mode_v1::Event::Finished = event {
+            if proxy.version() >= 3 {
+                proxy.release();
+            }
+        }
+    }
+}
+
+impl Dispatch<WlSeat, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WlSeat,
+        _event: <WlSeat as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The seat is only used to create the idle notifications, its events are not needed
+    }
+}
+
 delegate_compositor!(Wpaperd);
 delegate_output!(Wpaperd);
 delegate_shm!(Wpaperd);
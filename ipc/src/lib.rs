@@ -5,29 +5,253 @@ use xdg::{BaseDirectories, BaseDirectoriesError};
 
 #[derive(Serialize, Deserialize)]
 pub enum IpcMessage {
-    CurrentWallpaper { monitor: String },
-    NextWallpaper { monitors: Vec<String> },
-    PreviousWallpaper { monitors: Vec<String> },
-    PauseWallpaper { monitors: Vec<String> },
-    ResumeWallpaper { monitors: Vec<String> },
+    CurrentWallpaper {
+        monitor: String,
+    },
+    NextWallpaper {
+        monitors: Vec<String>,
+    },
+    PreviousWallpaper {
+        monitors: Vec<String>,
+    },
+    PauseWallpaper {
+        monitors: Vec<String>,
+    },
+    ResumeWallpaper {
+        monitors: Vec<String>,
+    },
     AllWallpapers,
-    ReloadWallpaper { monitors: Vec<String> },
+    ReloadWallpaper {
+        monitors: Vec<String>,
+    },
+    SetWallpaper {
+        monitor: String,
+        path: PathBuf,
+        pin: bool,
+    },
+    Debug {
+        monitor: String,
+    },
+    /// Show the next wallpaper on all the displays of a group at the same time
+    NextGroup {
+        group: String,
+    },
+    /// Show an image sent over the socket. This message is followed by exactly `size` bytes
+    /// containing the encoded image
+    SetWallpaperData {
+        monitor: String,
+        size: usize,
+        pin: bool,
+    },
+    /// List the outputs handled by wpaperd
+    Outputs,
+    /// The configuration applied to a display, after merging its section with `default`
+    Config {
+        monitor: String,
+    },
+    /// Exchange the images shown on two displays, each one using its own transition
+    SwapWallpapers {
+        first: String,
+        second: String,
+    },
+    /// Save the frame currently shown on a display as a PNG at `path`
+    Capture {
+        monitor: String,
+        path: PathBuf,
+    },
+    /// Keep the connection open and receive an [`IpcEvent`] each time something happens
+    Subscribe,
+    /// Forget the images drawn recently, so that the `random` sorting can pick any of them again
+    ResetHistory {
+        monitors: Vec<String>,
+    },
+    /// Enable or disable `initial_transition` when `enabled` is set, for the next time the
+    /// displays are connected. The current value is returned either way
+    InitialTransition {
+        monitors: Vec<String>,
+        enabled: Option<bool>,
+    },
+    /// Read again the images in the directory of a display or in a directory of the
+    /// configuration, or in all of them when `target` is not set
+    Rescan {
+        target: Option<String>,
+    },
+}
+
+/// Sent to the subscribed clients as they happen, one JSON value per line
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum IpcEvent {
+    WallpaperChanged { monitor: String, path: PathBuf },
+    TransitionStarted { monitor: String },
+    TransitionFinished { monitor: String },
+    OutputAdded { monitor: String },
+    OutputRemoved { monitor: String },
+    ConfigReloaded,
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum IpcResponse {
-    CurrentWallpaper { path: PathBuf },
-    AllWallpapers { entries: Vec<(String, PathBuf)> },
+    CurrentWallpaper {
+        path: PathBuf,
+    },
+    AllWallpapers {
+        entries: Vec<(String, PathBuf)>,
+    },
+    Debug(DebugInfo),
+    Outputs {
+        outputs: Vec<OutputInfo>,
+    },
+    Config(ConfigInfo),
+    /// How many images each display has forgotten
+    HistoryReset {
+        entries: Vec<(String, usize)>,
+    },
+    /// Whether each display fades in its first wallpaper
+    InitialTransition {
+        entries: Vec<(String, bool)>,
+    },
+    /// How many images each directory contains after reading it again
+    Rescanned {
+        entries: Vec<(PathBuf, usize)>,
+    },
     Ok,
 }
 
+/// An output handled by wpaperd, as listed by the `Outputs` message
+#[derive(Serialize, Deserialize)]
+pub struct OutputInfo {
+    pub name: String,
+    pub description: Option<String>,
+    /// Size of the buffer drawn on the output, in hardware pixels
+    pub width: u32,
+    pub height: u32,
+    /// Scale factor applied to the output, fractional when the compositor supports it
+    pub scale: f32,
+    pub wallpaper: PathBuf,
+}
+
+/// Format the outputs as a table with aligned columns, one output per line after a header.
+/// Both wpaperctl and `wpaperd --list-outputs` print it
+pub fn format_outputs(outputs: &[OutputInfo]) -> String {
+    let rows: Vec<[String; 5]> = outputs
+        .iter()
+        .map(|output| {
+            [
+                output.name.clone(),
+                format!("{}x{}", output.width, output.height),
+                format!("{}", output.scale),
+                output.wallpaper.to_string_lossy().into_owned(),
+                output.description.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    let header = ["NAME", "SIZE", "SCALE", "WALLPAPER", "DESCRIPTION"].map(String::from);
+    let mut widths = [0; 5];
+    for row in rows.iter().chain(std::iter::once(&header)) {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(column, width)| format!("{column:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// The configuration of a display, as returned by the `Config` message
+#[derive(Serialize, Deserialize)]
+pub struct ConfigInfo {
+    /// Name of the section matching the display, `any` when none of them does
+    pub section: String,
+    /// Every setting in effect, including the defaults of the keys that are not set
+    pub config: serde_json::Value,
+}
+
+/// Diagnostics about the rendering of a display
+#[derive(Serialize, Deserialize)]
+pub struct DebugInfo {
+    pub transition_going: bool,
+    /// Progress of the current transition, from `0.0` to `1.0`
+    pub progress: f32,
+    /// Timestamp in milliseconds of the frame where the current transition started
+    pub time_started: u32,
+    /// Duration of the transition in milliseconds
+    pub transition_time: u32,
+    /// Average interval in milliseconds between the frames drawn. It is only measured after
+    /// the first debug request, so it's `None` until new frames have been drawn
+    pub frame_interval: Option<f32>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum IpcError {
-    MonitorNotFound { monitor: String },
+    MonitorNotFound {
+        monitor: String,
+    },
     DrawErrors(Vec<(String, String)>),
+    InvalidImage {
+        path: PathBuf,
+        error: String,
+    },
+    GroupNotFound {
+        group: String,
+    },
+    CaptureFailed {
+        monitor: String,
+        error: String,
+    },
+    CannotSwap {
+        monitor: String,
+        reason: String,
+    },
+    /// Neither a display showing a directory nor a directory of the configuration
+    NothingToRescan {
+        target: String,
+    },
 }
 
 pub fn socket_path() -> Result<PathBuf, BaseDirectoriesError> {
     let xdg_dirs = BaseDirectories::with_prefix("wpaperd")?;
     Ok(xdg_dirs.get_runtime_directory()?.join("wpaperd.sock"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_outputs() {
+        let outputs = [
+            OutputInfo {
+                name: "DP-1".to_string(),
+                description: Some("Dell Inc. DELL U2720Q (DP-1)".to_string()),
+                width: 3840,
+                height: 2160,
+                scale: 1.5,
+                wallpaper: PathBuf::from("/a.png"),
+            },
+            OutputInfo {
+                name: "eDP-1".to_string(),
+                description: None,
+                width: 1920,
+                height: 1080,
+                scale: 1.0,
+                wallpaper: PathBuf::from("/wallpapers/b.png"),
+            },
+        ];
+        assert_eq!(
+            format_outputs(&outputs),
+            "NAME   SIZE       SCALE  WALLPAPER          DESCRIPTION\n\
+             DP-1   3840x2160  1.5    /a.png             Dell Inc. DELL U2720Q (DP-1)\n\
+             eDP-1  1920x1080  1      /wallpapers/b.png\n"
+        );
+    }
+}